@@ -1,22 +1,45 @@
-use crate::cred_store;
+use crate::cred_store::TokenStore;
 use crate::http::{AppendPaths, Client};
 use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
 use reqwest::{StatusCode, Url};
 use serde::Deserialize;
-use std::time::{Duration, Instant};
-use tokio::sync::mpsc::Sender;
+use std::{future::Future, path::PathBuf, time::Duration};
+use tokio::{sync::mpsc::Sender, time::Instant};
 
 const CLIENT_ID: &str = "9a021cf1-0d67-456b-b821-c1dff53de0e7";
 const SCOPE: &str = "offline_access files.read";
+const APP_FOLDER_SCOPE: &str = "offline_access files.read files.readwrite.appfolder";
+const READ_WRITE_SCOPE: &str = "offline_access files.readwrite";
 
 const REFRESH_TOKEN_PADDING: Duration = Duration::from_secs(60);
 
+/// How long a refresh token lasts without being used, after which the user has to sign in again.
+pub const REFRESH_TOKEN_LIFETIME: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// Something that access tokens for OneDrive can be gotten from.
+pub trait TokenSource: Send {
+    /// Gets an access token, refreshing it or signing in again as needed.
+    fn get_token(&mut self) -> impl Future<Output = Result<String>> + Send;
+
+    /// When the refresh token will expire if it isn't used before then, if that is known.
+    fn refresh_token_expires(&self) -> Option<DateTime<Utc>>;
+}
+
+/// Gets access tokens for OneDrive, signing in with a device code the first time and then keeping
+/// the refresh token in a `TokenStore` so that the user doesn't need to sign in again.
 pub struct Authenticator {
     client: Client,
     refresh_after: Instant,
     access_token: Option<String>,
     refresh_token: Option<String>,
     sender: Sender<AuthMessage>,
+    scope: &'static str,
+    token_store: TokenStore,
+    /// When the refresh token was issued, if known.
+    refresh_token_issued: Option<DateTime<Utc>>,
+    /// File to remember `refresh_token_issued` in between runs.
+    issued_record: Option<PathBuf>,
     device_code_url: Url,
     token_url: Url,
 }
@@ -50,13 +73,23 @@ enum TokenResponse {
     Failure(TokenResponseError),
 }
 
+/// Progress of signing in, which needs to be shown to the user.
 #[derive(Debug, Eq, PartialEq)]
 pub enum AuthMessage {
+    /// The user needs to open the URL (the first value) in a browser and enter the code (the
+    /// second value).
     HasClientCode(String, String),
+    /// The user has finished signing in.
     Completed,
 }
 
 impl Authenticator {
+    /// Creates an authenticator for the OAuth endpoints at `base_url` (e.g.,
+    /// `https://login.microsoftonline.com/consumers/oauth2/v2.0`), reporting sign in progress to
+    /// `sender`. `refresh_token` is one that was stored previously, if any (see
+    /// `TokenStore::get_refresh_token`).
+    ///
+    /// Panics if `base_url` isn't a valid URL.
     pub fn new(sender: Sender<AuthMessage>, base_url: &str, refresh_token: Option<String>) -> Self {
         let base_url = Url::parse(base_url).unwrap();
         Self {
@@ -65,12 +98,68 @@ impl Authenticator {
             access_token: None,
             refresh_token,
             sender,
+            scope: SCOPE,
+            token_store: TokenStore::default(),
+            refresh_token_issued: None,
+            issued_record: None,
             device_code_url: base_url.append_path("devicecode"),
             token_url: base_url.append_path("token"),
         }
     }
 
-    pub async fn get_token(&mut self) -> Result<String> {
+    /// Also requests access to write to the app's own folder in OneDrive.
+    pub fn with_app_folder_access(self) -> Self {
+        Self {
+            scope: APP_FOLDER_SCOPE,
+            ..self
+        }
+    }
+
+    /// Also requests access to write to any file in OneDrive, which includes the app's own folder.
+    pub fn with_write_access(self) -> Self {
+        Self {
+            scope: READ_WRITE_SCOPE,
+            ..self
+        }
+    }
+
+    /// Stores the refresh token somewhere other than the default credential.
+    pub fn with_token_store(self, token_store: TokenStore) -> Self {
+        Self {
+            token_store,
+            ..self
+        }
+    }
+
+    /// Remembers when the refresh token was issued in the file at `path`, so that when it expires
+    /// (see `refresh_token_expires`) is still known after restarting.
+    pub fn with_issued_record(self, path: PathBuf) -> Self {
+        // The record is only kept for the refresh token that was stored along with it.
+        let refresh_token_issued = self
+            .refresh_token
+            .as_ref()
+            .and_then(|_| std::fs::read_to_string(&path).ok())
+            .and_then(|issued| issued.trim().parse().ok());
+        Self {
+            refresh_token_issued,
+            issued_record: Some(path),
+            ..self
+        }
+    }
+}
+
+impl TokenSource for Authenticator {
+    /// When the refresh token will expire if it isn't used before then, if there is one and it is
+    /// known when it was issued. Getting a token after the access token has expired (hourly) gets
+    /// a new refresh token.
+    fn refresh_token_expires(&self) -> Option<DateTime<Utc>> {
+        self.refresh_token.as_ref()?;
+        Some(self.refresh_token_issued? + REFRESH_TOKEN_LIFETIME)
+    }
+
+    /// Gets an access token, refreshing it or signing in again as needed. Signing in waits until the
+    /// user has entered the code sent to the `AuthMessage` sender.
+    async fn get_token(&mut self) -> Result<String> {
         if self.access_token.is_none() || Instant::now() > self.refresh_after {
             let response = if let Some(refresh_token) = &self.refresh_token {
                 let result = self
@@ -80,25 +169,25 @@ impl Authenticator {
                         &[
                             ("client_id", CLIENT_ID),
                             ("grant_type", "refresh_token"),
-                            ("scope", SCOPE),
+                            ("scope", self.scope),
                             ("refresh_token", refresh_token),
                         ],
                         None,
                     )
-                    .await
-                    .with_context(|| "Refresh token");
-                if result.is_err() {
-                    // On error, assume we have a bad refresh token.
+                    .await;
+                if result.as_ref().is_err_and(|err| !err.is_transient()) {
+                    // The server rejected the refresh token, so assume it's bad. Otherwise, it can
+                    // be tried again once the network or server recovers.
                     self.refresh_token = None;
                 }
-                result?
+                result.with_context(|| "Refresh token")?
             } else {
                 'outer: loop {
                     let device_response = self
                         .client
                         .post::<DeviceAuthResponse>(
                             self.device_code_url.clone(),
-                            &[("client_id", CLIENT_ID), ("scope", SCOPE)],
+                            &[("client_id", CLIENT_ID), ("scope", self.scope)],
                             None,
                         )
                         .await
@@ -169,7 +258,14 @@ impl Authenticator {
                         .checked_sub(REFRESH_TOKEN_PADDING)
                         .and_then(|expires_in| Instant::now().checked_add(expires_in))
                         .ok_or_else(|| anyhow!("Token expires too quickly"))?;
-                    cred_store::store_refresh_token(&response.refresh_token);
+                    self.token_store
+                        .store_refresh_token(&response.refresh_token);
+                    let issued = Utc::now();
+                    if let Some(path) = &self.issued_record {
+                        // Failing to record this only means not warning before the token expires.
+                        let _ = std::fs::write(path, issued.to_rfc3339());
+                    }
+                    self.refresh_token_issued = Some(issued);
                     self.refresh_token = Some(response.refresh_token);
                     self.access_token = Some(response.access_token);
                 }
@@ -351,6 +447,42 @@ async fn with_existing_refresh_token() {
     refresh_token_mock.assert();
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn track_refresh_token_expiry() {
+    let directory = std::env::temp_dir().join("onedrive_slideshow_test/track_refresh_token_expiry");
+    std::fs::create_dir_all(&directory).unwrap();
+    let record = directory.join("refresh_token_issued");
+    let _ = std::fs::remove_file(&record);
+
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+    let (sender, _reciever) = tokio::sync::mpsc::channel(8);
+    let mut authenticator = Authenticator::new(sender.clone(), &url, Some("rt".to_string()))
+        .with_issued_record(record.clone());
+    // It isn't known when the stored refresh token was issued.
+    assert_eq!(authenticator.refresh_token_expires(), None);
+
+    let refresh_token_mock = server
+        .mock("POST", "/token")
+        .with_body(r#"{ "access_token": "ac2", "refresh_token": "rt2", "expires_in": 3600 } "#)
+        .expect(1)
+        .create();
+    let before = Utc::now();
+    authenticator.get_token().await.unwrap();
+    let expires = authenticator.refresh_token_expires().unwrap();
+    assert!(expires >= before + REFRESH_TOKEN_LIFETIME);
+    assert!(expires <= Utc::now() + REFRESH_TOKEN_LIFETIME);
+    refresh_token_mock.assert();
+
+    // Which is remembered for next time.
+    let authenticator = Authenticator::new(sender.clone(), &url, Some("rt2".to_string()))
+        .with_issued_record(record.clone());
+    assert_eq!(authenticator.refresh_token_expires(), Some(expires));
+    // Unless there's no refresh token to go with it.
+    let authenticator = Authenticator::new(sender, &url, None).with_issued_record(record);
+    assert_eq!(authenticator.refresh_token_expires(), None);
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn with_existing_but_expired_refresh_token() {
     let mut server = mockito::Server::new_async().await;
@@ -410,3 +542,66 @@ async fn with_existing_but_expired_refresh_token() {
     token_mock.assert();
     refresh_token_mock.assert();
 }
+
+#[tokio::test(flavor = "multi_thread")]
+async fn keep_refresh_token_on_server_error() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let (sender, _reciever) = tokio::sync::mpsc::channel(8);
+    let mut authenticator = Authenticator::new(sender, &url, Some("rt".to_string()));
+
+    // The server is down, which says nothing about whether the refresh token is still valid.
+    let refresh_token_mock = server
+        .mock("POST", "/token")
+        .with_status(503)
+        .expect(6)
+        .create();
+    assert!(authenticator.get_token().await.is_err());
+    refresh_token_mock.assert();
+    assert_eq!(authenticator.refresh_token.as_deref(), Some("rt"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn refresh_after_expiry() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let (sender, _reciever) = tokio::sync::mpsc::channel(8);
+    let mut authenticator = Authenticator::new(sender, &url, Some("rt".to_string()));
+
+    let refresh_token_mock = server
+        .mock("POST", "/token")
+        .match_body(mockito::Matcher::UrlEncoded(
+            "refresh_token".into(),
+            "rt".into(),
+        ))
+        .with_body(r#"{ "access_token": "ac", "refresh_token": "rt2", "expires_in": 3600 } "#)
+        .expect(1)
+        .create();
+    let token = authenticator.get_token().await.unwrap();
+    assert_eq!(token, "ac");
+
+    // Just before the token expires (minus padding) it is still used.
+    let valid_for = Duration::from_secs(3600) - REFRESH_TOKEN_PADDING;
+    tokio::time::advance(valid_for - Duration::from_secs(1)).await;
+    let token = authenticator.get_token().await.unwrap();
+    assert_eq!(token, "ac");
+    refresh_token_mock.assert();
+
+    // Once it has expired, it is refreshed.
+    let second_refresh_token_mock = server
+        .mock("POST", "/token")
+        .match_body(mockito::Matcher::UrlEncoded(
+            "refresh_token".into(),
+            "rt2".into(),
+        ))
+        .with_body(r#"{ "access_token": "ac2", "refresh_token": "rt3", "expires_in": 3600 } "#)
+        .expect(1)
+        .create();
+    tokio::time::advance(Duration::from_secs(2)).await;
+    let token = authenticator.get_token().await.unwrap();
+    assert_eq!(token, "ac2");
+    refresh_token_mock.assert();
+    second_refresh_token_mock.assert();
+}
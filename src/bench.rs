@@ -0,0 +1,144 @@
+use crate::{
+    auth::{Authenticator, TokenSource},
+    image_loader::ImageLoader,
+};
+use anyhow::{bail, Context, Result};
+use std::{fmt, time::Duration};
+use tokio::time::Instant;
+
+/// Screen size that slides are loaded at, since benchmarking doesn't open a window.
+const BENCH_SCREEN_HEIGHT: u32 = 1080;
+const BENCH_SCREEN_WIDTH: u32 = 1920;
+
+/// How long it took to get a single slide ready to show.
+#[derive(Debug)]
+pub struct SlideTiming {
+    /// Time spent reading the image from the cache or downloading it.
+    pub load: Duration,
+    pub decode: Duration,
+}
+
+impl SlideTiming {
+    /// Time between asking for the slide and it being ready to show.
+    fn total(&self) -> Duration {
+        self.load + self.decode
+    }
+}
+
+#[derive(Debug)]
+pub struct BenchmarkReport {
+    /// Time to authenticate and list the images.
+    pub startup: Duration,
+    pub slides: Vec<SlideTiming>,
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Startup (sign in and list images): {:?}", self.startup)?;
+        if let Some(first) = self.slides.first() {
+            writeln!(f, "Time to first slide: {:?}", self.startup + first.total())?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:>5} {:>12} {:>12} {:>12}",
+            "Slide", "Load", "Decode", "Total"
+        )?;
+        for (index, slide) in self.slides.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>5} {:>12} {:>12} {:>12}",
+                index + 1,
+                format!("{:?}", slide.load),
+                format!("{:?}", slide.decode),
+                format!("{:?}", slide.total())
+            )?;
+        }
+
+        let count = u32::try_from(self.slides.len()).unwrap_or(u32::MAX);
+        if count > 0 {
+            let sum = |timing: fn(&SlideTiming) -> Duration| {
+                self.slides.iter().map(timing).sum::<Duration>()
+            };
+            writeln!(
+                f,
+                "{:>5} {:>12} {:>12} {:>12}",
+                "Avg",
+                format!("{:?}", sum(|slide| slide.load) / count),
+                format!("{:?}", sum(|slide| slide.decode) / count),
+                format!("{:?}", sum(SlideTiming::total) / count)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Signs in, lists the images and then loads `slides` of them one after another, timing each step.
+pub async fn run(
+    authenticator: &mut Authenticator,
+    loader: &mut ImageLoader,
+    slides: usize,
+) -> Result<BenchmarkReport> {
+    let start = Instant::now();
+    let token = authenticator.get_token().await.context("Authenticating")?;
+    loader
+        .detect_drive_type(&token)
+        .await
+        .context("Detecting drive type")?;
+    let (listing, _) = loader
+        .get_image_list(&token)
+        .await
+        .context("Listing images")?;
+    let images = listing.images;
+    if images.is_empty() {
+        bail!("No images found");
+    }
+    let startup = start.elapsed();
+
+    let mut timings = Vec::new();
+    for image in images.iter().cycle().take(slides) {
+        let load_start = Instant::now();
+        let data = loader
+            .load_image_data(&token, BENCH_SCREEN_HEIGHT, BENCH_SCREEN_WIDTH, &image.id)
+            .await
+            .context("Loading image")?;
+        let decode_start = Instant::now();
+        loader.decode(&data).context("Decoding image")?;
+        timings.push(SlideTiming {
+            load: decode_start - load_start,
+            decode: decode_start.elapsed(),
+        });
+    }
+
+    Ok(BenchmarkReport {
+        startup,
+        slides: timings,
+    })
+}
+
+#[test]
+fn format_report() {
+    let report = BenchmarkReport {
+        startup: Duration::from_millis(500),
+        slides: vec![
+            SlideTiming {
+                load: Duration::from_millis(100),
+                decode: Duration::from_millis(20),
+            },
+            SlideTiming {
+                load: Duration::from_millis(300),
+                decode: Duration::from_millis(40),
+            },
+        ],
+    };
+    assert_eq!(
+        report.to_string(),
+        "Startup (sign in and list images): 500ms\n\
+         Time to first slide: 620ms\n\
+         \n\
+         Slide         Load       Decode        Total\n\
+         \x20   1        100ms         20ms        120ms\n\
+         \x20   2        300ms         40ms        340ms\n\
+         \x20 Avg        200ms         30ms        230ms\n"
+    );
+}
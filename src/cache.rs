@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+/// Extension of the files that images are written to before being moved into the cache, so that an
+/// image that was only partly written (e.g., because the device lost power) is never read back.
+const CACHE_TEMP_EXTENSION: &str = "tmp";
+
+/// How long a file in the cache must have gone untouched before garbage collection can remove it,
+/// so that images that are being written or were only just shown are left alone.
+const CACHE_GARBAGE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Percentage of the disk that is kept free by the default cache policy.
+pub const DEFAULT_MIN_FREE_PERCENT: u8 = 10;
+
+/// Where loaded images are kept, so that they can be shown again without downloading them.
+///
+/// [`DiskCache`] is what the slideshow uses, but an embedder can keep images somewhere else (e.g.,
+/// in memory, on a device without writable storage) by passing its own store to
+/// [`ImageLoader::with_cache`](crate::image_loader::ImageLoader::with_cache).
+pub trait CacheStore: Send + Sync {
+    /// IDs of the images in the cache, which can be shown without downloading them.
+    fn cached_ids(&self) -> HashSet<String>;
+
+    /// Reads a cached image, or `None` if it isn't in the cache.
+    fn read(&self, image_id: &str) -> impl Future<Output = Result<Option<Bytes>>> + Send;
+
+    /// Makes room for another image, returning whether it should be cached.
+    fn make_room(&self) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Adds an image to the cache, replacing any earlier copy of it.
+    fn store(&self, image_id: &str, data: &[u8]) -> impl Future<Output = Result<()>> + Send;
+
+    /// Removes images that won't be shown again, such as those that aren't in `listed` (if it is
+    /// given). Returns how many were removed.
+    fn collect_garbage(
+        &self,
+        listed: Option<&HashSet<String>>,
+    ) -> impl Future<Output = Result<usize>> + Send;
+}
+
+/// What to do with downloaded images when the disk is running low on space.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CachePolicy {
+    /// Cache every image.
+    Always,
+    /// Stop caching images while less than `min_free_percent` of the disk is free.
+    SkipWhenLow { min_free_percent: u8 },
+    /// Remove the least recently shown images from the cache to keep `min_free_percent` of the disk
+    /// free, and skip caching if that isn't enough.
+    EvictWhenLow { min_free_percent: u8 },
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy::SkipWhenLow {
+            min_free_percent: DEFAULT_MIN_FREE_PERCENT,
+        }
+    }
+}
+
+fn has_free_space(min_free_percent: u8) -> bool {
+    sys_info::disk_info().map_or(false, |disk_info| {
+        disk_info.free.saturating_mul(100)
+            >= disk_info.total.saturating_mul(min_free_percent.into())
+    })
+}
+
+/// Caches each image in a file named after its ID.
+pub struct DiskCache {
+    directory: PathBuf,
+    policy: CachePolicy,
+}
+
+impl DiskCache {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            policy: CachePolicy::default(),
+        }
+    }
+
+    /// Changes what happens to loaded images when the disk is running low on space.
+    pub fn with_policy(self, policy: CachePolicy) -> Self {
+        Self { policy, ..self }
+    }
+
+    async fn evict(&self, min_free_percent: u8) -> Result<()> {
+        if !self.directory.exists() {
+            return Ok(());
+        }
+
+        let mut cached_images = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                cached_images.push((metadata.modified()?, entry.path()));
+            }
+        }
+
+        // Oldest first.
+        cached_images.sort();
+        for (_, path) in cached_images {
+            if has_free_space(min_free_percent) {
+                break;
+            }
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(())
+    }
+}
+
+impl CacheStore for DiskCache {
+    fn cached_ids(&self) -> HashSet<String> {
+        std::fs::read_dir(&self.directory).map_or_else(
+            |_| HashSet::new(),
+            |entries| {
+                entries
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|name| !name.ends_with(&format!(".{CACHE_TEMP_EXTENSION}")))
+                    .collect()
+            },
+        )
+    }
+
+    async fn read(&self, image_id: &str) -> Result<Option<Bytes>> {
+        let path = self.directory.join(image_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = tokio::fs::read(&path)
+            .await
+            .with_context(|| "Reading cached image failed")?;
+        // Track when the image was last shown so that eviction removes the least recently used.
+        // This is only a hint, so failing to update it isn't an error.
+        if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+            file.set_modified(SystemTime::now()).ok();
+        }
+        Ok(Some(data.into()))
+    }
+
+    async fn make_room(&self) -> Result<bool> {
+        match self.policy {
+            CachePolicy::Always => {}
+            CachePolicy::SkipWhenLow { min_free_percent } => {
+                if !has_free_space(min_free_percent) {
+                    return Ok(false);
+                }
+            }
+            CachePolicy::EvictWhenLow { min_free_percent } => {
+                if !has_free_space(min_free_percent) {
+                    self.evict(min_free_percent)
+                        .await
+                        .with_context(|| "Evicting cached images")?;
+                    if !has_free_space(min_free_percent) {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+
+        if !self.directory.exists() {
+            tokio::fs::create_dir_all(&self.directory)
+                .await
+                .with_context(|| "Create cache directory")?;
+        }
+        Ok(true)
+    }
+
+    async fn store(&self, image_id: &str, data: &[u8]) -> Result<()> {
+        let path = self.directory.join(image_id);
+        let temp_path = path.with_extension(CACHE_TEMP_EXTENSION);
+        tokio::fs::write(&temp_path, data)
+            .await
+            .with_context(|| "Store image in cache")?;
+        tokio::fs::rename(&temp_path, &path)
+            .await
+            .with_context(|| "Store image in cache")
+    }
+
+    /// Besides images that aren't in `listed`, this removes images that were never completely
+    /// written and empty files.
+    async fn collect_garbage(&self, listed: Option<&HashSet<String>>) -> Result<usize> {
+        if !self.directory.exists() {
+            return Ok(0);
+        }
+
+        let mut removed = 0;
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            let is_old = metadata
+                .modified()?
+                .elapsed()
+                .map_or(false, |age| age >= CACHE_GARBAGE_AGE);
+            let is_garbage = metadata.len() == 0
+                || (is_old
+                    && (path
+                        .extension()
+                        .is_some_and(|extension| extension == CACHE_TEMP_EXTENSION)
+                        || listed.is_some_and(|listed| {
+                            !listed.contains(&*entry.file_name().to_string_lossy())
+                        })));
+            if is_garbage {
+                tokio::fs::remove_file(&path)
+                    .await
+                    .with_context(|| format!("Removing {}", path.display()))?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn collect_cache_garbage() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/collect_cache_garbage");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    let cache = DiskCache::new(temp_dir.clone());
+    assert_eq!(cache.collect_garbage(None).await.unwrap(), 0);
+
+    tokio::fs::create_dir_all(&temp_dir).await.unwrap();
+    let create = |name: &str, contents: &[u8], old: bool| {
+        std::fs::write(temp_dir.join(name), contents).unwrap();
+        if old {
+            std::fs::File::options()
+                .write(true)
+                .open(temp_dir.join(name))
+                .unwrap()
+                .set_modified(SystemTime::now() - CACHE_GARBAGE_AGE * 2)
+                .unwrap();
+        }
+    };
+    create("listed", b"image", true);
+    create("empty", b"", false);
+    create("unfinished.tmp", b"ima", true);
+    create("writing.tmp", b"ima", false);
+    create("deleted", b"image", true);
+    create("shown_recently", b"image", false);
+    let listed = HashSet::from(["listed".to_string(), "empty".to_string()]);
+
+    // Without a listing, only files that are certainly broken are removed.
+    assert_eq!(cache.collect_garbage(None).await.unwrap(), 2);
+    assert!(!temp_dir.join("empty").exists());
+    assert!(!temp_dir.join("unfinished.tmp").exists());
+    assert!(temp_dir.join("deleted").exists());
+
+    assert_eq!(cache.collect_garbage(Some(&listed)).await.unwrap(), 1);
+    assert!(!temp_dir.join("deleted").exists());
+    for kept in ["listed", "writing.tmp", "shown_recently"] {
+        assert!(temp_dir.join(kept).exists(), "{kept}");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn evict_cached_images() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/evict_cached_images");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    tokio::fs::create_dir_all(&temp_dir).await.unwrap();
+    tokio::fs::write(temp_dir.join("old_image"), b"old")
+        .await
+        .unwrap();
+
+    // Keeping no space free is always possible, so nothing is evicted.
+    let cache = DiskCache::new(temp_dir.clone()).with_policy(CachePolicy::EvictWhenLow {
+        min_free_percent: 0,
+    });
+    assert!(cache.make_room().await.unwrap());
+    assert!(temp_dir.join("old_image").exists());
+
+    // Keeping the entire disk free is never possible, so everything is evicted and caching is
+    // skipped.
+    let cache = cache.with_policy(CachePolicy::EvictWhenLow {
+        min_free_percent: 100,
+    });
+    assert!(!cache.make_room().await.unwrap());
+    assert!(!temp_dir.join("old_image").exists());
+
+    // Skipping leaves the cache alone.
+    tokio::fs::write(temp_dir.join("old_image"), b"old")
+        .await
+        .unwrap();
+    let cache = cache.with_policy(CachePolicy::SkipWhenLow {
+        min_free_percent: 100,
+    });
+    assert!(!cache.make_room().await.unwrap());
+    assert!(temp_dir.join("old_image").exists());
+}
@@ -0,0 +1,752 @@
+use crate::image_loader::FolderTheme;
+use anyhow::Result;
+use egui::{ColorImage, Rect};
+use rand::Rng;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How long after the user last interacted with the slideshow that new slides are held back, so
+/// that the slideshow doesn't move on while they are looking through it. After that, the slideshow
+/// returns to the latest slide.
+const INTERACTION_GRACE_PERIOD: Duration = Duration::from_secs(30);
+/// Furthest that the Ken Burns effect zooms in, as a multiple of the image's size.
+const KEN_BURNS_MAX_ZOOM: f32 = 1.25;
+/// Most thumbnails in a photo strip that are on screen at once.
+pub const PHOTO_STRIP_VISIBLE: usize = 4;
+/// How often a photo strip moves along by one thumbnail.
+const PHOTO_STRIP_STEP: Duration = Duration::from_secs(20);
+/// How long a title card is shown over the first slide of a new folder.
+const TITLE_CARD_TIME: Duration = Duration::from_secs(4);
+
+/// A slide that is ready to be shown.
+#[derive(Clone)]
+pub struct Slide {
+    pub image: Arc<ColorImage>,
+    /// Text shown on top of the image, e.g. the folder that it came from.
+    pub caption: Option<String>,
+    /// Colors of the caption.
+    pub theme: FolderTheme,
+    /// How long to crossfade from the previous slide to this one.
+    pub fade: Duration,
+    /// How to pan and zoom across the image while it is on screen, if at all.
+    pub ken_burns: Option<KenBurns>,
+    /// Thumbnails of other photos to show in a strip along the bottom, e.g. from the same folder.
+    pub photo_strip: Vec<Arc<ColorImage>>,
+    /// Title shown over the start of the slide when it is the first from a new folder, e.g.
+    /// "Summer 2022 · Italy".
+    pub title_card: Option<String>,
+    /// How long the slide is shown for, if a progress bar should count down to the next one.
+    pub progress_bar: Option<Duration>,
+}
+
+impl From<Arc<ColorImage>> for Slide {
+    fn from(image: Arc<ColorImage>) -> Self {
+        Self {
+            image,
+            caption: None,
+            theme: FolderTheme::default(),
+            fade: Duration::ZERO,
+            ken_burns: None,
+            photo_strip: Vec::new(),
+            title_card: None,
+            progress_bar: None,
+        }
+    }
+}
+
+impl Slide {
+    /// The thumbnails of the photo strip that are on screen after the slide has been shown for
+    /// `elapsed`. The strip moves along by one thumbnail every `PHOTO_STRIP_STEP`, wrapping around.
+    pub fn photo_strip_at(&self, elapsed: Duration) -> Vec<&Arc<ColorImage>> {
+        if self.photo_strip.len() <= PHOTO_STRIP_VISIBLE {
+            return self.photo_strip.iter().collect();
+        }
+        let step = (elapsed.as_secs_f64() / PHOTO_STRIP_STEP.as_secs_f64()) as usize;
+        (0..PHOTO_STRIP_VISIBLE)
+            .map(|index| &self.photo_strip[(step + index) % self.photo_strip.len()])
+            .collect()
+    }
+}
+
+/// A slow pan and zoom from one part of an image to another (the "Ken Burns effect").
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KenBurns {
+    /// Parts of the image that are shown at the start and end, as fractions of its size.
+    pub from: Rect,
+    pub to: Rect,
+    /// How long the motion takes, which should be how long the slide is on screen.
+    pub duration: Duration,
+}
+
+impl KenBurns {
+    /// Pans and zooms between two random parts of the image.
+    pub fn random(duration: Duration) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut part = || {
+            let size = 1.0 / rng.gen_range(1.0..=KEN_BURNS_MAX_ZOOM);
+            let min = egui::pos2(
+                rng.gen_range(0.0..=1.0 - size),
+                rng.gen_range(0.0..=1.0 - size),
+            );
+            Rect::from_min_size(min, egui::vec2(size, size))
+        };
+        Self {
+            from: part(),
+            to: part(),
+            duration,
+        }
+    }
+
+    /// The part of the image to show once `elapsed` has passed.
+    pub fn part_at(&self, elapsed: Duration) -> Rect {
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let progress = elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        // Ease in and out, so that the motion doesn't start or stop abruptly.
+        let eased = progress * progress * (3.0 - 2.0 * progress);
+        self.from.lerp_towards(&self.to, eased)
+    }
+}
+
+pub enum AppState {
+    WaitingForNetwork,
+    /// The network requires signing in (at the given page, if known) before it can be used.
+    CaptivePortal(Option<String>),
+    WaitingForAuth(String, String),
+    LoadingImage,
+    HasImage(Slide),
+}
+
+unsafe impl Send for AppState {}
+unsafe impl Sync for AppState {}
+
+/// Events that the UI forwards to the controller.
+pub enum SlideshowEvent {
+    /// The image loader has produced a new state.
+    StateChanged(Result<AppState>),
+    /// The user clicked on the slideshow.
+    Clicked,
+    /// A remote control client has started pairing (or has finished, if `None`).
+    PairingPinChanged(Option<String>),
+    /// Problems that don't stop the slideshow, but that the user should fix.
+    WarningsChanged(Vec<String>),
+    /// Message for the day, e.g. on a birthday (or no message, if `None`).
+    BannerChanged(Option<String>),
+    /// The slideshow has been paused or resumed.
+    PausedChanged(bool),
+    /// Time has passed, so if the user has stopped interacting the latest slide may now be shown.
+    Tick,
+}
+
+/// Tracks what the slideshow is showing, independent of how it is drawn.
+pub struct SlideshowController {
+    current_state: Result<AppState>,
+    previous_image: Option<Slide>,
+    /// The slide that was shown before the current one, and when the current one replaced it.
+    fading_from: Option<(Slide, Instant)>,
+    /// When the current state was first shown.
+    shown_at: Instant,
+    pairing_pin: Option<String>,
+    warnings: Vec<String>,
+    banner: Option<String>,
+    /// When the slideshow was paused, if it is.
+    paused_at: Option<Instant>,
+    /// When the current slide's interval started, moved on by however long it has been paused for
+    /// so that the progress bar picks up where it left off.
+    interval_started: Instant,
+    splash: Option<Arc<ColorImage>>,
+    interaction_grace_period: Duration,
+    interacting_until: Option<Instant>,
+    /// The latest slide, if it isn't being shown because the user has gone back to an earlier one or
+    /// it arrived while they were interacting. It is shown once they've stopped.
+    live_image: Option<Slide>,
+}
+
+impl Default for SlideshowController {
+    fn default() -> Self {
+        Self {
+            current_state: Ok(AppState::LoadingImage),
+            previous_image: None,
+            fading_from: None,
+            shown_at: Instant::now(),
+            pairing_pin: None,
+            warnings: Vec::new(),
+            banner: None,
+            paused_at: None,
+            interval_started: Instant::now(),
+            splash: None,
+            interaction_grace_period: INTERACTION_GRACE_PERIOD,
+            interacting_until: None,
+            live_image: None,
+        }
+    }
+}
+
+impl SlideshowController {
+    pub fn state(&self) -> &Result<AppState> {
+        &self.current_state
+    }
+
+    /// Shows `splash` instead of a spinner while the first slide is loading.
+    pub fn with_splash(self, splash: Arc<ColorImage>) -> Self {
+        Self {
+            splash: Some(splash),
+            ..self
+        }
+    }
+
+    /// Holds back new slides for `grace_period` after the user last interacted with the slideshow,
+    /// then returns to the latest slide.
+    pub fn with_interaction_grace_period(self, grace_period: Duration) -> Self {
+        Self {
+            interaction_grace_period: grace_period,
+            ..self
+        }
+    }
+
+    /// How long until the slideshow returns to the latest slide, if it isn't already showing it.
+    pub fn returns_to_live_in(&self) -> Option<Duration> {
+        self.live_image.as_ref()?;
+        Some(self.interacting_until.map_or(Duration::ZERO, |until| {
+            until.saturating_duration_since(Instant::now())
+        }))
+    }
+
+    /// The slide that is fading out under the current one, and how far through the fade it is (from 0
+    /// to 1), if the current slide is still fading in.
+    pub fn fading_from(&self) -> Option<(&Slide, f32)> {
+        let (previous, started) = self.fading_from.as_ref()?;
+        let Ok(AppState::HasImage(current)) = &self.current_state else {
+            return None;
+        };
+        let progress = started.elapsed().as_secs_f32() / current.fade.as_secs_f32();
+        // Without a fade, this is either infinite or NaN.
+        (progress < 1.0).then_some((previous, progress))
+    }
+
+    /// The part of the current slide to show, if it is panning and zooming.
+    pub fn ken_burns_part(&self) -> Option<Rect> {
+        let Ok(AppState::HasImage(slide)) = &self.current_state else {
+            return None;
+        };
+        Some(slide.ken_burns?.part_at(self.shown_at.elapsed()))
+    }
+
+    /// The thumbnails of the current slide's photo strip that are on screen, and how long until the
+    /// strip moves along.
+    pub fn photo_strip(&self) -> (Vec<&Arc<ColorImage>>, Duration) {
+        let Ok(AppState::HasImage(slide)) = &self.current_state else {
+            return (Vec::new(), PHOTO_STRIP_STEP);
+        };
+        let elapsed = self.shown_at.elapsed();
+        let into_step =
+            Duration::from_secs_f64(elapsed.as_secs_f64() % PHOTO_STRIP_STEP.as_secs_f64());
+        (slide.photo_strip_at(elapsed), PHOTO_STRIP_STEP - into_step)
+    }
+
+    /// The title card to show over the current slide, and how long until it is taken down.
+    pub fn title_card(&self) -> Option<(&str, Duration)> {
+        let Ok(AppState::HasImage(slide)) = &self.current_state else {
+            return None;
+        };
+        let remaining = TITLE_CARD_TIME.checked_sub(self.shown_at.elapsed())?;
+        Some((slide.title_card.as_deref()?, remaining))
+    }
+
+    /// How far through its interval the current slide is (from 0 to 1) and how long that interval
+    /// is, if it has a progress bar. There's nothing to count down to while paused or looking back
+    /// at an earlier slide.
+    pub fn progress(&self) -> Option<(f32, Duration)> {
+        let Ok(AppState::HasImage(slide)) = &self.current_state else {
+            return None;
+        };
+        let interval = slide.progress_bar?;
+        if self.paused_at.is_some() || self.live_image.is_some() || interval.is_zero() {
+            return None;
+        }
+        let progress = self.interval_started.elapsed().as_secs_f32() / interval.as_secs_f32();
+        Some((progress.min(1.0), interval))
+    }
+
+    /// The splash image, which is only shown until the first slide is ready.
+    pub fn splash(&self) -> Option<&Arc<ColorImage>> {
+        self.splash.as_ref()
+    }
+
+    pub fn pairing_pin(&self) -> Option<&str> {
+        self.pairing_pin.as_deref()
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    pub fn banner(&self) -> Option<&str> {
+        self.banner.as_deref()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    pub fn handle_event(&mut self, event: SlideshowEvent) {
+        match event {
+            SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide))) if self.interacting() => {
+                self.live_image = Some(slide);
+            }
+            SlideshowEvent::StateChanged(new_state) => {
+                // Otherwise new slides, and anything else (e.g., an error), are shown straight away.
+                self.live_image = None;
+                self.set_state(new_state);
+            }
+            SlideshowEvent::Clicked => {
+                // Clicking swaps back to the previous image (and clicking again swaps forward).
+                if let Some(slide) = self.previous_image.take() {
+                    self.interacting_until = Some(Instant::now() + self.interaction_grace_period);
+                    if let Ok(AppState::HasImage(current)) = &self.current_state {
+                        if self.live_image.is_none() {
+                            self.live_image = Some(current.clone());
+                        }
+                    }
+                    if self
+                        .live_image
+                        .as_ref()
+                        .is_some_and(|live| Arc::ptr_eq(&live.image, &slide.image))
+                    {
+                        self.live_image = None;
+                    }
+                    self.set_state(Ok(AppState::HasImage(slide)));
+                }
+            }
+            SlideshowEvent::Tick => {
+                if !self.interacting() {
+                    if let Some(slide) = self.live_image.take() {
+                        self.set_state(Ok(AppState::HasImage(slide)));
+                    }
+                }
+            }
+            SlideshowEvent::PairingPinChanged(pin) => self.pairing_pin = pin,
+            SlideshowEvent::WarningsChanged(warnings) => self.warnings = warnings,
+            SlideshowEvent::BannerChanged(banner) => self.banner = banner,
+            SlideshowEvent::PausedChanged(true) => {
+                self.paused_at.get_or_insert_with(Instant::now);
+            }
+            SlideshowEvent::PausedChanged(false) => {
+                if let Some(paused_at) = self.paused_at.take() {
+                    self.interval_started += paused_at.elapsed();
+                }
+            }
+        }
+    }
+
+    fn interacting(&self) -> bool {
+        self.interacting_until
+            .map_or(false, |until| Instant::now() < until)
+    }
+
+    fn set_state(&mut self, new_state: Result<AppState>) {
+        let has_image = matches!(new_state, Ok(AppState::HasImage(_)));
+        if has_image {
+            self.splash = None;
+        }
+        let old_state = std::mem::replace(&mut self.current_state, new_state);
+        // Only another image can be swapped back from, so anything else forgets the previous image.
+        self.previous_image = match old_state {
+            Ok(AppState::HasImage(slide)) if has_image => Some(slide),
+            _ => None,
+        };
+        self.shown_at = Instant::now();
+        self.interval_started = self.shown_at;
+        self.fading_from = self
+            .previous_image
+            .clone()
+            .map(|previous| (previous, self.shown_at));
+    }
+}
+
+#[cfg(test)]
+fn image_color(controller: &SlideshowController) -> Option<egui::Color32> {
+    match controller.state() {
+        Ok(AppState::HasImage(slide)) => Some(slide.image.pixels[0]),
+        _ => None,
+    }
+}
+
+#[test]
+fn click_swaps_to_previous_image() {
+    use egui::Color32;
+    let image = |color| Slide::from(Arc::new(ColorImage::new([1, 1], color)));
+    let mut controller = SlideshowController::default();
+    assert!(matches!(controller.state(), Ok(AppState::LoadingImage)));
+
+    // Nothing to go back to yet.
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert!(matches!(controller.state(), Ok(AppState::LoadingImage)));
+
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(image(
+        Color32::RED,
+    )))));
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(image(
+        Color32::BLUE,
+    )))));
+    assert_eq!(image_color(&controller), Some(Color32::BLUE));
+
+    // Clicking toggles between the current and previous images.
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert_eq!(image_color(&controller), Some(Color32::RED));
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert_eq!(image_color(&controller), Some(Color32::BLUE));
+
+    // Anything other than an image forgets the previous image.
+    controller.handle_event(SlideshowEvent::StateChanged(Err(anyhow::anyhow!("failed"))));
+    assert!(controller.state().is_err());
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert!(controller.state().is_err());
+}
+
+#[test]
+fn hold_slides_while_interacting() {
+    use egui::Color32;
+    let image = |color| Slide::from(Arc::new(ColorImage::new([1, 1], color)));
+    let show = |controller: &mut SlideshowController, color| {
+        controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(image(
+            color,
+        )))))
+    };
+    let mut controller =
+        SlideshowController::default().with_interaction_grace_period(Duration::from_millis(200));
+    show(&mut controller, Color32::RED);
+    show(&mut controller, Color32::BLUE);
+    assert_eq!(controller.returns_to_live_in(), None);
+
+    // A slide that arrives while the user is looking at the previous image is held back.
+    controller.handle_event(SlideshowEvent::Clicked);
+    show(&mut controller, Color32::GREEN);
+    assert_eq!(image_color(&controller), Some(Color32::RED));
+    assert!(controller.returns_to_live_in().is_some());
+    controller.handle_event(SlideshowEvent::Tick);
+    assert_eq!(image_color(&controller), Some(Color32::RED));
+
+    // Once the user has stopped interacting, the held slide is shown.
+    std::thread::sleep(Duration::from_millis(250));
+    assert_eq!(controller.returns_to_live_in(), Some(Duration::ZERO));
+    controller.handle_event(SlideshowEvent::Tick);
+    assert_eq!(image_color(&controller), Some(Color32::GREEN));
+    assert_eq!(controller.returns_to_live_in(), None);
+
+    // Going back to an earlier slide returns to the latest slide once the user stops.
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert_eq!(image_color(&controller), Some(Color32::RED));
+    std::thread::sleep(Duration::from_millis(250));
+    controller.handle_event(SlideshowEvent::Tick);
+    assert_eq!(image_color(&controller), Some(Color32::GREEN));
+
+    // Unless they've already swapped forward again.
+    controller.handle_event(SlideshowEvent::Clicked);
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert_eq!(image_color(&controller), Some(Color32::GREEN));
+    assert_eq!(controller.returns_to_live_in(), None);
+
+    // Errors aren't held back.
+    controller.handle_event(SlideshowEvent::Clicked);
+    controller.handle_event(SlideshowEvent::StateChanged(Err(anyhow::anyhow!("failed"))));
+    assert!(controller.state().is_err());
+
+    // A click with no previous image to go back to doesn't hold back the next slide.
+    std::thread::sleep(Duration::from_millis(250));
+    controller.handle_event(SlideshowEvent::Clicked);
+    show(&mut controller, Color32::BLUE);
+    assert_eq!(image_color(&controller), Some(Color32::BLUE));
+}
+
+#[test]
+fn caption_stays_with_slide() {
+    let slide = |caption: &str| Slide {
+        image: Arc::new(ColorImage::new([1, 1], egui::Color32::RED)),
+        caption: Some(caption.into()),
+        theme: FolderTheme::default(),
+        fade: Duration::ZERO,
+        ken_burns: None,
+        photo_strip: Vec::new(),
+        title_card: None,
+        progress_bar: None,
+    };
+    let caption = |controller: &SlideshowController| match controller.state() {
+        Ok(AppState::HasImage(slide)) => slide.caption.clone(),
+        _ => None,
+    };
+    let mut controller = SlideshowController::default();
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide(
+        "Folder: Beach",
+    )))));
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide(
+        "Folder: Hike",
+    )))));
+    assert_eq!(caption(&controller).as_deref(), Some("Folder: Hike"));
+
+    // Going back to the previous slide shows its caption too.
+    controller.handle_event(SlideshowEvent::Clicked);
+    assert_eq!(caption(&controller).as_deref(), Some("Folder: Beach"));
+}
+
+#[test]
+fn crossfade_between_slides() {
+    use egui::Color32;
+    let slide = |color, fade| Slide {
+        fade,
+        ..Slide::from(Arc::new(ColorImage::new([1, 1], color)))
+    };
+    let fading_from = |controller: &SlideshowController| {
+        controller
+            .fading_from()
+            .map(|(previous, _)| previous.image.pixels[0])
+    };
+    let mut controller = SlideshowController::default();
+    let fade = Duration::from_secs(60 * 60);
+
+    // Nothing to fade from yet.
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide(
+        Color32::RED,
+        fade,
+    )))));
+    assert_eq!(fading_from(&controller), None);
+
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide(
+        Color32::GREEN,
+        fade,
+    )))));
+    assert_eq!(fading_from(&controller), Some(Color32::RED));
+    let (_, progress) = controller.fading_from().unwrap();
+    assert!((0.0..0.01).contains(&progress));
+
+    // Slides without a fade switch straight away.
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide(
+        Color32::BLUE,
+        Duration::ZERO,
+    )))));
+    assert_eq!(fading_from(&controller), None);
+
+    // Anything other than a slide doesn't fade.
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::LoadingImage)));
+    assert_eq!(fading_from(&controller), None);
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide(
+        Color32::RED,
+        fade,
+    )))));
+    assert_eq!(fading_from(&controller), None);
+}
+
+#[test]
+fn ken_burns_pans_and_zooms() {
+    let ken_burns = KenBurns {
+        from: Rect::from_min_size(egui::pos2(0.0, 0.0), egui::vec2(0.8, 0.8)),
+        to: Rect::from_min_size(egui::pos2(0.2, 0.1), egui::vec2(0.6, 0.6)),
+        duration: Duration::from_secs(10),
+    };
+    assert_eq!(ken_burns.part_at(Duration::ZERO), ken_burns.from);
+    let middle = ken_burns.part_at(Duration::from_secs(5));
+    assert!((middle.min - egui::pos2(0.1, 0.05)).length() < 1e-6);
+    assert!((middle.size() - egui::vec2(0.7, 0.7)).length() < 1e-6);
+    // The motion stops at the end, even if the slide stays up for longer.
+    assert_eq!(ken_burns.part_at(Duration::from_secs(10)), ken_burns.to);
+    assert_eq!(ken_burns.part_at(Duration::from_secs(60)), ken_burns.to);
+    let still = KenBurns {
+        duration: Duration::ZERO,
+        ..ken_burns
+    };
+    assert_eq!(still.part_at(Duration::ZERO), ken_burns.to);
+
+    // Random parts are always within the image.
+    for _ in 0..100 {
+        let ken_burns = KenBurns::random(Duration::from_secs(10));
+        for part in [ken_burns.from, ken_burns.to] {
+            assert!(part.min.x >= 0.0 && part.min.y >= 0.0);
+            assert!(part.max.x <= 1.0 + 1e-6 && part.max.y <= 1.0 + 1e-6);
+            assert!(part.width() >= 1.0 / KEN_BURNS_MAX_ZOOM - 1e-6);
+            assert!((part.width() - part.height()).abs() < 1e-6);
+        }
+    }
+
+    let mut controller = SlideshowController::default();
+    assert_eq!(controller.ken_burns_part(), None);
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(
+        Slide {
+            ken_burns: Some(ken_burns),
+            ..Slide::from(Arc::new(ColorImage::new([1, 1], egui::Color32::RED)))
+        },
+    ))));
+    assert!(controller.ken_burns_part().is_some());
+}
+
+#[test]
+fn photo_strip_moves_along() {
+    let thumbnail = |shade| Arc::new(ColorImage::new([1, 1], egui::Color32::from_gray(shade)));
+    let shades = |thumbnails: Vec<&Arc<ColorImage>>| {
+        thumbnails
+            .iter()
+            .map(|thumbnail| thumbnail.pixels[0].r())
+            .collect::<Vec<_>>()
+    };
+    let mut slide = Slide::from(thumbnail(0));
+    assert!(slide.photo_strip_at(Duration::ZERO).is_empty());
+
+    // Short strips stay put.
+    slide.photo_strip = (1..=3).map(thumbnail).collect();
+    assert_eq!(
+        shades(slide.photo_strip_at(Duration::from_secs(60))),
+        [1, 2, 3]
+    );
+
+    // Longer strips move along by one thumbnail at a time, wrapping around.
+    slide.photo_strip = (1..=6).map(thumbnail).collect();
+    assert_eq!(shades(slide.photo_strip_at(Duration::ZERO)), [1, 2, 3, 4]);
+    assert_eq!(
+        shades(slide.photo_strip_at(PHOTO_STRIP_STEP + Duration::from_secs(1))),
+        [2, 3, 4, 5]
+    );
+    assert_eq!(
+        shades(slide.photo_strip_at(PHOTO_STRIP_STEP * 4)),
+        [5, 6, 1, 2]
+    );
+
+    let mut controller = SlideshowController::default();
+    assert!(controller.photo_strip().0.is_empty());
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide))));
+    let (thumbnails, moves_in) = controller.photo_strip();
+    assert_eq!(shades(thumbnails), [1, 2, 3, 4]);
+    assert!(moves_in <= PHOTO_STRIP_STEP);
+}
+
+#[test]
+fn title_card_is_shown_briefly() {
+    let mut controller = SlideshowController::default();
+    assert_eq!(controller.title_card(), None);
+
+    let mut slide = Slide::from(Arc::new(ColorImage::new([1, 1], egui::Color32::RED)));
+    slide.title_card = Some("Summer 2022 · Italy".into());
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(slide))));
+    let (title, remaining) = controller.title_card().unwrap();
+    assert_eq!(title, "Summer 2022 · Italy");
+    assert!(remaining <= TITLE_CARD_TIME);
+
+    // Once it has been up for long enough, only the slide is shown.
+    controller.shown_at -= TITLE_CARD_TIME;
+    assert_eq!(controller.title_card(), None);
+}
+
+#[test]
+fn progress_through_interval() {
+    let mut controller = SlideshowController::default();
+    assert_eq!(controller.progress(), None);
+
+    // Only slides with a progress bar have any progress to show.
+    let slide = Slide::from(Arc::new(ColorImage::new([1, 1], egui::Color32::RED)));
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(
+        slide.clone(),
+    ))));
+    assert_eq!(controller.progress(), None);
+
+    let interval = Duration::from_secs(60);
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(
+        Slide {
+            progress_bar: Some(interval),
+            ..slide
+        },
+    ))));
+    controller.interval_started -= interval / 2;
+    let (progress, _) = controller.progress().unwrap();
+    assert!((0.5..0.6).contains(&progress));
+
+    // Time spent paused doesn't count.
+    controller.handle_event(SlideshowEvent::PausedChanged(true));
+    assert_eq!(controller.progress(), None);
+    controller.interval_started -= interval;
+    controller.paused_at = controller.paused_at.map(|paused_at| paused_at - interval);
+    controller.handle_event(SlideshowEvent::PausedChanged(false));
+    let (progress, _) = controller.progress().unwrap();
+    assert!((0.5..0.6).contains(&progress));
+
+    // It doesn't run past the end if the next slide is late.
+    controller.interval_started -= interval;
+    assert_eq!(controller.progress(), Some((1.0, interval)));
+}
+
+#[test]
+fn pairing_pin_overlays_state() {
+    let mut controller = SlideshowController::default();
+    assert_eq!(controller.pairing_pin(), None);
+
+    controller.handle_event(SlideshowEvent::PairingPinChanged(Some("123456".into())));
+    assert_eq!(controller.pairing_pin(), Some("123456"));
+    assert!(matches!(controller.state(), Ok(AppState::LoadingImage)));
+
+    controller.handle_event(SlideshowEvent::PairingPinChanged(None));
+    assert_eq!(controller.pairing_pin(), None);
+}
+
+#[test]
+fn warnings_overlay_state() {
+    let mut controller = SlideshowController::default();
+    assert!(controller.warnings().is_empty());
+
+    controller.handle_event(SlideshowEvent::WarningsChanged(vec!["typo".into()]));
+    assert_eq!(controller.warnings(), ["typo"]);
+    controller.handle_event(SlideshowEvent::StateChanged(Err(anyhow::anyhow!("failed"))));
+    assert_eq!(controller.warnings(), ["typo"]);
+
+    controller.handle_event(SlideshowEvent::WarningsChanged(Vec::new()));
+    assert!(controller.warnings().is_empty());
+}
+
+#[test]
+fn banner_overlays_state() {
+    let mut controller = SlideshowController::default();
+    assert_eq!(controller.banner(), None);
+
+    controller.handle_event(SlideshowEvent::BannerChanged(Some(
+        "Happy birthday!".into(),
+    )));
+    assert_eq!(controller.banner(), Some("Happy birthday!"));
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::LoadingImage)));
+    assert_eq!(controller.banner(), Some("Happy birthday!"));
+
+    controller.handle_event(SlideshowEvent::BannerChanged(None));
+    assert_eq!(controller.banner(), None);
+}
+
+#[test]
+fn pause_indicator_overlays_state() {
+    let mut controller = SlideshowController::default();
+    assert!(!controller.is_paused());
+
+    controller.handle_event(SlideshowEvent::PausedChanged(true));
+    assert!(controller.is_paused());
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::LoadingImage)));
+    assert!(controller.is_paused());
+
+    controller.handle_event(SlideshowEvent::PausedChanged(false));
+    assert!(!controller.is_paused());
+}
+
+#[test]
+fn splash_until_first_image() {
+    let mut controller = SlideshowController::default()
+        .with_splash(Arc::new(ColorImage::new([1, 1], egui::Color32::GREEN)));
+    assert!(controller.splash().is_some());
+
+    // Authenticating doesn't replace the splash.
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::WaitingForAuth(
+        "url".into(),
+        "code".into(),
+    ))));
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::LoadingImage)));
+    assert!(controller.splash().is_some());
+
+    controller.handle_event(SlideshowEvent::StateChanged(Ok(AppState::HasImage(
+        Arc::new(ColorImage::new([1, 1], egui::Color32::RED)).into(),
+    ))));
+    assert!(controller.splash().is_none());
+}
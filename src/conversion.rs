@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+use tokio::{
+    sync::Semaphore,
+    task::{self, JoinHandle},
+};
+
+/// Most conversions that can be waiting to start before callers have to wait for room.
+const QUEUE_PER_WORKER: usize = 4;
+
+/// Runs CPU-heavy image conversions (decoding, scaling, etc.) on a limited number of blocking
+/// threads, so that they don't stall the tasks that download images and drive the slideshow.
+#[derive(Clone)]
+pub struct ConversionPool {
+    workers: Arc<Semaphore>,
+    queue: Arc<Semaphore>,
+}
+
+impl ConversionPool {
+    pub fn new(workers: usize) -> Self {
+        let workers = workers.max(1);
+        Self {
+            workers: Arc::new(Semaphore::new(workers)),
+            queue: Arc::new(Semaphore::new(workers * QUEUE_PER_WORKER)),
+        }
+    }
+
+    /// Uses one worker for each CPU.
+    pub fn for_available_cpus() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, usize::from))
+    }
+
+    /// Queues a conversion, waiting if the queue is full. The conversion runs in the background,
+    /// even if the returned `Conversion` isn't awaited straight away.
+    pub async fn convert<T: Send + 'static>(
+        &self,
+        convert: impl FnOnce() -> Result<T> + Send + 'static,
+    ) -> Conversion<T> {
+        let queued = self
+            .queue
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("Conversion queue is never closed");
+        let workers = self.workers.clone();
+        Conversion(tokio::spawn(async move {
+            let _queued = queued;
+            let _worker = workers
+                .acquire_owned()
+                .await
+                .expect("Conversion workers are never closed");
+            task::spawn_blocking(convert)
+                .await
+                .context("Converting image failed")?
+        }))
+    }
+}
+
+/// A conversion that has been queued on a `ConversionPool`.
+pub struct Conversion<T>(JoinHandle<Result<T>>);
+
+impl<T> Conversion<T> {
+    pub async fn wait(self) -> Result<T> {
+        self.0.await.context("Converting image failed")?
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn convert_in_background() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let pool = ConversionPool::new(2);
+    let running = Arc::new(AtomicUsize::new(0));
+    let most_running = Arc::new(AtomicUsize::new(0));
+    let mut conversions = Vec::new();
+    for index in 0..6 {
+        let running = running.clone();
+        let most_running = most_running.clone();
+        conversions.push(
+            pool.convert(move || {
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now_running, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(index)
+            })
+            .await,
+        );
+    }
+
+    let mut results = Vec::new();
+    for conversion in conversions {
+        results.push(conversion.wait().await.unwrap());
+    }
+    assert_eq!(results, [0, 1, 2, 3, 4, 5]);
+    // No more than the number of workers run at once.
+    assert!(most_running.load(Ordering::SeqCst) <= 2);
+
+    let failed = pool.convert(|| -> Result<()> { anyhow::bail!("Bad image") });
+    assert_eq!(
+        failed.await.wait().await.unwrap_err().to_string(),
+        "Bad image"
+    );
+}
@@ -1,19 +1,109 @@
+use std::path::PathBuf;
+
+/// Where the refresh token is kept between runs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenStore {
+    /// The system's credential store, under the given profile.
+    System { profile: Option<String> },
+    /// A file, for portable installs that shouldn't leave anything behind on the device. The token
+    /// isn't encrypted, since the key would have to be stored next to it, so instead (on Unix) only
+    /// the current user can read the file.
+    File(PathBuf),
+}
+
+impl Default for TokenStore {
+    fn default() -> Self {
+        Self::System { profile: None }
+    }
+}
+
+impl TokenStore {
+    pub fn get_refresh_token(&self) -> Option<String> {
+        match self {
+            Self::System { profile } => get_refresh_token(profile.as_deref()),
+            Self::File(path) => std::fs::read_to_string(path)
+                .ok()
+                .filter(|token| !token.is_empty()),
+        }
+    }
+
+    pub fn store_refresh_token(&self, cred: &str) {
+        match self {
+            Self::System { profile } => store_refresh_token(profile.as_deref(), cred),
+            Self::File(path) => {
+                // Failing to store the token only means signing in again next time.
+                let _ = write_private_file(path, cred);
+            }
+        }
+    }
+
+    /// Forgets the refresh token, so that the next run has to sign in again (e.g., to sign out or
+    /// switch to another account).
+    pub fn delete_refresh_token(&self) {
+        match self {
+            Self::System { profile } => delete_refresh_token(profile.as_deref()),
+            Self::File(path) => {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    /// Lists the profiles that have a refresh token in the system's credential store, so that one
+    /// can be chosen to switch to or sign out of. The default profile is listed as `None`.
+    pub fn system_profiles() -> Vec<Option<String>> {
+        let mut profiles = stored_profiles();
+        profiles.sort();
+        profiles
+    }
+}
+
+/// Writes a file that only the current user can read.
+fn write_private_file(path: &std::path::Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, so a file that was created some other way (e.g., copied
+    // onto a USB stick) is made private before anything is written to it.
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(contents.as_bytes())
+}
+
 #[cfg(windows)]
 mod windows {
     use windows_sys::core::PCWSTR;
     use windows_sys::w;
     use windows_sys::Win32::Foundation::{FILETIME, TRUE};
     use windows_sys::Win32::Security::Credentials::{
-        CredFree, CredReadW, CredWriteW, CREDENTIALW, CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
+        CredDeleteW, CredEnumerateW, CredFree, CredReadW, CredWriteW, CREDENTIALW,
+        CRED_PERSIST_LOCAL_MACHINE, CRED_TYPE_GENERIC,
     };
 
-    const TARGET_NAME: PCWSTR = w!("OneDriveSlideShow");
+    const TARGET_NAME: &str = "OneDriveSlideShow";
+
+    /// Each profile has its own credential, so that slideshows signed in to different accounts
+    /// don't overwrite each other's tokens.
+    fn target_name(profile: Option<&str>) -> Vec<u16> {
+        let name = match profile {
+            Some(profile) => format!("{TARGET_NAME}:{profile}"),
+            None => TARGET_NAME.to_string(),
+        };
+        name.encode_utf16().chain(std::iter::once(0)).collect()
+    }
 
-    pub fn get_refresh_token() -> Option<String> {
+    pub fn get_refresh_token(profile: Option<&str>) -> Option<String> {
+        let target_name = target_name(profile);
         let mut p_credential: *mut CREDENTIALW = std::ptr::null_mut() as *mut _;
         let bytes = unsafe {
             if CredReadW(
-                TARGET_NAME,
+                target_name.as_ptr() as PCWSTR,
                 CRED_TYPE_GENERIC,
                 0,
                 &mut p_credential as *mut _,
@@ -31,11 +121,12 @@ mod windows {
         token.ok()
     }
 
-    pub fn store_refresh_token(cred: &str) {
+    pub fn store_refresh_token(profile: Option<&str>, cred: &str) {
+        let mut target_name = target_name(profile);
         let credential = CREDENTIALW {
             Flags: 0,
             Type: CRED_TYPE_GENERIC,
-            TargetName: TARGET_NAME as *mut _,
+            TargetName: target_name.as_mut_ptr(),
             Comment: w!("OneDrive Slideshow Refresh Token") as *mut _,
             LastWritten: FILETIME {
                 dwLowDateTime: 0,
@@ -53,17 +144,103 @@ mod windows {
             CredWriteW(&credential, 0);
         }
     }
+
+    pub fn delete_refresh_token(profile: Option<&str>) {
+        let target_name = target_name(profile);
+        unsafe {
+            CredDeleteW(target_name.as_ptr() as PCWSTR, CRED_TYPE_GENERIC, 0);
+        }
+    }
+
+    /// The profile that a credential belongs to, or `None` if it isn't one of the slideshow's.
+    fn profile(target_name: &str) -> Option<Option<String>> {
+        match target_name.strip_prefix(TARGET_NAME)? {
+            "" => Some(None),
+            rest => Some(Some(rest.strip_prefix(':')?.to_string())),
+        }
+    }
+
+    pub fn stored_profiles() -> Vec<Option<String>> {
+        let filter = format!("{TARGET_NAME}*")
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .collect::<Vec<_>>();
+        let mut count = 0;
+        let mut p_credentials: *mut *mut CREDENTIALW = std::ptr::null_mut();
+        unsafe {
+            if CredEnumerateW(
+                filter.as_ptr() as PCWSTR,
+                0,
+                &mut count,
+                &mut p_credentials as *mut _,
+            ) != TRUE
+            {
+                return Vec::new();
+            }
+            let profiles = std::slice::from_raw_parts(p_credentials, count as usize)
+                .iter()
+                .filter_map(|p_credential| {
+                    let name = (**p_credential).TargetName;
+                    let len = (0..).take_while(|&i| *name.add(i) != 0).count();
+                    profile(&String::from_utf16_lossy(std::slice::from_raw_parts(
+                        name, len,
+                    )))
+                })
+                .collect();
+            CredFree(p_credentials as *mut _);
+            profiles
+        }
+    }
 }
 #[cfg(windows)]
-pub use windows::*;
+use windows::*;
 
 #[cfg(not(windows))]
 mod other {
-    pub fn get_refresh_token() -> Option<String> {
+    pub fn get_refresh_token(_profile: Option<&str>) -> Option<String> {
         None
     }
 
-    pub fn store_refresh_token(_cred: &str) {}
+    pub fn store_refresh_token(_profile: Option<&str>, _cred: &str) {}
+
+    pub fn delete_refresh_token(_profile: Option<&str>) {}
+
+    pub fn stored_profiles() -> Vec<Option<String>> {
+        Vec::new()
+    }
 }
 #[cfg(not(windows))]
-pub use other::*;
+use other::*;
+
+#[test]
+fn store_refresh_token_in_file() {
+    let directory =
+        std::env::temp_dir().join("onedrive_slideshow_test/store_refresh_token_in_file");
+    if directory.exists() {
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    let store = TokenStore::File(directory.join("refresh_token"));
+    assert_eq!(store.get_refresh_token(), None);
+    store.store_refresh_token("rt");
+    assert_eq!(store.get_refresh_token().as_deref(), Some("rt"));
+    store.store_refresh_token("rt2");
+    assert_eq!(store.get_refresh_token().as_deref(), Some("rt2"));
+
+    // Only the current user can read the token, even if the file was readable by others.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = directory.join("refresh_token");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        store.store_refresh_token("rt3");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // Signing out forgets the token.
+    store.delete_refresh_token();
+    assert_eq!(store.get_refresh_token(), None);
+    store.delete_refresh_token();
+}
@@ -0,0 +1,270 @@
+use crate::{
+    controller::{AppState, SlideshowController, SlideshowEvent},
+    rotation::Rotation,
+};
+use anyhow::{bail, Context, Result};
+use egui::ColorImage;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+use tokio::sync::{mpsc::Receiver, watch};
+
+/// Layout of a framebuffer's memory.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FramebufferInfo {
+    pub width: usize,
+    pub height: usize,
+    /// Number of bytes between the start of each row.
+    pub stride: usize,
+    pub bits_per_pixel: usize,
+}
+
+/// A Linux framebuffer device (e.g., `/dev/fb0`) that images are drawn to directly, so that the
+/// slideshow can run without a desktop session.
+pub struct Framebuffer {
+    device: File,
+    info: FramebufferInfo,
+    /// How far to turn images before drawing them, for a screen that is mounted on its side.
+    rotation: Option<Rotation>,
+}
+
+impl Framebuffer {
+    /// Opens a framebuffer device, reading its layout from sysfs.
+    pub fn open(device: &Path) -> Result<Self> {
+        let name = device
+            .file_name()
+            .with_context(|| format!("Invalid framebuffer device: {}", device.display()))?;
+        let sysfs = Path::new("/sys/class/graphics").join(name);
+        let read_attribute = |attribute: &str| -> Result<String> {
+            let path = sysfs.join(attribute);
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("Reading {}", path.display()))
+                .map(|value| value.trim().to_string())
+        };
+        let parse = |attribute: &str, value: &str| -> Result<usize> {
+            value
+                .parse()
+                .with_context(|| format!("Invalid {attribute} for framebuffer: {value}"))
+        };
+
+        let virtual_size = read_attribute("virtual_size")?;
+        let Some((width, height)) = virtual_size.split_once(',') else {
+            bail!("Invalid virtual_size for framebuffer: {virtual_size}");
+        };
+        let info = FramebufferInfo {
+            width: parse("virtual_size", width)?,
+            height: parse("virtual_size", height)?,
+            stride: parse("stride", &read_attribute("stride")?)?,
+            bits_per_pixel: parse("bits_per_pixel", &read_attribute("bits_per_pixel")?)?,
+        };
+        let device = OpenOptions::new()
+            .write(true)
+            .open(device)
+            .with_context(|| format!("Opening {}", device.display()))?;
+        Self::new(device, info)
+    }
+
+    pub fn new(device: File, info: FramebufferInfo) -> Result<Self> {
+        if !matches!(info.bits_per_pixel, 16 | 24 | 32) {
+            bail!(
+                "Unsupported framebuffer format: {} bits per pixel",
+                info.bits_per_pixel
+            );
+        }
+        Ok(Self {
+            device,
+            info,
+            rotation: None,
+        })
+    }
+
+    pub fn with_rotation(self, rotation: Option<Rotation>) -> Self {
+        Self { rotation, ..self }
+    }
+
+    pub fn info(&self) -> FramebufferInfo {
+        self.info
+    }
+
+    /// Draws an image centered on a black background, shrinking it to fit if it is larger than the
+    /// screen.
+    pub fn draw(&mut self, image: &ColorImage) -> Result<()> {
+        let rotated;
+        let image = match self.rotation {
+            Some(rotation) => {
+                rotated = rotation.rotate_image(image);
+                &rotated
+            }
+            None => image,
+        };
+        let FramebufferInfo {
+            width,
+            height,
+            stride,
+            bits_per_pixel,
+        } = self.info;
+        let [image_width, image_height] = image.size;
+        let scale = (width as f64 / image_width as f64)
+            .min(height as f64 / image_height as f64)
+            .min(1.0);
+        let scaled_width = ((image_width as f64 * scale) as usize).min(width);
+        let scaled_height = ((image_height as f64 * scale) as usize).min(height);
+        let left = (width - scaled_width) / 2;
+        let top = (height - scaled_height) / 2;
+
+        let bytes_per_pixel = bits_per_pixel / 8;
+        let mut buffer = vec![0; stride * height];
+        for y in 0..scaled_height {
+            let source_y = ((y as f64 / scale) as usize).min(image_height - 1);
+            let row = &mut buffer[(top + y) * stride..];
+            for x in 0..scaled_width {
+                let source_x = ((x as f64 / scale) as usize).min(image_width - 1);
+                let pixel = image.pixels[source_y * image_width + source_x];
+                let offset = (left + x) * bytes_per_pixel;
+                let target = &mut row[offset..offset + bytes_per_pixel];
+                match bits_per_pixel {
+                    16 => {
+                        let rgb565 = (u16::from(pixel.r() >> 3) << 11)
+                            | (u16::from(pixel.g() >> 2) << 5)
+                            | u16::from(pixel.b() >> 3);
+                        target.copy_from_slice(&rgb565.to_le_bytes());
+                    }
+                    _ => target[..3].copy_from_slice(&[pixel.b(), pixel.g(), pixel.r()]),
+                }
+            }
+        }
+
+        self.device
+            .seek(SeekFrom::Start(0))
+            .context("Seeking framebuffer")?;
+        self.device
+            .write_all(&buffer)
+            .context("Writing to framebuffer")
+    }
+}
+
+/// Shows slides on the framebuffer as they arrive. There's no text rendering, so anything other
+/// than an image (e.g., sign-in instructions or errors) is written to the console instead.
+pub async fn run(
+    mut framebuffer: Framebuffer,
+    mut controller: SlideshowController,
+    mut incoming_state: Receiver<Result<AppState>>,
+    mut pairing_pin: watch::Receiver<Option<String>>,
+    mut warnings: watch::Receiver<Vec<String>>,
+    mut banner: watch::Receiver<Option<String>>,
+) -> Result<()> {
+    if let Some(splash) = controller.splash() {
+        framebuffer.draw(splash)?;
+    }
+    loop {
+        tokio::select! {
+            new_state = incoming_state.recv() => {
+                let Some(new_state) = new_state else {
+                    return Ok(());
+                };
+                controller.handle_event(SlideshowEvent::StateChanged(new_state));
+                match controller.state() {
+                    Ok(AppState::HasImage(slide)) => framebuffer.draw(&slide.image)?,
+                    Ok(AppState::LoadingImage) => {}
+                    Ok(AppState::WaitingForNetwork) => {
+                        eprintln!("Waiting for the network to connect...")
+                    }
+                    Ok(AppState::CaptivePortal(sign_in_url)) => eprintln!(
+                        "This network requires signing in before the slideshow can connect to OneDrive{}",
+                        sign_in_url.as_ref().map_or(String::new(), |url| format!(": {url}"))
+                    ),
+                    Ok(AppState::WaitingForAuth(auth_url, code)) => eprintln!(
+                        "Authorize the slideshow to read from your OneDrive by opening {auth_url} in a browser and entering the code {code}"
+                    ),
+                    Err(err) => eprintln!("{err:?}"),
+                }
+            }
+            Ok(()) = warnings.changed() => {
+                for warning in warnings.borrow_and_update().iter() {
+                    eprintln!("{warning}");
+                }
+            }
+            Ok(()) = banner.changed() => {
+                if let Some(banner) = banner.borrow_and_update().as_deref() {
+                    eprintln!("{banner}");
+                }
+            }
+            Ok(()) = pairing_pin.changed() => {
+                if let Some(pin) = pairing_pin.borrow_and_update().as_deref() {
+                    eprintln!("Remote control pairing PIN: {pin}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn draw_to_framebuffer() {
+    use egui::Color32;
+
+    let path = std::env::temp_dir().join("onedrive_slideshow_test_framebuffer");
+    let read_back = |info: FramebufferInfo, image: &ColorImage| {
+        let device = File::create(&path).unwrap();
+        Framebuffer::new(device, info).unwrap().draw(image).unwrap();
+        std::fs::read(&path).unwrap()
+    };
+
+    // A 2x1 image centered on a 4x3 screen, with padding at the end of each row.
+    let image = ColorImage {
+        size: [2, 1],
+        pixels: vec![Color32::from_rgb(1, 2, 3), Color32::from_rgb(4, 5, 6)],
+    };
+    let info = FramebufferInfo {
+        width: 4,
+        height: 3,
+        stride: 20,
+        bits_per_pixel: 32,
+    };
+    let mut expected = vec![0; 60];
+    expected[24..32].copy_from_slice(&[3, 2, 1, 0, 6, 5, 4, 0]);
+    assert_eq!(read_back(info, &image), expected);
+
+    // Larger images are shrunk to fit.
+    let image = ColorImage {
+        size: [2, 2],
+        pixels: vec![Color32::WHITE; 4],
+    };
+    let info = FramebufferInfo {
+        width: 1,
+        height: 1,
+        stride: 2,
+        bits_per_pixel: 16,
+    };
+    assert_eq!(read_back(info, &image), [0xff, 0xff]);
+
+    // Images are turned to suit how the screen is mounted.
+    let image = ColorImage {
+        size: [1, 2],
+        pixels: vec![Color32::from_rgb(1, 2, 3), Color32::from_rgb(4, 5, 6)],
+    };
+    let info = FramebufferInfo {
+        width: 2,
+        height: 1,
+        stride: 6,
+        bits_per_pixel: 24,
+    };
+    let device = File::create(&path).unwrap();
+    Framebuffer::new(device, info)
+        .unwrap()
+        .with_rotation(Some(Rotation::Degrees90))
+        .draw(&image)
+        .unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), [6, 5, 4, 3, 2, 1]);
+
+    let device = File::create(&path).unwrap();
+    assert!(Framebuffer::new(
+        device,
+        FramebufferInfo {
+            bits_per_pixel: 8,
+            ..info
+        }
+    )
+    .is_err());
+}
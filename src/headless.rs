@@ -0,0 +1,118 @@
+use crate::{controller::AppState, history::History, remote::RemoteCommand};
+use anyhow::{Context, Result};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+/// Screen size that slides are loaded at, since there's no window.
+pub const HEADLESS_SCREEN_WIDTH: usize = 1920;
+pub const HEADLESS_SCREEN_HEIGHT: usize = 1080;
+
+/// "Shows" `slides` slides by logging them to the console instead of drawing them, moving on to
+/// the next slide as soon as each one is ready. This runs the whole slideshow (signing in, listing,
+/// choosing, downloading and decoding images) without needing a display.
+pub async fn run(
+    mut incoming_state: Receiver<Result<AppState>>,
+    commands: Sender<RemoteCommand>,
+    history: History,
+    slides: usize,
+) -> Result<()> {
+    let mut shown = 0;
+    while shown < slides {
+        let Some(new_state) = incoming_state.recv().await else {
+            anyhow::bail!("Slideshow stopped after {shown} slides");
+        };
+        match new_state {
+            Ok(AppState::HasImage(slide)) => {
+                shown += 1;
+                // The slide is recorded in the history before it is sent to be shown.
+                let item = history
+                    .recent(1)?
+                    .pop()
+                    .map_or_else(|| "<unknown item>".to_string(), |entry| entry.to_string());
+                println!(
+                    "Slide {shown}: {item} ({}x{})",
+                    slide.image.size[0], slide.image.size[1]
+                );
+                if shown < slides {
+                    commands
+                        .send(RemoteCommand::Next)
+                        .await
+                        .context("Slideshow stopped")?;
+                }
+            }
+            Ok(AppState::LoadingImage) => println!("Loading the next slide..."),
+            Ok(AppState::WaitingForNetwork) => println!("Waiting for the network to connect..."),
+            Ok(AppState::CaptivePortal(sign_in_url)) => println!(
+                "This network requires signing in before the slideshow can connect to OneDrive{}",
+                sign_in_url.as_ref().map_or(String::new(), |url| format!(": {url}"))
+            ),
+            Ok(AppState::WaitingForAuth(auth_url, code)) => println!(
+                "Authorize the slideshow to read from your OneDrive by opening {auth_url} in a browser and entering the code {code}"
+            ),
+            Err(err) => eprintln!("{err:?}"),
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn log_slides() {
+    use crate::history::{HistoryEntry, ShownItem};
+    use egui::ColorImage;
+    use std::sync::Arc;
+    use tokio::sync::mpsc::channel;
+
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/headless_log_slides");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    let history = History::new(temp_dir.join("history.jsonl"));
+    history
+        .record(&HistoryEntry {
+            time: chrono::Local::now(),
+            item: ShownItem {
+                item_id: "item".into(),
+                name: Some("IMG_1.jpg".into()),
+                source: "Pictures".into(),
+                folder: None,
+                taken: None,
+                location: None,
+            },
+        })
+        .await
+        .unwrap();
+
+    let (state_sender, state_receiver) = channel(8);
+    let (command_sender, mut commands) = channel(8);
+    let image = || {
+        Ok(AppState::HasImage(
+            Arc::new(ColorImage::new([1, 1], egui::Color32::RED)).into(),
+        ))
+    };
+    state_sender.send(Ok(AppState::LoadingImage)).await.unwrap();
+    state_sender
+        .send(Err(anyhow::anyhow!("failed")))
+        .await
+        .unwrap();
+    state_sender.send(image()).await.unwrap();
+    state_sender.send(image()).await.unwrap();
+
+    run(state_receiver, command_sender, history, 2)
+        .await
+        .unwrap();
+    // Only asks for the next slide if there are more to show.
+    assert_eq!(commands.try_recv(), Ok(RemoteCommand::Next));
+    assert!(commands.try_recv().is_err());
+
+    // Stopping early is an error.
+    let (state_sender, state_receiver) = channel(8);
+    let (command_sender, _commands) = channel(8);
+    drop(state_sender);
+    assert!(run(
+        state_receiver,
+        command_sender,
+        History::new(temp_dir.join("history.jsonl")),
+        1
+    )
+    .await
+    .is_err());
+}
@@ -0,0 +1,319 @@
+use crate::metadata::GeoCoordinates;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+use tokio::io::AsyncWriteExt;
+
+/// Size at which the history is moved aside and a new file started, so that it doesn't grow
+/// without bound. Only the most recent moved aside file is kept.
+const MAX_HISTORY_SIZE: u64 = 1024 * 1024;
+
+/// Header of the monthly summaries.
+const SUMMARY_HEADER: &str = "Time,Source,Name,Item ID\n";
+
+/// The item that a slide showed.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct ShownItem {
+    pub item_id: String,
+    pub name: Option<String>,
+    /// Where the slide came from: the configured directory, guest folder or remote control.
+    pub source: String,
+    /// Folder that the item is directly within, if it was found by listing a directory.
+    pub folder: Option<String>,
+    /// When the photo was taken, if known.
+    pub taken: Option<DateTime<Utc>>,
+    /// Where the photo was taken, if known.
+    pub location: Option<GeoCoordinates>,
+}
+
+/// A slide that was shown.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub item: ShownItem,
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}  {}  {} ({})",
+            self.time.format("%Y-%m-%d %H:%M:%S"),
+            self.item.source,
+            self.item.name.as_deref().unwrap_or("<unknown name>"),
+            self.item.item_id
+        )
+    }
+}
+
+/// An append-only log of the slides that have been shown, stored as one JSON object per line.
+pub struct History {
+    path: PathBuf,
+}
+
+impl History {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        self.path.with_extension("1.jsonl")
+    }
+
+    pub async fn record(&self, entry: &HistoryEntry) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        if tokio::fs::metadata(&self.path)
+            .await
+            .map_or(false, |metadata| metadata.len() >= MAX_HISTORY_SIZE)
+        {
+            tokio::fs::rename(&self.path, self.rotated_path())
+                .await
+                .with_context(|| "Rotating history")?;
+        }
+
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .with_context(|| format!("Opening {}", self.path.display()))?;
+        file.write_all(&line)
+            .await
+            .with_context(|| "Writing history")?;
+        // Tokio finishes writes in the background, so the entry may not be in the file until it has
+        // been flushed.
+        file.flush().await.with_context(|| "Writing history")
+    }
+
+    /// Reads up to `count` of the most recent entries, oldest first.
+    pub fn recent(&self, count: usize) -> Result<Vec<HistoryEntry>> {
+        let mut entries = Vec::new();
+        for path in [self.rotated_path(), self.path.clone()] {
+            entries.extend(read_entries(&path)?);
+        }
+        let skip = entries.len().saturating_sub(count);
+        Ok(entries.split_off(skip))
+    }
+
+    /// When each item in the history was last shown.
+    pub fn last_shown(&self) -> Result<HashMap<String, DateTime<Local>>> {
+        let mut last_shown = HashMap::new();
+        for path in [self.rotated_path(), self.path.clone()] {
+            // Entries are oldest first, so later showings replace earlier ones.
+            for entry in read_entries(&path)? {
+                last_shown.insert(entry.item.item_id, entry.time);
+            }
+        }
+        Ok(last_shown)
+    }
+}
+
+/// The slides that have been shown, rolled up into a CSV file for each month so that they can be
+/// exported. Unlike the history, these aren't rotated: a month's file is kept until it has been
+/// exported after the month is over.
+pub struct MonthlySummary {
+    directory: PathBuf,
+}
+
+impl MonthlySummary {
+    pub fn new(directory: PathBuf) -> Self {
+        Self { directory }
+    }
+
+    pub async fn record(&self, entry: &HistoryEntry) -> Result<()> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let path = self
+            .directory
+            .join(format!("{}.csv", entry.time.format("%Y-%m")));
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("Opening {}", path.display()))?;
+        let mut line = String::new();
+        if file.metadata().await?.len() == 0 {
+            line.push_str(SUMMARY_HEADER);
+        }
+        line.push_str(&format!(
+            "{},{},{},{}\n",
+            entry.time.format("%Y-%m-%d %H:%M:%S"),
+            csv_field(&entry.item.source),
+            csv_field(entry.item.name.as_deref().unwrap_or_default()),
+            csv_field(&entry.item.item_id)
+        ));
+        file.write_all(line.as_bytes())
+            .await
+            .with_context(|| "Writing monthly summary")?;
+        file.flush()
+            .await
+            .with_context(|| "Writing monthly summary")
+    }
+
+    /// Lists the months that have a summary (as `YYYY-MM`) along with their files, oldest first.
+    pub fn months(&self) -> Result<Vec<(String, PathBuf)>> {
+        let entries = match std::fs::read_dir(&self.directory) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Reading {}", self.directory.display()))
+            }
+        };
+        let mut months = entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "csv" {
+                    return None;
+                }
+                Some((path.file_stem()?.to_str()?.to_string(), path))
+            })
+            .collect::<Vec<_>>();
+        months.sort();
+        Ok(months)
+    }
+}
+
+/// Quotes a CSV field if it contains anything that would otherwise be misread.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn read_entries(path: &Path) -> Result<Vec<HistoryEntry>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Reading {}", path.display())),
+    };
+    // A line may be incomplete if the slideshow was stopped while writing it.
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn record_history() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/record_history");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+
+    let history = History::new(temp_dir.join("history.jsonl"));
+    assert!(history.recent(10).unwrap().is_empty());
+
+    let entry = |index: usize| HistoryEntry {
+        time: Local::now(),
+        item: ShownItem {
+            item_id: format!("item{index}"),
+            name: Some(format!("IMG_{index}.jpg")),
+            source: "Pictures".into(),
+            folder: Some("Pictures".into()),
+            taken: None,
+            location: None,
+        },
+    };
+    for index in 0..5 {
+        history.record(&entry(index)).await.unwrap();
+    }
+    let recent = history.recent(3).unwrap();
+    assert_eq!(
+        recent
+            .iter()
+            .map(|entry| entry.item.item_id.as_str())
+            .collect::<Vec<_>>(),
+        ["item2", "item3", "item4"]
+    );
+    assert!(recent[0]
+        .to_string()
+        .ends_with("  Pictures  IMG_2.jpg (item2)"));
+
+    // Once the history is too large it is moved aside, but is still read.
+    let file = std::fs::File::options()
+        .append(true)
+        .open(temp_dir.join("history.jsonl"))
+        .unwrap();
+    file.set_len(MAX_HISTORY_SIZE).unwrap();
+    history.record(&entry(5)).await.unwrap();
+    assert!(temp_dir.join("history.1.jsonl").exists());
+    assert!(
+        std::fs::metadata(temp_dir.join("history.jsonl"))
+            .unwrap()
+            .len()
+            < MAX_HISTORY_SIZE
+    );
+    let recent = history.recent(2).unwrap();
+    assert_eq!(recent[0].item.item_id, "item4");
+    assert_eq!(recent[1].item.item_id, "item5");
+
+    // The most recent showing of each item is remembered from both files.
+    history.record(&entry(2)).await.unwrap();
+    let last_shown = history.last_shown().unwrap();
+    assert_eq!(last_shown.len(), 6);
+    assert_eq!(last_shown["item2"], history.recent(1).unwrap()[0].time);
+    assert!(last_shown["item1"] < last_shown["item2"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn summarize_by_month() {
+    use chrono::TimeZone;
+
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/summarize_by_month");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+
+    let summary = MonthlySummary::new(temp_dir.clone());
+    assert!(summary.months().unwrap().is_empty());
+
+    let entry = |month: u32, name: &str| HistoryEntry {
+        time: Local.with_ymd_and_hms(2024, month, 5, 8, 30, 0).unwrap(),
+        item: ShownItem {
+            item_id: "item".into(),
+            name: Some(name.into()),
+            source: "Pictures".into(),
+            folder: None,
+            taken: None,
+            location: None,
+        },
+    };
+    summary.record(&entry(2, "Beach.jpg")).await.unwrap();
+    summary
+        .record(&entry(3, "Lunch, \"outside\".jpg"))
+        .await
+        .unwrap();
+    summary.record(&entry(2, "Hike.jpg")).await.unwrap();
+
+    let months = summary.months().unwrap();
+    assert_eq!(
+        months
+            .iter()
+            .map(|(month, _)| month.as_str())
+            .collect::<Vec<_>>(),
+        ["2024-02", "2024-03"]
+    );
+    assert_eq!(
+        std::fs::read_to_string(&months[0].1).unwrap(),
+        "Time,Source,Name,Item ID\n\
+         2024-02-05 08:30:00,Pictures,Beach.jpg,item\n\
+         2024-02-05 08:30:00,Pictures,Hike.jpg,item\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&months[1].1).unwrap(),
+        "Time,Source,Name,Item ID\n\
+         2024-03-05 08:30:00,Pictures,\"Lunch, \"\"outside\"\".jpg\",item\n"
+    );
+}
@@ -1,25 +1,306 @@
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
 use bytes::Bytes;
-use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode, Url};
+use serde::Deserialize;
+
+/// Graph's error code for an item that doesn't exist.
+pub const ITEM_NOT_FOUND: &str = "itemNotFound";
+/// Graph's error code for when requests are being throttled.
+pub const ACTIVITY_LIMIT_REACHED: &str = "activityLimitReached";
+/// Longest that a server can make us wait before retrying.
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(30);
+/// How long a daily budget lasts before it is reset.
+const BUDGET_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+/// Fraction of a daily budget after which only cached images are shown, leaving the rest for
+/// anything that can't be served from the cache (e.g., refreshing the token).
+const BUDGET_CACHE_ONLY_FRACTION: f64 = 0.9;
+
+/// Why a request failed. Responses are boxed to keep the error (and so every `Result` holding one)
+/// small.
+#[derive(Debug)]
+pub enum GraphError {
+    /// The request timed out, even after retrying.
+    Timeout(reqwest::Error),
+    /// The server is throttling requests, and may have said how long to wait before trying again.
+    Throttled {
+        retry_after: Option<Duration>,
+        response: Box<ResponseError>,
+    },
+    /// The token was missing, invalid or has expired.
+    Unauthorized(Box<ResponseError>),
+    NotFound(Box<ResponseError>),
+    /// The response didn't have the expected format.
+    Decode(reqwest::Error),
+    /// The daily budget has been used up, so the request wasn't sent.
+    OverBudget {
+        resets_in: Duration,
+    },
+    /// Any other error status, or failure to send the request.
+    Other(Box<dyn Error + Send + Sync>),
+}
+
+impl GraphError {
+    fn from_response(response: ResponseError, retry_after: Option<Duration>) -> Self {
+        let response = Box::new(response);
+        match (response.status, response.code.as_deref()) {
+            (StatusCode::NOT_FOUND, _) | (_, Some(ITEM_NOT_FOUND)) => Self::NotFound(response),
+            (StatusCode::TOO_MANY_REQUESTS, _) | (_, Some(ACTIVITY_LIMIT_REACHED)) => {
+                Self::Throttled {
+                    retry_after,
+                    response,
+                }
+            }
+            (StatusCode::UNAUTHORIZED, _) => Self::Unauthorized(response),
+            _ => Self::Other(response),
+        }
+    }
+
+    /// Whether trying again later might succeed, rather than the server having rejected the
+    /// request.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Timeout(_) | Self::Throttled { .. } | Self::OverBudget { .. } => true,
+            Self::Unauthorized(_) | Self::NotFound(_) | Self::Decode(_) => false,
+            // Either the server had an error, or there was no response at all.
+            Self::Other(err) => err
+                .downcast_ref::<ResponseError>()
+                .map_or(true, |response| response.status.is_server_error()),
+        }
+    }
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(_) => write!(f, "Request timed out"),
+            Self::Throttled { .. } => write!(f, "Too many requests"),
+            Self::Unauthorized(_) => write!(f, "Not authorized"),
+            Self::NotFound(_) => write!(f, "Not found"),
+            Self::Decode(_) => write!(f, "Parsing response failed"),
+            Self::OverBudget { resets_in } => write!(
+                f,
+                "Daily budget used up, resets in {} minutes",
+                resets_in.as_secs().div_ceil(60)
+            ),
+            Self::Other(_) => write!(f, "Request failed"),
+        }
+    }
+}
+
+impl Error for GraphError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Timeout(err) | Self::Decode(err) => Some(err),
+            Self::Throttled { response, .. }
+            | Self::Unauthorized(response)
+            | Self::NotFound(response) => Some(response.as_ref()),
+            Self::Other(err) => Some(err.as_ref()),
+            Self::OverBudget { .. } => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for GraphError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            Self::Timeout(err)
+        } else if err.is_decode() {
+            Self::Decode(err)
+        } else {
+            Self::Other(Box::new(err))
+        }
+    }
+}
+
+/// An error status returned by a server, along with the code and message from the body if it was a
+/// Microsoft Graph error.
+#[derive(Debug)]
+pub struct ResponseError {
+    pub status: StatusCode,
+    pub url: Url,
+    pub code: Option<String>,
+    pub message: Option<String>,
+}
+
+impl fmt::Display for ResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} for {}", self.status, self.url)?;
+        if let Some(code) = &self.code {
+            write!(f, ": {code}")?;
+        }
+        if let Some(message) = &self.message {
+            write!(f, " - {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ResponseError {}
+
+#[derive(Deserialize)]
+struct GraphErrorResponse {
+    error: GraphErrorBody,
+}
+
+#[derive(Deserialize)]
+struct GraphErrorBody {
+    code: Option<String>,
+    message: Option<String>,
+}
+
+/// Turns an error status into a `GraphError`, like `Response::error_for_status` but keeping the
+/// details from the body.
+async fn check_status(response: Response) -> Result<Response, GraphError> {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return Ok(response);
+    }
+
+    let url = response.url().clone();
+    let retry_after = retry_after(&response);
+    let body = response.bytes().await.unwrap_or_default();
+    let error = serde_json::from_slice::<GraphErrorResponse>(&body)
+        .ok()
+        .map(|response| response.error);
+    Err(GraphError::from_response(
+        ResponseError {
+            status,
+            url,
+            code: error.as_ref().and_then(|error| error.code.clone()),
+            message: error.and_then(|error| error.message),
+        },
+        retry_after,
+    ))
+}
+
+/// How long the server asked to wait before retrying, if it said.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
+
+/// Caps how many requests are made and how much is downloaded each day, e.g. on a metered
+/// connection. The first day starts when the budget is created, and the budget is reset
+/// at the end of each day.
+pub struct DailyBudget {
+    max_requests: Option<u64>,
+    max_bytes: Option<u64>,
+    usage: Mutex<BudgetUsage>,
+}
+
+struct BudgetUsage {
+    started: Instant,
+    requests: u64,
+    bytes: u64,
+}
+
+impl DailyBudget {
+    pub fn new(max_requests: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Self {
+            max_requests,
+            max_bytes,
+            usage: Mutex::new(BudgetUsage {
+                started: Instant::now(),
+                requests: 0,
+                bytes: 0,
+            }),
+        }
+    }
+
+    fn usage(&self) -> MutexGuard<'_, BudgetUsage> {
+        let mut usage = self.usage.lock().unwrap();
+        if usage.started.elapsed() >= BUDGET_WINDOW {
+            *usage = BudgetUsage {
+                started: Instant::now(),
+                requests: 0,
+                bytes: 0,
+            };
+        }
+        usage
+    }
+
+    /// How much of the budget has been used, where 1.0 is all of it.
+    fn used(&self) -> f64 {
+        let usage = self.usage();
+        let fraction =
+            |used: u64, max: Option<u64>| max.map_or(0.0, |max| used as f64 / max.max(1) as f64);
+        fraction(usage.requests, self.max_requests).max(fraction(usage.bytes, self.max_bytes))
+    }
+
+    /// Whether so much of the budget has been used that only cached images should be shown.
+    pub fn is_nearly_used(&self) -> bool {
+        self.used() >= BUDGET_CACHE_ONLY_FRACTION
+    }
+
+    pub fn resets_in(&self) -> Duration {
+        BUDGET_WINDOW.saturating_sub(self.usage().started.elapsed())
+    }
+
+    /// Counts a request, or fails if the budget has been used up.
+    fn start_request(&self) -> Result<(), GraphError> {
+        if self.used() >= 1.0 {
+            return Err(GraphError::OverBudget {
+                resets_in: self.resets_in(),
+            });
+        }
+        self.usage().requests += 1;
+        Ok(())
+    }
+
+    fn record_download(&self, bytes: usize) {
+        self.usage().bytes += bytes as u64;
+    }
+}
 
 pub struct Client {
     inner: reqwest::Client,
+    budget: Option<Arc<DailyBudget>>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Client {
     pub fn new() -> Self {
         Self {
             inner: reqwest::Client::builder().gzip(true).build().unwrap(),
+            budget: None,
         }
     }
 
+    /// Counts requests and downloads against a daily budget, failing requests once it's used up.
+    pub fn with_budget(self, budget: Arc<DailyBudget>) -> Self {
+        Self {
+            budget: Some(budget),
+            ..self
+        }
+    }
+
+    pub fn budget(&self) -> Option<&DailyBudget> {
+        self.budget.as_deref()
+    }
+
     fn should_retry(response: &reqwest::Result<Response>) -> bool {
         match response {
             Ok(response) => {
-                // Retry on server error
+                // Retry on server error, or if the server is throttling requests.
                 response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS
             }
             Err(err) => {
                 // Retry on timeout.
@@ -51,7 +332,7 @@ impl Client {
     async fn send_with_retry(
         &self,
         make_request: impl Fn(&reqwest::Client) -> RequestBuilder,
-    ) -> reqwest::Result<Response> {
+    ) -> Result<Response, GraphError> {
         const MAX_RETRIES: u32 = 5;
         const RETRY_DELAY: Duration = if cfg!(test) {
             Duration::from_millis(5)
@@ -61,28 +342,35 @@ impl Client {
         let mut retries = 0;
 
         loop {
+            if let Some(budget) = &self.budget {
+                budget.start_request()?;
+            }
             let response = make_request(&self.inner).send().await;
 
             if retries < MAX_RETRIES && Client::should_retry(&response) {
-                tokio::time::sleep(RETRY_DELAY.saturating_mul(retries)).await;
+                let delay = response
+                    .as_ref()
+                    .ok()
+                    .and_then(retry_after)
+                    .map_or(RETRY_DELAY.saturating_mul(retries), |delay| {
+                        delay.min(MAX_RETRY_AFTER)
+                    });
+                tokio::time::sleep(delay).await;
                 retries += 1;
             } else {
-                break response;
+                break Ok(response?);
             }
         }
     }
 
-    pub async fn get<T>(&self, token: &str, url: Url) -> Result<T>
+    pub async fn get<T>(&self, token: &str, url: Url) -> Result<T, GraphError>
     where
         T: serde::de::DeserializeOwned,
     {
-        self.send_with_retry(|client| client.get(url.clone()).bearer_auth(token))
-            .await
-            .with_context(|| "Sending request failed")?
-            .error_for_status()?
-            .json::<T>()
-            .await
-            .with_context(|| "Parsing response failed")
+        let response = self
+            .send_with_retry(|client| client.get(url.clone()).bearer_auth(token))
+            .await?;
+        Ok(check_status(response).await?.json::<T>().await?)
     }
 
     pub async fn post<T>(
@@ -90,33 +378,95 @@ impl Client {
         url: Url,
         parameters: &[(&str, &str)],
         expected_error: Option<StatusCode>,
-    ) -> Result<T>
+    ) -> Result<T, GraphError>
     where
         T: serde::de::DeserializeOwned,
     {
         let response = self
             .send_with_retry(|client| client.post(url.clone()).form(parameters))
-            .await
-            .with_context(|| "Sending request failed")?;
+            .await?;
 
         let response = match expected_error {
             Some(expected_error) if response.status() == expected_error => response,
-            _ => response.error_for_status()?,
+            _ => check_status(response).await?,
         };
 
-        response
-            .json::<T>()
-            .await
-            .with_context(|| "Parsing response failed")
+        Ok(response.json::<T>().await?)
+    }
+
+    pub async fn put(&self, token: &str, url: Url, body: Bytes) -> Result<(), GraphError> {
+        let response = self
+            .send_with_retry(|client| {
+                client
+                    .put(url.clone())
+                    .bearer_auth(token)
+                    .body(body.clone())
+            })
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    pub async fn delete(&self, token: &str, url: Url) -> Result<(), GraphError> {
+        let response = self
+            .send_with_retry(|client| client.delete(url.clone()).bearer_auth(token))
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Gets the contents of the resource at `url`, or `None` if it doesn't exist.
+    pub async fn get_if_exists(&self, token: &str, url: Url) -> Result<Option<Bytes>, GraphError> {
+        let response = self
+            .send_with_retry(|client| client.get(url.clone()).bearer_auth(token))
+            .await?;
+        match check_status(response).await {
+            Ok(response) => Ok(Some(self.read_download(response).await?)),
+            Err(GraphError::NotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Checks if the resource at `url` exists.
+    pub async fn exists(&self, token: &str, url: Url) -> Result<bool, GraphError> {
+        let response = self
+            .send_with_retry(|client| client.get(url.clone()).bearer_auth(token))
+            .await?;
+        match check_status(response).await {
+            Ok(_) => Ok(true),
+            Err(GraphError::NotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
     }
 
-    pub async fn download(&self, token: &str, url: Url) -> Result<Bytes> {
-        Ok(self
+    pub async fn download(&self, token: &str, url: Url) -> Result<Bytes, GraphError> {
+        let response = self
             .send_with_retry(|client| client.get(url.clone()).bearer_auth(token))
-            .await
-            .with_context(|| "Sending request failed")?
-            .bytes()
-            .await?)
+            .await?;
+        self.read_download(check_status(response).await?).await
+    }
+
+    /// Reads the body of a download, counting it against the budget. Other responses aren't
+    /// counted, since they're small compared to images.
+    async fn read_download(&self, response: Response) -> Result<Bytes, GraphError> {
+        let expected_length = response.content_length();
+        let data = response.bytes().await?;
+        if let Some(budget) = &self.budget {
+            budget.record_download(data.len());
+        }
+        // Don't let a truncated download be cached as if it were the whole file.
+        if let Some(expected_length) = expected_length {
+            if data.len() as u64 != expected_length {
+                return Err(GraphError::Other(
+                    format!(
+                        "Download was {} bytes, but expected {expected_length} bytes",
+                        data.len()
+                    )
+                    .into(),
+                ));
+            }
+        }
+        Ok(data)
     }
 }
 
@@ -167,6 +517,148 @@ async fn retry_always_error() {
     mock.assert();
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn retry_when_throttled() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let throttled_mock = server
+        .mock("GET", "/")
+        .with_status(429)
+        .with_body(r#"{ "error": { "code": "activityLimitReached", "message": "Slow down" } }"#)
+        .expect(6)
+        .create();
+
+    let client = Client::new();
+    let err = client
+        .get::<serde_json::Value>("token", Url::parse(&url).unwrap())
+        .await
+        .unwrap_err();
+    throttled_mock.assert();
+
+    assert!(err.is_transient());
+    let GraphError::Throttled {
+        retry_after: None,
+        response,
+    } = err
+    else {
+        panic!("Unexpected error: {err:?}");
+    };
+    assert_eq!(response.code.as_deref(), Some(ACTIVITY_LIMIT_REACHED));
+    assert_eq!(
+        response.to_string(),
+        format!("429 Too Many Requests for {url}/: activityLimitReached - Slow down")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn error_without_graph_body() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let mock = server
+        .mock("GET", "/missing")
+        .with_status(404)
+        .with_body("Not here")
+        .expect(1)
+        .create();
+
+    let err = Client::new()
+        .download("token", Url::parse(&format!("{url}/missing")).unwrap())
+        .await
+        .unwrap_err();
+    mock.assert();
+
+    assert!(!err.is_transient());
+    let GraphError::NotFound(response) = err else {
+        panic!("Unexpected error: {err:?}");
+    };
+    assert_eq!(response.code, None);
+    assert_eq!(response.message, None);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn classify_errors() {
+    let mut server = mockito::Server::new_async().await;
+    let url = Url::parse(&server.url()).unwrap();
+
+    let _unauthorized_mock = server
+        .mock("GET", "/unauthorized")
+        .with_status(401)
+        .with_body(r#"{ "error": { "code": "InvalidAuthenticationToken" } }"#)
+        .create();
+    let _item_not_found_mock = server
+        .mock("GET", "/gone")
+        .with_status(400)
+        .with_body(r#"{ "error": { "code": "itemNotFound" } }"#)
+        .create();
+    let _bad_request_mock = server.mock("GET", "/bad").with_status(400).create();
+    let _invalid_json_mock = server
+        .mock("GET", "/invalid")
+        .with_body("not json")
+        .create();
+
+    let client = Client::new();
+    let get = |path: &'static str| client.get::<serde_json::Value>("token", url.append_path(path));
+    assert!(matches!(
+        get("unauthorized").await,
+        Err(GraphError::Unauthorized(_))
+    ));
+    assert!(matches!(get("gone").await, Err(GraphError::NotFound(_))));
+    let err = get("bad").await.unwrap_err();
+    assert!(matches!(err, GraphError::Other(_)));
+    assert!(!err.is_transient());
+    assert!(matches!(get("invalid").await, Err(GraphError::Decode(_))));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn daily_budget() {
+    let mut server = mockito::Server::new_async().await;
+    let url = Url::parse(&server.url()).unwrap();
+
+    let mock = server
+        .mock("GET", "/image")
+        .with_body(vec![0u8; 100])
+        .expect(3)
+        .create();
+
+    // Downloads count towards the bandwidth limit.
+    let budget = Arc::new(DailyBudget::new(None, Some(250)));
+    let client = Client::new().with_budget(budget.clone());
+    let download = || client.download("token", url.append_path("image"));
+    download().await.unwrap();
+    download().await.unwrap();
+    assert!(!budget.is_nearly_used());
+    download().await.unwrap();
+    assert!(budget.is_nearly_used());
+    let err = download().await.unwrap_err();
+    assert!(matches!(err, GraphError::OverBudget { .. }));
+    assert!(err.is_transient());
+    mock.assert();
+
+    // As do requests, including retries.
+    let mock = server
+        .mock("GET", "/error")
+        .with_status(500)
+        .expect(10)
+        .create();
+    let budget = Arc::new(DailyBudget::new(Some(10), None));
+    let client = Client::new().with_budget(budget.clone());
+    assert!(client
+        .download("token", url.append_path("error"))
+        .await
+        .is_err());
+    assert!(!budget.is_nearly_used());
+    let err = client
+        .download("token", url.append_path("error"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, GraphError::OverBudget { .. }));
+    assert!(budget.is_nearly_used());
+    assert!(budget.resets_in() <= BUDGET_WINDOW);
+    mock.assert();
+}
+
 pub trait AppendPaths {
     fn append_path(&self, path: &str) -> Self;
     fn append_paths(&self, paths: &[&str]) -> Self;
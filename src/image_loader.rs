@@ -1,17 +1,113 @@
-use crate::http::{AppendPaths, Client};
-use anyhow::{anyhow, Context, Result};
-use egui::ColorImage;
+use crate::{
+    cache::{CachePolicy, CacheStore, DiskCache},
+    conversion::ConversionPool,
+    http::{AppendPaths, Client, DailyBudget, GraphError, ResponseError},
+    metadata::{GeoCoordinates, Place},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, NaiveDate, Utc, Weekday};
+use egui::{Color32, ColorImage};
+use image::ImageDecoder;
 use rand::Rng;
-use reqwest::Url;
-use serde::Deserialize;
-use std::{collections::HashMap, path::PathBuf};
-use tokio::sync::mpsc::unbounded_channel;
+use reqwest::{StatusCode, Url};
+use serde::{de::IgnoredAny, Deserialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-pub struct ImageLoader {
+/// Somewhere that images can be listed and downloaded from. [`ImageLoader`] gets them from OneDrive,
+/// but code that only lists and downloads images can be written against this instead (e.g., to test
+/// it without a drive).
+pub trait PhotoSource: Send + Sync {
+    /// Recursively lists all the images within `directories`.
+    fn list_images(
+        &self,
+        token: &str,
+        directories: &[String],
+    ) -> impl Future<Output = Result<Listing>> + Send;
+
+    /// Downloads an encoded image, sized to fit within `width` by `height` if the source can resize
+    /// it.
+    fn download_image(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> impl Future<Output = Result<Bytes>> + Send;
+}
+
+/// Lists and loads images from a OneDrive drive (e.g., `https://graph.microsoft.com/v1.0/me/drive`),
+/// caching the loaded images in a [`CacheStore`] (on disk, unless another is given with
+/// [`ImageLoader::with_cache`]).
+pub struct ImageLoader<C = DiskCache> {
     client: Client,
     base_url: Url,
     config_url: Url,
-    cache_directory: PathBuf,
+    shares_url: Url,
+    cache: C,
+    /// JPEG quality to re-encode images at before caching them, to save space.
+    cache_quality: Option<u8>,
+    drive_type: DriveType,
+    /// Largest width or height to download and decode images at.
+    max_image_dimension: Option<u32>,
+    /// Pause between listing each directory, to spread out the work of crawling large folders.
+    listing_delay: Duration,
+    conversions: ConversionPool,
+    /// Name of this device, for finding its group's settings in slideshow.txt.
+    device_name: Option<String>,
+}
+
+/// Most items to ask for in each page of a directory's listing, so that huge folders are fetched
+/// (and held in memory) a page at a time rather than in one enormous response.
+const LISTING_PAGE_SIZE: usize = 1000;
+
+/// Roughly how many bytes each pixel of an image takes up while it is being decoded: the decoded
+/// image, the RGBA copy of it and the `ColorImage` that is made from that.
+const DECODING_BYTES_PER_PIXEL: u64 = 12;
+
+/// Checks that decoding an image of the given size would leave at least half of the available
+/// memory (in KB) free, so that a huge image (e.g., a panorama) can't get the slideshow killed on
+/// a device with little memory. Devices where the available memory can't be read are assumed to
+/// have enough.
+fn check_decoding_memory(width: u32, height: u32, available_kb: Option<u64>) -> Result<()> {
+    let needed = u64::from(width) * u64::from(height) * DECODING_BYTES_PER_PIXEL;
+    match available_kb {
+        Some(available_kb) if needed > available_kb.saturating_mul(1024) / 2 => bail!(
+            "Not enough memory to decode a {width}x{height} image ({} MB needed, {} MB available)",
+            needed / (1024 * 1024),
+            available_kb / 1024
+        ),
+        _ => Ok(()),
+    }
+}
+
+fn available_memory_kb() -> Option<u64> {
+    sys_info::mem_info().ok().map(|memory| memory.avail)
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+enum DriveType {
+    #[default]
+    Personal,
+    /// Business drives and SharePoint document libraries.
+    #[serde(other)]
+    Business,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DriveInfo {
+    drive_type: DriveType,
 }
 
 #[derive(Deserialize)]
@@ -24,6 +120,105 @@ struct DriveResponse {
 #[derive(Deserialize)]
 struct DriveItem {
     id: String,
+    name: Option<String>,
+    folder: Option<IgnoredAny>,
+    image: Option<ImageFacet>,
+    file: Option<FileFacet>,
+    photo: Option<PhotoFacet>,
+    location: Option<GeoCoordinates>,
+    #[serde(rename = "lastModifiedDateTime")]
+    last_modified_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+struct ImageFacet {
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PhotoFacet {
+    taken_date_time: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileFacet {
+    mime_type: Option<String>,
+}
+
+impl DriveItem {
+    fn is_image(&self) -> bool {
+        self.image.is_some()
+            || self
+                .file
+                .as_ref()
+                .and_then(|file| file.mime_type.as_deref())
+                .map_or(false, |mime_type| mime_type.starts_with("image/"))
+    }
+}
+
+/// An image found while listing directories.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    /// OneDrive item id, used to load the image.
+    pub id: String,
+    /// File name of the image.
+    pub name: Option<String>,
+    /// Configured directory that the image was found within.
+    pub directory: Arc<str>,
+    /// Folder that the image is directly within, relative to the root of the drive (e.g.,
+    /// `Pictures/Vacations 2023`).
+    pub folder: Arc<str>,
+    /// Size of the original image, if OneDrive knows it.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// When the photo was taken, if OneDrive knows.
+    pub taken: Option<DateTime<Utc>>,
+    /// When the file was last changed.
+    pub modified: Option<DateTime<Utc>>,
+    /// Where the photo was taken, if OneDrive knows.
+    pub location: Option<GeoCoordinates>,
+}
+
+impl Image {
+    fn new(item: DriveItem, directory: &Arc<str>, folder: &Arc<str>) -> Self {
+        let (width, height) = item
+            .image
+            .map_or((None, None), |image| (image.width, image.height));
+        Self {
+            id: item.id,
+            name: item.name,
+            directory: directory.clone(),
+            folder: folder.clone(),
+            width,
+            height,
+            taken: item.photo.and_then(|photo| photo.taken_date_time),
+            modified: item.last_modified_date_time,
+            location: item.location,
+        }
+    }
+
+    /// An image that is only known by its id, without any of its other details.
+    pub fn with_id(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            name: None,
+            directory: "".into(),
+            folder: "".into(),
+            width: None,
+            height: None,
+            taken: None,
+            modified: None,
+            location: None,
+        }
+    }
+
+    /// Name of the folder that the image is directly within, e.g. for showing in a caption.
+    pub fn folder_name(&self) -> &str {
+        self.folder.rsplit('/').next().unwrap_or_default()
+    }
 }
 
 #[derive(Deserialize)]
@@ -36,314 +231,1989 @@ struct ThumbnailItem {
     url: String,
 }
 
+/// Images found by listing directories.
+#[derive(Default)]
+pub struct Listing {
+    pub images: Vec<Image>,
+    /// Directories that were asked for but don't exist, e.g. because of a typo in slideshow.txt.
+    pub missing_directories: Vec<String>,
+    /// Directories that couldn't be listed, and so are missing from `images`.
+    pub failed_directories: Vec<FailedDirectory>,
+}
+
+/// A directory (and everything under it) that couldn't be listed, which can be retried later.
+pub struct FailedDirectory {
+    path: String,
+    /// The directory that was asked for that this directory is within.
+    pub directory: Arc<str>,
+    folder: Arc<str>,
+    pub error: anyhow::Error,
+}
+
+/// A directory that is waiting to be listed.
+struct PendingDirectory {
+    /// Path to the directory, relative to the drive's URL.
+    path: String,
+    /// The directory that was asked for that this directory is within.
+    directory: Arc<str>,
+    /// Path to the directory from the root of the drive.
+    folder: Arc<str>,
+}
+
+fn is_not_found(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<GraphError>(),
+        Some(GraphError::NotFound(_))
+    )
+}
+
+/// Whether OneDrive refused to make a thumbnail of an image, rather than the request failing.
+fn is_thumbnail_unavailable(err: &anyhow::Error) -> bool {
+    match err.downcast_ref::<GraphError>() {
+        Some(GraphError::NotFound(_)) => true,
+        Some(GraphError::Other(err)) => err
+            .downcast_ref::<ResponseError>()
+            .is_some_and(|response| response.status == StatusCode::BAD_REQUEST),
+        _ => false,
+    }
+}
+
+/// Configuration that is enough to get started with.
+pub const SAMPLE_CONFIG: &str = r#"{ "directories": [ "Pictures" ], "interval": 60 }"#;
+/// slideshow.txt that is created for people who don't have one yet. JSON doesn't allow comments, so
+/// the explanation is in a field that is ignored.
+const SAMPLE_CONFIG_FILE: &str = r#"{
+    "_comment": "Created by OneDrive Slideshow. Add the folders to show to \"directories\", and set \"interval\" to the number of seconds to show each image for. See https://github.com/dpaoliello/onedrive-slideshow for more settings.",
+    "directories": [ "Pictures" ],
+    "interval": 60
+}
+"#;
+/// Longest snippet of an invalid configuration file to show.
+const MAX_CONFIG_SNIPPET_LENGTH: usize = 80;
+
+/// A problem with slideshow.txt that needs to be fixed in OneDrive.
+#[derive(Debug, Eq, PartialEq)]
+pub enum ConfigError {
+    Missing,
+    Invalid {
+        message: String,
+        /// The line that the problem is on.
+        snippet: String,
+    },
+}
+
+impl ConfigError {
+    /// A problem described by `message`, on the given `line` (starting from 1) of `content`.
+    fn invalid(message: String, line: Option<usize>, content: &str) -> Self {
+        let snippet = line
+            .and_then(|line| content.lines().nth(line.saturating_sub(1)))
+            .unwrap_or_default()
+            .trim()
+            .chars()
+            .take(MAX_CONFIG_SNIPPET_LENGTH)
+            .collect();
+        Self::Invalid { message, snippet }
+    }
+}
+
+/// The formats that slideshow.txt can be written in, which all have the same settings.
+#[derive(Debug, Eq, PartialEq)]
+enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Guesses the format from the first line that isn't blank or a comment: JSON is an object,
+    /// TOML starts with a `[table]` or a `key = value`, and anything else is taken to be YAML
+    /// (e.g., `key: value`).
+    fn sniff(content: &str) -> Self {
+        let first_line = content
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        if first_line.starts_with('{') {
+            Self::Json
+        } else if first_line.starts_with('[')
+            || first_line
+                .find('=')
+                .is_some_and(|equals| first_line.find(':').map_or(true, |colon| equals < colon))
+        {
+            Self::Toml
+        } else {
+            Self::Yaml
+        }
+    }
+}
+
+/// The first line (starting from 1) that mentions `setting`, to point at where a problem is.
+fn line_of(content: &str, setting: &str) -> Option<usize> {
+    content
+        .lines()
+        .position(|line| line.contains(setting))
+        .map(|index| index + 1)
+}
+
+/// Checks that `directory` is the path of a folder from the root of the drive, which is easily
+/// confused with a path on the computer that OneDrive syncs to.
+fn check_directory(directory: &str) -> Result<(), String> {
+    let example = "such as \"Pictures/Vacations\"";
+    if directory.trim().is_empty() {
+        Err(format!(
+            "Directories can't be empty; use the path of a folder from the root of your OneDrive, {example}"
+        ))
+    } else if directory.contains("://")
+        || directory.as_bytes().get(1) == Some(&b':')
+        || directory.starts_with('/')
+    {
+        Err(format!(
+            "\"{directory}\" isn't a path within your OneDrive; use the path of a folder from the root of your OneDrive, {example}"
+        ))
+    } else if directory.contains('\\') {
+        Err(format!(
+            "\"{directory}\" uses \\ between folders; use / instead, {example}"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Missing => write!(
+                f,
+                "slideshow.txt wasn't found. Create slideshow.txt in the root of your OneDrive listing the folders to show, for example: {SAMPLE_CONFIG}"
+            ),
+            ConfigError::Invalid { message, snippet } => {
+                write!(f, "slideshow.txt is invalid: {message}")?;
+                if !snippet.is_empty() {
+                    write!(f, "\n    {snippet}")?;
+                }
+                write!(f, "\nFor example, this shows a folder of pictures: {SAMPLE_CONFIG}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Deserialize)]
+pub struct Config {
+    /// Folders to show the images within, relative to the root of the drive.
+    #[serde(default)]
+    pub directories: Vec<String>,
+    pub interval: u64,
+    /// Roughly how long (in hours) it should take to show every image. If set, the interval is
+    /// lengthened so that smaller libraries don't repeat more often than this.
+    pub cycle_hours: Option<f64>,
+    #[serde(default)]
+    pub playlists: Vec<Playlist>,
+    #[serde(default)]
+    pub special_days: Vec<SpecialDay>,
+    pub pinned: Option<Pinned>,
+    /// Window (in seconds) over which devices spread their image list refreshes.
+    pub refresh_window: Option<u64>,
+    pub interval_rules: Option<IntervalRules>,
+    /// Whether to show the name of the folder that each image came from.
+    #[serde(default)]
+    pub folder_captions: bool,
+    /// Whether to show each image's file name, folder and the date it was taken.
+    #[serde(default)]
+    pub photo_info: bool,
+    /// Template for each image's caption, e.g. `{month} · {place}`, which takes priority over
+    /// `photo_info` and `folder_captions`.
+    pub caption: Option<String>,
+    /// Template for a title card to show when the slideshow moves on to a new folder, e.g.
+    /// `{folder} · {year}`, using the same placeholders as `caption`.
+    pub title_card: Option<String>,
+    /// Places to name in captions when a photo was taken near them.
+    #[serde(default)]
+    pub places: Vec<Place>,
+    /// Colors for the captions of images within each folder (and the folders within it), keyed by
+    /// the folder's path from the root of the drive.
+    #[serde(default)]
+    pub folder_themes: HashMap<String, FolderTheme>,
+    /// Whether to avoid showing two images from the same folder one after another.
+    #[serde(default)]
+    pub avoid_repeating_folders: bool,
+    /// How much of the slideshow's time each directory gets relative to the others (which have a
+    /// weight of 1), however many images it has.
+    #[serde(default)]
+    pub directory_weights: HashMap<String, f64>,
+    /// How long (in seconds) to crossfade from one image to the next, or 0 to switch straight away.
+    pub fade_seconds: Option<f64>,
+    /// Whether to slowly pan and zoom across each image while it is shown.
+    #[serde(default)]
+    pub ken_burns: bool,
+    /// How long (in seconds) a slide has to be shown for to get a strip of other photos from the
+    /// same folder, or taken on the same day, along the bottom.
+    pub photo_strip_after: Option<u64>,
+    /// Whether to show a thin bar along the bottom of the screen that fills up until the next
+    /// slide is shown.
+    #[serde(default)]
+    pub progress_bar: bool,
+    /// Only show images of this shape, e.g. on a frame that is mounted in portrait.
+    pub orientation: Option<OrientationSetting>,
+    /// What order to show images in.
+    #[serde(default)]
+    pub order: Order,
+    /// Only show photos taken on or after this date.
+    pub min_date: Option<NaiveDate>,
+    /// Only show photos taken on or before this date.
+    pub max_date: Option<NaiveDate>,
+    /// Groups of frames that share settings, which override the settings above on those frames.
+    #[serde(default)]
+    pub groups: Vec<FrameGroup>,
+    /// Settings that weren't recognized, such as `intreval`.
+    #[serde(skip)]
+    pub unknown_fields: Vec<String>,
+}
+
+impl Config {
+    /// Reads the contents of slideshow.txt, whichever format it is in, and checks that its
+    /// settings make sense.
+    pub fn parse(content: &[u8]) -> Result<Self, ConfigError> {
+        let content = String::from_utf8_lossy(content);
+        // Settings that aren't recognized are most likely misspelled. Those starting with `_` are
+        // comments, since JSON doesn't have any.
+        let mut unknown_fields = Vec::new();
+        let mut unknown_field = |path: serde_ignored::Path| {
+            let path = path.to_string();
+            if !path.split('.').any(|key| key.starts_with('_')) {
+                unknown_fields.push(path);
+            }
+        };
+        let parsed = match ConfigFormat::sniff(&content) {
+            ConfigFormat::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(&content);
+                serde_ignored::deserialize(&mut deserializer, &mut unknown_field)
+                    .and_then(|config| deserializer.end().map(|()| config))
+                    .map_err(|err| (err.to_string(), Some(err.line())))
+            }
+            ConfigFormat::Toml => {
+                serde_ignored::deserialize(toml::Deserializer::new(&content), &mut unknown_field)
+                    .map_err(|err| {
+                        let line = err
+                            .span()
+                            .map(|span| content[..span.start].matches('\n').count() + 1);
+                        (err.message().to_string(), line)
+                    })
+            }
+            ConfigFormat::Yaml => serde_ignored::deserialize(
+                serde_yaml::Deserializer::from_str(&content),
+                &mut unknown_field,
+            )
+            .map_err(|err| {
+                (
+                    err.to_string(),
+                    err.location().map(|location| location.line()),
+                )
+            }),
+        };
+        let mut config: Config = match parsed {
+            Ok(config) => config,
+            Err((mut message, line)) => {
+                // e.g., a missing `interval` because it was written as `intreval`.
+                if !unknown_fields.is_empty() {
+                    message.push_str(&format!(
+                        " (these settings aren't recognized, so may be misspelled: {})",
+                        unknown_fields.join(", ")
+                    ));
+                }
+                return Err(ConfigError::invalid(message, line, &content));
+            }
+        };
+        config.validate().map_err(|(setting, message)| {
+            ConfigError::invalid(message, line_of(&content, setting), &content)
+        })?;
+        config.unknown_fields = unknown_fields;
+        Ok(config)
+    }
+
+    /// Checks for settings that can be read but won't work, returning the setting that is wrong
+    /// and what is wrong with it.
+    fn validate(&self) -> Result<(), (&str, String)> {
+        let intervals = std::iter::once(Some(self.interval)).chain(
+            self.groups
+                .iter()
+                .flat_map(|group| std::iter::once(&group.shared).chain(group.frames.values()))
+                .map(|settings| settings.interval),
+        );
+        if intervals.flatten().any(|interval| interval == 0) {
+            return Err((
+                "interval",
+                "interval must be at least 1 second, e.g. 60 to show each image for a minute"
+                    .into(),
+            ));
+        }
+        if let Some(cycle_hours) = self.cycle_hours {
+            if !(cycle_hours.is_finite() && cycle_hours > 0.0) {
+                return Err((
+                    "cycle_hours",
+                    format!("cycle_hours must be more than 0 hours, not {cycle_hours}"),
+                ));
+            }
+        }
+        if let Some(fade_seconds) = self.fade_seconds {
+            if !(fade_seconds.is_finite() && fade_seconds >= 0.0) {
+                return Err((
+                    "fade_seconds",
+                    format!("fade_seconds must be 0 or more seconds, not {fade_seconds}"),
+                ));
+            }
+        }
+        if let Some((directory, weight)) = self
+            .directory_weights
+            .iter()
+            .find(|(_, weight)| !(weight.is_finite() && **weight > 0.0))
+        {
+            return Err((
+                directory,
+                format!("The weight of {directory} must be more than 0, not {weight}"),
+            ));
+        }
+        if let (Some(min_date), Some(max_date)) = (self.min_date, self.max_date) {
+            if min_date > max_date {
+                return Err((
+                    "min_date",
+                    format!("min_date ({min_date}) is after max_date ({max_date}), so no photos would be shown"),
+                ));
+            }
+        }
+
+        let directories = self
+            .directories
+            .iter()
+            .chain(
+                self.playlists
+                    .iter()
+                    .flat_map(|playlist| &playlist.directories),
+            )
+            .chain(
+                self.special_days
+                    .iter()
+                    .filter_map(|special_day| special_day.directories.as_ref())
+                    .flatten(),
+            )
+            .chain(
+                self.groups
+                    .iter()
+                    .flat_map(|group| std::iter::once(&group.shared).chain(group.frames.values()))
+                    .filter_map(|settings| settings.directories.as_ref())
+                    .flatten(),
+            );
+        for directory in directories {
+            // Empty directories can only be pointed at through the setting that they are in.
+            let setting = if directory.trim().is_empty() {
+                "directories"
+            } else {
+                directory
+            };
+            check_directory(directory).map_err(|message| (setting, message))?;
+        }
+        Ok(())
+    }
+
+    /// Overrides the settings with those of the group that `device_name` is in (if any), and then
+    /// with the frame's own settings within that group.
+    fn apply_frame_group(&mut self, device_name: &str) {
+        let groups = std::mem::take(&mut self.groups);
+        let Some(mut group) = groups
+            .into_iter()
+            .find(|group| group.frames.contains_key(device_name))
+        else {
+            return;
+        };
+        let frame = group.frames.remove(device_name).unwrap_or_default();
+        for settings in [group.shared, frame] {
+            if let Some(directories) = settings.directories {
+                self.directories = directories;
+            }
+            if let Some(interval) = settings.interval {
+                self.interval = interval;
+            }
+            if settings.orientation.is_some() {
+                self.orientation = settings.orientation;
+            }
+        }
+    }
+}
+
+/// Frames that share settings, such as all of the frames in a house.
+#[derive(Debug, Deserialize)]
+pub struct FrameGroup {
+    /// Settings for each frame in the group, keyed by its device name, which override the group's.
+    pub frames: HashMap<String, FrameSettings>,
+    /// Settings for every frame in the group.
+    #[serde(flatten)]
+    pub shared: FrameSettings,
+}
+
+/// Settings that can be changed for a group of frames, or a single frame.
+#[derive(Debug, Default, Deserialize)]
+pub struct FrameSettings {
+    pub directories: Option<Vec<String>>,
+    pub interval: Option<u64>,
+    pub orientation: Option<OrientationSetting>,
+}
+
+/// Dates (inclusive) that photos have to have been taken within to be shown. Photos that OneDrive
+/// doesn't know the date of are dated by when their file was last changed, or else always shown.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DateRange {
+    pub min: Option<NaiveDate>,
+    pub max: Option<NaiveDate>,
+}
+
+impl DateRange {
+    pub fn contains(&self, image: &Image) -> bool {
+        let Some(date) = image.taken.or(image.modified) else {
+            return true;
+        };
+        let date = date.date_naive();
+        self.min.map_or(true, |min| date >= min) && self.max.map_or(true, |max| date <= max)
+    }
+}
+
+/// The order that images are shown in.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Order {
+    /// A random image each time, favoring images that haven't been shown recently.
+    #[default]
+    Random,
+    /// The order that the images were listed in, i.e. directory by directory.
+    Sequential,
+    /// Oldest photo first, by when it was taken.
+    ByDateAsc,
+    /// Newest photo first, by when it was taken.
+    NewestFirst,
+}
+
+impl Order {
+    /// Puts `images` in this order, or leaves them as they are for a random order. Images whose
+    /// date isn't known come after the rest when ordering by date.
+    pub fn sort(self, images: &[Image]) -> Vec<&Image> {
+        let mut sorted = images.iter().collect::<Vec<_>>();
+        match self {
+            Self::Random | Self::Sequential => {}
+            Self::ByDateAsc => sorted.sort_by_key(|image| (image.taken.is_none(), image.taken)),
+            Self::NewestFirst => {
+                sorted.sort_by_key(|image| (image.taken.is_none(), std::cmp::Reverse(image.taken)))
+            }
+        }
+        sorted
+    }
+}
+
+/// The shape of an image, or of the screen that it is shown on.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+impl Orientation {
+    /// Whether an image is this shape. Square images, and images whose size isn't known, are
+    /// either.
+    pub fn matches(self, image: &Image) -> bool {
+        let (Some(width), Some(height)) = (image.width, image.height) else {
+            return true;
+        };
+        match self {
+            Self::Portrait => height >= width,
+            Self::Landscape => width >= height,
+        }
+    }
+}
+
+impl Orientation {
+    /// The shape of a screen (or window) of the given size.
+    pub fn of_screen(width: f32, height: f32) -> Self {
+        if height > width {
+            Self::Portrait
+        } else {
+            Self::Landscape
+        }
+    }
+}
+
+/// The shape of image to show, as set in slideshow.txt.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrientationSetting {
+    /// Whichever shape the screen is, so that turning the frame (or resizing the window) changes
+    /// which images are shown.
+    Auto,
+    #[serde(untagged)]
+    Fixed(Orientation),
+}
+
+impl OrientationSetting {
+    pub fn for_screen(self, width: f32, height: f32) -> Orientation {
+        match self {
+            Self::Auto => Orientation::of_screen(width, height),
+            Self::Fixed(orientation) => orientation,
+        }
+    }
+}
+
+impl fmt::Display for Orientation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Portrait => "portrait",
+            Self::Landscape => "landscape",
+        })
+    }
+}
+
+/// Colors for the overlays shown on top of images from a folder.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub struct FolderTheme {
+    /// Color of the caption's text.
+    pub caption_color: Option<ThemeColor>,
+    /// Color of the caption's border.
+    pub accent_color: Option<ThemeColor>,
+}
+
+/// A color written as `#RRGGBB`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub struct ThemeColor(pub Color32);
+
+impl TryFrom<String> for ThemeColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid color \"{value}\", expected #RRGGBB");
+        let hex = value.strip_prefix('#').ok_or_else(invalid)?;
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let component = |index: usize| {
+            u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid())
+        };
+        Ok(Self(Color32::from_rgb(
+            component(0)?,
+            component(1)?,
+            component(2)?,
+        )))
+    }
+}
+
+/// Finds the theme for `folder`, using the theme of the closest folder that contains it if it
+/// doesn't have its own.
+pub fn theme_for_folder(themes: &HashMap<String, FolderTheme>, folder: &str) -> FolderTheme {
+    themes
+        .iter()
+        .filter(|(themed, _)| is_within_folder(folder, themed))
+        .max_by_key(|(themed, _)| themed.len())
+        .map_or_else(FolderTheme::default, |(_, theme)| *theme)
+}
+
+/// Whether `folder` is `parent` or one of the folders within it, where both are paths from the root
+/// of the drive.
+pub fn is_within_folder(folder: &str, parent: &str) -> bool {
+    folder == parent
+        || folder
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Rules for showing some images for longer than the normal interval.
+#[derive(Default, Deserialize)]
+pub struct IntervalRules {
+    /// Aspect ratio (width / height) at or above which an image is considered to be a panorama.
+    pub panorama_aspect_ratio: Option<f64>,
+    /// Multiplier applied to the interval of panoramas.
+    pub panorama_multiplier: Option<f64>,
+    /// Multipliers applied to the interval of images within specific directories.
+    #[serde(default)]
+    pub directories: HashMap<String, f64>,
+}
+
+/// Set of directories to show instead of the default directories on certain days of the week.
+#[derive(Deserialize)]
+pub struct Playlist {
+    pub days: Vec<Weekday>,
+    pub directories: Vec<String>,
+}
+
+/// A day, such as a birthday or holiday, that shows different directories and/or a banner.
+#[derive(Deserialize)]
+pub struct SpecialDay {
+    pub date: SpecialDate,
+    /// Directories to show instead of the default directories (or any playlist) on that day.
+    pub directories: Option<Vec<String>>,
+    /// Message shown on top of the slides all day.
+    pub banner: Option<String>,
+}
+
+/// A date that is either every year (`MM-DD`) or only once (`YYYY-MM-DD`).
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(try_from = "String")]
+pub struct SpecialDate {
+    year: Option<i32>,
+    month: u32,
+    day: u32,
+}
+
+impl SpecialDate {
+    pub fn matches(&self, date: NaiveDate) -> bool {
+        self.year.map_or(true, |year| year == date.year())
+            && self.month == date.month()
+            && self.day == date.day()
+    }
+}
+
+impl TryFrom<String> for SpecialDate {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid date \"{value}\", expected MM-DD or YYYY-MM-DD");
+        let (year, month, day) = match value.split('-').collect::<Vec<_>>()[..] {
+            [month, day] => (None, month, day),
+            [year, month, day] => (Some(year), month, day),
+            _ => return Err(invalid()),
+        };
+        let year = year.map(str::parse).transpose().map_err(|_| invalid())?;
+        let month = month.parse().map_err(|_| invalid())?;
+        let day = day.parse().map_err(|_| invalid())?;
+        // Every year dates are checked against a leap year, so that birthdays can be on Feb 29.
+        NaiveDate::from_ymd_opt(year.unwrap_or(2000), month, day).ok_or_else(invalid)?;
+        Ok(Self { year, month, day })
+    }
+}
+
+/// Image, or folder of images, that is shown at a regular frequency.
 #[derive(Deserialize)]
-struct Config {
-    directories: Vec<String>,
-    interval: u64,
+pub struct Pinned {
+    pub path: String,
+    /// Show a pinned image every this many slides.
+    pub every: Option<NonZeroU32>,
 }
 
 impl ImageLoader {
+    /// Creates a loader for the drive at `base_url`, caching images in `cache_directory`.
+    ///
+    /// Panics if `base_url` isn't a valid URL.
     pub fn new(base_url: &str, cache_directory: PathBuf) -> Self {
         let base_url = Url::parse(base_url).unwrap();
         Self {
             client: Client::new(),
             config_url: base_url.append_paths(&["root:", "slideshow.txt:", "content"]),
+            shares_url: base_url.join("../shares").unwrap(),
             base_url,
-            cache_directory,
+            cache: DiskCache::new(cache_directory),
+            cache_quality: None,
+            drive_type: DriveType::default(),
+            max_image_dimension: None,
+            listing_delay: Duration::ZERO,
+            conversions: ConversionPool::for_available_cpus(),
+            device_name: None,
         }
     }
 
-    async fn get_all_ids(&self, token: &str, first_url: Url) -> Result<Vec<DriveItem>> {
-        let response = self
-            .client
-            .get::<DriveResponse>(token, first_url)
-            .await
-            .with_context(|| "Get all items")?;
-        let mut items = response.value;
-        let mut next_url = response.next_link;
-        while let Some(url) = next_url {
-            let response = self
-                .client
-                .get::<DriveResponse>(
-                    token,
-                    Url::parse(&url).with_context(|| "Next link invalid")?,
-                )
-                .await
-                .with_context(|| "Get all items - next link")?;
-            next_url = response.next_link;
-            items.extend(response.value);
+    /// Applies the settings in slideshow.txt for this device's group of frames, if it is in one.
+    pub fn with_device_name(self, device_name: String) -> Self {
+        Self {
+            device_name: Some(device_name),
+            ..self
         }
-        Ok(items)
     }
 
-    pub async fn get_image_list(&self, token: &str) -> Result<(Vec<String>, u64)> {
-        let config = self
-            .client
-            .get::<Config>(token, self.config_url.clone())
-            .await
-            .with_context(|| "Get slideshow.txt")?;
-
-        let (image_sender, mut image_receiver) = unbounded_channel();
-        let (directory_sender, mut directory_receiver) = unbounded_channel();
-
-        let process_directory = |directory: String| {
-            let mut paths = directory.split('/').collect::<Vec<_>>();
-            paths.push("children");
-            let get_children_url = self.base_url.append_paths(&paths);
-
-            // Gather sub-directories to process.
-            let mut list_directories_url = get_children_url.clone();
-            list_directories_url.set_query(Some("$select=id&$filter=folder ne null&$top=999999"));
-            directory_sender
-                .send(self.get_all_ids(token, list_directories_url))
-                .ok()
-                .unwrap();
-
-            // Gather images.
-            let mut list_images_url = get_children_url;
-            list_images_url.set_query(Some("$select=id&$filter=image ne null&$top=999999"));
-            image_sender
-                .send(self.get_all_ids(token, list_images_url))
-                .ok()
-                .unwrap();
-        };
+    /// Changes what happens to loaded images when the disk is running low on space.
+    pub fn with_cache_policy(self, cache_policy: CachePolicy) -> Self {
+        Self {
+            cache: self.cache.with_policy(cache_policy),
+            ..self
+        }
+    }
+}
 
-        // Seed with initial directories.
-        for directory in config.directories {
-            process_directory(format!("root:/{directory}:"));
+impl<C: CacheStore> ImageLoader<C> {
+    /// Caches loaded images in `cache` instead of on disk.
+    pub fn with_cache<D: CacheStore>(self, cache: D) -> ImageLoader<D> {
+        let Self {
+            client,
+            base_url,
+            config_url,
+            shares_url,
+            cache: _,
+            cache_quality,
+            drive_type,
+            max_image_dimension,
+            listing_delay,
+            conversions,
+            device_name,
+        } = self;
+        ImageLoader {
+            client,
+            base_url,
+            config_url,
+            shares_url,
+            cache,
+            cache_quality,
+            drive_type,
+            max_image_dimension,
+            listing_delay,
+            conversions,
+            device_name,
         }
+    }
 
-        // Depth-first processing of directories...
-        while let Ok(directories) = directory_receiver.try_recv() {
-            for directory_item in directories.await.with_context(|| "Get sub-directories")? {
-                let id = directory_item.id;
-                process_directory(format!("items/{id}"));
-            }
+    /// Re-encodes images at the given JPEG quality (1-100) before caching them, trading some CPU
+    /// when downloading for a smaller cache.
+    pub fn with_cache_quality(self, quality: u8) -> Self {
+        Self {
+            cache_quality: Some(quality),
+            ..self
         }
+    }
 
-        let mut all_images = Vec::new();
-        while let Ok(images) = image_receiver.try_recv() {
-            all_images.extend(
-                images
-                    .await
-                    .with_context(|| "Get images")?
-                    .into_iter()
-                    .map(|DriveItem { id }| id),
-            )
+    /// Counts requests and downloads against a daily budget, so that only cached images are shown
+    /// once it's nearly used.
+    pub fn with_daily_budget(self, budget: Arc<DailyBudget>) -> Self {
+        Self {
+            client: Client::new().with_budget(budget),
+            ..self
+        }
+    }
+
+    /// If so much of the daily budget has been used that only cached images should be shown, how
+    /// long until it resets.
+    pub fn cache_only_for(&self) -> Option<Duration> {
+        self.client
+            .budget()
+            .filter(|budget| budget.is_nearly_used())
+            .map(DailyBudget::resets_in)
+    }
+
+    /// IDs of the images in the cache, which can be shown without downloading them.
+    pub fn cached_image_ids(&self) -> HashSet<String> {
+        self.cache.cached_ids()
+    }
+
+    /// Limits the resolution of images and throttles listing directories, for low-end hardware.
+    pub fn with_low_resource_limits(
+        self,
+        max_image_dimension: u32,
+        listing_delay: Duration,
+    ) -> Self {
+        Self {
+            max_image_dimension: Some(max_image_dimension),
+            listing_delay,
+            ..self
         }
+    }
 
-        Ok((all_images, config.interval))
+    /// Removes images from the cache that won't be shown again, such as those that aren't in
+    /// `listed` if it is given (e.g., because they have been deleted from OneDrive). Returns how
+    /// many were removed.
+    pub async fn collect_garbage(&self, listed: Option<&HashSet<String>>) -> Result<usize> {
+        self.cache.collect_garbage(listed).await
     }
 
-    pub async fn load_next(
+    /// Probes the type of the drive, since business drives don't support all of the queries that
+    /// personal drives do.
+    pub async fn detect_drive_type(&mut self, token: &str) -> Result<()> {
+        let mut drive_url = self.base_url.clone();
+        drive_url.set_query(Some("$select=driveType"));
+        let drive_info = self
+            .client
+            .get::<DriveInfo>(token, drive_url)
+            .await
+            .with_context(|| "Get drive type")?;
+        self.drive_type = drive_info.drive_type;
+        Ok(())
+    }
+
+    fn app_file_url(&self, file_name: &str) -> Url {
+        self.base_url
+            .append_paths(&["special", "approot:", &format!("{file_name}:")])
+    }
+
+    /// Writes a file into the app's folder in OneDrive.
+    pub async fn upload_app_file(
         &self,
         token: &str,
-        height: u32,
-        width: u32,
-        all_images: &[String],
-    ) -> Result<ColorImage> {
-        let index = rand::thread_rng().gen_range(0..all_images.len());
-        let image_id = all_images.get(index).unwrap();
-
-        let cache_path = self.cache_directory.join(image_id);
-        let data = if cache_path.exists() {
-            tokio::fs::read(cache_path)
-                .await
-                .with_context(|| "Reading cached image failed")?
-                .into()
-        } else {
-            let mut thumbnail_url = self
-                .base_url
-                .append_paths(&["items", image_id, "thumbnails"]);
-            thumbnail_url.set_query(Some(&format!("select=c{height}x{width}")));
-            let thumbnail_response = self
-                .client
-                .get::<ThumbnailResponse>(token, thumbnail_url)
-                .await
-                .with_context(|| "Get thumbnail")?;
-            let (_, ThumbnailItem { url: download_url }) = thumbnail_response
-                .value
-                .into_iter()
-                .next()
-                .ok_or(anyhow!("Bad thumbnail response"))?
-                .into_iter()
-                .next()
-                .ok_or(anyhow!("No thumbnail returned"))?;
-            let data = self
-                .client
-                .download(
-                    token,
-                    Url::parse(&download_url).with_context(|| "Download URL invalid")?,
-                )
-                .await
-                .with_context(|| "Downloading image failed")?;
-
-            if should_cache_image() {
-                if !self.cache_directory.exists() {
-                    tokio::fs::create_dir_all(&self.cache_directory)
-                        .await
-                        .with_context(|| "Create cache directory")?;
-                }
-                tokio::fs::write(&cache_path, &data)
-                    .await
-                    .with_context(|| "Store image in cache")?;
-            }
-
-            data
-        };
+        file_name: &str,
+        contents: impl Into<Bytes>,
+    ) -> Result<()> {
+        self.client
+            .put(
+                token,
+                self.app_file_url(file_name).append_path("content"),
+                contents.into(),
+            )
+            .await
+            .with_context(|| format!("Upload {file_name}"))
+    }
 
-        let image = image::load_from_memory(&data)
-            .map_err(|err| anyhow!(err).context("Image parsing failed"))?;
-        let size = [image.width() as _, image.height() as _];
-        let image_buffer = image.to_rgba8();
-        let pixels = image_buffer.as_flat_samples();
-        Ok(egui::ColorImage::from_rgba_unmultiplied(
-            size,
-            pixels.as_slice(),
-        ))
+    /// Checks if a file exists in the app's folder in OneDrive and, if it does, deletes it.
+    pub async fn take_app_file(&self, token: &str, file_name: &str) -> Result<bool> {
+        let file_url = self.app_file_url(file_name);
+        if !self
+            .client
+            .exists(token, file_url.clone())
+            .await
+            .with_context(|| format!("Check for {file_name}"))?
+        {
+            return Ok(false);
+        }
+        self.client
+            .delete(token, file_url)
+            .await
+            .with_context(|| format!("Delete {file_name}"))?;
+        Ok(true)
     }
-}
 
-fn should_cache_image() -> bool {
-    cfg_if::cfg_if! {
-        if #[cfg(test)] {
-            true
-        } else {
-            let disk_info = sys_info::disk_info().unwrap();
-            disk_info.free >= disk_info.total / 10
+    /// Gets the pages of items at `first_url`, one page at a time.
+    fn get_pages<'a>(&'a self, token: &'a str, first_url: Url) -> ItemPages<'a> {
+        ItemPages {
+            client: &self.client,
+            token,
+            next_url: Some(first_url),
+            is_first_page: true,
         }
     }
+
+    /// Lists the sub-directories and images directly within `directory`.
+    async fn list_directory(
+        &self,
+        token: &str,
+        directory: &str,
+    ) -> Result<(Vec<DriveItem>, Vec<DriveItem>)> {
+        let mut paths = directory.split('/').collect::<Vec<_>>();
+        paths.push("children");
+        let get_children_url = self.base_url.append_paths(&paths);
+
+        match self.drive_type {
+            DriveType::Personal => {
+                // Gather sub-directories to process.
+                let mut list_directories_url = get_children_url.clone();
+                list_directories_url.set_query(Some(&format!(
+                    "$select=id,name&$filter=folder ne null&$top={LISTING_PAGE_SIZE}"
+                )));
+                let mut pages = self.get_pages(token, list_directories_url);
+                let mut sub_directories = Vec::new();
+                while let Some(page) = pages.next().await.with_context(|| "Get sub-directories")? {
+                    sub_directories.extend(page);
+                }
+
+                // Gather images.
+                let mut list_images_url = get_children_url;
+                list_images_url.set_query(Some(&format!(
+                    "$select=id,name,image,photo,location,lastModifiedDateTime&$filter=image ne null&$top={LISTING_PAGE_SIZE}"
+                )));
+                let mut pages = self.get_pages(token, list_images_url);
+                let mut images = Vec::new();
+                while let Some(page) = pages.next().await.with_context(|| "Get images")? {
+                    images.extend(page);
+                }
+
+                Ok((sub_directories, images))
+            }
+            DriveType::Business => {
+                // Business drives can't filter on facets, so list everything and filter locally.
+                // This is done a page at a time so that huge folders of other files don't all need
+                // to be held in memory.
+                let mut list_children_url = get_children_url;
+                list_children_url.set_query(Some(&format!(
+                    "$select=id,name,folder,image,file,photo,location,lastModifiedDateTime&$top={LISTING_PAGE_SIZE}"
+                )));
+                let mut pages = self.get_pages(token, list_children_url);
+                let mut sub_directories = Vec::new();
+                let mut images = Vec::new();
+                while let Some(page) = pages.next().await.with_context(|| "Get children")? {
+                    for item in page {
+                        if item.folder.is_some() {
+                            sub_directories.push(item);
+                        } else if item.is_image() {
+                            images.push(item);
+                        }
+                    }
+                }
+
+                Ok((sub_directories, images))
+            }
+        }
+    }
+
+    /// Creates slideshow.txt with a sample configuration that shows the Pictures folder.
+    pub async fn create_sample_config(&self, token: &str) -> Result<()> {
+        self.client
+            .put(token, self.config_url.clone(), SAMPLE_CONFIG_FILE.into())
+            .await
+            .with_context(|| "Create slideshow.txt")
+    }
+
+    /// Reads slideshow.txt from the root of the drive, then lists the images in its directories.
+    /// Fails with a `ConfigError` if slideshow.txt is missing or invalid.
+    pub async fn get_image_list(&self, token: &str) -> Result<(Listing, Config)> {
+        let content = self
+            .client
+            .get_if_exists(token, self.config_url.clone())
+            .await
+            .with_context(|| "Get slideshow.txt")?
+            .ok_or(ConfigError::Missing)?;
+        let mut config = Config::parse(&content)?;
+        if let Some(device_name) = &self.device_name {
+            config.apply_frame_group(device_name);
+        }
+
+        let listing = self.list_images(token, &config.directories).await?;
+        Ok((listing, config))
+    }
+
+    /// Resolves a sharing link to the id of the item that it refers to.
+    pub async fn resolve_share_url(&self, token: &str, share_url: &str) -> Result<String> {
+        let share_id = format!("u!{}", URL_SAFE_NO_PAD.encode(share_url));
+        let mut item_url = self
+            .shares_url
+            .append_paths(&[share_id.as_str(), "driveItem"]);
+        item_url.set_query(Some("$select=id"));
+        let item = self
+            .client
+            .get::<DriveItem>(token, item_url)
+            .await
+            .with_context(|| "Resolve share link")?;
+        Ok(item.id)
+    }
+
+    /// Lists the images at `path`, which may either be a single image or a folder of images.
+    pub async fn list_images_at(&self, token: &str, path: &str) -> Result<Vec<Image>> {
+        let item_path = format!("root:/{path}:");
+        let mut item_url = self
+            .base_url
+            .append_paths(&item_path.split('/').collect::<Vec<_>>());
+        item_url.set_query(Some(
+            "$select=id,name,folder,image,file,photo,location,lastModifiedDateTime",
+        ));
+        let item = self
+            .client
+            .get::<DriveItem>(token, item_url)
+            .await
+            .with_context(|| "Get item")?;
+
+        if item.folder.is_some() {
+            Ok(self.list_images(token, &[path.to_string()]).await?.images)
+        } else if item.is_image() {
+            let folder = path.rsplit_once('/').map_or("", |(folder, _)| folder);
+            Ok(vec![Image::new(item, &Arc::from(path), &Arc::from(folder))])
+        } else {
+            bail!("{path} is not an image or a folder")
+        }
+    }
+
+    /// Lists a directory that previously failed, and everything under it.
+    pub async fn retry_failed_directory(&self, token: &str, failed: FailedDirectory) -> Listing {
+        self.list_paths(
+            token,
+            vec![PendingDirectory {
+                path: failed.path,
+                directory: failed.directory,
+                folder: failed.folder,
+            }],
+        )
+        .await
+    }
+
+    /// Recursively lists all the images within the directories at `directories`.
+    async fn list_paths(&self, token: &str, mut directories: Vec<PendingDirectory>) -> Listing {
+        // Depth-first processing of directories...
+        let mut listing = Listing::default();
+        while let Some(PendingDirectory {
+            path,
+            directory,
+            folder,
+        }) = directories.pop()
+        {
+            if !self.listing_delay.is_zero() {
+                tokio::time::sleep(self.listing_delay).await;
+            }
+            let (sub_directories, images) = match self.list_directory(token, &path).await {
+                Ok(children) => children,
+                // Only the directories that were asked for are looked up by path, so only they can
+                // be missing because of a typo.
+                Err(err) if path.starts_with("root:/") && is_not_found(&err) => {
+                    listing.missing_directories.push(directory.to_string());
+                    continue;
+                }
+                // Keep going, so that one failing directory doesn't lose everything else.
+                Err(error) => {
+                    listing.failed_directories.push(FailedDirectory {
+                        path,
+                        directory,
+                        folder,
+                        error,
+                    });
+                    continue;
+                }
+            };
+            directories.extend(sub_directories.into_iter().map(|item| PendingDirectory {
+                path: format!("items/{}", item.id),
+                directory: directory.clone(),
+                folder: format!("{folder}/{}", item.name.as_deref().unwrap_or(&item.id)).into(),
+            }));
+            listing.images.extend(
+                images
+                    .into_iter()
+                    .map(|item| Image::new(item, &directory, &folder)),
+            );
+        }
+
+        listing
+    }
+
+    /// Loads an image, sized to fit within `width` by `height`, and decodes it.
+    pub async fn load_image(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<ColorImage> {
+        let data = self.load_image_data(token, height, width, image_id).await?;
+        self.convert(data).await
+    }
+
+    /// Decodes an image that has already been loaded, in the background.
+    pub async fn convert(&self, data: Bytes) -> Result<ColorImage> {
+        let max_dimension = self.max_image_dimension;
+        self.conversions
+            .convert(move || decode_image_within(&data, max_dimension))
+            .await
+            .wait()
+            .await
+    }
+
+    /// Gets the encoded image, either from the cache or by downloading it.
+    pub async fn load_image_data(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<Bytes> {
+        let (height, width) = match self.max_image_dimension {
+            Some(max) if height.max(width) > max => {
+                let scale = f64::from(max) / f64::from(height.max(width));
+                (
+                    (f64::from(height) * scale) as u32,
+                    (f64::from(width) * scale) as u32,
+                )
+            }
+            _ => (height, width),
+        };
+        let (data, is_thumbnail) = match self.cache.read(image_id).await? {
+            Some(data) => (data, false),
+            None => {
+                let (data, is_thumbnail) = self.download(token, height, width, image_id).await?;
+                self.store_in_cache(image_id, &data).await?;
+                (data, is_thumbnail)
+            }
+        };
+
+        // An image that is too large to decode with the memory that is available (e.g., a panorama
+        // that was cached at its original size) is replaced by OneDrive's thumbnail of it.
+        let Some(Err(err)) = image_dimensions(&data).map(|(image_width, image_height)| {
+            check_decoding_memory(image_width, image_height, available_memory_kb())
+        }) else {
+            return Ok(data);
+        };
+        let thumbnail_url = if is_thumbnail {
+            None
+        } else {
+            self.thumbnail_url(token, height, width, image_id).await?
+        };
+        let Some(thumbnail_url) = thumbnail_url else {
+            return Err(err);
+        };
+        let data = self
+            .client
+            .download(token, thumbnail_url)
+            .await
+            .with_context(|| "Downloading image failed")?;
+        self.store_in_cache(image_id, &data).await?;
+        Ok(data)
+    }
+
+    /// Downloads OneDrive's thumbnail of an image, sized to fit within `width` by `height`, or the
+    /// original if it can't make one. Returns the image and whether it is a thumbnail.
+    async fn download(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<(Bytes, bool)> {
+        // OneDrive can't make thumbnails of some images (e.g., some RAW formats), so the original
+        // has to be downloaded instead. Any other failure (e.g., a timeout or a rejected token) is
+        // left to the caller, as downloading the original would likely fail too.
+        let thumbnail_url = match self.thumbnail_url(token, height, width, image_id).await {
+            Ok(url) => url,
+            Err(err) if is_thumbnail_unavailable(&err) => None,
+            Err(err) => return Err(err),
+        };
+        let is_thumbnail = thumbnail_url.is_some();
+        let download_url = thumbnail_url
+            .unwrap_or_else(|| self.base_url.append_paths(&["items", image_id, "content"]));
+        let data = self
+            .client
+            .download(token, download_url)
+            .await
+            .with_context(|| "Downloading image failed")?;
+        Ok((data, is_thumbnail))
+    }
+
+    /// Gets the URL of OneDrive's thumbnail of an image, or `None` if it didn't return one.
+    async fn thumbnail_url(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<Option<Url>> {
+        let mut thumbnail_url = self
+            .base_url
+            .append_paths(&["items", image_id, "thumbnails"]);
+        thumbnail_url.set_query(Some(&format!("select=c{height}x{width}")));
+        let thumbnail_response = self
+            .client
+            .get::<ThumbnailResponse>(token, thumbnail_url)
+            .await
+            .with_context(|| "Get thumbnail")?;
+        thumbnail_response
+            .value
+            .into_iter()
+            .next()
+            .and_then(|sizes| sizes.into_values().next())
+            .map(|ThumbnailItem { url }| Url::parse(&url).with_context(|| "Download URL invalid"))
+            .transpose()
+    }
+
+    /// Writes a downloaded image to the cache, if there is room for it.
+    async fn store_in_cache(&self, image_id: &str, data: &[u8]) -> Result<()> {
+        if self.cache.make_room().await? {
+            // Images that can't be compressed are cached as they are.
+            let compressed = self
+                .cache_quality
+                .and_then(|quality| compress_for_cache(data, quality).ok().flatten());
+            self.cache
+                .store(image_id, compressed.as_deref().unwrap_or(data))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Decodes an image straight away, within any limits on the size of images.
+    pub fn decode(&self, data: &[u8]) -> Result<ColorImage> {
+        decode_image_within(data, self.max_image_dimension)
+    }
+}
+
+impl<C: CacheStore> PhotoSource for ImageLoader<C> {
+    /// Recursively lists all the images within `directories`, skipping any that don't exist. If
+    /// some directories can't be listed, then the images from the rest are still returned, unless
+    /// none could be listed at all.
+    async fn list_images(&self, token: &str, directories: &[String]) -> Result<Listing> {
+        // Seed with initial directories, remembering which one each sub-directory came from.
+        let directories = directories
+            .iter()
+            .map(|directory| {
+                let directory = Arc::<str>::from(directory.as_str());
+                PendingDirectory {
+                    path: format!("root:/{directory}:"),
+                    directory: directory.clone(),
+                    folder: directory,
+                }
+            })
+            .collect();
+
+        let mut listing = self.list_paths(token, directories).await;
+        if listing.images.is_empty() && !listing.failed_directories.is_empty() {
+            return Err(listing.failed_directories.swap_remove(0).error);
+        }
+        Ok(listing)
+    }
+
+    /// Downloads OneDrive's thumbnail of the image, or the original if it can't make one. Unlike
+    /// [`ImageLoader::load_image_data`], this doesn't use the cache.
+    async fn download_image(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<Bytes> {
+        self.download(token, height, width, image_id)
+            .await
+            .map(|(data, _)| data)
+    }
+}
+
+/// Pages of a listing from OneDrive, which are only fetched as they are needed.
+struct ItemPages<'a> {
+    client: &'a Client,
+    token: &'a str,
+    next_url: Option<Url>,
+    is_first_page: bool,
+}
+
+impl ItemPages<'_> {
+    /// Gets the next page of items, or `None` once all pages have been returned.
+    async fn next(&mut self) -> Result<Option<Vec<DriveItem>>> {
+        let Some(url) = self.next_url.take() else {
+            return Ok(None);
+        };
+        let context = if std::mem::take(&mut self.is_first_page) {
+            "Get all items"
+        } else {
+            "Get all items - next link"
+        };
+        let response = self
+            .client
+            .get::<DriveResponse>(self.token, url)
+            .await
+            .with_context(|| context)?;
+        self.next_url = response
+            .next_link
+            .map(|next_link| Url::parse(&next_link))
+            .transpose()
+            .with_context(|| "Next link invalid")?;
+        Ok(Some(response.value))
+    }
+}
+
+/// Decodes an image at its full size.
+pub fn decode_image(data: &[u8]) -> Result<ColorImage> {
+    decode_image_within(data, None)
+}
+
+/// Picks a random image to show.
+pub fn choose_image(images: &[Image]) -> Result<&Image> {
+    if images.is_empty() {
+        bail!("No images found");
+    }
+    let index = rand::thread_rng().gen_range(0..images.len());
+    Ok(&images[index])
+}
+
+/// Re-encodes an image as a JPEG at the given quality, or returns `None` if that wouldn't make it
+/// any smaller.
+fn compress_for_cache(data: &[u8], quality: u8) -> Result<Option<Vec<u8>>> {
+    // The EXIF data isn't kept, so the image has to be turned upright before it is re-encoded.
+    let image = decode_upright(data)?;
+    let mut compressed = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut compressed, quality)
+        .encode_image(&image.to_rgb8())
+        .with_context(|| "Encoding image failed")?;
+    Ok((compressed.len() < data.len()).then_some(compressed))
+}
+
+/// Decodes an image, turning it upright if its EXIF data says that the camera was held on its side
+/// or upside down (e.g., portrait photos taken on a phone).
+fn decode_upright(data: &[u8]) -> Result<image::DynamicImage> {
+    let parse_error = |err: image::ImageError| anyhow!(err).context("Image parsing failed");
+    let mut decoder = image::ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()
+        .map_err(parse_error)?;
+    // Images without a (readable) orientation are assumed to be upright already.
+    let orientation = decoder
+        .orientation()
+        .unwrap_or(image::metadata::Orientation::NoTransforms);
+    let (width, height) = decoder.dimensions();
+    check_decoding_memory(width, height, available_memory_kb())?;
+    let mut image = image::DynamicImage::from_decoder(decoder).map_err(parse_error)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+/// Reads the size of an encoded image without decoding it.
+fn image_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::ImageReader::new(std::io::Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+/// Decodes an image, shrinking it if either dimension is larger than `max_dimension`.
+fn decode_image_within(data: &[u8], max_dimension: Option<u32>) -> Result<ColorImage> {
+    let image = decode_upright(data)?;
+    // Images in the cache may have been downloaded at a larger size before the limit was set.
+    let image = match max_dimension {
+        Some(max) if image.width() > max || image.height() > max => image.thumbnail(max, max),
+        _ => image,
+    };
+    let size = [image.width() as _, image.height() as _];
+    let image_buffer = image.to_rgba8();
+    let pixels = image_buffer.as_flat_samples();
+    Ok(egui::ColorImage::from_rgba_unmultiplied(
+        size,
+        pixels.as_slice(),
+    ))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_images() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let config_content_redirect_mock = server
+        .mock("GET", "/root:/slideshow.txt:/content")
+        .match_header("authorization", "Bearer token")
+        .with_status(302)
+        .with_header("location", &format!("{url}/slideshow.txt"))
+        .expect(1)
+        .create();
+
+    let config_content_mock = server
+        .mock("GET", "/slideshow.txt")
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "directories": [ "d1", "d2" ], "interval": 42 } "#)
+        .expect(1)
+        .create();
+
+    let folder_query = mockito::Matcher::AllOf(vec![
+        mockito::Matcher::UrlEncoded("$select".into(), "id,name".into()),
+        mockito::Matcher::UrlEncoded("$filter".into(), "folder ne null".into()),
+    ]);
+    let image_query = mockito::Matcher::AllOf(vec![
+        mockito::Matcher::UrlEncoded(
+            "$select".into(),
+            "id,name,image,photo,location,lastModifiedDateTime".into(),
+        ),
+        mockito::Matcher::UrlEncoded("$filter".into(), "image ne null".into()),
+    ]);
+
+    let d1_folder_mock = server
+        .mock("GET", "/root:/d1:/children")
+        .match_query(folder_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(format!(
+            r#"{{ "@odata.nextLink": "{url}/d1_folder_next", "value": [ {{ "id": "d1_1", "name": "Vacations 2023" }} ] }}"#
+        ))
+        .expect(1)
+        .create();
+    let d1_folder_next_mock = server
+        .mock("GET", "/d1_folder_next")
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "d1_2" } ] }"#)
+        .expect(1)
+        .create();
+    let d1_images_mock = server
+        .mock("GET", "/root:/d1:/children")
+        .match_query(image_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(format!(
+            r#"{{ "@odata.nextLink": "{url}/d1_image_next", "value": [ {{ "id": "d1_3" }} ] }}"#
+        ))
+        .expect(1)
+        .create();
+    let d1_image_next_mock = server
+        .mock("GET", "/d1_image_next")
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "d1_4" } ] }"#)
+        .expect(1)
+        .create();
+
+    let d2_folder_mock = server
+        .mock("GET", "/root:/d2:/children")
+        .match_query(folder_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+    let d2_image_mock = server
+        .mock("GET", "/root:/d2:/children")
+        .match_query(image_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(
+            r#"{ "value": [ { "id": "d2_1", "image": { "width": 4000, "height": 1000 }, "photo": { "takenDateTime": "2023-06-05T14:30:00Z" }, "lastModifiedDateTime": "2024-01-02T03:04:05Z", "location": { "latitude": 38.72, "longitude": -9.14, "altitude": 10.0 } } ] }"#,
+        )
+        .expect(1)
+        .create();
+
+    let d1_1_folder_mock = server
+        .mock("GET", "/items/d1_1/children")
+        .match_query(folder_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+    let d1_1_image_mock = server
+        .mock("GET", "/items/d1_1/children")
+        .match_query(image_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "d1_1_1" } ] }"#)
+        .expect(1)
+        .create();
+
+    let d1_2_folder_mock = server
+        .mock("GET", "/items/d1_2/children")
+        .match_query(folder_query)
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+    let d1_2_image_next_mock = server
+        .mock("GET", "/items/d1_2/children")
+        .match_query(image_query)
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "d1_2_1" } ] }"#)
+        .expect(1)
+        .create();
+
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/list_images");
+    let image_loader = ImageLoader::new(&url, temp_dir);
+    let (
+        Listing {
+            images: all_images, ..
+        },
+        config,
+    ) = image_loader.get_image_list("token").await.unwrap();
+    assert_eq!(config.interval, 42);
+    assert_eq!(config.refresh_window, None);
+    assert_eq!(
+        all_images.iter().find(|image| image.id == "d2_1").unwrap(),
+        &Image {
+            id: "d2_1".into(),
+            name: None,
+            directory: "d2".into(),
+            folder: "d2".into(),
+            width: Some(4000),
+            height: Some(1000),
+            taken: Some("2023-06-05T14:30:00Z".parse().unwrap()),
+            modified: Some("2024-01-02T03:04:05Z".parse().unwrap()),
+            location: Some(GeoCoordinates {
+                latitude: 38.72,
+                longitude: -9.14,
+            }),
+        }
+    );
+    assert!(all_images
+        .iter()
+        .filter(|image| image.id.starts_with("d1"))
+        .all(|image| &*image.directory == "d1"));
+    let vacation_image = all_images
+        .iter()
+        .find(|image| image.id == "d1_1_1")
+        .unwrap();
+    assert_eq!(&*vacation_image.folder, "d1/Vacations 2023");
+    assert_eq!(vacation_image.folder_name(), "Vacations 2023");
+    let mut all_images = all_images
+        .into_iter()
+        .map(|image| image.id)
+        .collect::<Vec<_>>();
+    all_images.sort();
+    assert_eq!(&all_images, &["d1_1_1", "d1_2_1", "d1_3", "d1_4", "d2_1"]);
+
+    config_content_redirect_mock.assert();
+    config_content_mock.assert();
+    d1_folder_mock.assert();
+    d1_folder_next_mock.assert();
+    d1_images_mock.assert();
+    d1_image_next_mock.assert();
+    d2_folder_mock.assert();
+    d2_image_mock.assert();
+    d1_1_folder_mock.assert();
+    d1_1_image_mock.assert();
+    d1_2_folder_mock.assert();
+    d1_2_image_next_mock.assert();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_images_business() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let drive_mock = server
+        .mock("GET", "/")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$select".into(),
+            "driveType".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "driveType": "business" }"#)
+        .expect(1)
+        .create();
+
+    let config_content_mock = server
+        .mock("GET", "/root:/slideshow.txt:/content")
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "directories": [ "d1" ], "interval": 42 } "#)
+        .expect(1)
+        .create();
+
+    // Business drives list all children without filtering.
+    let children_query = mockito::Matcher::UrlEncoded(
+        "$select".into(),
+        "id,name,folder,image,file,photo,location,lastModifiedDateTime".into(),
+    );
+    let d1_children_mock = server
+        .mock("GET", "/root:/d1:/children")
+        .match_query(children_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(
+            r#"{ "value": [
+                { "id": "d1_1", "folder": { "childCount": 1 } },
+                { "id": "d1_2", "image": { } },
+                { "id": "d1_3", "file": { "mimeType": "image/jpeg" } },
+                { "id": "d1_4", "file": { "mimeType": "text/plain" } }
+            ] }"#,
+        )
+        .expect(1)
+        .create();
+    let d1_1_children_mock = server
+        .mock("GET", "/items/d1_1/children")
+        .match_query(children_query)
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "d1_1_1", "image": { "width": 1 } } ] }"#)
+        .expect(1)
+        .create();
+
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/list_images_business");
+    let mut image_loader = ImageLoader::new(&url, temp_dir);
+    image_loader.detect_drive_type("token").await.unwrap();
+    assert_eq!(image_loader.drive_type, DriveType::Business);
+    let (
+        Listing {
+            images: all_images, ..
+        },
+        _,
+    ) = image_loader.get_image_list("token").await.unwrap();
+    let mut all_images = all_images
+        .into_iter()
+        .map(|image| image.id)
+        .collect::<Vec<_>>();
+    all_images.sort();
+    assert_eq!(&all_images, &["d1_1_1", "d1_2", "d1_3"]);
+
+    drive_mock.assert();
+    config_content_mock.assert();
+    d1_children_mock.assert();
+    d1_1_children_mock.assert();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_images_at() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let item_query = mockito::Matcher::UrlEncoded(
+        "$select".into(),
+        "id,name,folder,image,file,photo,location,lastModifiedDateTime".into(),
+    );
+    let image_mock = server
+        .mock("GET", "/root:/pinned.jpg:")
+        .match_query(item_query.clone())
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "id": "pinned_image", "name": "pinned.jpg", "image": { } }"#)
+        .expect(1)
+        .create();
+    let folder_mock = server
+        .mock("GET", "/root:/pinned:")
+        .match_query(item_query)
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "id": "pinned_folder", "folder": { } }"#)
+        .expect(1)
+        .create();
+    let folder_children_mock = server
+        .mock("GET", "/root:/pinned:/children")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$filter".into(),
+            "image ne null".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "pinned_1" } ] }"#)
+        .expect(1)
+        .create();
+    let sub_folder_mock = server
+        .mock("GET", "/root:/pinned:/children")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$filter".into(),
+            "folder ne null".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/list_images_at");
+    let image_loader = ImageLoader::new(&url, temp_dir);
+    assert_eq!(
+        image_loader
+            .list_images_at("token", "pinned.jpg")
+            .await
+            .unwrap(),
+        &[Image {
+            name: Some("pinned.jpg".into()),
+            directory: "pinned.jpg".into(),
+            ..Image::with_id("pinned_image")
+        }]
+    );
+    assert_eq!(
+        image_loader
+            .list_images_at("token", "pinned")
+            .await
+            .unwrap(),
+        &[Image {
+            directory: "pinned".into(),
+            folder: "pinned".into(),
+            ..Image::with_id("pinned_1")
+        }]
+    );
+
+    image_mock.assert();
+    folder_mock.assert();
+    folder_children_mock.assert();
+    sub_folder_mock.assert();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn load_image() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/load_image");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let thumbnail_mock = server
+        .mock("GET", "/items/1/thumbnails")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "select".into(),
+            "c1024x768".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(format!(
+            r#"{{ "value": [ {{ "c1024x768": {{ "url": "{url}/download" }} }} ] }} "#
+        ))
+        .expect(1)
+        .create();
+
+    let mut image_data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
+        .encode_image(&image::RgbImage::new(1, 1))
+        .unwrap();
+    let download_mock = server
+        .mock("GET", "/download")
+        .with_body(image_data)
+        .expect(1)
+        .create();
+
+    let image_loader =
+        ImageLoader::new(&url, temp_dir.clone()).with_cache_policy(CachePolicy::Always);
+    let actual_image = image_loader
+        .load_image("token", 1024, 768, "1")
+        .await
+        .unwrap();
+    assert_eq!(actual_image.height(), 1);
+    assert_eq!(actual_image.width(), 1);
+    thumbnail_mock.assert();
+    download_mock.assert();
+    assert_eq!(image_loader.cached_image_ids(), HashSet::from(["1".into()]));
+    assert!(!temp_dir.join("1.tmp").exists());
+
+    // Loading again should use the cached image.
+    thumbnail_mock.remove();
+    download_mock.remove();
+    let actual_image = image_loader
+        .load_image("token", 1024, 768, "1")
+        .await
+        .unwrap();
+    assert_eq!(actual_image.height(), 1);
+    assert_eq!(actual_image.width(), 1);
+
+    // But loading a different image will download again.
+    let thumbnail_mock = server
+        .mock("GET", "/items/2/thumbnails")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "select".into(),
+            "c1024x768".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(format!(
+            r#"{{ "value": [ {{ "c1024x768": {{ "url": "{url}/download" }} }} ] }} "#
+        ))
+        .expect(1)
+        .create();
+
+    let mut image_data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
+        .encode_image(&image::RgbImage::new(2, 2))
+        .unwrap();
+    let download_mock = download_mock.with_body(image_data).create();
+    let actual_image = image_loader
+        .load_image("token", 1024, 768, "2")
+        .await
+        .unwrap();
+    assert_eq!(actual_image.height(), 2);
+    assert_eq!(actual_image.width(), 2);
+    thumbnail_mock.assert();
+    download_mock.assert();
+
+    // Without a thumbnail, the original is downloaded instead.
+    let thumbnail_mock = server
+        .mock("GET", "/items/3/thumbnails")
+        .match_query(mockito::Matcher::Any)
+        .with_body(r#"{ "value": [] }"#)
+        .expect(1)
+        .create();
+    let mut image_data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
+        .encode_image(&image::RgbImage::new(3, 3))
+        .unwrap();
+    let content_mock = server
+        .mock("GET", "/items/3/content")
+        .match_header("authorization", "Bearer token")
+        .with_body(image_data)
+        .expect(1)
+        .create();
+    let actual_image = image_loader
+        .load_image("token", 1024, 768, "3")
+        .await
+        .unwrap();
+    assert_eq!(actual_image.height(), 3);
+    thumbnail_mock.assert();
+    content_mock.assert();
+
+    // Nor if OneDrive refuses to make one.
+    let thumbnail_mock = server
+        .mock("GET", "/items/4/thumbnails")
+        .match_query(mockito::Matcher::Any)
+        .with_status(400)
+        .expect(1)
+        .create();
+    let mut image_data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
+        .encode_image(&image::RgbImage::new(4, 4))
+        .unwrap();
+    let content_mock = server
+        .mock("GET", "/items/4/content")
+        .with_body(image_data)
+        .expect(1)
+        .create();
+    let actual_image = image_loader
+        .load_image("token", 1024, 768, "4")
+        .await
+        .unwrap();
+    assert_eq!(actual_image.height(), 4);
+    thumbnail_mock.assert();
+    content_mock.assert();
+
+    // But if the token was rejected, downloading the original would be too.
+    let thumbnail_mock = server
+        .mock("GET", "/items/5/thumbnails")
+        .match_query(mockito::Matcher::Any)
+        .with_status(401)
+        .expect(1)
+        .create();
+    let content_mock = server.mock("GET", "/items/5/content").expect(0).create();
+    let err = image_loader
+        .load_image("token", 1024, 768, "5")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<GraphError>(),
+        Some(GraphError::Unauthorized(_))
+    ));
+    thumbnail_mock.assert();
+    content_mock.assert();
+
+    // Nor if OneDrive is failing, as that isn't about the image.
+    let thumbnail_mock = server
+        .mock("GET", "/items/6/thumbnails")
+        .match_query(mockito::Matcher::Any)
+        .with_status(500)
+        .expect_at_least(1)
+        .create();
+    let content_mock = server.mock("GET", "/items/6/content").expect(0).create();
+    image_loader
+        .load_image("token", 1024, 768, "6")
+        .await
+        .unwrap_err();
+    thumbnail_mock.assert();
+    content_mock.assert();
+}
+
+#[cfg(test)]
+#[derive(Default)]
+struct MemoryCache(std::sync::Mutex<std::collections::HashMap<String, Bytes>>);
+
+#[cfg(test)]
+impl CacheStore for MemoryCache {
+    fn cached_ids(&self) -> HashSet<String> {
+        self.0.lock().unwrap().keys().cloned().collect()
+    }
+
+    async fn read(&self, image_id: &str) -> Result<Option<Bytes>> {
+        Ok(self.0.lock().unwrap().get(image_id).cloned())
+    }
+
+    async fn make_room(&self) -> Result<bool> {
+        Ok(true)
+    }
+
+    async fn store(&self, image_id: &str, data: &[u8]) -> Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(image_id.to_string(), Bytes::copy_from_slice(data));
+        Ok(())
+    }
+
+    async fn collect_garbage(&self, _listed: Option<&HashSet<String>>) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn load_image_with_custom_cache() {
+    let temp_dir =
+        std::env::temp_dir().join("onedrive_slideshow_test/load_image_with_custom_cache");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let thumbnail_mock = server
+        .mock("GET", "/items/1/thumbnails")
+        .match_query(mockito::Matcher::Any)
+        .with_body(format!(
+            r#"{{ "value": [ {{ "c1024x768": {{ "url": "{url}/download" }} }} ] }} "#
+        ))
+        .expect(1)
+        .create();
+    let mut image_data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
+        .encode_image(&image::RgbImage::new(1, 1))
+        .unwrap();
+    let download_mock = server
+        .mock("GET", "/download")
+        .with_body(image_data)
+        .expect(1)
+        .create();
+
+    // Loading twice only downloads once, and nothing is written to the cache directory.
+    let image_loader = ImageLoader::new(&url, temp_dir.clone()).with_cache(MemoryCache::default());
+    for _ in 0..2 {
+        let actual_image = image_loader
+            .load_image("token", 1024, 768, "1")
+            .await
+            .unwrap();
+        assert_eq!(actual_image.width(), 1);
+    }
+    thumbnail_mock.assert();
+    download_mock.assert();
+    assert!(!temp_dir.exists());
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn list_images() {
+async fn resolve_share_url() {
     let mut server = mockito::Server::new_async().await;
     let url = server.url();
 
-    let config_content_redirect_mock = server
-        .mock("GET", "/root:/slideshow.txt:/content")
+    let share_mock = server
+        .mock(
+            "GET",
+            "/shares/u!aHR0cHM6Ly8xZHJ2Lm1zL2kvcyFBYkNkRWY_ZT14MQ/driveItem",
+        )
+        .match_query(mockito::Matcher::UrlEncoded("$select".into(), "id".into()))
         .match_header("authorization", "Bearer token")
-        .with_status(302)
-        .with_header("location", &format!("{url}/slideshow.txt"))
+        .with_body(r#"{ "id": "shared_item" }"#)
         .expect(1)
         .create();
 
-    let config_content_mock = server
-        .mock("GET", "/slideshow.txt")
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "directories": [ "d1", "d2" ], "interval": 42 } "#)
-        .expect(1)
-        .create();
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/resolve_share_url");
+    let image_loader = ImageLoader::new(&url, temp_dir);
+    let id = image_loader
+        .resolve_share_url("token", "https://1drv.ms/i/s!AbCdEf?e=x1")
+        .await
+        .unwrap();
+    assert_eq!(id, "shared_item");
+    share_mock.assert();
+}
 
-    let folder_query = mockito::Matcher::AllOf(vec![
-        mockito::Matcher::UrlEncoded("$select".into(), "id".into()),
-        mockito::Matcher::UrlEncoded("$filter".into(), "folder ne null".into()),
-    ]);
-    let image_query = mockito::Matcher::AllOf(vec![
-        mockito::Matcher::UrlEncoded("$select".into(), "id".into()),
-        mockito::Matcher::UrlEncoded("$filter".into(), "image ne null".into()),
-    ]);
+#[tokio::test(flavor = "multi_thread")]
+async fn upload_app_file() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
 
-    let d1_folder_mock = server
-        .mock("GET", "/root:/d1:/children")
-        .match_query(folder_query.clone())
-        .match_header("authorization", "Bearer token")
-        .with_body(format!(
-            r#"{{ "@odata.nextLink": "{url}/d1_folder_next", "value": [ {{ "id": "d1_1" }} ] }}"#
-        ))
-        .expect(1)
-        .create();
-    let d1_folder_next_mock = server
-        .mock("GET", "/d1_folder_next")
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ { "id": "d1_2" } ] }"#)
-        .expect(1)
-        .create();
-    let d1_images_mock = server
-        .mock("GET", "/root:/d1:/children")
-        .match_query(image_query.clone())
+    let upload_mock = server
+        .mock("PUT", "/special/approot:/status.json:/content")
         .match_header("authorization", "Bearer token")
-        .with_body(format!(
-            r#"{{ "@odata.nextLink": "{url}/d1_image_next", "value": [ {{ "id": "d1_3" }} ] }}"#
+        .match_body(mockito::Matcher::Json(
+            serde_json::json!({ "healthy": true }),
         ))
         .expect(1)
         .create();
-    let d1_image_next_mock = server
-        .mock("GET", "/d1_image_next")
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ { "id": "d1_4" } ] }"#)
-        .expect(1)
-        .create();
 
-    let d2_folder_mock = server
-        .mock("GET", "/root:/d2:/children")
-        .match_query(folder_query.clone())
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ ] }"#)
-        .expect(1)
-        .create();
-    let d2_image_mock = server
-        .mock("GET", "/root:/d2:/children")
-        .match_query(image_query.clone())
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ { "id": "d2_1" } ] }"#)
-        .expect(1)
-        .create();
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/upload_app_file");
+    let image_loader = ImageLoader::new(&url, temp_dir);
+    image_loader
+        .upload_app_file("token", "status.json", r#"{ "healthy": true }"#)
+        .await
+        .unwrap();
+    upload_mock.assert();
+}
 
-    let d1_1_folder_mock = server
-        .mock("GET", "/items/d1_1/children")
-        .match_query(folder_query.clone())
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ ] }"#)
-        .expect(1)
-        .create();
-    let d1_1_image_mock = server
-        .mock("GET", "/items/d1_1/children")
-        .match_query(image_query.clone())
-        .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ { "id": "d1_1_1" } ] }"#)
-        .expect(1)
-        .create();
+#[tokio::test(flavor = "multi_thread")]
+async fn take_app_file() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
 
-    let d1_2_folder_mock = server
-        .mock("GET", "/items/d1_2/children")
-        .match_query(folder_query)
+    let exists_mock = server
+        .mock("GET", "/special/approot:/flag.txt:")
         .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ ] }"#)
+        .with_body(r#"{ "id": "flag" }"#)
         .expect(1)
         .create();
-    let d1_2_image_next_mock = server
-        .mock("GET", "/items/d1_2/children")
-        .match_query(image_query)
+    let delete_mock = server
+        .mock("DELETE", "/special/approot:/flag.txt:")
         .match_header("authorization", "Bearer token")
-        .with_body(r#"{ "value": [ { "id": "d1_2_1" } ] }"#)
+        .with_status(204)
         .expect(1)
         .create();
 
-    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/list_images");
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/take_app_file");
     let image_loader = ImageLoader::new(&url, temp_dir);
-    let (mut all_images, interval) = image_loader.get_image_list("token").await.unwrap();
-    all_images.sort();
-    assert_eq!(interval, 42);
-    assert_eq!(&all_images, &["d1_1_1", "d1_2_1", "d1_3", "d1_4", "d2_1"]);
+    assert!(image_loader
+        .take_app_file("token", "flag.txt")
+        .await
+        .unwrap());
+    exists_mock.assert();
+    delete_mock.assert();
 
-    config_content_redirect_mock.assert();
-    config_content_mock.assert();
-    d1_folder_mock.assert();
-    d1_folder_next_mock.assert();
-    d1_images_mock.assert();
-    d1_image_next_mock.assert();
-    d2_folder_mock.assert();
-    d2_image_mock.assert();
-    d1_1_folder_mock.assert();
-    d1_1_image_mock.assert();
-    d1_2_folder_mock.assert();
-    d1_2_image_next_mock.assert();
+    // Once it's gone, there's nothing to take.
+    exists_mock.remove();
+    let missing_mock = server
+        .mock("GET", "/special/approot:/flag.txt:")
+        .with_status(404)
+        .expect(1)
+        .create();
+    assert!(!image_loader
+        .take_app_file("token", "flag.txt")
+        .await
+        .unwrap());
+    missing_mock.assert();
+    delete_mock.assert();
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn load_image() {
-    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/load_image");
+async fn load_image_with_low_resource_limits() {
+    let temp_dir =
+        std::env::temp_dir().join("onedrive_slideshow_test/load_image_with_low_resource_limits");
     if temp_dir.exists() {
         tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
     }
@@ -351,22 +2221,24 @@ async fn load_image() {
     let mut server = mockito::Server::new_async().await;
     let url = server.url();
 
+    // Thumbnails are requested at the reduced size...
     let thumbnail_mock = server
         .mock("GET", "/items/1/thumbnails")
         .match_query(mockito::Matcher::UrlEncoded(
             "select".into(),
-            "c1024x768".into(),
+            "c40x30".into(),
         ))
         .match_header("authorization", "Bearer token")
         .with_body(format!(
-            r#"{{ "value": [ {{ "c1024x768": {{ "url": "{url}/download" }} }} ] }} "#
+            r#"{{ "value": [ {{ "c40x30": {{ "url": "{url}/download" }} }} ] }} "#
         ))
         .expect(1)
         .create();
 
+    // ...and anything larger is shrunk when decoded.
     let mut image_data = Vec::new();
     image::codecs::jpeg::JpegEncoder::new(&mut image_data)
-        .encode_image(&image::RgbImage::new(1, 1))
+        .encode_image(&image::RgbImage::new(100, 50))
         .unwrap();
     let download_mock = server
         .mock("GET", "/download")
@@ -374,51 +2246,550 @@ async fn load_image() {
         .expect(1)
         .create();
 
-    let image_loader = ImageLoader::new(&url, temp_dir);
+    let image_loader = ImageLoader::new(&url, temp_dir)
+        .with_cache_policy(CachePolicy::Always)
+        .with_low_resource_limits(40, Duration::ZERO);
     let actual_image = image_loader
-        .load_next("token", 1024, 768, &["1".into()])
+        .load_image("token", 1024, 768, "1")
         .await
         .unwrap();
-    assert_eq!(actual_image.height(), 1);
-    assert_eq!(actual_image.width(), 1);
+    assert_eq!(actual_image.width(), 40);
+    assert_eq!(actual_image.height(), 20);
     thumbnail_mock.assert();
     download_mock.assert();
+}
 
-    // Loading again should use the cached image.
-    thumbnail_mock.remove();
-    download_mock.remove();
-    let actual_image = image_loader
-        .load_next("token", 1024, 768, &["1".into()])
+#[test]
+fn compress_cached_images() {
+    let image = image::RgbImage::from_fn(64, 48, |x, y| {
+        image::Rgb([(x * 4) as u8, (y * 5) as u8, ((x + y) * 2) as u8])
+    });
+    let mut png = Vec::new();
+    image
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+
+    let compressed = compress_for_cache(&png, 50).unwrap().unwrap();
+    assert!(compressed.len() < png.len());
+    let decoded = decode_image(&compressed).unwrap();
+    assert_eq!(decoded.size, [64, 48]);
+
+    // Already small enough images are left alone.
+    let jpeg = compressed;
+    assert_eq!(compress_for_cache(&jpeg, 100).unwrap(), None);
+    assert!(compress_for_cache(b"not an image", 50).is_err());
+}
+
+#[test]
+fn check_memory_before_decoding() {
+    // A 12 megapixel photo needs about 137MB to decode.
+    assert!(check_decoding_memory(4000, 3000, None).is_ok());
+    assert!(check_decoding_memory(4000, 3000, Some(512 * 1024)).is_ok());
+    let err = check_decoding_memory(4000, 3000, Some(200 * 1024)).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Not enough memory to decode a 4000x3000 image (137 MB needed, 200 MB available)"
+    );
+
+    let mut png = Vec::new();
+    image::RgbImage::new(64, 48)
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .unwrap();
+    assert_eq!(image_dimensions(&png), Some((64, 48)));
+    assert_eq!(image_dimensions(b"not an image"), None);
+}
+
+#[test]
+fn turn_images_upright() {
+    let image = image::RgbImage::from_fn(64, 48, |x, _| image::Rgb([(x * 4) as u8, 0, 0]));
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 100)
+        .encode_image(&image)
+        .unwrap();
+    assert_eq!(decode_image(&jpeg).unwrap().size, [64, 48]);
+
+    // An EXIF segment, straight after the start of the image, that says the camera was turned so
+    // that the image has to be rotated 90° clockwise to be upright.
+    let exif: &[u8] = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+    let mut turned = jpeg[..2].to_vec();
+    turned.extend([0xff, 0xe1]);
+    turned.extend(((exif.len() + 2) as u16).to_be_bytes());
+    turned.extend(exif);
+    turned.extend(&jpeg[2..]);
+    let decoded = decode_image(&turned).unwrap();
+    assert_eq!(decoded.size, [48, 64]);
+    // The left edge of the image (which is darkest) is now at the top.
+    assert!(decoded.pixels[0].r() < decoded.pixels[decoded.pixels.len() - 1].r());
+
+    // Which is kept when the image is compressed for the cache.
+    let compressed = compress_for_cache(&turned, 50).unwrap().unwrap();
+    assert_eq!(decode_image(&compressed).unwrap().size, [48, 64]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn config_errors() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+    let image_loader = ImageLoader::new(&url, std::env::temp_dir());
+
+    let missing_mock = server
+        .mock("GET", "/root:/slideshow.txt:/content")
+        .with_status(404)
+        .expect(1)
+        .create();
+    let err = image_loader.get_image_list("token").await.err().unwrap();
+    assert_eq!(
+        err.downcast_ref::<ConfigError>(),
+        Some(&ConfigError::Missing)
+    );
+    assert!(err.to_string().contains(SAMPLE_CONFIG));
+    missing_mock.assert();
+    missing_mock.remove();
+
+    let invalid_mock = server
+        .mock("GET", "/root:/slideshow.txt:/content")
+        .with_body("{ \"directories\": [ \"d1\" ],\n  \"interval\": \"often\" }")
+        .expect(1)
+        .create();
+    let err = image_loader.get_image_list("token").await.err().unwrap();
+    let Some(ConfigError::Invalid { message, snippet }) = err.downcast_ref::<ConfigError>() else {
+        panic!("Unexpected error: {err:?}");
+    };
+    assert!(message.contains("invalid type"), "{message}");
+    assert_eq!(snippet, "\"interval\": \"often\" }");
+    invalid_mock.assert();
+}
+
+#[test]
+fn parse_config_formats() {
+    let json = r#"
+        {
+            "directories": [ "Pictures", "Vacations" ],
+            "interval": 30,
+            "caption": "{month} · {place}"
+        }"#;
+    let toml = r#"
+        # Written on my phone
+        directories = [ "Pictures", "Vacations" ]
+        interval = 30
+        caption = "{month} · {place}"
+        "#;
+    let yaml = r#"
+        # Written on my phone
+        directories:
+          - Pictures
+          - Vacations
+        interval: 30
+        caption: "{month} · {place}"
+        "#;
+    for (content, format) in [
+        (json, ConfigFormat::Json),
+        (toml, ConfigFormat::Toml),
+        (yaml, ConfigFormat::Yaml),
+    ] {
+        assert_eq!(ConfigFormat::sniff(content), format);
+        let config = Config::parse(content.as_bytes()).unwrap();
+        assert_eq!(config.directories, ["Pictures", "Vacations"]);
+        assert_eq!(config.interval, 30);
+        assert_eq!(config.caption.as_deref(), Some("{month} · {place}"));
+    }
+    assert_eq!(
+        ConfigFormat::sniff("[[special_days]]\ndate = \"12-25\""),
+        ConfigFormat::Toml
+    );
+    // An equals sign in a YAML value doesn't make it TOML.
+    assert_eq!(
+        ConfigFormat::sniff("caption: \"a = b\""),
+        ConfigFormat::Yaml
+    );
+
+    // Problems point at the line that they are on, whichever the format.
+    let Err(ConfigError::Invalid { snippet, .. }) =
+        Config::parse(b"directories = [ \"Pictures\" ]\ninterval = \"often\"\n")
+    else {
+        panic!("TOML with an invalid interval was accepted");
+    };
+    assert_eq!(snippet, "interval = \"often\"");
+    let Err(ConfigError::Invalid { snippet, .. }) =
+        Config::parse(b"directories: [ Pictures ]\ninterval: often\n")
+    else {
+        panic!("YAML with an invalid interval was accepted");
+    };
+    assert_eq!(snippet, "interval: often");
+}
+
+#[test]
+fn validate_config() {
+    let invalid = |content: &str| match Config::parse(content.as_bytes()) {
+        Err(ConfigError::Invalid { message, snippet }) => (message, snippet),
+        Err(err) => panic!("Unexpected error: {err}"),
+        Ok(_) => panic!("Invalid config was accepted: {content}"),
+    };
+
+    // Misspelled settings are reported, and explain why a required setting is missing.
+    let config = Config::parse(
+        br#"{ "_comment": "Mine", "directories": [ "Pictures" ], "interval": 60, "ken_burn": true }"#,
+    )
+    .unwrap();
+    assert_eq!(config.unknown_fields, ["ken_burn"]);
+    let (message, _) = invalid(r#"{ "directories": [ "Pictures" ], "intreval": 60 }"#);
+    assert!(message.contains("missing field `interval`"), "{message}");
+    assert!(
+        message.ends_with("may be misspelled: intreval)"),
+        "{message}"
+    );
+
+    // Settings that can be read but won't work point at where they are.
+    let (message, snippet) = invalid("directories: [ Pictures ]\ninterval: 0\n");
+    assert!(message.starts_with("interval must be at least 1 second"));
+    assert_eq!(snippet, "interval: 0");
+    let (message, snippet) = invalid(
+        "interval = 60\ndirectories = [\n  \"Pictures\",\n  'C:\\Users\\me\\OneDrive\\Vacations',\n]\n",
+    );
+    assert!(
+        message.contains("isn't a path within your OneDrive"),
+        "{message}"
+    );
+    assert_eq!(snippet, "'C:\\Users\\me\\OneDrive\\Vacations',");
+    let (message, _) = invalid(r#"{ "directories": [ "Pictures\\Vacations" ], "interval": 60 }"#);
+    assert!(message.contains("use / instead"), "{message}");
+    // Frames' own settings are checked too.
+    let (message, _) = invalid(
+        r#"{ "directories": [ "Pictures" ], "interval": 60,
+            "groups": [ { "frames": { "kitchen": { "interval": 0 } } } ] }"#,
+    );
+    assert!(message.starts_with("interval must be"), "{message}");
+    let (message, _) = invalid(
+        r#"{ "directories": [ "Pictures" ], "interval": 60, "min_date": "2024-01-01", "max_date": "2023-01-01" }"#,
+    );
+    assert!(message.contains("no photos would be shown"), "{message}");
+    let (message, _) = invalid(
+        r#"{ "directories": [ "Pictures" ], "interval": 60, "directory_weights": { "Pictures": 0 } }"#,
+    );
+    assert!(message.starts_with("The weight of Pictures must be more than 0"));
+
+    // The error shows what a working slideshow.txt looks like.
+    assert!(ConfigError::invalid("bad".into(), None, "")
+        .to_string()
+        .ends_with(SAMPLE_CONFIG));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_sample_config() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let config = serde_json::from_str::<Config>(SAMPLE_CONFIG_FILE).unwrap();
+    assert_eq!(config.directories, ["Pictures"]);
+
+    let upload_mock = server
+        .mock("PUT", "/root:/slideshow.txt:/content")
+        .match_header("authorization", "Bearer token")
+        .match_body(SAMPLE_CONFIG_FILE)
+        .with_status(201)
+        .expect(1)
+        .create();
+    ImageLoader::new(&url, std::env::temp_dir())
+        .create_sample_config("token")
         .await
         .unwrap();
-    assert_eq!(actual_image.height(), 1);
-    assert_eq!(actual_image.width(), 1);
+    upload_mock.assert();
+}
 
-    // But loading a different image will download again.
-    let thumbnail_mock = server
-        .mock("GET", "/items/2/thumbnails")
+#[tokio::test(flavor = "multi_thread")]
+async fn list_images_with_missing_directory() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let missing_mock = server
+        .mock("GET", "/root:/typo:/children")
+        .match_query(mockito::Matcher::Any)
+        .match_header("authorization", "Bearer token")
+        .with_status(404)
+        .expect(1)
+        .create();
+    let folder_mock = server
+        .mock("GET", "/root:/d1:/children")
         .match_query(mockito::Matcher::UrlEncoded(
-            "select".into(),
-            "c1024x768".into(),
+            "$filter".into(),
+            "folder ne null".into(),
         ))
         .match_header("authorization", "Bearer token")
-        .with_body(format!(
-            r#"{{ "value": [ {{ "c1024x768": {{ "url": "{url}/download" }} }} ] }} "#
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+    let image_mock = server
+        .mock("GET", "/root:/d1:/children")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$filter".into(),
+            "image ne null".into(),
         ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "d1_1" } ] }"#)
         .expect(1)
         .create();
 
-    let mut image_data = Vec::new();
-    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
-        .encode_image(&image::RgbImage::new(2, 2))
+    let listing = ImageLoader::new(&url, std::env::temp_dir())
+        .list_images("token", &["d1".into(), "typo".into()])
+        .await
         .unwrap();
-    let download_mock = download_mock.with_body(image_data).create();
-    let actual_image = image_loader
-        .load_next("token", 1024, 768, &["2".into()])
+    assert_eq!(
+        listing
+            .images
+            .iter()
+            .map(|image| image.id.as_str())
+            .collect::<Vec<_>>(),
+        ["d1_1"]
+    );
+    assert_eq!(listing.missing_directories, ["typo"]);
+    missing_mock.assert();
+    folder_mock.assert();
+    image_mock.assert();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_images_with_failed_directory() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let folder_query =
+        |filter: &str| mockito::Matcher::UrlEncoded("$filter".into(), format!("{filter} ne null"));
+    let d1_folder_mock = server
+        .mock("GET", "/root:/d1:/children")
+        .match_query(folder_query("folder"))
+        .with_body(r#"{ "value": [ { "id": "d1_1" } ] }"#)
+        .expect(1)
+        .create();
+    let d1_image_mock = server
+        .mock("GET", "/root:/d1:/children")
+        .match_query(folder_query("image"))
+        .with_body(r#"{ "value": [ { "id": "d1_image" } ] }"#)
+        .expect(1)
+        .create();
+    let d1_1_failed_mock = server
+        .mock("GET", "/items/d1_1/children")
+        .match_query(mockito::Matcher::Any)
+        .with_status(403)
+        .expect(1)
+        .create();
+
+    // The images that could be listed are kept.
+    let image_loader = ImageLoader::new(&url, std::env::temp_dir());
+    let mut listing = image_loader
+        .list_images("token", &["d1".into()])
         .await
         .unwrap();
-    assert_eq!(actual_image.height(), 2);
-    assert_eq!(actual_image.width(), 2);
-    thumbnail_mock.assert();
-    download_mock.assert();
+    assert_eq!(listing.images.len(), 1);
+    assert_eq!(listing.images[0].id, "d1_image");
+    assert_eq!(listing.failed_directories.len(), 1);
+    assert_eq!(&*listing.failed_directories[0].directory, "d1");
+    d1_folder_mock.assert();
+    d1_image_mock.assert();
+    d1_1_failed_mock.assert();
+    d1_1_failed_mock.remove();
+
+    // Retrying only lists the directory that failed.
+    let d1_1_folder_mock = server
+        .mock("GET", "/items/d1_1/children")
+        .match_query(folder_query("folder"))
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+    let d1_1_image_mock = server
+        .mock("GET", "/items/d1_1/children")
+        .match_query(folder_query("image"))
+        .with_body(r#"{ "value": [ { "id": "d1_1_image" } ] }"#)
+        .expect(1)
+        .create();
+    let retried = image_loader
+        .retry_failed_directory("token", listing.failed_directories.remove(0))
+        .await;
+    assert_eq!(retried.images.len(), 1);
+    assert_eq!(retried.images[0].id, "d1_1_image");
+    assert_eq!(&*retried.images[0].directory, "d1");
+    assert!(retried.failed_directories.is_empty());
+    d1_folder_mock.assert();
+    d1_1_folder_mock.assert();
+    d1_1_image_mock.assert();
+
+    // If nothing can be listed, then that's an error.
+    let failed_mock = server
+        .mock("GET", "/root:/d2:/children")
+        .match_query(mockito::Matcher::Any)
+        .with_status(403)
+        .expect(1)
+        .create();
+    assert!(image_loader
+        .list_images("token", &["d2".into()])
+        .await
+        .is_err());
+    failed_mock.assert();
+}
+
+#[test]
+fn parse_special_dates() {
+    let parse = |date: &str| SpecialDate::try_from(date.to_string());
+    let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+
+    let birthday = parse("02-29").unwrap();
+    assert!(birthday.matches(date(2024, 2, 29)));
+    assert!(!birthday.matches(date(2024, 3, 1)));
+
+    let wedding = parse("2025-06-14").unwrap();
+    assert!(wedding.matches(date(2025, 6, 14)));
+    assert!(!wedding.matches(date(2026, 6, 14)));
+
+    for invalid in [
+        "",
+        "06",
+        "13-01",
+        "02-30",
+        "2023-02-29",
+        "June-14",
+        "2025-06-14-01",
+    ] {
+        assert!(parse(invalid).is_err(), "{invalid}");
+    }
+    assert!(serde_json::from_str::<SpecialDay>(
+        r#"{ "date": "12-25", "banner": "Merry Christmas" }"#
+    )
+    .is_ok());
+    assert!(serde_json::from_str::<SpecialDay>(r#"{ "date": "12-32" }"#).is_err());
+}
+
+#[test]
+fn filter_by_date() {
+    let image = |taken: Option<&str>, modified: Option<&str>| Image {
+        taken: taken.map(|taken| taken.parse().unwrap()),
+        modified: modified.map(|modified| modified.parse().unwrap()),
+        ..Image::with_id("id")
+    };
+    let date_range = DateRange {
+        min: NaiveDate::from_ymd_opt(2020, 1, 1),
+        max: NaiveDate::from_ymd_opt(2022, 12, 31),
+    };
+    assert!(date_range.contains(&image(Some("2020-01-01T00:00:00Z"), None)));
+    assert!(date_range.contains(&image(Some("2022-12-31T23:59:59Z"), None)));
+    assert!(!date_range.contains(&image(Some("2019-12-31T23:59:59Z"), None)));
+    assert!(!date_range.contains(&image(Some("2023-01-01T00:00:00Z"), None)));
+    // When the photo was taken wins over when its file was changed.
+    assert!(date_range.contains(&image(
+        Some("2021-06-01T00:00:00Z"),
+        Some("2024-06-01T00:00:00Z")
+    )));
+    assert!(!date_range.contains(&image(None, Some("2024-06-01T00:00:00Z"))));
+    // Images without any date are always shown.
+    assert!(date_range.contains(&image(None, None)));
+    // As is everything without a range.
+    assert!(DateRange::default().contains(&image(Some("1999-01-01T00:00:00Z"), None)));
+}
+
+#[test]
+fn apply_frame_group() {
+    let config = || {
+        serde_json::from_str::<Config>(
+            r#"{
+                "directories": [ "Pictures" ],
+                "interval": 10,
+                "groups": [
+                    {
+                        "directories": [ "Family" ],
+                        "interval": 30,
+                        "frames": {
+                            "hallway": { "orientation": "portrait" },
+                            "tv": { "orientation": "landscape", "interval": 60 }
+                        }
+                    },
+                    {
+                        "frames": { "kiosk": { "orientation": "auto" } }
+                    }
+                ]
+            }"#,
+        )
+        .unwrap()
+    };
+
+    // The group's settings apply to each frame in it, along with the frame's own settings.
+    let mut hallway = config();
+    hallway.apply_frame_group("hallway");
+    assert_eq!(hallway.directories, ["Family"]);
+    assert_eq!(hallway.interval, 30);
+    assert_eq!(
+        hallway.orientation,
+        Some(OrientationSetting::Fixed(Orientation::Portrait))
+    );
+    let mut tv = config();
+    tv.apply_frame_group("tv");
+    assert_eq!(tv.directories, ["Family"]);
+    assert_eq!(tv.interval, 60);
+    assert_eq!(
+        tv.orientation,
+        Some(OrientationSetting::Fixed(Orientation::Landscape))
+    );
+
+    // Frames outside of any group just use the settings at the top.
+    let mut kitchen = config();
+    kitchen.apply_frame_group("kitchen");
+    assert_eq!(kitchen.directories, ["Pictures"]);
+    assert_eq!(kitchen.interval, 10);
+    assert_eq!(kitchen.orientation, None);
+
+    // Frames can follow the shape of their screen.
+    let mut kiosk = config();
+    kiosk.apply_frame_group("kiosk");
+    let auto = kiosk.orientation.unwrap();
+    assert_eq!(auto, OrientationSetting::Auto);
+    assert_eq!(auto.for_screen(1080.0, 1920.0), Orientation::Portrait);
+    assert_eq!(auto.for_screen(1920.0, 1080.0), Orientation::Landscape);
+    assert_eq!(
+        OrientationSetting::Fixed(Orientation::Portrait).for_screen(1920.0, 1080.0),
+        Orientation::Portrait
+    );
+    assert!(serde_json::from_str::<OrientationSetting>(r#""sideways""#).is_err());
+
+    let image = |width, height| Image {
+        width,
+        height,
+        ..Image::with_id("image")
+    };
+    assert!(Orientation::Portrait.matches(&image(Some(600), Some(800))));
+    assert!(!Orientation::Portrait.matches(&image(Some(800), Some(600))));
+    assert!(Orientation::Landscape.matches(&image(Some(800), Some(600))));
+    assert!(Orientation::Landscape.matches(&image(Some(800), Some(800))));
+    assert!(Orientation::Portrait.matches(&image(None, None)));
+}
+
+#[test]
+fn find_folder_themes() {
+    let themes = serde_json::from_str::<HashMap<String, FolderTheme>>(
+        r##"{
+            "Pictures": { "caption_color": "#FFFFFF" },
+            "Pictures/Vacations 2023": { "caption_color": "#ffd700", "accent_color": "#1E90FF" }
+        }"##,
+    )
+    .unwrap();
+    let caption_color = |folder| {
+        theme_for_folder(&themes, folder)
+            .caption_color
+            .map(|color| color.0)
+    };
+    assert_eq!(caption_color("Pictures"), Some(Color32::WHITE));
+    assert_eq!(
+        caption_color("Pictures/Vacations 2023/Beach"),
+        Some(Color32::from_rgb(0xff, 0xd7, 0x00))
+    );
+    assert_eq!(
+        theme_for_folder(&themes, "Pictures/Vacations 2023").accent_color,
+        Some(ThemeColor(Color32::from_rgb(0x1e, 0x90, 0xff)))
+    );
+    // Only whole folder names match.
+    assert_eq!(caption_color("Pictures 2"), None);
+    assert_eq!(theme_for_folder(&themes, "Music"), FolderTheme::default());
+
+    for invalid in ["", "FFFFFF", "#FFF", "#GGGGGG", "#FFFFFFF", "#ÿÿÿ"] {
+        assert!(
+            ThemeColor::try_from(invalid.to_string()).is_err(),
+            "{invalid}"
+        );
+    }
 }
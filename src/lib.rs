@@ -0,0 +1,55 @@
+//! Signs in to OneDrive, lists the images to show and loads them, independent of how they are
+//! shown. The slideshow app (behind the `gui` feature) is built on top of this, but it can also be
+//! used to drive other displays:
+//!
+//! ```no_run
+//! use onedrive_slideshow::{
+//!     auth::{AuthMessage, Authenticator, TokenSource},
+//!     cred_store::TokenStore,
+//!     image_loader::{choose_image, ImageLoader},
+//! };
+//!
+//! # async fn show_a_slide() -> anyhow::Result<()> {
+//! let (sender, mut messages) = tokio::sync::mpsc::channel(1);
+//! tokio::spawn(async move {
+//!     while let Some(message) = messages.recv().await {
+//!         if let AuthMessage::HasClientCode(url, code) = message {
+//!             println!("Open {url} and enter {code}");
+//!         }
+//!     }
+//! });
+//! let token_store = TokenStore::default();
+//! let mut authenticator = Authenticator::new(
+//!     sender,
+//!     "https://login.microsoftonline.com/consumers/oauth2/v2.0",
+//!     token_store.get_refresh_token(),
+//! )
+//! .with_token_store(token_store);
+//! let token = authenticator.get_token().await?;
+//!
+//! let mut loader = ImageLoader::new(
+//!     "https://graph.microsoft.com/v1.0/me/drive",
+//!     std::env::temp_dir().join("my_frame_cache"),
+//! );
+//! loader.detect_drive_type(&token).await?;
+//! let (listing, _config) = loader.get_image_list(&token).await?;
+//! let image = choose_image(&listing.images)?;
+//! let slide = loader.load_image(&token, 480, 800, &image.id).await?;
+//! println!("Loaded {:?} ({}x{})", image.name, slide.size[0], slide.size[1]);
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod auth;
+pub mod cache;
+mod conversion;
+pub mod cred_store;
+pub mod history;
+pub mod http;
+pub mod image_loader;
+pub mod metadata;
+pub mod network;
+
+pub use auth::Authenticator;
+pub use cache::{CacheStore, DiskCache};
+pub use image_loader::{Image, ImageLoader, PhotoSource};
@@ -0,0 +1,99 @@
+use crate::image_loader;
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// Restricts the slideshow to a set of folders (e.g., on a frame in a child's room), however
+/// slideshow.txt changes. Once turned off, the lock stays off across restarts until it is turned
+/// back on.
+pub struct FolderLock {
+    /// Paths from the root of the drive. If empty, there's no lock.
+    folders: Vec<String>,
+    /// Marker file that records that the lock has been turned off.
+    unlocked_path: PathBuf,
+    locked: bool,
+}
+
+impl FolderLock {
+    pub fn load(folders: Vec<String>, unlocked_path: PathBuf) -> Self {
+        let locked = !folders.is_empty() && !unlocked_path.exists();
+        Self {
+            folders,
+            unlocked_path,
+            locked,
+        }
+    }
+
+    /// The only folders whose images can be shown, or `None` if any can be shown.
+    pub fn allowed_folders(&self) -> Option<Vec<String>> {
+        self.locked.then(|| self.folders.clone())
+    }
+
+    pub fn allows(&self, folder: &str) -> bool {
+        !self.locked
+            || self
+                .folders
+                .iter()
+                .any(|allowed| image_loader::is_within_folder(folder, allowed))
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub async fn set_locked(&mut self, locked: bool) -> Result<()> {
+        if self.folders.is_empty() {
+            bail!("There are no locked folders");
+        }
+        if locked {
+            match tokio::fs::remove_file(&self.unlocked_path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err)
+                        .with_context(|| format!("Removing {}", self.unlocked_path.display()))
+                }
+                _ => {}
+            }
+        } else {
+            if let Some(parent) = self.unlocked_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::write(&self.unlocked_path, b"")
+                .await
+                .with_context(|| format!("Writing {}", self.unlocked_path.display()))?;
+        }
+        self.locked = locked;
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lock_folders() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/lock_folders");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    let unlocked_path = temp_dir.join("unlocked");
+
+    // Without any folders, there's nothing to lock.
+    let mut lock = FolderLock::load(Vec::new(), unlocked_path.clone());
+    assert!(!lock.is_locked());
+    assert!(lock.allows("Anything"));
+    assert!(lock.set_locked(true).await.is_err());
+
+    // Locked by default, allowing only the folders and the folders within them.
+    let mut lock = FolderLock::load(vec!["Kids".into()], unlocked_path.clone());
+    assert!(lock.is_locked());
+    assert_eq!(lock.allowed_folders(), Some(vec!["Kids".to_string()]));
+    assert!(lock.allows("Kids"));
+    assert!(lock.allows("Kids/Zoo"));
+    assert!(!lock.allows("Kidsmovies"));
+    assert!(!lock.allows("Work"));
+
+    // Unlocking is remembered across restarts, until locked again.
+    lock.set_locked(false).await.unwrap();
+    assert!(lock.allows("Work"));
+    assert_eq!(lock.allowed_folders(), None);
+    let mut lock = FolderLock::load(vec!["Kids".into()], unlocked_path.clone());
+    assert!(!lock.is_locked());
+    lock.set_locked(true).await.unwrap();
+    assert!(FolderLock::load(vec!["Kids".into()], unlocked_path).is_locked());
+}
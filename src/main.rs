@@ -1,270 +1,2956 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
-mod auth;
-mod cred_store;
-mod http;
-mod image_loader;
+mod bench;
+mod controller;
+#[cfg(feature = "framebuffer")]
+mod framebuffer;
+mod headless;
+mod lock;
+mod mock;
+mod pairing;
+mod playback;
+mod power;
+mod remote;
+mod rotation;
+mod settings;
+mod source;
+mod status;
+mod video;
 
-use anyhow::Result;
-use auth::Authenticator;
+use anyhow::{anyhow, bail, Context, Result};
+use auth::{Authenticator, TokenSource};
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, Local, NaiveDate, Utc, Weekday};
+use controller::{
+    AppState, KenBurns, Slide, SlideshowController, SlideshowEvent, PHOTO_STRIP_VISIBLE,
+};
+use cred_store::TokenStore;
 use eframe::epaint::{Color32, Rect};
-use egui::{self, ColorImage, RichText, Sense, Style, ViewportBuilder, Visuals};
-use image_loader::ImageLoader;
-use std::{process, time::Duration};
+use egui::{
+    self, ColorImage, RichText, Sense, Style, TextureHandle, ViewportBuilder, ViewportCommand,
+    Visuals, WindowLevel,
+};
+use history::{History, HistoryEntry, MonthlySummary, ShownItem};
+use image_loader::{
+    ConfigError, DateRange, FailedDirectory, FolderTheme, Image, ImageLoader, IntervalRules, Order,
+    Orientation, OrientationSetting, SpecialDate,
+};
+use lock::FolderLock;
+use mdns_sd::ServiceDaemon;
+use metadata::{PhotoMetadata, Place};
+use mock::{MockAuthenticator, MockDrive};
+use network::CaptivePortalDetector;
+use onedrive_slideshow::{auth, cache, cred_store, history, http, image_loader, metadata, network};
+use pairing::Pairing;
+use playback::Playback;
+use power::PowerStatus;
+use rand::{seq::SliceRandom, Rng};
+use remote::{ItemReference, RemoteCommand, RemoteControl, UpcomingItem};
+use reqwest::Url;
+use rotation::Rotation;
+use settings::{Settings, Widget};
+use source::SlideSource;
+use status::Status;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{
-    sync::mpsc::{channel, error::TryRecvError, Receiver, Sender},
-    task,
+    net::{TcpListener, TcpStream},
+    sync::{
+        mpsc::{
+            channel,
+            error::{TryRecvError, TrySendError},
+            Receiver, Sender,
+        },
+        watch,
+    },
+    task::{self, JoinHandle},
     time::Instant,
 };
 
 use crate::auth::AuthMessage;
 
 const ON_ERROR_REFRESH_TIME: Duration = Duration::from_secs(1);
+// Fixing slideshow.txt takes a person, so there's no point in checking it as often as for other errors.
+const MISSING_CONFIG_REFRESH_TIME: Duration = Duration::from_secs(60);
+const INVALID_CONFIG_REFRESH_TIME: Duration = Duration::from_secs(15);
+const THROTTLED_REFRESH_TIME: Duration = Duration::from_secs(60);
+const NETWORK_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+const NETWORK_RETRY_TIME: Duration = if cfg!(test) {
+    Duration::from_millis(10)
+} else {
+    Duration::from_secs(2)
+};
+const FAILED_DIRECTORY_RETRY_TIME: Duration = Duration::from_secs(60);
 const IMAGE_LIST_REFRESH_TIME: Duration = Duration::from_secs(60 * 60);
+/// How long to wait before retrying a failed image list refresh, doubling with each failure in a
+/// row (up to `IMAGE_LIST_REFRESH_TIME`).
+const IMAGE_LIST_RETRY_TIME: Duration = Duration::from_secs(60);
+const DEFAULT_REFRESH_WINDOW: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_FADE: Duration = Duration::from_secs(1);
+/// How often to redraw a slide that is panning and zooming.
+const KEN_BURNS_FRAME_TIME: Duration = Duration::from_millis(1000 / 30);
+/// Height of the progress bar along the bottom of the screen.
+const PROGRESS_BAR_HEIGHT: f32 = 4.0;
+/// Most often to redraw the progress bar. It is otherwise only redrawn when it has grown by a pixel,
+/// so that long intervals don't keep the screen busy.
+const PROGRESS_BAR_FRAME_TIME: Duration = Duration::from_millis(1000 / 20);
+/// Most thumbnails to load for a slide's photo strip.
+const PHOTO_STRIP_SIZE: usize = 8;
+/// Height of the thumbnails in a photo strip.
+const PHOTO_STRIP_HEIGHT: u32 = 120;
+/// Names of the textures for the thumbnails of the photo strip that are on screen.
+const PHOTO_STRIP_TEXTURES: [&str; PHOTO_STRIP_VISIBLE] = [
+    "photo_strip_0",
+    "photo_strip_1",
+    "photo_strip_2",
+    "photo_strip_3",
+];
+const ONEDRIVE_AUTH_URL: &str = "https://login.microsoftonline.com/consumers/oauth2/v2.0";
+const ONEDRIVE_DRIVE_URL: &str = "https://graph.microsoft.com/v1.0/me/drive";
+const REFRESH_JITTER_FILE: &str = "refresh_jitter";
+const REFRESH_TOKEN_FILE: &str = "refresh_token";
+const REFRESH_TOKEN_ISSUED_FILE: &str = "refresh_token_issued";
+/// Longest to go without getting a new access token (and with it a new refresh token), even while
+/// paused or waiting for the battery to charge, so that the refresh token never expires from disuse.
+const KEEP_SIGNED_IN_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long before the refresh token expires to warn that signing in again will soon be needed.
+const SIGN_IN_EXPIRY_WARNING: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+const CACHE_DIRECTORY: &str = "cache";
+const REMOTE_TOKENS_FILE: &str = "remote_tokens";
+const UNLOCKED_FILE: &str = "unlocked";
+const HISTORY_FILE: &str = "history.jsonl";
+const HISTORY_SUMMARY_DIRECTORY: &str = "history_summary";
+const DEFAULT_PINNED_EVERY: u64 = 10;
+/// How many slides are chosen ahead of time, so that they can be downloaded early and listed by
+/// the remote control.
+const UPCOMING_SLIDES: u64 = 3;
+/// How long after an image is shown until it is as likely to be chosen as one that hasn't been
+/// shown. Until then, it becomes steadily more likely to be chosen again.
+const SHOWN_IMAGE_RECOVERY_TIME: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How likely an image that was just shown is to be chosen, relative to one that hasn't been shown.
+const JUST_SHOWN_WEIGHT: f64 = 0.05;
+const DEFAULT_PANORAMA_ASPECT_RATIO: f64 = 2.0;
+const DEFAULT_PANORAMA_MULTIPLIER: f64 = 2.0;
+const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const HISTORY_EXPORT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const CACHE_GARBAGE_COLLECTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_VIDEO_SLIDES: usize = 30;
+/// Most cached slides to cycle through while the slideshow is starting up.
+const WARM_UP_SLIDES: usize = 50;
+/// How long each cached slide is shown while the slideshow is starting up.
+const WARM_UP_INTERVAL: Duration = Duration::from_secs(30);
+/// Largest width or height that images are shown at on low-end hardware.
+const LOW_RESOURCE_MAX_IMAGE_DIMENSION: u32 = 1280;
+const LOW_RESOURCE_LISTING_DELAY: Duration = Duration::from_millis(250);
+const LOW_BATTERY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_SLIDE_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
 
 fn main() -> Result<(), eframe::Error> {
-    tokio::runtime::Builder::new_multi_thread()
+    let settings = match Settings::from_env_and_args(
+        // Variables that aren't valid Unicode can't be settings, so are ignored.
+        std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        }),
+        std::env::args().skip(1),
+    ) {
+        Ok(settings) => settings,
+        Err(err) => {
+            eprintln!("{err:?}");
+            process::exit(2);
+        }
+    };
+    let splash = match settings.splash.as_deref().map(load_splash).transpose() {
+        Ok(splash) => splash,
+        Err(err) => {
+            eprintln!("{err:?}");
+            process::exit(2);
+        }
+    };
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
-        .unwrap()
-        .block_on(async {
-            let options = eframe::NativeOptions {
-                viewport: ViewportBuilder::default().with_fullscreen(true),
-                ..Default::default()
-            };
-            eframe::run_native(
-                "OneDrive Slideshow",
-                options,
-                Box::new(move |cc| {
-                    let style = Style {
-                        visuals: Visuals::dark(),
-                        ..Style::default()
-                    };
-                    cc.egui_ctx.set_style(style);
-                    egui_extras::install_image_loaders(&cc.egui_ctx);
-                    let (sender, receiver) = channel(8);
-                    task::spawn(image_load_loop(sender.clone(), cc.egui_ctx.clone()));
-                    Ok(Box::new(Slideshow::new(receiver, sender)))
-                }),
-            )
-        })
+        .unwrap();
+    let environment = if settings.mock || settings.preview.is_some() {
+        match start_mock(&settings) {
+            Ok(environment) => environment,
+            Err(err) => {
+                eprintln!("{err:?}");
+                process::exit(1);
+            }
+        }
+    } else {
+        Environment::onedrive(&settings)
+    };
+    if settings.check {
+        let reachable = runtime.block_on(check_network(&environment));
+        process::exit(if reachable { 0 } else { 1 });
+    }
+    if let Some(path) = &settings.provision_token {
+        if let Err(err) = runtime.block_on(provision_token(&settings, environment, path)) {
+            eprintln!("{err:?}");
+            process::exit(1);
+        }
+        println!("Signed in");
+        return Ok(());
+    }
+    if let Some(count) = settings.history {
+        let history = History::new(environment.data_directory.join(HISTORY_FILE));
+        match history.recent(count) {
+            Ok(entries) => {
+                for entry in entries {
+                    println!("{entry}");
+                }
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("{err:?}");
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(output) = &settings.export_video {
+        let data_directory = &environment.data_directory;
+        let result = video::recent_cached_images(
+            &History::new(data_directory.join(HISTORY_FILE)),
+            &data_directory.join(CACHE_DIRECTORY),
+            settings.video_slides.unwrap_or(DEFAULT_VIDEO_SLIDES),
+        )
+        .and_then(|images| video::export(&images, output));
+        if let Err(err) = result {
+            eprintln!("{err:?}");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(slides) = settings.bench {
+        if let Err(err) = runtime.block_on(benchmark(&settings, environment, slides)) {
+            eprintln!("{err:?}");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(slides) = settings.headless_cycle {
+        if let Err(err) = runtime.block_on(run_headless(settings, environment, slides)) {
+            eprintln!("{err:?}");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+    #[cfg(feature = "framebuffer")]
+    if let Some(device) = settings.framebuffer.clone() {
+        if let Err(err) =
+            runtime.block_on(run_on_framebuffer(device, settings, environment, splash))
+        {
+            eprintln!("{err:?}");
+            process::exit(1);
+        }
+        return Ok(());
+    }
+
+    runtime.block_on(async {
+        let mut options = eframe::NativeOptions {
+            viewport: viewport(&settings),
+            ..Default::default()
+        };
+        if let Some(hardware_acceleration) = settings.hardware_acceleration {
+            options.hardware_acceleration = hardware_acceleration;
+        }
+        eframe::run_native(
+            "OneDrive Slideshow",
+            options,
+            Box::new(move |cc| {
+                let style = Style {
+                    visuals: Visuals::dark(),
+                    ..Style::default()
+                };
+                cc.egui_ctx.set_style(style);
+                egui_extras::install_image_loaders(&cc.egui_ctx);
+                let (sender, receiver) = channel(8);
+                let (pairing_pin_sender, pairing_pin) = watch::channel(None);
+                let (warnings_sender, warnings) = watch::channel(Vec::new());
+                let (banner_sender, banner) = watch::channel(None);
+                let (paused_sender, paused) = watch::channel(false);
+                let sender = UiSender::new(
+                    sender,
+                    pairing_pin_sender,
+                    warnings_sender,
+                    banner_sender,
+                    paused_sender,
+                    cc.egui_ctx.clone(),
+                );
+                let (command_sender, commands) = channel(8);
+                let environment = Environment::onedrive(&settings);
+                let parts = SlideshowParts {
+                    incoming_state: receiver,
+                    pairing_pin,
+                    warnings,
+                    banner,
+                    paused,
+                    commands: command_sender.clone(),
+                    widget: settings.widget,
+                    offer_sample_config: settings.offer_sample_config,
+                    screenshot_directory: environment.data_directory.clone(),
+                    ui_sender: sender.clone(),
+                    exit_pin: settings.exit_pin.clone(),
+                    rotation: settings.rotation,
+                };
+                let mut controller = SlideshowController::default();
+                if let Some(splash) = splash {
+                    controller = controller.with_splash(Arc::new(splash));
+                }
+                if let Some(return_to_live) = settings.return_to_live {
+                    controller = controller.with_interaction_grace_period(return_to_live);
+                }
+                task::spawn(image_load_loop(
+                    sender,
+                    cc.egui_ctx.clone(),
+                    settings,
+                    environment,
+                    command_sender.clone(),
+                    commands,
+                ));
+                Ok(Box::new(Slideshow::new(controller, parts)))
+            }),
+        )
+    })
 }
 
-enum AppState {
-    WaitingForAuth(String, String),
-    LoadingImage,
-    HasImage(ColorImage),
+/// Runs the slideshow on a framebuffer device rather than in a window.
+#[cfg(feature = "framebuffer")]
+async fn run_on_framebuffer(
+    device: PathBuf,
+    settings: Settings,
+    environment: Environment,
+    splash: Option<ColorImage>,
+) -> Result<()> {
+    let framebuffer = framebuffer::Framebuffer::open(&device)?;
+
+    // Images are sized to fit egui's screen, so give it the framebuffer's size (as it is mounted).
+    let framebuffer = framebuffer.with_rotation(settings.rotation);
+    let info = framebuffer.info();
+    let screen = egui::vec2(info.width as f32, info.height as f32);
+    let screen = settings
+        .rotation
+        .map_or(screen, |rotation| rotation.logical_size(screen));
+    let ctx = screen_context(screen.x as usize, screen.y as usize);
+
+    let (sender, receiver) = channel(8);
+    let (pairing_pin_sender, pairing_pin) = watch::channel(None);
+    let (warnings_sender, warnings) = watch::channel(Vec::new());
+    let (banner_sender, banner) = watch::channel(None);
+    // There's nowhere to show that the slideshow is paused.
+    let (paused_sender, _paused) = watch::channel(false);
+    let sender = UiSender::new(
+        sender,
+        pairing_pin_sender,
+        warnings_sender,
+        banner_sender,
+        paused_sender,
+        ctx.clone(),
+    );
+    let (command_sender, commands) = channel(8);
+    task::spawn(image_load_loop(
+        sender,
+        ctx,
+        settings,
+        environment,
+        command_sender,
+        commands,
+    ));
+
+    let mut controller = SlideshowController::default();
+    if let Some(splash) = splash {
+        controller = controller.with_splash(Arc::new(splash));
+    }
+    framebuffer::run(
+        framebuffer,
+        controller,
+        receiver,
+        pairing_pin,
+        warnings,
+        banner,
+    )
+    .await
+}
+
+/// Runs the slideshow without a window, logging each slide instead of showing it.
+async fn run_headless(settings: Settings, environment: Environment, slides: usize) -> Result<()> {
+    let ctx = screen_context(
+        headless::HEADLESS_SCREEN_WIDTH,
+        headless::HEADLESS_SCREEN_HEIGHT,
+    );
+    let (sender, receiver) = channel(8);
+    let (pairing_pin_sender, _pairing_pin) = watch::channel(None);
+    let (warnings_sender, _warnings) = watch::channel(Vec::new());
+    let (banner_sender, _banner) = watch::channel(None);
+    let (paused_sender, _paused) = watch::channel(false);
+    let sender = UiSender::new(
+        sender,
+        pairing_pin_sender,
+        warnings_sender,
+        banner_sender,
+        paused_sender,
+        ctx.clone(),
+    );
+    let (command_sender, commands) = channel(8);
+    let history = History::new(environment.data_directory.join(HISTORY_FILE));
+    task::spawn(image_load_loop(
+        sender,
+        ctx,
+        settings,
+        environment,
+        command_sender.clone(),
+        commands,
+    ));
+    headless::run(receiver, command_sender, history, slides).await
+}
+
+/// Creates an egui context for drawing without a window, since images are sized to fit egui's
+/// screen.
+fn screen_context(width: usize, height: usize) -> egui::Context {
+    let ctx = egui::Context::default();
+    // Nothing is drawn, so there is no output to use.
+    let _ = ctx.run(
+        egui::RawInput {
+            screen_rect: Some(Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(width as f32, height as f32),
+            )),
+            ..Default::default()
+        },
+        |_| {},
+    );
+    ctx
 }
 
-unsafe impl Send for AppState {}
-unsafe impl Sync for AppState {}
+fn viewport(settings: &Settings) -> ViewportBuilder {
+    let viewport = if let Some(widget) = settings.widget {
+        // The widget is moved into its corner once the monitor's size is known.
+        ViewportBuilder::default()
+            .with_inner_size(widget.size)
+            .with_window_level(WindowLevel::AlwaysOnTop)
+            .with_decorations(false)
+            .with_taskbar(false)
+    } else if settings.background {
+        // eframe doesn't support Wayland's layer-shell, so the closest it can get to being the
+        // desktop background is a borderless, maximized window that stays below everything else.
+        ViewportBuilder::default()
+            .with_window_level(WindowLevel::AlwaysOnBottom)
+            .with_decorations(false)
+            .with_maximized(true)
+            .with_taskbar(false)
+    } else if settings.windowed {
+        match settings.window_size {
+            Some(size) => ViewportBuilder::default().with_inner_size(size),
+            None => ViewportBuilder::default(),
+        }
+    } else {
+        ViewportBuilder::default().with_fullscreen(true)
+    };
+    // Fullscreen and maximized windows fill whichever monitor they are placed on.
+    let viewport = match settings.window_position {
+        Some(position) => viewport.with_position(position),
+        None => viewport,
+    };
+    viewport.with_mouse_passthrough(settings.click_through)
+}
 
 struct Slideshow {
-    current_state: Result<AppState>,
+    controller: SlideshowController,
     incoming_state: Receiver<Result<AppState>>,
-    state_sender: Sender<Result<AppState>>,
-    previous_image: Option<ColorImage>,
+    pairing_pin: watch::Receiver<Option<String>>,
+    /// The textures that are on screen and the images they were loaded from, so that an image is
+    /// only uploaded again once it changes.
+    textures: HashMap<&'static str, (Arc<ColorImage>, TextureHandle)>,
+    warnings: watch::Receiver<Vec<String>>,
+    banner: watch::Receiver<Option<String>>,
+    paused: watch::Receiver<bool>,
+    /// Widget that still needs to be moved into position.
+    unplaced_widget: Option<Widget>,
+    commands: Sender<RemoteCommand>,
+    offer_sample_config: bool,
+    screenshot_directory: PathBuf,
+    /// Used to warn about screenshots that couldn't be saved.
+    ui_sender: UiSender,
+    /// PIN that must be entered before the slideshow can be closed, if any.
+    exit_pin: Option<String>,
+    /// What has been typed so far, while asking for the exit PIN.
+    pin_entry: Option<String>,
+    /// How far to turn everything that is drawn, for a screen that is mounted on its side.
+    rotation: Option<Rotation>,
+    /// Size of the window before it was turned, once it is known.
+    physical_screen: Option<egui::Vec2>,
+}
+
+/// The channels to and from the image load loop, and the settings, that a slideshow is created with.
+struct SlideshowParts {
+    incoming_state: Receiver<Result<AppState>>,
+    pairing_pin: watch::Receiver<Option<String>>,
+    warnings: watch::Receiver<Vec<String>>,
+    banner: watch::Receiver<Option<String>>,
+    paused: watch::Receiver<bool>,
+    commands: Sender<RemoteCommand>,
+    widget: Option<Widget>,
+    offer_sample_config: bool,
+    screenshot_directory: PathBuf,
+    ui_sender: UiSender,
+    exit_pin: Option<String>,
+    rotation: Option<Rotation>,
 }
 
 impl Slideshow {
-    fn new(
-        image_receiver: Receiver<Result<AppState>>,
-        state_sender: Sender<Result<AppState>>,
-    ) -> Self {
+    fn new(controller: SlideshowController, parts: SlideshowParts) -> Self {
+        let SlideshowParts {
+            incoming_state,
+            pairing_pin,
+            warnings,
+            banner,
+            paused,
+            commands,
+            widget,
+            offer_sample_config,
+            screenshot_directory,
+            ui_sender,
+            exit_pin,
+            rotation,
+        } = parts;
         Self {
-            current_state: Ok(AppState::LoadingImage),
-            incoming_state: image_receiver,
-            state_sender,
-            previous_image: None,
+            controller,
+            incoming_state,
+            pairing_pin,
+            textures: HashMap::new(),
+            warnings,
+            banner,
+            paused,
+            unplaced_widget: widget,
+            commands,
+            offer_sample_config,
+            screenshot_directory,
+            ui_sender,
+            exit_pin,
+            pin_entry: None,
+            rotation,
+            physical_screen: None,
         }
     }
 }
 
 impl eframe::App for Slideshow {
+    fn raw_input_hook(&mut self, _ctx: &egui::Context, raw_input: &mut egui::RawInput) {
+        if let Some(rotation) = self.rotation {
+            self.physical_screen = rotation.rotate_input(raw_input).or(self.physical_screen);
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // If it's been long enough between updates, then start getting another image and switch images.
         match self.incoming_state.try_recv() {
-            Ok(new_state) => {
-                let mut old_state = new_state;
-                core::mem::swap(&mut self.current_state, &mut old_state);
-                self.previous_image = if let Ok(AppState::HasImage(image)) = old_state {
-                    Some(image)
-                } else {
-                    None
-                };
-            }
+            Ok(new_state) => self
+                .controller
+                .handle_event(SlideshowEvent::StateChanged(new_state)),
             Err(TryRecvError::Disconnected) => process::exit(1),
             _ => (),
         }
+        self.controller.handle_event(SlideshowEvent::Tick);
+        if let Some(delay) = self.controller.returns_to_live_in() {
+            ctx.request_repaint_after(delay);
+        }
+        if self.pairing_pin.has_changed().unwrap_or(false) {
+            let pin = self.pairing_pin.borrow_and_update().clone();
+            self.controller
+                .handle_event(SlideshowEvent::PairingPinChanged(pin));
+        }
+        if self.warnings.has_changed().unwrap_or(false) {
+            let warnings = self.warnings.borrow_and_update().clone();
+            self.controller
+                .handle_event(SlideshowEvent::WarningsChanged(warnings));
+        }
+        if self.banner.has_changed().unwrap_or(false) {
+            let banner = self.banner.borrow_and_update().clone();
+            self.controller
+                .handle_event(SlideshowEvent::BannerChanged(banner));
+        }
+        if self.paused.has_changed().unwrap_or(false) {
+            let paused = *self.paused.borrow_and_update();
+            self.controller
+                .handle_event(SlideshowEvent::PausedChanged(paused));
+        }
+        // Keyboard controls, e.g. for a kiosk PC without a mouse. Like the remote control, if the loop
+        // is busy with another command then the key can be pressed again. They're ignored while the
+        // exit PIN is being typed.
+        let (next, previous, toggle_pause, quit, close_requested) = ctx.input(|input| {
+            let keys = self.pin_entry.is_none();
+            (
+                keys && input.key_pressed(egui::Key::ArrowRight),
+                keys && input.key_pressed(egui::Key::ArrowLeft),
+                keys && input.key_pressed(egui::Key::Space),
+                input.key_pressed(egui::Key::Escape),
+                input.viewport().close_requested(),
+            )
+        });
+        if next {
+            self.commands.try_send(RemoteCommand::Next).ok();
+        }
+        if previous {
+            self.commands.try_send(RemoteCommand::Previous).ok();
+        }
+        if toggle_pause {
+            self.commands.try_send(RemoteCommand::TogglePause).ok();
+        }
+        if self.exit_pin.is_some() && (quit || close_requested) {
+            // Closing the window some other way (e.g., Alt+F4) also asks for the PIN.
+            if close_requested {
+                ctx.send_viewport_cmd(ViewportCommand::CancelClose);
+            }
+            // Pressing Escape again stops asking.
+            self.pin_entry = match self.pin_entry {
+                Some(_) if quit => None,
+                _ => Some(String::new()),
+            };
+        } else if quit {
+            ctx.send_viewport_cmd(ViewportCommand::Close);
+        }
+        let screenshots = ctx.input(|input| {
+            input
+                .events
+                .iter()
+                .filter_map(|event| match event {
+                    egui::Event::Screenshot { image, .. } => Some(Arc::clone(image)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        });
+        for screenshot in screenshots {
+            let path = self.screenshot_directory.join(format!(
+                "screenshot-{}.png",
+                Local::now().format("%Y%m%d-%H%M%S")
+            ));
+            let ui_sender = self.ui_sender.clone();
+            // Encoding is too slow to do while drawing.
+            task::spawn_blocking(move || {
+                if let Err(err) = save_screenshot(&path, &screenshot) {
+                    ui_sender.add_warning(format!("{err:#}"));
+                }
+            });
+        }
+        if let Some(widget) = self.unplaced_widget {
+            if let Some(monitor_size) = ctx.input(|input| input.viewport().monitor_size) {
+                ctx.send_viewport_cmd(ViewportCommand::OuterPosition(
+                    widget.position(monitor_size),
+                ));
+                self.unplaced_widget = None;
+            }
+        }
 
         let response = egui::CentralPanel::default().show(ctx, |ui|
             ui.centered_and_justified(|ui|
-                 match &self.current_state {
+                 match self.controller.state() {
                     Ok(AppState::LoadingImage) => {
-                        ui.spinner();
+                        if let Some(splash) = self.controller.splash() {
+                            let texture = slide_texture(&mut self.textures, ctx, "splash_image", splash);
+                            ui.add(egui::Image::new(&texture).shrink_to_fit());
+                        } else {
+                            ui.spinner();
+                        }
                     }
-                    Ok(AppState::HasImage(image)) => {
+                    Ok(AppState::HasImage(slide)) => {
                         ctx.set_cursor_icon(egui::CursorIcon::None);
-                        let texture = ctx.load_texture("downloaded_image", image.clone(), Default::default());
-                        ui.add(egui::Image::new(&texture).shrink_to_fit());
+                        let texture =
+                            slide_texture(&mut self.textures, ctx, "downloaded_image", &slide.image);
+                        let mut image = egui::Image::new(&texture).shrink_to_fit();
+                        if let Some(part) = self.controller.ken_burns_part() {
+                            image = image.uv(part);
+                            ctx.request_repaint_after(KEN_BURNS_FRAME_TIME);
+                        }
+                        if let Some((previous, progress)) = self.controller.fading_from() {
+                            // Fade the previous slide out while the new one fades in over it.
+                            let previous_texture = slide_texture(&mut self.textures, ctx, "previous_image", &previous.image);
+                            let mut previous_image = egui::Image::new(&previous_texture);
+                            if let Some(ken_burns) = previous.ken_burns {
+                                previous_image = previous_image.uv(ken_burns.to);
+                            }
+                            previous_image
+                                .tint(Color32::WHITE.gamma_multiply(1.0 - progress))
+                                .paint_at(ui, fit_image(ui.max_rect(), previous.image.size));
+                            image = image.tint(Color32::WHITE.gamma_multiply(progress));
+                            ctx.request_repaint();
+                        }
+                        ui.add(image);
+                    }
+                    Ok(AppState::WaitingForNetwork) => {
+                        ui.label(RichText::new("Waiting for the network to connect...").size(20.0).color(Color32::WHITE));
+                    }
+                    Ok(AppState::CaptivePortal(sign_in_url)) => {
+                        let sign_in_page = sign_in_url.as_ref().map_or(String::new(), |url| format!(" (the sign-in page is {url})"));
+                        ui.label(RichText::new(format!("This network requires signing in before the slideshow can connect to OneDrive. Open a web browser on this device to sign in to the network{sign_in_page}.")).size(20.0).color(Color32::WHITE));
                     }
                     Ok(AppState::WaitingForAuth(auth_url, code)) => {
                         ui.label(RichText::new(format!("Authorize the slideshow to read from your OneDrive by opening {auth_url} in a browser and entering the code {code}")).size(20.0).color(Color32::WHITE));
                     }
+                    Err(err) if self.offer_sample_config && matches!(err.downcast_ref::<ConfigError>(), Some(ConfigError::Missing)) => {
+                        ui.vertical_centered(|ui| {
+                            ui.label(RichText::new("There's no slideshow.txt in the root of your OneDrive yet. Create one that shows your Pictures folder?").size(20.0).color(Color32::WHITE));
+                            if ui.button(RichText::new("Create slideshow.txt").size(20.0)).clicked() {
+                                // If the loop is already busy with another command, then the button can be clicked again.
+                                self.commands.try_send(RemoteCommand::CreateSampleConfig).ok();
+                            }
+                        });
+                    }
                     Err(err) => {
                         ui.colored_label(ui.visuals().error_fg_color, format!("{err:?}")); // something went wrong
                     }
                 })).response;
 
-        if response.interact(Sense::click()).clicked() {
-            if let Some(image) = self.previous_image.take() {
-                let sender = self.state_sender.clone();
-                let ctx = ctx.clone();
-                tokio::spawn(async move {
-                    send_update(&sender, &ctx, Ok(AppState::HasImage(image))).await
+        if let Some(pin) = self.controller.pairing_pin() {
+            egui::Area::new(egui::Id::new("pairing_pin"))
+                .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(
+                            RichText::new(format!("Remote control pairing PIN: {pin}"))
+                                .size(40.0)
+                                .color(Color32::WHITE),
+                        );
+                    });
+                });
+        } else if let Some(banner) = self.controller.banner() {
+            // The PIN is only shown briefly, so it takes the banner's place.
+            egui::Area::new(egui::Id::new("banner"))
+                .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(RichText::new(banner).size(40.0).color(Color32::WHITE));
+                    });
+                });
+        }
+
+        if let Ok(AppState::HasImage(Slide {
+            caption: Some(caption),
+            theme,
+            ..
+        })) = self.controller.state()
+        {
+            egui::Area::new(egui::Id::new("caption"))
+                .anchor(egui::Align2::LEFT_BOTTOM, [40.0, -40.0])
+                .show(ctx, |ui| {
+                    let mut frame = egui::Frame::popup(ui.style());
+                    if let Some(accent) = theme.accent_color {
+                        frame = frame.stroke(egui::Stroke::new(2.0, accent.0));
+                    }
+                    frame.show(ui, |ui| {
+                        ui.label(
+                            RichText::new(caption)
+                                .size(24.0)
+                                .color(theme.caption_color.map_or(Color32::WHITE, |color| color.0)),
+                        );
+                    });
+                });
+        }
+
+        let (photo_strip, moves_in) = self.controller.photo_strip();
+        if !photo_strip.is_empty() {
+            let textures = photo_strip
+                .into_iter()
+                .zip(PHOTO_STRIP_TEXTURES)
+                .map(|(thumbnail, name)| slide_texture(&mut self.textures, ctx, name, thumbnail))
+                .collect::<Vec<_>>();
+            ctx.request_repaint_after(moves_in);
+            egui::Area::new(egui::Id::new("photo_strip"))
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -20.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            for texture in &textures {
+                                ui.add(
+                                    egui::Image::new(texture).max_height(PHOTO_STRIP_HEIGHT as f32),
+                                );
+                            }
+                        });
+                    });
+                });
+        }
+
+        if let (Some((progress, interval)), Ok(AppState::HasImage(slide))) =
+            (self.controller.progress(), self.controller.state())
+        {
+            let screen = ctx.screen_rect();
+            if progress < 1.0 {
+                // Redraw when the bar has grown by about a pixel.
+                let pixels = screen.width() * ctx.pixels_per_point();
+                ctx.request_repaint_after(
+                    interval
+                        .div_f32(pixels.max(1.0))
+                        .max(PROGRESS_BAR_FRAME_TIME),
+                );
+            }
+            ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("progress_bar"),
+            ))
+            .rect_filled(
+                egui::Rect::from_min_max(
+                    egui::pos2(screen.left(), screen.bottom() - PROGRESS_BAR_HEIGHT),
+                    egui::pos2(screen.left() + screen.width() * progress, screen.bottom()),
+                ),
+                0.0,
+                slide
+                    .theme
+                    .accent_color
+                    .map_or(Color32::from_white_alpha(160), |color| color.0),
+            );
+        }
+
+        if let Some((title, remaining)) = self.controller.title_card() {
+            ctx.request_repaint_after(remaining);
+            let screen = ctx.screen_rect();
+            egui::Area::new(egui::Id::new("title_card"))
+                .fixed_pos(screen.min)
+                .show(ctx, |ui| {
+                    let painter = ui.painter();
+                    painter.rect_filled(screen, 0.0, Color32::from_black_alpha(200));
+                    painter.text(
+                        screen.center(),
+                        egui::Align2::CENTER_CENTER,
+                        title,
+                        egui::FontId::proportional(48.0),
+                        Color32::WHITE,
+                    );
+                });
+        }
+
+        if self.controller.is_paused() {
+            egui::Area::new(egui::Id::new("paused"))
+                .anchor(egui::Align2::RIGHT_TOP, [-40.0, 40.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(RichText::new("⏸ Paused").size(24.0).color(Color32::WHITE));
+                    });
+                });
+        }
+
+        if let Some(pin_entry) = &mut self.pin_entry {
+            let mut entered = false;
+            egui::Area::new(egui::Id::new("exit_pin"))
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.label(
+                            RichText::new("Enter the PIN to close the slideshow")
+                                .size(24.0)
+                                .color(Color32::WHITE),
+                        );
+                        let response = ui.add(
+                            egui::TextEdit::singleline(pin_entry)
+                                .password(true)
+                                .font(egui::TextStyle::Heading),
+                        );
+                        response.request_focus();
+                        entered = ui.input(|input| input.key_pressed(egui::Key::Enter));
+                    });
                 });
+            if entered {
+                if self.exit_pin.as_deref() == Some(pin_entry.as_str()) {
+                    self.exit_pin = None;
+                    ctx.send_viewport_cmd(ViewportCommand::Close);
+                }
+                self.pin_entry = None;
             }
         }
+
+        if !self.controller.warnings().is_empty() {
+            egui::Area::new(egui::Id::new("warnings"))
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -40.0])
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        for warning in self.controller.warnings() {
+                            ui.label(
+                                RichText::new(warning)
+                                    .size(20.0)
+                                    .color(ui.visuals().warn_fg_color),
+                            );
+                        }
+                    });
+                });
+        }
+
+        let response = response.interact(Sense::click());
+        if response.clicked() {
+            self.controller.handle_event(SlideshowEvent::Clicked);
+        }
+        // Double-clicking also clicks twice, which swaps to the previous image and back again.
+        if response.double_clicked() {
+            self.commands.try_send(RemoteCommand::TogglePause).ok();
+        }
+
+        if let (Some(rotation), Some(physical_screen)) = (self.rotation, self.physical_screen) {
+            rotation.rotate_painted(ctx, physical_screen);
+        }
+    }
+}
+
+/// Gets the texture for a slide's image, only uploading it if it isn't the image that was drawn last
+/// time.
+fn slide_texture(
+    textures: &mut HashMap<&'static str, (Arc<ColorImage>, TextureHandle)>,
+    ctx: &egui::Context,
+    name: &'static str,
+    image: &Arc<ColorImage>,
+) -> TextureHandle {
+    if let Some((drawn, texture)) = textures.get(name) {
+        if Arc::ptr_eq(drawn, image) {
+            return texture.clone();
+        }
+    }
+    let texture = ctx.load_texture(name, ColorImage::clone(image), Default::default());
+    textures.insert(name, (Arc::clone(image), texture.clone()));
+    texture
+}
+
+/// Where an image of `size` is drawn within `available`: centered, and shrunk to fit if needed.
+fn fit_image(available: egui::Rect, [width, height]: [usize; 2]) -> egui::Rect {
+    let size = egui::vec2(width as f32, height as f32);
+    let scale = (available.width() / size.x)
+        .min(available.height() / size.y)
+        .min(1.0);
+    egui::Rect::from_center_size(available.center(), size * scale)
+}
+
+fn save_screenshot(path: &Path, screenshot: &ColorImage) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
     }
+    std::fs::write(path, status::encode_png(screenshot)?)
+        .with_context(|| format!("Saving screenshot to {}", path.display()))
+}
+
+fn load_splash(path: &Path) -> Result<ColorImage> {
+    let data =
+        std::fs::read(path).with_context(|| format!("Reading splash image {}", path.display()))?;
+    image_loader::decode_image(&data)
+        .with_context(|| format!("Decoding splash image {}", path.display()))
 }
 
 struct ImageList {
-    images: Vec<String>,
+    images: Vec<Image>,
+    /// Directories in slideshow.txt that don't exist.
+    missing_directories: Vec<String>,
+    /// Settings in slideshow.txt that weren't recognized, which are likely misspelled.
+    unknown_fields: Vec<String>,
+    /// Directories that couldn't be listed, along with the set of images they are for.
+    failed_directories: Vec<(ImageSet, FailedDirectory)>,
+    retry_failed_after: Instant,
+    playlists: Vec<PlaylistImages>,
+    special_days: Vec<SpecialDayImages>,
+    pinned: Option<PinnedImages>,
     interval: Duration,
+    interval_rules: Option<IntervalRules>,
+    folder_captions: bool,
+    /// Whether to caption slides with their file name, folder and when they were taken.
+    photo_info: bool,
+    /// Template for captions, which takes priority over `photo_info` and `folder_captions`.
+    caption: Option<String>,
+    /// Template for the title cards shown when the slideshow moves on to a new folder.
+    title_card: Option<String>,
+    places: Vec<Place>,
+    folder_themes: HashMap<String, FolderTheme>,
+    avoid_repeating_folders: bool,
+    directory_weights: HashMap<String, f64>,
+    /// How long to crossfade between slides.
+    fade: Duration,
+    /// Whether to pan and zoom across each slide.
+    ken_burns: bool,
+    /// How long a slide has to be shown for to get a photo strip, if at all.
+    photo_strip_after: Option<Duration>,
+    /// Whether to show a progress bar counting down to the next slide.
+    progress_bar: bool,
+    /// Only images of this shape are shown, if set.
+    orientation: Option<OrientationSetting>,
+    order: Order,
+    /// Only images taken within this range are shown.
+    date_range: DateRange,
     refresh_after: Instant,
 }
 
-async fn image_load_loop(ui_sender: Sender<Result<AppState>>, ctx: egui::Context) {
-    let (auth_sender, mut auth_receiver) = channel(8);
-    let captured_ui_sender = ui_sender.clone();
-    let captured_ctx = ctx.clone();
-    let _auth_manager = task::spawn(async move {
-        while let Some(message) = auth_receiver.recv().await {
-            match message {
-                AuthMessage::HasClientCode(auth_url, code) => {
-                    send_update(
-                        &captured_ui_sender,
-                        &captured_ctx,
-                        Ok(AppState::WaitingForAuth(auth_url, code)),
-                    )
-                    .await;
-                }
-                AuthMessage::Completed => {
-                    send_update(
-                        &captured_ui_sender,
-                        &captured_ctx,
-                        Ok(AppState::LoadingImage),
-                    )
-                    .await;
+/// One of the sets of images in an `ImageList`.
+#[derive(Clone, Copy, Debug)]
+enum ImageSet {
+    Default,
+    Playlist(usize),
+    SpecialDay(usize),
+}
+
+struct PlaylistImages {
+    days: Vec<Weekday>,
+    images: Vec<Image>,
+}
+
+struct SpecialDayImages {
+    date: SpecialDate,
+    banner: Option<String>,
+    /// Images to show instead of the default directories, if the day has its own directories.
+    images: Option<Vec<Image>>,
+}
+
+struct PinnedImages {
+    every: u64,
+    images: Vec<Image>,
+}
+
+impl ImageList {
+    /// IDs of every image in the list, including playlists, special days and pinned images.
+    fn image_ids(&self) -> HashSet<String> {
+        let playlists = self.playlists.iter().flat_map(|playlist| &playlist.images);
+        let special_days = self
+            .special_days
+            .iter()
+            .flat_map(|special_day| special_day.images.iter().flatten());
+        let pinned = self.pinned.iter().flat_map(|pinned| &pinned.images);
+        self.images
+            .iter()
+            .chain(playlists)
+            .chain(special_days)
+            .chain(pinned)
+            .map(|image| image.id.clone())
+            .collect()
+    }
+
+    /// Caption to show with a slide, if slideshow.txt asks for one.
+    fn caption_for(&self, item: &ShownItem) -> Option<String> {
+        let photo = self.metadata_for(item);
+        if let Some(template) = &self.caption {
+            return metadata::format_caption(template, &photo);
+        }
+        let folder = photo.folder;
+        if self.photo_info {
+            let info = [
+                item.name.clone(),
+                folder.map(str::to_string),
+                item.taken
+                    .map(|taken| taken.format("%-d %B %Y").to_string()),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+            return (!info.is_empty()).then(|| info.join(" · "));
+        }
+        if !self.folder_captions {
+            return None;
+        }
+        folder.map(|folder| format!("Folder: {folder}"))
+    }
+
+    /// Title card to show before a slide that starts a new folder, if slideshow.txt asks for them.
+    fn title_card_for(&self, item: &ShownItem) -> Option<String> {
+        metadata::format_caption(self.title_card.as_deref()?, &self.metadata_for(item))
+    }
+
+    /// What is known about a slide's photo, for filling in captions and title cards.
+    fn metadata_for<'a>(&self, item: &'a ShownItem) -> PhotoMetadata<'a> {
+        PhotoMetadata {
+            name: item.name.as_deref(),
+            // Images at the root of the drive aren't in a folder.
+            folder: item
+                .folder
+                .as_deref()
+                .and_then(|folder| folder.rsplit('/').next())
+                .filter(|folder| !folder.is_empty()),
+            taken: item.taken,
+            place: item
+                .location
+                .map(|location| metadata::place_name(&self.places, location)),
+        }
+    }
+
+    /// Colors of the caption for a slide, from the theme of the folder that it came from.
+    fn theme_for(&self, item: &ShownItem) -> FolderTheme {
+        item.folder
+            .as_deref()
+            .map_or_else(FolderTheme::default, |folder| {
+                image_loader::theme_for_folder(&self.folder_themes, folder)
+            })
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if !self.unknown_fields.is_empty() {
+            warnings.push(format!(
+                "These settings in slideshow.txt aren't recognized, so may be misspelled: {}",
+                self.unknown_fields.join(", ")
+            ));
+        }
+        if !self.missing_directories.is_empty() {
+            warnings.push(format!(
+                "These directories in slideshow.txt weren't found: {}",
+                self.missing_directories.join(", ")
+            ));
+        }
+        if !self.failed_directories.is_empty() {
+            let mut directories = self
+                .failed_directories
+                .iter()
+                .map(|(_, failed)| &*failed.directory)
+                .collect::<Vec<_>>();
+            directories.sort_unstable();
+            directories.dedup();
+            warnings.push(format!(
+                "Some images couldn't be listed and will be retried: {}",
+                directories.join(", ")
+            ));
+        }
+        warnings
+    }
+
+    /// Lists the directories that failed last time, adding their images to the list.
+    async fn retry_failed_directories(&mut self, loader: &impl SlideSource, token: &str) {
+        for (set, failed) in std::mem::take(&mut self.failed_directories) {
+            let listing = loader.retry_failed_directory(token, failed).await;
+            let images = match set {
+                ImageSet::Default => &mut self.images,
+                ImageSet::Playlist(index) => &mut self.playlists[index].images,
+                ImageSet::SpecialDay(index) => {
+                    self.special_days[index].images.get_or_insert_with(Vec::new)
                 }
+            };
+            images.extend(
+                listing
+                    .images
+                    .into_iter()
+                    .filter(|image| self.date_range.contains(image)),
+            );
+            self.failed_directories.extend(
+                listing
+                    .failed_directories
+                    .into_iter()
+                    .map(|failed| (set, failed)),
+            );
+        }
+        self.retry_failed_after = time_from_now(FAILED_DIRECTORY_RETRY_TIME);
+    }
+
+    /// Gets the images to choose from for the `slide_number`th slide (starting from 1) shown on
+    /// `date`: the pinned images if it's their turn, otherwise the first special day with its own
+    /// directories on that date, otherwise the first playlist scheduled for that day of the week,
+    /// otherwise the default directories.
+    fn images_for(&self, slide_number: u64, date: NaiveDate) -> &[Image] {
+        if let Some(pinned) = &self.pinned {
+            if !pinned.images.is_empty() && slide_number % pinned.every == 0 {
+                return &pinned.images;
             }
         }
-    });
 
-    let mut authenticator = Authenticator::new(
-        auth_sender,
-        "https://login.microsoftonline.com/consumers/oauth2/v2.0",
-        cred_store::get_refresh_token(),
-    );
-    let loader = ImageLoader::new(
-        "https://graph.microsoft.com/v1.0/me/drive",
-        std::env::temp_dir().join("onedrive_slideshow"),
-    );
-    let mut next_image = get_next_image(
-        &loader,
-        get_auth_token(&mut authenticator, &ui_sender, &ctx).await,
-        ctx.screen_rect(),
-        None,
-    );
-    let mut interval = Duration::ZERO;
-    loop {
-        tokio::time::sleep(interval).await;
+        if let Some(images) = self
+            .special_days
+            .iter()
+            .filter(|special_day| special_day.date.matches(date))
+            .find_map(|special_day| special_day.images.as_deref())
+        {
+            return images;
+        }
 
-        let all_images = match next_image.await {
-            Ok((image, all_images)) => {
-                interval = all_images.interval;
-                send_update(&ui_sender, &ctx, Ok(AppState::HasImage(image))).await;
-                Some(all_images)
-            }
-            Err((err, all_images)) => {
-                interval = ON_ERROR_REFRESH_TIME;
-                send_update(&ui_sender, &ctx, Err(err.context("Loading image"))).await;
-                all_images
-            }
+        let day = date.weekday();
+        self.playlists
+            .iter()
+            .find(|playlist| playlist.days.contains(&day))
+            .map_or(&self.images, |playlist| &playlist.images)
+    }
+
+    /// Gets the banner to show on `date`, if it's a special day that has one.
+    fn banner_for(&self, date: NaiveDate) -> Option<String> {
+        self.special_days
+            .iter()
+            .filter(|special_day| special_day.date.matches(date))
+            .find_map(|special_day| special_day.banner.clone())
+    }
+
+    /// Gets how long to show `image` for, after applying the interval rules.
+    fn interval_for(&self, image: &Image) -> Duration {
+        let Some(rules) = &self.interval_rules else {
+            return self.interval;
         };
 
-        next_image = get_next_image(
-            &loader,
-            get_auth_token(&mut authenticator, &ui_sender, &ctx).await,
-            ctx.screen_rect(),
-            all_images,
-        );
+        let mut multiplier = rules
+            .directories
+            .get(&*image.directory)
+            .copied()
+            .unwrap_or(1.0);
+        if let (Some(width), Some(height)) = (image.width, image.height) {
+            let aspect_ratio = f64::from(width) / f64::from(height);
+            if height > 0
+                && aspect_ratio
+                    >= rules
+                        .panorama_aspect_ratio
+                        .unwrap_or(DEFAULT_PANORAMA_ASPECT_RATIO)
+            {
+                multiplier *= rules
+                    .panorama_multiplier
+                    .unwrap_or(DEFAULT_PANORAMA_MULTIPLIER);
+            }
+        }
+
+        // Ignore nonsensical multipliers rather than failing to show the image.
+        Duration::try_from_secs_f64(self.interval.as_secs_f64() * multiplier)
+            .unwrap_or(self.interval)
     }
 }
 
-async fn get_auth_token(
-    authenticator: &mut Authenticator,
-    ui_sender: &Sender<Result<AppState>>,
-    ctx: &egui::Context,
-) -> String {
-    loop {
-        match authenticator.get_token().await {
-            Ok(token) => return token,
-            Err(err) => {
-                send_update(ui_sender, ctx, Err(err.context("Authenticating"))).await;
+/// Tracks the current image list, refreshing it in the background once it has expired so that the
+/// slideshow can continue from the old list in the meantime.
+struct ImageListManager {
+    current: Option<ImageList>,
+    pending_refresh: Option<JoinHandle<Result<ImageList>>>,
+    /// How many background refreshes in a row have failed.
+    refresh_failures: u32,
+    /// Why the last background refresh failed, if it did.
+    refresh_error: Option<String>,
+    refresh_jitter: f64,
+}
+
+impl ImageListManager {
+    fn new(refresh_jitter: f64) -> Self {
+        Self {
+            current: None,
+            pending_refresh: None,
+            refresh_failures: 0,
+            refresh_error: None,
+            refresh_jitter,
+        }
+    }
+
+    /// Warnings about the current image list, and about refreshing it.
+    fn warnings(&self) -> Vec<String> {
+        let mut warnings = self
+            .current
+            .as_ref()
+            .map_or_else(Vec::new, ImageList::warnings);
+        warnings.extend(self.refresh_error.clone());
+        warnings
+    }
+
+    async fn get<S: SlideSource>(&mut self, loader: &Arc<S>, token: &str) -> Result<&ImageList> {
+        // Swap in the new list if the background refresh has completed.
+        if self
+            .pending_refresh
+            .as_ref()
+            .map_or(false, |refresh| refresh.is_finished())
+        {
+            let refreshed = self
+                .pending_refresh
+                .take()
+                .unwrap()
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|refreshed| refreshed);
+            match refreshed {
+                Ok(new_list) => {
+                    self.current = Some(new_list);
+                    self.refresh_failures = 0;
+                    self.refresh_error = None;
+                }
+                Err(err) => {
+                    // Keep the old list, but back off before trying again so that an outage isn't
+                    // made worse by listing everything with every slide.
+                    let retry_in = IMAGE_LIST_RETRY_TIME
+                        .saturating_mul(1 << self.refresh_failures.min(6))
+                        .min(IMAGE_LIST_REFRESH_TIME);
+                    self.refresh_failures += 1;
+                    self.refresh_error = Some(format!(
+                        "The image list couldn't be refreshed, so the previous list is shown until it can be: {err}"
+                    ));
+                    if let Some(current) = &mut self.current {
+                        current.refresh_after = Instant::now() + retry_in;
+                    }
+                }
             }
         }
+
+        // Get the list inline only if we don't have one at all.
+        let mut current = match self.current.take() {
+            Some(current) => current,
+            None => get_image_list(&**loader, token, self.refresh_jitter).await?,
+        };
+
+        // Listing is put off until the daily budget resets, since the old list is still usable.
+        let cache_only = loader.cache_only_for().is_some();
+
+        // Rather than waiting for the next refresh, retry anything that couldn't be listed.
+        if !cache_only
+            && !current.failed_directories.is_empty()
+            && Instant::now() >= current.retry_failed_after
+        {
+            current.retry_failed_directories(&**loader, token).await;
+        }
+
+        // Check for expiry.
+        if !cache_only && self.pending_refresh.is_none() && Instant::now() >= current.refresh_after
+        {
+            let loader = loader.clone();
+            let token = token.to_string();
+            let refresh_jitter = self.refresh_jitter;
+            let refresh =
+                task::spawn(async move { get_image_list(&*loader, &token, refresh_jitter).await });
+            self.pending_refresh = Some(refresh);
+        }
+
+        Ok(&*self.current.insert(current))
     }
 }
 
-async fn get_next_image(
-    loader: &ImageLoader,
-    token: String,
-    size: Rect,
-    mut all_images: Option<ImageList>,
-) -> Result<(ColorImage, ImageList), (anyhow::Error, Option<ImageList>)> {
-    // Check for expiry.
-    if all_images
-        .as_ref()
-        .map_or(false, |list| Instant::now() >= list.refresh_after)
-    {
-        all_images = None;
+/// Images temporarily shown instead of the normal image list.
+struct GuestImages {
+    images: Vec<Image>,
+    until: Instant,
+}
+
+/// A slide that has been chosen to be shown soon.
+struct UpcomingSlide {
+    /// Which slide this was chosen for, since pinned images are only shown on some slides.
+    slide_number: u64,
+    image: Image,
+    /// The encoded image, once it has been downloaded.
+    data: Option<Bytes>,
+}
+
+/// The next few slides to show, in order.
+struct UpcomingSlides {
+    slides: VecDeque<UpcomingSlide>,
+    /// Images that have been skipped, which aren't chosen again unless there's nothing else to show.
+    skipped: HashSet<String>,
+    /// While only cached images are being shown, the images in the cache.
+    cached: Option<HashSet<String>>,
+    /// Whether to avoid showing two images from the same folder one after another.
+    avoid_repeating_folders: bool,
+    /// The only folders that images can be chosen from, if the folder lock is on.
+    allowed_folders: Option<Vec<String>>,
+    /// The only shape of image that can be chosen, if set.
+    orientation: Option<Orientation>,
+    /// What order images are chosen in.
+    order: Order,
+    /// How often images are chosen from each directory relative to the others, if set.
+    directory_weights: HashMap<String, f64>,
+    /// The slide that was loaded most recently, which the next slide shouldn't repeat.
+    last_loaded: Option<Image>,
+    /// When each image was last shown, from the history, so that images that were shown recently
+    /// are less likely to be chosen even once the image list has been refreshed.
+    last_shown: HashMap<String, DateTime<Local>>,
+    sender: watch::Sender<Vec<UpcomingItem>>,
+}
+
+impl UpcomingSlides {
+    fn new(sender: watch::Sender<Vec<UpcomingItem>>) -> Self {
+        Self {
+            slides: VecDeque::new(),
+            skipped: HashSet::new(),
+            cached: None,
+            avoid_repeating_folders: false,
+            allowed_folders: None,
+            orientation: None,
+            order: Order::Random,
+            directory_weights: HashMap::new(),
+            last_loaded: None,
+            last_shown: HashMap::new(),
+            sender,
+        }
     }
 
-    // Get the new list of images if we don't have one.
-    let all_images = if let Some(all_images) = all_images {
-        all_images
-    } else {
-        let (images, interval) = loader
-            .get_image_list(&token)
-            .await
-            .map_err(|err| (err, None))?;
-        ImageList {
-            images,
-            interval: Duration::from_secs(interval),
-            refresh_after: Instant::now().checked_add(IMAGE_LIST_REFRESH_TIME).unwrap(),
+    /// Chooses slides for any of the next few positions, starting at `first_slide`, that don't
+    /// have one yet.
+    fn plan<'a>(
+        &mut self,
+        first_slide: u64,
+        images_for: impl Fn(u64) -> &'a [Image],
+    ) -> Result<()> {
+        let mut planned = std::mem::take(&mut self.slides);
+        let mut previous = self.last_loaded.clone();
+        for slide_number in first_slide..first_slide + UPCOMING_SLIDES {
+            let images = images_for(slide_number);
+            // Slides that were chosen from a different list (e.g., before a guest folder was shown)
+            // are chosen again.
+            let slide = match planned
+                .iter()
+                .position(|slide| slide.slide_number == slide_number)
+            {
+                Some(index)
+                    if images
+                        .iter()
+                        .any(|image| image.id == planned[index].image.id)
+                        && (planned[index].data.is_some()
+                            || self.is_cached(&planned[index].image))
+                        && self.is_allowed(&planned[index].image) =>
+                {
+                    planned.remove(index).unwrap()
+                }
+                _ => UpcomingSlide {
+                    slide_number,
+                    image: self.choose(images, previous.as_ref())?,
+                    data: None,
+                },
+            };
+            previous = Some(slide.image.clone());
+            self.slides.push_back(slide);
+        }
+        self.publish();
+        Ok(())
+    }
+
+    /// Only chooses images that are in the cache, or stops doing so if `cached` is `None`.
+    fn set_cached(&mut self, cached: Option<HashSet<String>>) {
+        self.cached = cached;
+    }
+
+    /// Avoids (or stops avoiding) choosing two images from the same folder in a row.
+    fn set_avoid_repeating_folders(&mut self, avoid_repeating_folders: bool) {
+        self.avoid_repeating_folders = avoid_repeating_folders;
+    }
+
+    /// Only chooses images within `allowed_folders`, or any image if it is `None`.
+    fn set_allowed_folders(&mut self, allowed_folders: Option<Vec<String>>) {
+        self.allowed_folders = allowed_folders;
+    }
+
+    /// Only chooses images of the given shape, or of any shape if it is `None`.
+    fn set_orientation(&mut self, orientation: Option<Orientation>) {
+        self.orientation = orientation;
+    }
+
+    fn set_order(&mut self, order: Order) {
+        self.order = order;
+    }
+
+    /// Chooses a directory first (in proportion to its weight, or 1 if it doesn't have one) and
+    /// then an image within it, or any image equally if there are no weights.
+    fn set_directory_weights(&mut self, directory_weights: HashMap<String, f64>) {
+        self.directory_weights = directory_weights;
+    }
+
+    /// Remembers when images were last shown, e.g. from the history when the slideshow starts.
+    fn set_last_shown(&mut self, last_shown: HashMap<String, DateTime<Local>>) {
+        self.last_shown = last_shown;
+    }
+
+    fn record_shown(&mut self, image_id: String, time: DateTime<Local>) {
+        self.last_shown.insert(image_id, time);
+    }
+
+    /// How likely an image is to be chosen: images that were shown recently are less likely to be
+    /// chosen, until `SHOWN_IMAGE_RECOVERY_TIME` has passed.
+    fn weight(&self, image: &Image, now: DateTime<Local>) -> f64 {
+        let Some(shown) = self.last_shown.get(&image.id) else {
+            return 1.0;
+        };
+        let since = (now - *shown).to_std().unwrap_or_default();
+        (since.as_secs_f64() / SHOWN_IMAGE_RECOVERY_TIME.as_secs_f64())
+            .clamp(JUST_SHOWN_WEIGHT, 1.0)
+    }
+
+    /// How likely each of `candidates` is to be chosen because of its directory's weight, which is
+    /// shared between the images in that directory.
+    fn directory_shares(&self, candidates: &[&Image]) -> impl Fn(&Image) -> f64 + '_ {
+        let mut counts = HashMap::<Arc<str>, usize>::new();
+        if !self.directory_weights.is_empty() {
+            for image in candidates {
+                *counts.entry(image.directory.clone()).or_default() += 1;
+            }
+        }
+        move |image| match counts.get(&image.directory) {
+            Some(count) => {
+                let weight = self
+                    .directory_weights
+                    .get(&*image.directory)
+                    .copied()
+                    .filter(|weight| *weight > 0.0)
+                    .unwrap_or(1.0);
+                weight / *count as f64
+            }
+            None => 1.0,
+        }
+    }
+
+    fn is_allowed(&self, image: &Image) -> bool {
+        self.is_in_allowed_folder(image)
+            && self
+                .orientation
+                .map_or(true, |orientation| orientation.matches(image))
+    }
+
+    fn is_in_allowed_folder(&self, image: &Image) -> bool {
+        self.allowed_folders.as_ref().map_or(true, |allowed| {
+            allowed
+                .iter()
+                .any(|folder| image_loader::is_within_folder(&image.folder, folder))
+        })
+    }
+
+    /// Whether an image can be shown without downloading it, if only cached images are being shown.
+    fn is_cached(&self, image: &Image) -> bool {
+        self.cached
+            .as_ref()
+            .map_or(true, |cached| cached.contains(&image.id))
+    }
+
+    /// Picks an image that hasn't been skipped (and is cached, if only cached images are being
+    /// shown) and that doesn't repeat the `previous` slide. Each of these is given up, starting with
+    /// not repeating the previous slide, if there's nothing that meets them. Images outside of the
+    /// locked folders, or of the wrong orientation, are never chosen.
+    ///
+    /// In a random order, images that haven't been shown recently are favored. Otherwise, the image
+    /// is the next one after `previous` (or after the image that was shown last, when starting up).
+    fn choose(&self, images: &[Image], previous: Option<&Image>) -> Result<Image> {
+        let preferred = |image: &Image| !self.skipped.contains(&image.id) && self.is_cached(image);
+        let new_image = |image: &Image| previous.map_or(true, |previous| image.id != previous.id);
+        // Going through the images in order means staying in the same folder.
+        let new_folder = |image: &Image| {
+            !self.avoid_repeating_folders
+                || self.order != Order::Random
+                || previous.map_or(true, |previous| image.folder != previous.folder)
+        };
+        let allowed = |image: &Image| self.is_allowed(image);
+        let constraints: [&dyn Fn(&Image) -> bool; 4] = [
+            &|image| allowed(image) && preferred(image) && new_image(image) && new_folder(image),
+            &|image| allowed(image) && preferred(image) && new_image(image),
+            &|image| allowed(image) && preferred(image),
+            &allowed,
+        ];
+        if self.order == Order::Random {
+            let now = Local::now();
+            for constraint in constraints {
+                let candidates = images
+                    .iter()
+                    .filter(|image| constraint(image))
+                    .collect::<Vec<_>>();
+                if !candidates.is_empty() {
+                    let share = self.directory_shares(&candidates);
+                    let chosen = candidates.choose_weighted(&mut rand::thread_rng(), |image| {
+                        self.weight(image, now) * share(image)
+                    })?;
+                    return Ok((*chosen).clone());
+                }
+            }
+        } else {
+            let sorted = self.order.sort(images);
+            let start = match previous {
+                Some(previous) => sorted.iter().position(|image| image.id == previous.id),
+                // Pick up after the image that was shown last before the slideshow restarted.
+                None => sorted
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, image)| Some((self.last_shown.get(&image.id)?, index)))
+                    .max()
+                    .map(|(_, index)| index),
+            };
+            let next = start.map_or(0, |index| index + 1);
+            for constraint in constraints {
+                if let Some(chosen) = (0..sorted.len())
+                    .map(|offset| sorted[(next + offset) % sorted.len()])
+                    .find(|image| constraint(image))
+                {
+                    return Ok(chosen.clone());
+                }
+            }
+        }
+        // Either there are no images at all, or none of them can be shown on this frame.
+        image_loader::choose_image(images)?;
+        match self.orientation {
+            Some(orientation) if images.iter().any(|image| self.is_in_allowed_folder(image)) => {
+                bail!("None of the images are {orientation}")
+            }
+            _ => bail!("None of the images are within the locked folders"),
+        }
+    }
+
+    /// Downloads the upcoming slides that haven't been downloaded yet.
+    async fn prefetch(&mut self, loader: &impl SlideSource, token: &str, size: Rect) {
+        for index in 0..self.slides.len() {
+            let slide = &mut self.slides[index];
+            if slide.data.is_some() {
+                continue;
+            }
+            match loader
+                .load_image_data(
+                    token,
+                    size.height() as u32,
+                    size.width() as u32,
+                    &slide.image.id,
+                )
+                .await
+            {
+                Ok(data) => slide.data = Some(data),
+                // Try again when the slide is due to be shown, which will report the error.
+                Err(_) => break,
+            }
+            self.publish();
+        }
+    }
+
+    /// Loads the first upcoming slide, then removes it from the queue (even if it failed to load,
+    /// so that a broken image doesn't hold up the slideshow).
+    async fn load_next(
+        &mut self,
+        loader: &impl SlideSource,
+        token: &str,
+        size: Rect,
+    ) -> Result<(ColorImage, Image)> {
+        let slide = self.slides.front().context("No slides planned")?;
+        let (image_id, data) = (slide.image.id.clone(), slide.data.clone());
+        let image = match data {
+            Some(data) => loader.convert(data).await,
+            None => {
+                loader
+                    .load_image(token, size.height() as u32, size.width() as u32, &image_id)
+                    .await
+            }
+        };
+        let slide = self.slides.pop_front().unwrap();
+        self.last_loaded = Some(slide.image.clone());
+        self.publish();
+        Ok((image?, slide.image))
+    }
+
+    /// Removes an upcoming slide, so that another is chosen in its place, and stops it from being
+    /// chosen again.
+    fn skip(&mut self, image_id: &str) {
+        self.slides.retain(|slide| slide.image.id != image_id);
+        self.skipped.insert(image_id.to_string());
+        self.publish();
+    }
+
+    fn publish(&self) {
+        self.sender.send_replace(
+            self.slides
+                .iter()
+                .map(|slide| UpcomingItem {
+                    item_id: slide.image.id.clone(),
+                    directory: slide.image.directory.to_string(),
+                    ready: slide.data.is_some(),
+                })
+                .collect(),
+        );
+    }
+}
+
+/// The services and local storage that the slideshow uses.
+struct Environment {
+    auth_url: String,
+    drive_url: String,
+    data_directory: PathBuf,
+    token_store: TokenStore,
+    refresh_token: Option<String>,
+    captive_portal_probe_url: String,
+    /// Stand-in for OneDrive to show images from instead, without signing in (see `--mock`).
+    mock: Option<MockDrive>,
+}
+
+impl Environment {
+    fn onedrive(settings: &Settings) -> Self {
+        let (data_directory, token_store) = match (&settings.data_dir, &settings.profile) {
+            (Some(data_dir), _) => (
+                data_dir.clone(),
+                TokenStore::File(data_dir.join(REFRESH_TOKEN_FILE)),
+            ),
+            (None, profile) => (
+                std::env::temp_dir().join(match profile {
+                    Some(profile) => format!("onedrive_slideshow_{profile}"),
+                    None => "onedrive_slideshow".into(),
+                }),
+                TokenStore::System {
+                    profile: profile.clone(),
+                },
+            ),
+        };
+        Self {
+            auth_url: ONEDRIVE_AUTH_URL.into(),
+            drive_url: ONEDRIVE_DRIVE_URL.into(),
+            data_directory,
+            refresh_token: token_store.get_refresh_token(),
+            token_store,
+            captive_portal_probe_url: network::PROBE_URL.into(),
+            mock: None,
+        }
+    }
+
+    /// Shows the images in `drive` instead of OneDrive's, keeping its cache and history apart from
+    /// the real slideshow's.
+    fn mock(drive: MockDrive) -> Self {
+        let data_directory = std::env::temp_dir().join("onedrive_slideshow_mock");
+        Self {
+            auth_url: ONEDRIVE_AUTH_URL.into(),
+            drive_url: ONEDRIVE_DRIVE_URL.into(),
+            token_store: TokenStore::File(data_directory.join(REFRESH_TOKEN_FILE)),
+            refresh_token: None,
+            captive_portal_probe_url: network::PROBE_URL.into(),
+            data_directory,
+            mock: Some(drive),
+        }
+    }
+
+    /// Creates an authenticator that asks for the access that `settings` needs.
+    fn create_authenticator(
+        &self,
+        settings: &Settings,
+        sender: Sender<AuthMessage>,
+    ) -> Authenticator {
+        let mut authenticator =
+            Authenticator::new(sender, &self.auth_url, self.refresh_token.clone())
+                .with_token_store(self.token_store.clone())
+                .with_issued_record(self.data_directory.join(REFRESH_TOKEN_ISSUED_FILE));
+        if settings.report_status || settings.export_history {
+            authenticator = authenticator.with_app_folder_access();
+        }
+        if settings.offer_sample_config {
+            authenticator = authenticator.with_write_access();
+        }
+        authenticator
+    }
+}
+
+async fn image_load_loop(
+    ui_sender: UiSender,
+    ctx: egui::Context,
+    settings: Settings,
+    mut environment: Environment,
+    command_sender: Sender<RemoteCommand>,
+    commands: Receiver<RemoteCommand>,
+) {
+    let (auth_sender, mut auth_receiver) = channel(8);
+    let captured_ui_sender = ui_sender.clone();
+    let _auth_manager = task::spawn(async move {
+        while let Some(message) = auth_receiver.recv().await {
+            match message {
+                AuthMessage::HasClientCode(auth_url, code) => {
+                    captured_ui_sender
+                        .send(Ok(AppState::WaitingForAuth(auth_url, code)))
+                        .await;
+                }
+                AuthMessage::Completed => {
+                    captured_ui_sender.send(Ok(AppState::LoadingImage)).await;
+                }
+            }
+        }
+    });
+
+    // Show the slides that are already in the cache while signing in and listing images, unless
+    // the slideshow is locked to some folders, since the cached slides may be from anywhere.
+    if settings.locked_folders.is_empty() {
+        let history = History::new(environment.data_directory.join(HISTORY_FILE));
+        if let Ok(images) = video::recent_cached_images(
+            &history,
+            &environment.data_directory.join(CACHE_DIRECTORY),
+            WARM_UP_SLIDES,
+        ) {
+            ui_sender.show_cached_slides(images);
+        }
+    }
+
+    match environment.mock.take() {
+        Some(drive) => {
+            let drive = Drive {
+                authenticator: MockAuthenticator,
+                loader: drive,
+            };
+            show_slides(
+                drive,
+                ui_sender,
+                ctx,
+                settings,
+                environment,
+                command_sender,
+                commands,
+            )
+            .await;
+        }
+        None => {
+            let drive = Drive {
+                authenticator: environment.create_authenticator(&settings, auth_sender),
+                loader: create_image_loader(&settings, &environment),
+            };
+            // Give the device a chance to finish booting (e.g., connecting to Wi-Fi) before doing
+            // anything that needs the network.
+            tokio::time::sleep(settings.start_delay).await;
+            wait_for_network(&environment.auth_url, &ui_sender).await;
+            show_slides(
+                drive,
+                ui_sender,
+                ctx,
+                settings,
+                environment,
+                command_sender,
+                commands,
+            )
+            .await;
+        }
+    }
+}
+
+/// Where the slideshow signs in to and loads its images from.
+struct Drive<A, S> {
+    authenticator: A,
+    loader: S,
+}
+
+/// Shows slides from `drive` until the app is closed, handling the commands sent by the remote
+/// control along the way.
+async fn show_slides<A: TokenSource, S: SlideSource>(
+    drive: Drive<A, S>,
+    ui_sender: UiSender,
+    ctx: egui::Context,
+    settings: Settings,
+    environment: Environment,
+    command_sender: Sender<RemoteCommand>,
+    mut commands: Receiver<RemoteCommand>,
+) {
+    let Drive {
+        mut authenticator,
+        mut loader,
+    } = drive;
+    let mut image_lists =
+        ImageListManager::new(get_refresh_jitter(&environment.data_directory).await);
+    let captive_portal = CaptivePortalDetector::new(&environment.captive_portal_probe_url);
+    loop {
+        let token = get_auth_token(&mut authenticator, &captive_portal, &ui_sender).await;
+        match loader.connect(&token).await {
+            Ok(()) => break,
+            Err(err) => {
+                report_error(
+                    err.context("Detecting drive type"),
+                    &captive_portal,
+                    &ui_sender,
+                )
+                .await;
+                tokio::time::sleep(ON_ERROR_REFRESH_TIME).await;
+            }
+        }
+    }
+    let loader = Arc::new(loader);
+
+    let (current_image_sender, current_image_receiver) = watch::channel(None);
+    let (upcoming_sender, upcoming_receiver) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(upcoming_sender);
+    let mut folder_lock = FolderLock::load(
+        settings.locked_folders.clone(),
+        environment.data_directory.join(UNLOCKED_FILE),
+    );
+    upcoming.set_allowed_folders(folder_lock.allowed_folders());
+    // Keeps the remote control server advertised for as long as the slideshow is running.
+    let _advertisement = match settings.remote_control {
+        Some(address) => {
+            let parts = RemoteControlParts {
+                address,
+                device_name: settings.device_name(),
+                tokens_path: environment.data_directory.join(REMOTE_TOKENS_FILE),
+                lock_pin: settings.lock_pin.clone(),
+                sender: command_sender,
+                current_image: current_image_receiver,
+                upcoming: upcoming_receiver,
+            };
+            start_remote_control(parts, &ui_sender).await
+        }
+        None => None,
+    };
+
+    let mut slides_shown = 0;
+    let mut playback = Playback::default();
+    let mut status = Status::new(settings.device_name());
+    let history = History::new(environment.data_directory.join(HISTORY_FILE));
+    let summary = Arc::new(MonthlySummary::new(
+        environment.data_directory.join(HISTORY_SUMMARY_DIRECTORY),
+    ));
+    let mut report_status_at = Instant::now();
+    let mut collect_garbage_at = Instant::now();
+    let mut export_history_at = time_from_now(HISTORY_EXPORT_INTERVAL);
+    let mut last_folder = None;
+    // Images that were shown before the slideshow was restarted are still less likely to be chosen.
+    upcoming.set_last_shown(history.last_shown().unwrap_or_default());
+    loop {
+        // Fully prepare the next slide while the current one is still on screen, and only then wait
+        // out the rest of the current slide's interval. This way the interval measures how long a
+        // slide is on screen, rather than also including how long the next one took to download.
+        let keep_signed_in_at = time_from_now(KEEP_SIGNED_IN_INTERVAL);
+        let woken = tokio::select! {
+            () = async {
+                if playback.needs_preparing() {
+                    // Stop downloading while the battery is low, but leave the current slide up.
+                    while PowerStatus::current().is_battery_low() {
+                        tokio::time::sleep(LOW_BATTERY_CHECK_INTERVAL).await;
+                    }
+                    let token = get_auth_token(&mut authenticator, &captive_portal, &ui_sender).await;
+                    let next_image = get_next_image(
+                        &loader,
+                        &token,
+                        screen_pixels(&ctx),
+                        &mut image_lists,
+                        playback.guest(),
+                        slides_shown + 1,
+                        &mut upcoming,
+                    )
+                    .await
+                    .context("Loading image");
+                    // Along with the thumbnails for its photo strip, if it is shown for long enough.
+                    let photo_strip = match (&next_image, image_lists.current.as_ref()) {
+                        (Ok((_, interval, item)), Some(image_list))
+                            if image_list
+                                .photo_strip_after
+                                .is_some_and(|after| *interval >= after) =>
+                        {
+                            load_photo_strip(&*loader, &token, &image_list.images, item).await
+                        }
+                        _ => Vec::new(),
+                    };
+                    playback.prepare(next_image, photo_strip);
+                }
+                // Download the slides after this one while waiting to show it.
+                let token = get_auth_token(&mut authenticator, &captive_portal, &ui_sender).await;
+                let show_at = playback.wait_until();
+                tokio::select! {
+                    () = upcoming.prefetch(&*loader, &token, screen_pixels(&ctx)) => {}
+                    () = wait_to_show(show_at) => {}
+                }
+                wait_to_show(show_at).await;
+            } => Woken::Ready,
+            Some(command) = commands.recv() => Woken::Command(command),
+            () = tokio::time::sleep_until(keep_signed_in_at) => Woken::KeepSignedIn,
+        };
+
+        // Waiting to show the slide may have taken long enough for the token to expire, so the
+        // commands get a fresh one.
+        let token = get_auth_token(&mut authenticator, &captive_portal, &ui_sender).await;
+        let mut photo_strip = Vec::new();
+        let next_image = match woken {
+            Woken::Ready => {
+                let (next_image, strip) = playback.take_prepared().unwrap();
+                photo_strip = strip;
+                next_image
+            }
+            Woken::Command(RemoteCommand::ShowFolder { folder, .. })
+                if !folder_lock.allows(&folder) =>
+            {
+                Err(anyhow!("{folder} isn't one of the locked folders").context("Showing folder"))
+            }
+            Woken::Command(RemoteCommand::ShowFolder { folder, duration }) => {
+                match get_guest_images(&*loader, &token, folder, duration).await {
+                    Ok(guest_images) => {
+                        playback.show_guest(guest_images);
+                        continue;
+                    }
+                    Err(err) => Err(err.context("Showing folder")),
+                }
+            }
+            Woken::Command(RemoteCommand::ShowItem(_)) if folder_lock.is_locked() => {
+                // Items are looked up by ID, so there's no telling which folder they are in.
+                Err(anyhow!("Only images in the locked folders can be shown")
+                    .context("Showing item"))
+            }
+            Woken::Command(RemoteCommand::ShowItem(item)) => {
+                get_requested_image(&loader, &token, screen_pixels(&ctx), &mut image_lists, item)
+                    .await
+                    .context("Showing item")
+            }
+            // Getting the fresh token above was all that was needed.
+            Woken::KeepSignedIn => continue,
+            Woken::Command(RemoteCommand::Next) => {
+                playback.next();
+                continue;
+            }
+            Woken::Command(RemoteCommand::Skip(image_id)) => {
+                upcoming.skip(&image_id);
+                continue;
+            }
+            Woken::Command(RemoteCommand::Previous) => {
+                if let Some(slide) = playback.previous() {
+                    current_image_sender.send_replace(Some(Arc::clone(&slide.image)));
+                    ui_sender.send(Ok(AppState::HasImage(slide))).await;
+                }
+                continue;
+            }
+            Woken::Command(
+                command @ (RemoteCommand::Pause
+                | RemoteCommand::Resume
+                | RemoteCommand::TogglePause),
+            ) => {
+                let pause = match command {
+                    RemoteCommand::Pause => true,
+                    RemoteCommand::Resume => false,
+                    _ => !playback.is_paused(),
+                };
+                playback.set_paused(pause);
+                ui_sender.show_paused(playback.is_paused());
+                continue;
+            }
+            Woken::Command(RemoteCommand::Screenshot) => {
+                ui_sender.take_screenshot();
+                continue;
+            }
+            Woken::Command(RemoteCommand::SetLocked(locked)) => {
+                match folder_lock.set_locked(locked).await {
+                    Ok(()) => {
+                        // Upcoming slides outside of the folders are chosen again, and the next
+                        // slide is replaced straight away in case it is outside of them too.
+                        upcoming.set_allowed_folders(folder_lock.allowed_folders());
+                        if locked {
+                            playback.restart();
+                        }
+                        continue;
+                    }
+                    Err(err) => Err(err.context("Changing the folder lock")),
+                }
+            }
+            Woken::Command(RemoteCommand::CreateSampleConfig) => {
+                match loader.create_sample_config(&token).await {
+                    Ok(()) => {
+                        ui_sender.send(Ok(AppState::LoadingImage)).await;
+                        playback.restart();
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+        };
+
+        match next_image {
+            Ok((image, interval, item)) => {
+                slides_shown += 1;
+                // The history is only informational, so failing to write it isn't an error.
+                let entry = HistoryEntry {
+                    time: Local::now(),
+                    item,
+                };
+                let _ = history.record(&entry).await;
+                upcoming.record_shown(entry.item.item_id.clone(), entry.time);
+                if settings.export_history {
+                    let _ = summary.record(&entry).await;
+                }
+                let interval = PowerStatus::current().slide_interval(interval);
+                playback.shown(interval);
+                status.record_slide(
+                    image_lists
+                        .current
+                        .as_ref()
+                        .map_or(0, |image_list| image_list.images.len()),
+                );
+                let mut warnings = image_lists.warnings();
+                if let Some(resets_in) = loader.cache_only_for() {
+                    warnings.push(format!(
+                        "The daily budget for OneDrive is nearly used, so only cached images will be shown for the next {} minutes",
+                        resets_in.as_secs().div_ceil(60)
+                    ));
+                }
+                ui_sender.show_warnings(warnings);
+                // Checked with every slide, so the banner changes with the first slide after midnight.
+                ui_sender.show_banner(
+                    image_lists
+                        .current
+                        .as_ref()
+                        .and_then(|image_list| image_list.banner_for(Local::now().date_naive())),
+                );
+                let image_list = image_lists.current.as_ref();
+                // Introduce each folder with a title card as the slideshow moves into it.
+                let title_card = image_list
+                    .filter(|_| entry.item.folder.is_some() && entry.item.folder != last_folder)
+                    .and_then(|image_list| image_list.title_card_for(&entry.item));
+                last_folder.clone_from(&entry.item.folder);
+                let slide = Slide {
+                    image: Arc::new(image),
+                    caption: image_list.and_then(|image_list| image_list.caption_for(&entry.item)),
+                    theme: image_list.map_or_else(FolderTheme::default, |image_list| {
+                        image_list.theme_for(&entry.item)
+                    }),
+                    fade: image_list.map_or(DEFAULT_FADE, |image_list| image_list.fade),
+                    // Timed so that the motion finishes as the next slide is shown.
+                    ken_burns: image_list
+                        .filter(|image_list| image_list.ken_burns)
+                        .map(|_| KenBurns::random(interval)),
+                    photo_strip,
+                    title_card,
+                    progress_bar: image_list
+                        .filter(|image_list| image_list.progress_bar)
+                        .map(|_| interval),
+                };
+                playback.shown_image(slide.clone());
+                current_image_sender.send_replace(Some(Arc::clone(&slide.image)));
+                ui_sender.send(Ok(AppState::HasImage(slide))).await;
+            }
+            Err(err) => {
+                playback.shown(error_refresh_time(&err));
+                status.record_error(&err);
+                report_error(err, &captive_portal, &ui_sender).await;
+            }
+        }
+
+        if settings.report_status && Instant::now() >= report_status_at {
+            report_status_at = time_from_now(STATUS_REPORT_INTERVAL);
+            // The status report is best effort: there's nowhere better to report a failure to
+            // write it, and the next report will try again.
+            task::spawn(report_status(
+                loader.clone(),
+                token.clone(),
+                status.clone(),
+                playback.current().map(|slide| Arc::clone(&slide.image)),
+            ));
+        }
+        if let Some(image_list) = image_lists
+            .current
+            .as_ref()
+            .filter(|_| Instant::now() >= collect_garbage_at)
+        {
+            collect_garbage_at = time_from_now(CACHE_GARBAGE_COLLECTION_INTERVAL);
+            // Images that aren't listed are only removed if everything could be listed, otherwise
+            // the images in a folder that failed would be lost.
+            let listed = image_list
+                .failed_directories
+                .is_empty()
+                .then(|| image_list.image_ids());
+            let loader = loader.clone();
+            // Like the status report, this is best effort: anything left behind is removed next time.
+            task::spawn(async move { loader.collect_garbage(listed.as_ref()).await });
+        }
+        if settings.export_history && Instant::now() >= export_history_at {
+            export_history_at = time_from_now(HISTORY_EXPORT_INTERVAL);
+            // Like the status report, this is best effort: anything that fails to upload is kept
+            // and uploaded next time.
+            task::spawn(export_history(
+                loader.clone(),
+                token,
+                summary.clone(),
+                status.clone(),
+            ));
+        }
+    }
+}
+
+/// Uploads the monthly summaries of shown slides to the app folder in OneDrive. Once a past
+/// month's summary has been uploaded it is complete, so the local copy is removed.
+async fn export_history(
+    loader: Arc<impl SlideSource>,
+    token: String,
+    summary: Arc<MonthlySummary>,
+    status: Status,
+) -> Result<()> {
+    let current_month = Local::now().format("%Y-%m").to_string();
+    for (month, path) in summary.months()? {
+        let contents = tokio::fs::read(&path).await?;
+        loader
+            .upload_app_file(
+                &token,
+                &status.device_file_name(&format!("history-{month}"), "csv"),
+                contents,
+            )
+            .await?;
+        if month != current_month {
+            tokio::fs::remove_file(&path).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Uploads the slideshow's status to the app folder in OneDrive, along with its recent log and a
+/// screenshot if diagnostics have been requested by creating a flag file in the app folder.
+async fn report_status(
+    loader: Arc<impl SlideSource>,
+    token: String,
+    status: Status,
+    screenshot: Option<Arc<ColorImage>>,
+) -> Result<()> {
+    loader
+        .upload_app_file(
+            &token,
+            &status.device_file_name("status", "json"),
+            serde_json::to_vec(&status)?,
+        )
+        .await?;
+
+    if loader
+        .take_app_file(&token, &status.device_file_name("send-diagnostics", "txt"))
+        .await?
+    {
+        loader
+            .upload_app_file(
+                &token,
+                &status.device_file_name("log", "txt"),
+                status.log_text(),
+            )
+            .await?;
+        if let Some(screenshot) = screenshot {
+            loader
+                .upload_app_file(
+                    &token,
+                    &status.device_file_name("screenshot", "png"),
+                    status::encode_png(&screenshot)?,
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Waits until the authentication server can be reached, so that the first attempt to authenticate
+/// doesn't fail just because the network is still coming up.
+async fn wait_for_network(auth_url: &str, ui_sender: &UiSender) {
+    let Ok(url) = Url::parse(auth_url) else {
+        return;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return;
+    };
+
+    let mut waiting = false;
+    while !matches!(
+        tokio::time::timeout(NETWORK_CHECK_TIMEOUT, TcpStream::connect((host, port))).await,
+        Ok(Ok(_))
+    ) {
+        if !waiting {
+            waiting = true;
+            ui_sender.send(Ok(AppState::WaitingForNetwork)).await;
+        }
+        tokio::time::sleep(NETWORK_RETRY_TIME).await;
+    }
+    if waiting {
+        ui_sender.send(Ok(AppState::LoadingImage)).await;
+    }
+}
+
+fn create_image_loader(settings: &Settings, environment: &Environment) -> ImageLoader {
+    let mut loader = ImageLoader::new(
+        &environment.drive_url,
+        environment.data_directory.join(CACHE_DIRECTORY),
+    )
+    .with_cache_policy(settings.cache_policy)
+    .with_device_name(settings.device_name());
+    if let Some(quality) = settings.cache_quality {
+        loader = loader.with_cache_quality(quality);
+    }
+    if settings.daily_requests.is_some() || settings.daily_download_mb.is_some() {
+        loader = loader.with_daily_budget(Arc::new(http::DailyBudget::new(
+            settings.daily_requests,
+            settings
+                .daily_download_mb
+                .map(|megabytes| megabytes * 1024 * 1024),
+        )));
+    }
+    if settings.low_resource() {
+        loader
+            .with_low_resource_limits(LOW_RESOURCE_MAX_IMAGE_DIMENSION, LOW_RESOURCE_LISTING_DELAY)
+    } else {
+        loader
+    }
+}
+
+/// Times how long it takes to get slides ready on this device, then prints a report.
+async fn benchmark(settings: &Settings, environment: Environment, slides: usize) -> Result<()> {
+    let (auth_sender, mut auth_receiver) = channel(8);
+    task::spawn(async move {
+        while let Some(message) = auth_receiver.recv().await {
+            if let AuthMessage::HasClientCode(auth_url, code) = message {
+                eprintln!("Authorize the slideshow to read from your OneDrive by opening {auth_url} in a browser and entering the code {code}");
+            }
+        }
+    });
+    let mut authenticator = environment.create_authenticator(settings, auth_sender);
+    let mut loader = create_image_loader(settings, &environment);
+    let report = bench::run(&mut authenticator, &mut loader, slides).await?;
+    print!("{report}");
+    Ok(())
+}
+
+/// Shows made up sample images (or those in `--mock-images`) instead of OneDrive's, for developing
+/// or demoing the slideshow without a Microsoft account or network.
+fn start_mock(settings: &Settings) -> Result<Environment> {
+    let mut drive = MockDrive::new(settings.mock_images.as_deref())?;
+    if let Some(template) = &settings.preview {
+        drive = drive.with_preview(template);
+    }
+    Ok(Environment::mock(drive))
+}
+
+/// Signs in with a refresh token read from `path` (or stdin, if it is `-`), e.g. from a script
+/// that sets up many frames. The token is used once to check that it works, which also stores the
+/// refresh token that replaces it, so that the slideshow starts without asking to sign in.
+async fn provision_token(
+    settings: &Settings,
+    mut environment: Environment,
+    path: &Path,
+) -> Result<()> {
+    let token = if path == Path::new("-") {
+        std::io::read_to_string(std::io::stdin()).with_context(|| "Reading stdin")?
+    } else {
+        tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Reading {}", path.display()))?
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("There is no refresh token in {}", path.display());
+    }
+    environment.refresh_token = Some(token.to_string());
+    // There's no one to show a device code to, so signing in with one is never started: the
+    // refresh token is the only thing tried.
+    let (auth_sender, _) = channel(1);
+    environment
+        .create_authenticator(settings, auth_sender)
+        .get_token()
+        .await
+        .with_context(|| "Checking the refresh token")?;
+    Ok(())
+}
+
+/// Prints whether each of the endpoints that the slideshow needs can be reached over IPv4 and IPv6.
+async fn check_network(environment: &Environment) -> bool {
+    let mut reachable = true;
+    for url in [&environment.auth_url, &environment.drive_url] {
+        match network::check_endpoint(url).await {
+            Ok(report) => {
+                println!("{report}");
+                reachable &= report.is_reachable();
+            }
+            Err(err) => {
+                println!("{url}: {err:#}");
+                reachable = false;
+            }
+        }
+    }
+    reachable
+}
+
+/// Where to serve the remote control from, and the channels that it controls the slideshow with.
+struct RemoteControlParts {
+    address: SocketAddr,
+    device_name: String,
+    tokens_path: PathBuf,
+    lock_pin: Option<String>,
+    sender: Sender<RemoteCommand>,
+    current_image: remote::CurrentImage,
+    upcoming: remote::Upcoming,
+}
+
+async fn start_remote_control(
+    parts: RemoteControlParts,
+    ui_sender: &UiSender,
+) -> Option<ServiceDaemon> {
+    let RemoteControlParts {
+        address,
+        device_name,
+        tokens_path,
+        lock_pin,
+        sender,
+        current_image,
+        upcoming,
+    } = parts;
+    let listener = match TcpListener::bind(address).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            ui_sender
+                .send(Err(anyhow!(err).context("Starting remote control server")))
+                .await;
+            return None;
+        }
+    };
+    // If the port was picked by the OS, then advertise the one that it picked.
+    let address = listener.local_addr().unwrap_or(address);
+    let pin_sender = ui_sender.clone();
+    let pairing = Pairing::load(tokens_path, move |pin| pin_sender.show_pairing_pin(pin)).await;
+    task::spawn(remote::run_server(
+        listener,
+        RemoteControl {
+            sender,
+            current_image,
+            upcoming,
+            pairing: Arc::new(pairing),
+            lock_pin,
+        },
+    ));
+
+    match remote::advertise(address, &device_name) {
+        Ok(daemon) => Some(daemon),
+        Err(err) => {
+            ui_sender
+                .send(Err(err.context("Advertising remote control server")))
+                .await;
+            None
+        }
+    }
+}
+
+/// Waits until `show_at`, or forever if there's no time to show the next slide at (i.e., while
+/// paused).
+async fn wait_to_show(show_at: Option<Instant>) {
+    match show_at {
+        Some(show_at) => tokio::time::sleep_until(show_at).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Reason that the image load loop woke up.
+enum Woken {
+    /// It's time to show the prepared slide.
+    Ready,
+    Command(RemoteCommand),
+    /// It's time to use the refresh token, so that it doesn't expire.
+    KeepSignedIn,
+}
+
+/// The screen's size in pixels rather than points, so that images are downloaded at the display's
+/// full resolution (e.g., on high-DPI screens).
+fn screen_pixels(ctx: &egui::Context) -> Rect {
+    let screen = ctx.screen_rect();
+    Rect::from_min_size(screen.min, screen.size() * ctx.pixels_per_point())
+}
+
+/// Gets the time `duration` from now, clamping rather than overflowing for huge durations.
+fn time_from_now(duration: Duration) -> Instant {
+    let now = Instant::now();
+    now.checked_add(duration)
+        .unwrap_or_else(|| now + MAX_SLIDE_INTERVAL)
+}
+
+async fn get_auth_token(
+    authenticator: &mut impl TokenSource,
+    captive_portal: &CaptivePortalDetector,
+    ui_sender: &UiSender,
+) -> String {
+    loop {
+        match authenticator.get_token().await {
+            Ok(token) => return token,
+            Err(err) => {
+                let mut err = err.context("Authenticating");
+                // Signing in again needs someone to be around, so give them some notice.
+                if let Some(expires) = authenticator
+                    .refresh_token_expires()
+                    .filter(|expires| *expires < Utc::now() + SIGN_IN_EXPIRY_WARNING)
+                {
+                    err = err.context(format!(
+                        "Unless OneDrive can be reached before {}, you will need to sign in again",
+                        expires.with_timezone(&Local).format("%-d %B %Y")
+                    ));
+                }
+                report_error(err, captive_portal, ui_sender).await;
+                tokio::time::sleep(ON_ERROR_REFRESH_TIME).await;
+            }
+        }
+    }
+}
+
+/// How long to wait before trying again after an error.
+fn error_refresh_time(err: &anyhow::Error) -> Duration {
+    match err.downcast_ref::<ConfigError>() {
+        Some(ConfigError::Missing) => MISSING_CONFIG_REFRESH_TIME,
+        Some(ConfigError::Invalid { .. }) => INVALID_CONFIG_REFRESH_TIME,
+        // Requests have already been retried, so OneDrive needs a longer break.
+        None => match err.downcast_ref::<http::GraphError>() {
+            Some(http::GraphError::Throttled { retry_after, .. }) => {
+                retry_after.unwrap_or(THROTTLED_REFRESH_TIME)
+            }
+            // Nothing will be sent until the budget resets, but cached images may still be shown.
+            Some(http::GraphError::OverBudget { resets_in }) => {
+                (*resets_in).min(THROTTLED_REFRESH_TIME)
+            }
+            _ => ON_ERROR_REFRESH_TIME,
+        },
+    }
+}
+
+/// Reports an error to the UI, unless it was caused by a captive portal, in which case the user is
+/// told how to get past the portal instead. Connection failures include the results of checking
+/// whether the endpoint can be reached over IPv4 and IPv6.
+async fn report_error(
+    err: anyhow::Error,
+    captive_portal: &CaptivePortalDetector,
+    ui_sender: &UiSender,
+) {
+    match captive_portal.detect().await {
+        Some(portal) => {
+            ui_sender
+                .send(Ok(AppState::CaptivePortal(portal.sign_in_url)))
+                .await
+        }
+        None => {
+            let err = match network::failed_connection_url(&err).cloned() {
+                Some(url) => match network::check_endpoint(url.as_str()).await {
+                    Ok(report) => err.context(report.to_string()),
+                    Err(_) => err,
+                },
+                None => err,
+            };
+            ui_sender.send(Err(err)).await
+        }
+    }
+}
+
+/// Gets this device's position within the refresh window, generating and persisting it on first use
+/// so that frames provisioned from the same image don't all refresh at the same moment.
+async fn get_refresh_jitter(data_directory: &Path) -> f64 {
+    let path = data_directory.join(REFRESH_JITTER_FILE);
+    if let Some(jitter) = tokio::fs::read_to_string(&path)
+        .await
+        .ok()
+        .and_then(|contents| contents.trim().parse::<f64>().ok())
+        .filter(|jitter| (0.0..1.0).contains(jitter))
+    {
+        return jitter;
+    }
+
+    // Failing to persist the jitter only means that a new one is picked next time.
+    let jitter = rand::thread_rng().gen::<f64>();
+    if tokio::fs::create_dir_all(data_directory).await.is_ok() {
+        tokio::fs::write(&path, jitter.to_string()).await.ok();
+    }
+    jitter
+}
+
+async fn get_image_list(
+    loader: &impl SlideSource,
+    token: &str,
+    refresh_jitter: f64,
+) -> Result<ImageList> {
+    let (mut listing, config) = loader.get_image_list(token).await?;
+    let date_range = DateRange {
+        min: config.min_date,
+        max: config.max_date,
+    };
+    listing.images.retain(|image| date_range.contains(image));
+    let mut missing_directories = listing.missing_directories;
+    let mut failed_directories = listing
+        .failed_directories
+        .into_iter()
+        .map(|failed| (ImageSet::Default, failed))
+        .collect::<Vec<_>>();
+
+    // List every playlist up front so that switching between them doesn't need to wait for a refresh.
+    let mut playlists = Vec::with_capacity(config.playlists.len());
+    for (index, playlist) in config.playlists.into_iter().enumerate() {
+        let listing = loader
+            .list_images(token, &playlist.directories)
+            .await
+            .with_context(|| format!("Listing playlist for {:?}", playlist.days))?;
+        missing_directories.extend(listing.missing_directories);
+        failed_directories.extend(
+            listing
+                .failed_directories
+                .into_iter()
+                .map(|failed| (ImageSet::Playlist(index), failed)),
+        );
+        playlists.push(PlaylistImages {
+            images: listing
+                .images
+                .into_iter()
+                .filter(|image| date_range.contains(image))
+                .collect(),
+            days: playlist.days,
+        });
+    }
+
+    let mut special_days = Vec::with_capacity(config.special_days.len());
+    for (index, special_day) in config.special_days.into_iter().enumerate() {
+        let images = match &special_day.directories {
+            Some(directories) => {
+                let listing = loader
+                    .list_images(token, directories)
+                    .await
+                    .with_context(|| format!("Listing special day {:?}", special_day.date))?;
+                missing_directories.extend(listing.missing_directories);
+                failed_directories.extend(
+                    listing
+                        .failed_directories
+                        .into_iter()
+                        .map(|failed| (ImageSet::SpecialDay(index), failed)),
+                );
+                Some(
+                    listing
+                        .images
+                        .into_iter()
+                        .filter(|image| date_range.contains(image))
+                        .collect(),
+                )
+            }
+            None => None,
+        };
+        special_days.push(SpecialDayImages {
+            date: special_day.date,
+            banner: special_day.banner,
+            images,
+        });
+    }
+
+    let pinned = match config.pinned {
+        Some(pinned) => Some(PinnedImages {
+            every: pinned
+                .every
+                .map_or(DEFAULT_PINNED_EVERY, |every| every.get().into()),
+            images: loader
+                .list_images_at(token, &pinned.path)
+                .await
+                .with_context(|| format!("Listing pinned {}", pinned.path))?,
+        }),
+        None => None,
+    };
+
+    let refresh_window = config
+        .refresh_window
+        .map_or(DEFAULT_REFRESH_WINDOW, Duration::from_secs);
+    let refresh_after = refresh_window
+        .mul_f64(refresh_jitter)
+        .checked_add(IMAGE_LIST_REFRESH_TIME)
+        .and_then(|refresh_time| Instant::now().checked_add(refresh_time))
+        .ok_or_else(|| anyhow!("Refresh window is too large"))?;
+    let mut interval = Duration::from_secs(config.interval);
+    if let Some(cycle_hours) = config.cycle_hours {
+        let cycle = Duration::try_from_secs_f64(cycle_hours * 60.0 * 60.0)
+            .map_err(|_| anyhow!("Invalid cycle_hours: {cycle_hours}"))?;
+        interval = auto_interval(interval, cycle, listing.images.len());
+    }
+    let fade = match config.fade_seconds {
+        Some(fade_seconds) => Duration::try_from_secs_f64(fade_seconds)
+            .map_err(|_| anyhow!("Invalid fade_seconds: {fade_seconds}"))?,
+        None => DEFAULT_FADE,
+    };
+    Ok(ImageList {
+        images: listing.images,
+        missing_directories,
+        unknown_fields: config.unknown_fields,
+        failed_directories,
+        retry_failed_after: time_from_now(FAILED_DIRECTORY_RETRY_TIME),
+        playlists,
+        special_days,
+        pinned,
+        interval,
+        interval_rules: config.interval_rules,
+        folder_captions: config.folder_captions,
+        photo_info: config.photo_info,
+        caption: config.caption,
+        title_card: config.title_card,
+        places: config.places,
+        folder_themes: config.folder_themes,
+        avoid_repeating_folders: config.avoid_repeating_folders,
+        directory_weights: config.directory_weights,
+        fade,
+        ken_burns: config.ken_burns,
+        photo_strip_after: config.photo_strip_after.map(Duration::from_secs),
+        progress_bar: config.progress_bar,
+        orientation: config.orientation,
+        order: config.order,
+        date_range,
+        refresh_after,
+    })
+}
+
+/// Spreads `image_count` images over `cycle`, but shows each for at least `min_interval` so that
+/// large libraries don't flash past.
+fn auto_interval(min_interval: Duration, cycle: Duration, image_count: usize) -> Duration {
+    let image_count = u32::try_from(image_count.max(1)).unwrap_or(u32::MAX);
+    (cycle / image_count).max(min_interval)
+}
+
+async fn get_guest_images(
+    loader: &impl SlideSource,
+    token: &str,
+    folder: String,
+    duration: Duration,
+) -> Result<GuestImages> {
+    let listing = loader
+        .list_images(token, std::slice::from_ref(&folder))
+        .await?;
+    if !listing.missing_directories.is_empty() {
+        bail!("{folder} doesn't exist");
+    }
+    let images = listing.images;
+    if images.is_empty() {
+        bail!("No images found in {folder}");
+    }
+    Ok(GuestImages {
+        images,
+        until: Instant::now()
+            .checked_add(duration)
+            .ok_or_else(|| anyhow!("Duration is too long"))?,
+    })
+}
+
+async fn get_requested_image<S: SlideSource>(
+    loader: &Arc<S>,
+    token: &str,
+    size: Rect,
+    image_lists: &mut ImageListManager,
+    item: ItemReference,
+) -> Result<(ColorImage, Duration, ShownItem)> {
+    let interval = image_lists.get(loader, token).await?.interval;
+    let image_id = match item {
+        ItemReference::ItemId(id) => id,
+        ItemReference::ShareUrl(share_url) => loader.resolve_share_url(token, &share_url).await?,
+    };
+    let image = loader
+        .load_image(token, size.height() as u32, size.width() as u32, &image_id)
+        .await?;
+    Ok((
+        image,
+        interval,
+        ShownItem {
+            item_id: image_id,
+            name: None,
+            source: "Remote control".into(),
+            folder: None,
+            taken: None,
+            location: None,
+        },
+    ))
+}
+
+async fn get_next_image<S: SlideSource>(
+    loader: &Arc<S>,
+    token: &str,
+    size: Rect,
+    image_lists: &mut ImageListManager,
+    guest: Option<&GuestImages>,
+    slide_number: u64,
+    upcoming: &mut UpcomingSlides,
+) -> Result<(ColorImage, Duration, ShownItem)> {
+    let all_images = image_lists.get(loader, token).await?;
+    let today = Local::now().date_naive();
+    upcoming.set_cached(loader.cache_only_for().map(|_| loader.cached_image_ids()));
+    upcoming.set_avoid_repeating_folders(all_images.avoid_repeating_folders);
+    upcoming.set_order(all_images.order);
+    upcoming.set_directory_weights(all_images.directory_weights.clone());
+    upcoming.set_orientation(
+        all_images
+            .orientation
+            .map(|orientation| orientation.for_screen(size.width(), size.height())),
+    );
+    upcoming.plan(slide_number, move |slide_number| match guest {
+        Some(guest) => guest.images.as_slice(),
+        None => all_images.images_for(slide_number, today),
+    })?;
+    let (image, chosen) = upcoming.load_next(&**loader, token, size).await?;
+    let interval = all_images.interval_for(&chosen);
+    Ok((
+        image,
+        interval,
+        ShownItem {
+            item_id: chosen.id,
+            name: chosen.name,
+            source: chosen.directory.to_string(),
+            folder: Some(chosen.folder.to_string()),
+            taken: chosen.taken,
+            location: chosen.location,
+        },
+    ))
+}
+
+/// Loads thumbnails of other photos from the same folder as a slide, or taken on the same day, to
+/// show in a strip along the bottom of it. Thumbnails that fail to load are left out.
+async fn load_photo_strip(
+    loader: &impl SlideSource,
+    token: &str,
+    images: &[Image],
+    item: &ShownItem,
+) -> Vec<Arc<ColorImage>> {
+    let mut photo_strip = Vec::new();
+    for image in photo_strip_images(images, item) {
+        if let Ok(thumbnail) = loader
+            .load_image(token, PHOTO_STRIP_HEIGHT, PHOTO_STRIP_HEIGHT * 2, &image.id)
+            .await
+        {
+            photo_strip.push(Arc::new(thumbnail));
+        }
+    }
+    photo_strip
+}
+
+/// Picks up to `PHOTO_STRIP_SIZE` images, in a random order, from the same folder as `item` or
+/// taken on the same day.
+fn photo_strip_images<'a>(images: &'a [Image], item: &ShownItem) -> Vec<&'a Image> {
+    let related = |image: &Image| {
+        item.folder.as_deref() == Some(&*image.folder)
+            || item
+                .taken
+                .zip(image.taken)
+                .is_some_and(|(shown, taken)| shown.date_naive() == taken.date_naive())
+    };
+    let mut related_images = images
+        .iter()
+        .filter(|image| image.id != item.item_id && related(image))
+        .collect::<Vec<_>>();
+    related_images.shuffle(&mut rand::thread_rng());
+    related_images.truncate(PHOTO_STRIP_SIZE);
+    related_images
+}
+
+/// Sends state updates from the loader to the UI.
+///
+/// New states wait for the UI to make room in the channel, but errors are only sent if there is room:
+/// if the UI is behind then it will be showing a stale error at worst, and the loader will retry
+/// anyway. Repeated identical errors are coalesced so that a persistent failure doesn't flood the UI.
+#[derive(Clone)]
+struct UiSender {
+    sender: Sender<Result<AppState>>,
+    pairing_pin: Arc<watch::Sender<Option<String>>>,
+    warnings: Arc<watch::Sender<Vec<String>>>,
+    banner: Arc<watch::Sender<Option<String>>>,
+    paused: Arc<watch::Sender<bool>>,
+    ctx: egui::Context,
+    last_error: Arc<Mutex<Option<String>>>,
+    /// Shows cached slides while the slideshow is starting up, until something else is sent.
+    warm_up: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl UiSender {
+    fn new(
+        sender: Sender<Result<AppState>>,
+        pairing_pin: watch::Sender<Option<String>>,
+        warnings: watch::Sender<Vec<String>>,
+        banner: watch::Sender<Option<String>>,
+        paused: watch::Sender<bool>,
+        ctx: egui::Context,
+    ) -> Self {
+        Self {
+            sender,
+            pairing_pin: Arc::new(pairing_pin),
+            warnings: Arc::new(warnings),
+            banner: Arc::new(banner),
+            paused: Arc::new(paused),
+            ctx,
+            last_error: Default::default(),
+            warm_up: Default::default(),
+        }
+    }
+
+    /// Cycles through `images` from the cache, so that there's something to look at while the
+    /// slideshow signs in and lists images. This carries on until the first live slide, or anything
+    /// else that needs to be seen (e.g., a sign in code), is sent. In the meantime errors are shown
+    /// as warnings, and loading or waiting for the network isn't shown at all.
+    fn show_cached_slides(&self, mut images: Vec<PathBuf>) {
+        if images.is_empty() {
+            return;
+        }
+        images.shuffle(&mut rand::thread_rng());
+        let sender = self.sender.clone();
+        let ctx = self.ctx.clone();
+        let task = task::spawn(async move {
+            let mut index = 0;
+            while !images.is_empty() {
+                index %= images.len();
+                let image = tokio::fs::read(&images[index])
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|data| image_loader::decode_image(&data));
+                let Ok(image) = image else {
+                    // The image may have been removed from the cache since it was shown.
+                    images.remove(index);
+                    continue;
+                };
+                if sender
+                    .send(Ok(AppState::HasImage(Arc::new(image).into())))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                ctx.request_repaint();
+                index += 1;
+                tokio::time::sleep(WARM_UP_INTERVAL).await;
+            }
+        });
+        *self.warm_up.lock().unwrap() = Some(task);
+    }
+
+    /// Shows, or hides, the PIN for pairing a remote control client. This is shown on top of
+    /// whatever else is on screen, so it bypasses the state channel.
+    fn show_pairing_pin(&self, pin: Option<String>) {
+        self.pairing_pin.send_replace(pin);
+        self.ctx.request_repaint();
+    }
+
+    /// Shows a message for the day (e.g., on a birthday) on top of whatever else is on screen.
+    fn show_banner(&self, banner: Option<String>) {
+        let changed = self.banner.send_if_modified(|current| {
+            let changed = *current != banner;
+            *current = banner;
+            changed
+        });
+        if changed {
+            self.ctx.request_repaint();
+        }
+    }
+
+    /// Shows, or hides, that the slideshow is paused.
+    fn show_paused(&self, paused: bool) {
+        if self.paused.send_replace(paused) != paused {
+            self.ctx.request_repaint();
+        }
+    }
+
+    /// Asks the UI to capture everything on screen, which it saves once the next frame is drawn.
+    fn take_screenshot(&self) {
+        self.ctx
+            .send_viewport_cmd(ViewportCommand::Screenshot(Default::default()));
+    }
+
+    /// Shows problems that don't stop the slideshow on top of whatever else is on screen.
+    fn show_warnings(&self, warnings: Vec<String>) {
+        let changed = self.warnings.send_if_modified(|current| {
+            let changed = *current != warnings;
+            *current = warnings;
+            changed
+        });
+        if changed {
+            self.ctx.request_repaint();
+        }
+    }
+
+    /// Adds a warning to those already shown, until the loader next updates them (when the next
+    /// slide is shown).
+    fn add_warning(&self, warning: String) {
+        self.warnings.send_modify(|current| current.push(warning));
+        self.ctx.request_repaint();
+    }
+
+    async fn send(&self, update: Result<AppState>) {
+        let update = {
+            let mut warm_up = self.warm_up.lock().unwrap();
+            match update {
+                Ok(AppState::LoadingImage | AppState::WaitingForNetwork) if warm_up.is_some() => {
+                    return
+                }
+                Err(err) if warm_up.is_some() => {
+                    self.show_warnings(vec![format!("{err:#}")]);
+                    return;
+                }
+                update => {
+                    if let Some(warm_up) = warm_up.take() {
+                        warm_up.abort();
+                    }
+                    update
+                }
+            }
+        };
+        let result = match update {
+            Ok(state) => {
+                *self.last_error.lock().unwrap() = None;
+                self.sender.send(Ok(state)).await.map_err(|_| ())
+            }
+            Err(err) => {
+                // The alternate format has the whole chain of causes, but not the backtrace, which
+                // differs even when the error is the same.
+                let message = format!("{err:#}");
+                let mut last_error = self.last_error.lock().unwrap();
+                if last_error.as_ref() == Some(&message) {
+                    return;
+                }
+                match self.sender.try_send(Err(err)) {
+                    Ok(()) => {
+                        *last_error = Some(message);
+                        Ok(())
+                    }
+                    Err(TrySendError::Full(_)) => return,
+                    Err(TrySendError::Closed(_)) => Err(()),
+                }
+            }
+        };
+        if result.is_err() {
+            process::exit(1);
         }
-    };
-
-    match loader
-        .load_next(
-            &token,
-            size.height() as u32,
-            size.width() as u32,
-            &all_images.images,
-        )
-        .await
-    {
-        Ok(image) => Ok((image, all_images)),
-        Err(err) => Err((err, Some(all_images))),
-    }
-}
-
-async fn send_update<T>(sender: &Sender<T>, ctx: &egui::Context, message: T) {
-    if sender.send(message).await.is_err() {
-        process::exit(1);
+        self.ctx.request_repaint();
     }
-    ctx.request_repaint();
 }
 
 #[tokio::test(flavor = "multi_thread")]
@@ -285,11 +2971,14 @@ async fn load_multiple_images() {
         .create();
 
     let folder_query = mockito::Matcher::AllOf(vec![
-        mockito::Matcher::UrlEncoded("$select".into(), "id".into()),
+        mockito::Matcher::UrlEncoded("$select".into(), "id,name".into()),
         mockito::Matcher::UrlEncoded("$filter".into(), "folder ne null".into()),
     ]);
     let image_query = mockito::Matcher::AllOf(vec![
-        mockito::Matcher::UrlEncoded("$select".into(), "id".into()),
+        mockito::Matcher::UrlEncoded(
+            "$select".into(),
+            "id,name,image,photo,location,lastModifiedDateTime".into(),
+        ),
         mockito::Matcher::UrlEncoded("$filter".into(), "image ne null".into()),
     ]);
 
@@ -332,25 +3021,50 @@ async fn load_multiple_images() {
         .create();
 
     // First load should get the config and directory listing.
-    let image_loader = ImageLoader::new(&url, temp_dir);
-    let (actual_image, all_images) = get_next_image(
-        &image_loader,
-        "token".into(),
-        Rect {
-            min: eframe::epaint::Pos2::ZERO,
-            max: eframe::epaint::Pos2 {
-                y: 1024.0,
-                x: 768.0,
-            },
+    let screen = Rect {
+        min: eframe::epaint::Pos2::ZERO,
+        max: eframe::epaint::Pos2 {
+            y: 1024.0,
+            x: 768.0,
         },
+    };
+    let image_loader =
+        Arc::new(ImageLoader::new(&url, temp_dir).with_cache_policy(cache::CachePolicy::Always));
+    let mut image_lists = ImageListManager::new(0.0);
+    let mut upcoming = UpcomingSlides::new(watch::channel(Vec::new()).0);
+    let (actual_image, interval, shown) = get_next_image(
+        &image_loader,
+        "token",
+        screen,
+        &mut image_lists,
         None,
+        1,
+        &mut upcoming,
     )
     .await
-    .ok()
     .unwrap();
     assert_eq!(actual_image.height(), 1);
     assert_eq!(actual_image.width(), 1);
-    assert_eq!(all_images.images, &["the_image".to_string()]);
+    assert_eq!(interval, Duration::from_secs(42));
+    assert_eq!(
+        shown,
+        ShownItem {
+            item_id: "the_image".into(),
+            name: None,
+            source: "d1".into(),
+            folder: Some("d1".into()),
+            taken: None,
+            location: None,
+        }
+    );
+    assert_eq!(
+        image_lists.current.as_ref().unwrap().images,
+        &[Image {
+            directory: "d1".into(),
+            folder: "d1".into(),
+            ..Image::with_id("the_image")
+        }]
+    );
     config_content_mock.assert();
     d1_folder_mock.assert();
     d1_image_mock.assert();
@@ -363,49 +3077,991 @@ async fn load_multiple_images() {
     d1_image_mock.remove();
     thumbnail_mock.remove();
     download_mock.remove();
-    let (actual_image, mut all_images) = get_next_image(
+    let (actual_image, _, _) = get_next_image(
         &image_loader,
-        "token".into(),
-        Rect {
-            min: eframe::epaint::Pos2::ZERO,
-            max: eframe::epaint::Pos2 {
-                y: 1024.0,
-                x: 768.0,
-            },
-        },
-        Some(all_images),
+        "token",
+        screen,
+        &mut image_lists,
+        None,
+        1,
+        &mut upcoming,
     )
     .await
-    .ok()
     .unwrap();
     assert_eq!(actual_image.height(), 1);
     assert_eq!(actual_image.width(), 1);
-    assert_eq!(all_images.images, &["the_image".to_string()]);
+    assert!(image_lists.pending_refresh.is_none());
 
-    // Make the image list expire: this will cause it to reload, but the image should come from cache.
-    let config_content_mock = config_content_mock.create();
+    // Make the image list expire: this will start a reload in the background, while the image
+    // continues to come from the old list and the cache.
+    let config_content_mock = config_content_mock
+        .with_body(r#"{ "directories": [ "d1" ], "interval": 7 } "#)
+        .create();
     let d1_folder_mock = d1_folder_mock.create();
     let d1_image_mock = d1_image_mock.create();
-    all_images.refresh_after = Instant::now();
-    let (actual_image, all_images) = get_next_image(
+    image_lists.current.as_mut().unwrap().refresh_after = Instant::now();
+    let (actual_image, interval, _) = get_next_image(
         &image_loader,
-        "token".into(),
-        Rect {
-            min: eframe::epaint::Pos2::ZERO,
-            max: eframe::epaint::Pos2 {
-                y: 1024.0,
-                x: 768.0,
-            },
-        },
-        Some(all_images),
+        "token",
+        screen,
+        &mut image_lists,
+        None,
+        1,
+        &mut upcoming,
     )
     .await
-    .ok()
     .unwrap();
     assert_eq!(actual_image.height(), 1);
     assert_eq!(actual_image.width(), 1);
-    assert_eq!(all_images.images, &["the_image".to_string()]);
+    assert_eq!(interval, Duration::from_secs(42));
+    while !image_lists.pending_refresh.as_ref().unwrap().is_finished() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    config_content_mock.assert();
+    d1_folder_mock.assert();
+    d1_image_mock.assert();
+
+    // Once the refresh has completed, the new list is swapped in.
+    let (_, interval, _) = get_next_image(
+        &image_loader,
+        "token",
+        screen,
+        &mut image_lists,
+        None,
+        1,
+        &mut upcoming,
+    )
+    .await
+    .unwrap();
+    assert_eq!(interval, Duration::from_secs(7));
+    assert!(image_lists.pending_refresh.is_none());
+    assert_eq!(
+        image_lists.current.as_ref().unwrap().images,
+        &[Image {
+            directory: "d1".into(),
+            folder: "d1".into(),
+            ..Image::with_id("the_image")
+        }]
+    );
+
+    // If a refresh fails, the old list is kept and the failure is shown as a warning. The next
+    // refresh is put off, rather than started again with the next slide.
+    config_content_mock.remove();
+    let config_missing_mock = server
+        .mock("GET", "/root:/slideshow.txt:/content")
+        .with_status(404)
+        .expect(1)
+        .create();
+    image_lists.current.as_mut().unwrap().refresh_after = Instant::now();
+    for _ in 0..2 {
+        get_next_image(
+            &image_loader,
+            "token",
+            screen,
+            &mut image_lists,
+            None,
+            1,
+            &mut upcoming,
+        )
+        .await
+        .unwrap();
+        while image_lists
+            .pending_refresh
+            .as_ref()
+            .is_some_and(|refresh| !refresh.is_finished())
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+    assert!(image_lists.pending_refresh.is_none());
+    assert_eq!(
+        image_lists.current.as_ref().unwrap().interval,
+        Duration::from_secs(7)
+    );
+    assert!(
+        image_lists.current.as_ref().unwrap().refresh_after
+            > Instant::now() + IMAGE_LIST_RETRY_TIME / 2
+    );
+    assert_eq!(image_lists.warnings().len(), 1);
+    config_missing_mock.assert();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn refresh_jitter_is_persisted() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/refresh_jitter_is_persisted");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+
+    let jitter = get_refresh_jitter(&temp_dir).await;
+    assert!((0.0..1.0).contains(&jitter));
+    assert!(temp_dir.join(REFRESH_JITTER_FILE).exists());
+
+    // The same jitter is used from then on.
+    assert_eq!(get_refresh_jitter(&temp_dir).await, jitter);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn provision_refresh_token() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/provision_refresh_token");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    tokio::fs::create_dir_all(&temp_dir).await.unwrap();
+
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+    let environment = || Environment {
+        auth_url: format!("{url}/auth"),
+        drive_url: format!("{url}/drive"),
+        data_directory: temp_dir.clone(),
+        token_store: TokenStore::File(temp_dir.join(REFRESH_TOKEN_FILE)),
+        refresh_token: None,
+        captive_portal_probe_url: format!("{url}/connecttest.txt"),
+        mock: None,
+    };
+    let token_file = temp_dir.join("provisioned");
+
+    // An empty file is rejected without asking the server.
+    tokio::fs::write(&token_file, "\n").await.unwrap();
+    assert!(
+        provision_token(&Settings::default(), environment(), &token_file)
+            .await
+            .is_err()
+    );
+
+    // The token is checked, and the refresh token that replaces it is stored.
+    let token_mock = server
+        .mock("POST", "/auth/token")
+        .match_body(mockito::Matcher::UrlEncoded(
+            "refresh_token".into(),
+            "provisioned".into(),
+        ))
+        .with_body(r#"{ "access_token": "token", "refresh_token": "rt", "expires_in": 3600 } "#)
+        .expect(1)
+        .create();
+    tokio::fs::write(&token_file, "provisioned\n")
+        .await
+        .unwrap();
+    provision_token(&Settings::default(), environment(), &token_file)
+        .await
+        .unwrap();
+    token_mock.assert();
+    assert_eq!(
+        environment().token_store.get_refresh_token().as_deref(),
+        Some("rt")
+    );
+    assert!(temp_dir.join(REFRESH_TOKEN_ISSUED_FILE).exists());
+}
+
+#[test]
+fn skip_upcoming_slides() {
+    let (sender, receiver) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(sender);
+    let images: &[Image] = &[Image::with_id("a"), Image::with_id("b")];
+    let planned_ids = |receiver: &watch::Receiver<Vec<UpcomingItem>>| {
+        receiver
+            .borrow()
+            .iter()
+            .map(|item| item.item_id.clone())
+            .collect::<Vec<_>>()
+    };
+
+    upcoming.plan(1, |_| images).unwrap();
+    let planned = planned_ids(&receiver);
+    assert_eq!(planned.len(), UPCOMING_SLIDES as usize);
+
+    // Planning again keeps the slides that were already chosen.
+    upcoming.plan(1, |_| images).unwrap();
+    assert_eq!(planned_ids(&receiver), planned);
+
+    // Skipped images are replaced and not chosen again.
+    upcoming.skip("a");
+    upcoming.plan(1, |_| images).unwrap();
+    assert_eq!(planned_ids(&receiver), ["b", "b", "b"]);
+
+    // Unless there's nothing else to show.
+    upcoming.skip("b");
+    upcoming.plan(1, |_| images).unwrap();
+    assert_eq!(planned_ids(&receiver).len(), UPCOMING_SLIDES as usize);
+
+    // Slides chosen from a different list are chosen again.
+    let guest_images: &[Image] = &[Image::with_id("guest")];
+    upcoming.plan(1, |_| guest_images).unwrap();
+    assert_eq!(planned_ids(&receiver), ["guest", "guest", "guest"]);
+    let no_images: &[Image] = &[];
+    assert!(upcoming.plan(1, |_| no_images).is_err());
+
+    // While only cached images are shown, slides that would need downloading are chosen again.
+    let images: &[Image] = &[Image::with_id("c"), Image::with_id("d")];
+    upcoming.plan(1, |_| images).unwrap();
+    upcoming.set_cached(Some(HashSet::from(["d".to_string()])));
+    upcoming.plan(1, |_| images).unwrap();
+    assert_eq!(planned_ids(&receiver), ["d", "d", "d"]);
+}
+
+#[test]
+fn prefer_images_not_shown_recently() {
+    let (sender, _) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(sender);
+    let now = Local::now();
+    let image = Image::with_id("a");
+    assert_eq!(upcoming.weight(&image, now), 1.0);
+
+    // Images that were just shown are much less likely to be chosen, but not ruled out.
+    upcoming.record_shown("a".into(), now);
+    assert_eq!(upcoming.weight(&image, now), JUST_SHOWN_WEIGHT);
+
+    // And become more likely to be chosen as time passes.
+    let half_recovered = now + SHOWN_IMAGE_RECOVERY_TIME / 2;
+    assert!((upcoming.weight(&image, half_recovered) - 0.5).abs() < 0.001);
+    let recovered = now + SHOWN_IMAGE_RECOVERY_TIME * 2;
+    assert_eq!(upcoming.weight(&image, recovered), 1.0);
+
+    // This carries over when the slideshow is restarted.
+    upcoming.set_last_shown(HashMap::new());
+    assert_eq!(upcoming.weight(&image, now), 1.0);
+    upcoming.set_last_shown(HashMap::from([("a".to_string(), now)]));
+    assert_eq!(upcoming.weight(&image, now), JUST_SHOWN_WEIGHT);
+}
+
+#[test]
+fn share_time_between_directories() {
+    let (sender, _) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(sender);
+    let image = |id: &str, directory: &str| Image {
+        directory: directory.into(),
+        ..Image::with_id(id)
+    };
+    let images = (0..8)
+        .map(|index| image(&format!("kid{index}"), "Kids"))
+        .chain([image("wedding0", "Wedding"), image("wedding1", "Wedding")])
+        .collect::<Vec<_>>();
+    let candidates = images.iter().collect::<Vec<_>>();
+    let total = |upcoming: &UpcomingSlides, directory: &str| {
+        let share = upcoming.directory_shares(&candidates);
+        images
+            .iter()
+            .filter(|image| &*image.directory == directory)
+            .map(share)
+            .sum::<f64>()
+    };
+
+    // Without weights, every image is as likely as any other.
+    assert_eq!(total(&upcoming, "Kids"), 8.0);
+    assert_eq!(total(&upcoming, "Wedding"), 2.0);
+
+    // With weights, each directory gets its share however many images it has.
+    upcoming.set_directory_weights(HashMap::from([("Wedding".to_string(), 1.0)]));
+    assert_eq!(total(&upcoming, "Kids"), 1.0);
+    assert_eq!(total(&upcoming, "Wedding"), 1.0);
+    upcoming.set_directory_weights(HashMap::from([("Kids".to_string(), 3.0)]));
+    assert_eq!(total(&upcoming, "Kids"), 3.0);
+    assert_eq!(total(&upcoming, "Wedding"), 1.0);
+}
+
+#[test]
+fn choose_slides_in_order() {
+    let (sender, receiver) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(sender);
+    let planned_ids = || {
+        receiver
+            .borrow()
+            .iter()
+            .map(|item| item.item_id.clone())
+            .collect::<Vec<_>>()
+    };
+    let image = |id, folder: &str, taken: Option<&str>| Image {
+        folder: folder.into(),
+        taken: taken.map(|taken| taken.parse().unwrap()),
+        ..Image::with_id(id)
+    };
+    let images: &[Image] = &[
+        image("beach", "Vacations", Some("2023-07-01T10:00:00Z")),
+        image("scan", "Vacations", None),
+        image("cake", "Birthdays", Some("2021-03-05T18:00:00Z")),
+        image("hike", "Hikes", Some("2022-09-10T08:00:00Z")),
+    ];
+
+    // In the order that they were listed, picking up after the image that was shown last.
+    upcoming.set_order(Order::Sequential);
+    upcoming.set_last_shown(HashMap::from([
+        ("scan".to_string(), Local::now()),
+        ("beach".to_string(), Local::now() - Duration::from_secs(60)),
+    ]));
+    upcoming.plan(1, |_| images).unwrap();
+    assert_eq!(planned_ids(), ["cake", "hike", "beach"]);
+
+    // By date, with the images whose date isn't known last.
+    let mut upcoming = UpcomingSlides::new(watch::channel(Vec::new()).0);
+    upcoming.set_order(Order::ByDateAsc);
+    let chosen = |upcoming: &UpcomingSlides, previous: Option<&Image>| {
+        upcoming.choose(images, previous).unwrap().id
+    };
+    assert_eq!(chosen(&upcoming, None), "cake");
+    assert_eq!(chosen(&upcoming, Some(&images[0])), "scan");
+    assert_eq!(chosen(&upcoming, Some(&images[1])), "cake");
+    upcoming.set_order(Order::NewestFirst);
+    assert_eq!(chosen(&upcoming, None), "beach");
+    assert_eq!(chosen(&upcoming, Some(&images[0])), "hike");
+    assert_eq!(chosen(&upcoming, Some(&images[2])), "scan");
+
+    // Skipped images are passed over.
+    upcoming.skip("hike");
+    assert_eq!(chosen(&upcoming, Some(&images[0])), "cake");
+}
+
+#[test]
+fn avoid_repeating_slides() {
+    let (sender, receiver) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(sender);
+    let planned_ids = || {
+        receiver
+            .borrow()
+            .iter()
+            .map(|item| item.item_id.clone())
+            .collect::<Vec<_>>()
+    };
+
+    // The same image isn't chosen twice in a row.
+    let images: &[Image] = &[Image::with_id("a"), Image::with_id("b")];
+    upcoming.plan(1, |_| images).unwrap();
+    let planned = planned_ids();
+    assert!(
+        planned.windows(2).all(|pair| pair[0] != pair[1]),
+        "{planned:?}"
+    );
+
+    // Nor, if asked, two images from the same folder.
+    let image = |id, folder: &str| Image {
+        folder: folder.into(),
+        ..Image::with_id(id)
+    };
+    let images: &[Image] = &[
+        image("beach", "Vacations"),
+        image("hike", "Vacations"),
+        image("cake", "Birthdays"),
+    ];
+    upcoming.set_avoid_repeating_folders(true);
+    upcoming.plan(1, |_| images).unwrap();
+    let planned = planned_ids();
+    assert!(
+        planned
+            .windows(2)
+            .all(|pair| (pair[0] == "cake") != (pair[1] == "cake")),
+        "{planned:?}"
+    );
+}
+
+#[test]
+fn lock_upcoming_slides() {
+    let (sender, receiver) = watch::channel(Vec::new());
+    let mut upcoming = UpcomingSlides::new(sender);
+    let image = |id, folder: &str| Image {
+        folder: folder.into(),
+        ..Image::with_id(id)
+    };
+    let images: &[Image] = &[
+        image("zoo", "Kids/Zoo"),
+        image("party", "Parties"),
+        image("office", "Work"),
+    ];
+    upcoming.plan(1, |_| images).unwrap();
+
+    // Once locked, slides outside of the allowed folders are chosen again.
+    upcoming.set_allowed_folders(Some(vec!["Kids".into()]));
+    upcoming.plan(1, |_| images).unwrap();
+    assert!(receiver.borrow().iter().all(|item| item.item_id == "zoo"));
+
+    upcoming.set_allowed_folders(Some(vec!["Holidays".into()]));
+    assert_eq!(
+        upcoming.plan(1, |_| images).unwrap_err().to_string(),
+        "None of the images are within the locked folders"
+    );
+    assert!(upcoming.plan(1, |_| &[]).is_err());
+
+    // Only images of the right shape are chosen, if asked.
+    upcoming.set_allowed_folders(None);
+    upcoming.set_orientation(Some(Orientation::Portrait));
+    let images: &[Image] = &[
+        Image {
+            width: Some(600),
+            height: Some(800),
+            ..image("tall", "Kids")
+        },
+        Image {
+            width: Some(800),
+            height: Some(600),
+            ..image("wide", "Kids")
+        },
+    ];
+    upcoming.plan(1, |_| images).unwrap();
+    assert!(receiver.borrow().iter().all(|item| item.item_id == "tall"));
+    upcoming.set_orientation(Some(Orientation::Portrait));
+    assert_eq!(
+        upcoming.plan(1, |_| &images[1..]).unwrap_err().to_string(),
+        "None of the images are portrait"
+    );
+}
+
+#[test]
+fn images_for_slide() {
+    let all_images = ImageList {
+        images: vec![Image::with_id("default")],
+        missing_directories: Vec::new(),
+        unknown_fields: Vec::new(),
+        failed_directories: Vec::new(),
+        retry_failed_after: Instant::now(),
+        playlists: vec![
+            PlaylistImages {
+                days: vec![Weekday::Sat, Weekday::Sun],
+                images: vec![Image::with_id("family")],
+            },
+            PlaylistImages {
+                days: vec![Weekday::Sun, Weekday::Mon],
+                images: vec![Image::with_id("art")],
+            },
+        ],
+        special_days: vec![
+            SpecialDayImages {
+                date: SpecialDate::try_from("06-04".to_string()).unwrap(),
+                banner: Some("Happy birthday!".into()),
+                images: None,
+            },
+            SpecialDayImages {
+                date: SpecialDate::try_from("2024-06-08".to_string()).unwrap(),
+                banner: None,
+                images: Some(vec![Image::with_id("wedding")]),
+            },
+        ],
+        pinned: Some(PinnedImages {
+            every: 3,
+            images: vec![Image::with_id("pinned")],
+        }),
+        interval: Duration::ZERO,
+        interval_rules: None,
+        folder_captions: false,
+        photo_info: false,
+        caption: None,
+        title_card: None,
+        places: Vec::new(),
+        folder_themes: HashMap::new(),
+        avoid_repeating_folders: false,
+        directory_weights: HashMap::new(),
+        fade: Duration::ZERO,
+        ken_burns: false,
+        photo_strip_after: None,
+        progress_bar: false,
+        orientation: None,
+        order: Order::Random,
+        date_range: DateRange::default(),
+        refresh_after: Instant::now(),
+    };
+    let june_2024 = |day| NaiveDate::from_ymd_opt(2024, 6, day).unwrap();
+    let images_for = |slide_number, day| {
+        all_images
+            .images_for(slide_number, june_2024(day))
+            .iter()
+            .map(|image| image.id.as_str())
+            .collect::<Vec<_>>()
+    };
+    // June 1st 2024 is a Saturday.
+    assert_eq!(images_for(1, 1), &["family"]);
+    // First matching playlist wins.
+    assert_eq!(images_for(1, 2), &["family"]);
+    assert_eq!(images_for(1, 3), &["art"]);
+    assert_eq!(images_for(1, 4), &["default"]);
+    // Pinned images take priority every 3rd slide.
+    assert_eq!(images_for(2, 4), &["default"]);
+    assert_eq!(images_for(3, 4), &["pinned"]);
+    assert_eq!(images_for(6, 1), &["pinned"]);
+    assert_eq!(images_for(7, 1), &["family"]);
+    // Special days with their own directories take priority over playlists.
+    assert_eq!(images_for(1, 8), &["wedding"]);
+    assert_eq!(images_for(1, 15), &["family"]);
+
+    assert_eq!(
+        all_images.banner_for(june_2024(4)).as_deref(),
+        Some("Happy birthday!")
+    );
+    assert_eq!(all_images.banner_for(june_2024(8)), None);
+}
+
+#[test]
+fn adapt_interval_to_library_size() {
+    let hour = Duration::from_secs(60 * 60);
+    let ten_seconds = Duration::from_secs(10);
+    // 50 images over a day: each is shown for about half an hour.
+    assert_eq!(
+        auto_interval(ten_seconds, 24 * hour, 50),
+        Duration::from_secs(1728)
+    );
+    // Too many images to show within the cycle, so they are shown for the minimum interval.
+    assert_eq!(auto_interval(ten_seconds, 24 * hour, 100_000), ten_seconds);
+    assert_eq!(auto_interval(ten_seconds, hour, 0), hour);
+}
+
+#[test]
+fn folder_captions() {
+    let mut all_images = ImageList {
+        images: Vec::new(),
+        missing_directories: Vec::new(),
+        unknown_fields: Vec::new(),
+        failed_directories: Vec::new(),
+        retry_failed_after: Instant::now(),
+        playlists: Vec::new(),
+        special_days: Vec::new(),
+        pinned: None,
+        interval: Duration::from_secs(10),
+        interval_rules: None,
+        folder_captions: false,
+        photo_info: false,
+        caption: None,
+        title_card: None,
+        places: Vec::new(),
+        folder_themes: HashMap::new(),
+        avoid_repeating_folders: false,
+        directory_weights: HashMap::new(),
+        fade: Duration::ZERO,
+        ken_burns: false,
+        photo_strip_after: None,
+        progress_bar: false,
+        orientation: None,
+        order: Order::Random,
+        date_range: DateRange::default(),
+        refresh_after: Instant::now(),
+    };
+    let item = |folder: Option<&str>| ShownItem {
+        item_id: "id".into(),
+        name: None,
+        source: "Pictures".into(),
+        folder: folder.map(Into::into),
+        taken: None,
+        location: None,
+    };
+    let vacation = item(Some("Pictures/Vacations 2023"));
+    assert_eq!(all_images.caption_for(&vacation), None);
+
+    all_images.folder_captions = true;
+    assert_eq!(
+        all_images.caption_for(&vacation).as_deref(),
+        Some("Folder: Vacations 2023")
+    );
+    assert_eq!(all_images.caption_for(&item(Some(""))), None);
+    assert_eq!(all_images.caption_for(&item(None)), None);
+
+    // Photo info shows whatever is known about the image instead.
+    all_images.photo_info = true;
+    let photo = ShownItem {
+        name: Some("IMG_1234.jpg".into()),
+        taken: Some("2023-06-05T14:30:00Z".parse().unwrap()),
+        ..item(Some("Pictures/Vacations 2023"))
+    };
+    assert_eq!(
+        all_images.caption_for(&photo).as_deref(),
+        Some("IMG_1234.jpg · Vacations 2023 · 5 June 2023")
+    );
+    assert_eq!(
+        all_images.caption_for(&vacation).as_deref(),
+        Some("Vacations 2023")
+    );
+    assert_eq!(all_images.caption_for(&item(None)), None);
+
+    // A template picks what to show, naming the place if it is a known one.
+    all_images.caption = Some("{month} · {place}".into());
+    all_images.places = vec![Place {
+        name: "Lisbon, Portugal".into(),
+        latitude: 38.72,
+        longitude: -9.14,
+        radius_km: None,
+    }];
+    let lisbon = ShownItem {
+        location: Some(metadata::GeoCoordinates {
+            latitude: 38.7223,
+            longitude: -9.1393,
+        }),
+        ..photo
+    };
+    assert_eq!(
+        all_images.caption_for(&lisbon).as_deref(),
+        Some("June 2023 · Lisbon, Portugal")
+    );
+    assert_eq!(all_images.caption_for(&vacation), None);
+
+    // Title cards use the same placeholders, but only if they are asked for.
+    assert_eq!(all_images.title_card_for(&lisbon), None);
+    all_images.title_card = Some("{folder} · {year}".into());
+    assert_eq!(
+        all_images.title_card_for(&lisbon).as_deref(),
+        Some("Vacations 2023 · 2023")
+    );
+    assert_eq!(
+        all_images.title_card_for(&vacation).as_deref(),
+        Some("Vacations 2023")
+    );
+}
+
+#[test]
+fn choose_photo_strip_images() {
+    let image = |id: &str, folder: &str, taken: Option<&str>| Image {
+        folder: folder.into(),
+        taken: taken.map(|taken| taken.parse().unwrap()),
+        ..Image::with_id(id)
+    };
+    let images = [
+        image("beach", "Vacations", Some("2023-06-05T09:00:00Z")),
+        image("hike", "Vacations", None),
+        image("cake", "Birthdays", Some("2023-06-05T18:00:00Z")),
+        image("party", "Birthdays", Some("2023-06-06T18:00:00Z")),
+    ];
+    let item = ShownItem {
+        item_id: "beach".into(),
+        name: None,
+        source: "Pictures".into(),
+        folder: Some("Vacations".into()),
+        taken: images[0].taken,
+        location: None,
+    };
+    let mut ids = photo_strip_images(&images, &item)
+        .iter()
+        .map(|image| image.id.as_str())
+        .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids, ["cake", "hike"]);
+
+    let many = (0..20)
+        .map(|index| image(&index.to_string(), "Vacations", None))
+        .collect::<Vec<_>>();
+    assert_eq!(photo_strip_images(&many, &item).len(), PHOTO_STRIP_SIZE);
+}
+
+#[test]
+fn interval_rules() {
+    let mut all_images = ImageList {
+        images: Vec::new(),
+        missing_directories: vec!["typo".into()],
+        unknown_fields: vec!["intreval".into()],
+        failed_directories: Vec::new(),
+        retry_failed_after: Instant::now(),
+        playlists: Vec::new(),
+        special_days: Vec::new(),
+        pinned: None,
+        interval: Duration::from_secs(10),
+        interval_rules: None,
+        folder_captions: false,
+        photo_info: false,
+        caption: None,
+        title_card: None,
+        places: Vec::new(),
+        folder_themes: HashMap::new(),
+        avoid_repeating_folders: false,
+        directory_weights: HashMap::new(),
+        fade: Duration::ZERO,
+        ken_burns: false,
+        photo_strip_after: None,
+        progress_bar: false,
+        orientation: None,
+        order: Order::Random,
+        date_range: DateRange::default(),
+        refresh_after: Instant::now(),
+    };
+    assert_eq!(
+        all_images.warnings(),
+        [
+            "These settings in slideshow.txt aren't recognized, so may be misspelled: intreval",
+            "These directories in slideshow.txt weren't found: typo"
+        ]
+    );
+    let image = |directory: &str, width, height| Image {
+        directory: directory.into(),
+        width,
+        height,
+        ..Image::with_id("id")
+    };
+    let panorama = image("d1", Some(4000), Some(1000));
+    let group_shot = image("groups", Some(1000), Some(1000));
+    let unknown_size = image("groups", None, None);
+
+    // Without rules, everything gets the normal interval.
+    assert_eq!(all_images.interval_for(&panorama), Duration::from_secs(10));
+
+    all_images.interval_rules = Some(IntervalRules {
+        panorama_aspect_ratio: None,
+        panorama_multiplier: Some(3.0),
+        directories: [("groups".to_string(), 1.5)].into(),
+    });
+    assert_eq!(all_images.interval_for(&panorama), Duration::from_secs(30));
+    assert_eq!(
+        all_images.interval_for(&group_shot),
+        Duration::from_secs(15)
+    );
+    assert_eq!(
+        all_images.interval_for(&unknown_size),
+        Duration::from_secs(15)
+    );
+    assert_eq!(
+        all_images.interval_for(&image("d1", Some(1500), Some(1000))),
+        Duration::from_secs(10)
+    );
+
+    // Nonsensical multipliers are ignored.
+    all_images.interval_rules = Some(IntervalRules {
+        directories: [("d1".to_string(), -1.0)].into(),
+        ..Default::default()
+    });
+    assert_eq!(
+        all_images.interval_for(&image("d1", None, None)),
+        Duration::from_secs(10)
+    );
+}
+
+#[tokio::test]
+async fn ui_sender_coalesces_errors() {
+    let (sender, mut receiver) = channel(2);
+    let sender = UiSender::new(
+        sender,
+        watch::channel(None).0,
+        watch::channel(Vec::new()).0,
+        watch::channel(None).0,
+        watch::channel(false).0,
+        egui::Context::default(),
+    );
+
+    // Repeated errors are only sent once.
+    sender.send(Err(anyhow!("first"))).await;
+    sender.send(Err(anyhow!("first"))).await;
+    sender.send(Err(anyhow!("second"))).await;
+    // The channel is full, so further errors are dropped instead of blocking.
+    sender.send(Err(anyhow!("third"))).await;
+    assert_eq!(
+        format!("{}", receiver.recv().await.unwrap().err().unwrap()),
+        "first"
+    );
+    assert_eq!(
+        format!("{}", receiver.recv().await.unwrap().err().unwrap()),
+        "second"
+    );
+    assert!(receiver.try_recv().is_err());
+
+    // A new state resets coalescing.
+    sender.send(Ok(AppState::LoadingImage)).await;
+    sender.send(Err(anyhow!("second"))).await;
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::LoadingImage)
+    ));
+    assert_eq!(
+        format!("{}", receiver.recv().await.unwrap().err().unwrap()),
+        "second"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn ui_sender_warms_up_with_cached_slides() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/ui_sender_warms_up");
+    std::fs::create_dir_all(&temp_dir).unwrap();
+    let path = temp_dir.join("cached");
+    image::RgbImage::new(4, 3)
+        .save_with_format(&path, image::ImageFormat::Png)
+        .unwrap();
+
+    let (sender, mut receiver) = channel(8);
+    let (warnings_sender, warnings) = watch::channel(Vec::new());
+    let sender = UiSender::new(
+        sender,
+        watch::channel(None).0,
+        warnings_sender,
+        watch::channel(None).0,
+        watch::channel(false).0,
+        egui::Context::default(),
+    );
+    sender.show_cached_slides(vec![temp_dir.join("missing"), path]);
+    match receiver.recv().await.unwrap() {
+        Ok(AppState::HasImage(slide)) => assert_eq!(slide.image.size, [4, 3]),
+        _ => panic!("Expected a cached slide"),
+    }
+
+    // Loading isn't shown over the cached slides, and errors are only warnings.
+    sender.send(Ok(AppState::LoadingImage)).await;
+    sender.send(Ok(AppState::WaitingForNetwork)).await;
+    sender.send(Err(anyhow!("offline"))).await;
+    assert!(receiver.try_recv().is_err());
+    assert_eq!(*warnings.borrow(), ["offline"]);
+
+    // Until something else takes over.
+    sender
+        .send(Ok(AppState::WaitingForAuth("url".into(), "code".into())))
+        .await;
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::WaitingForAuth(..))
+    ));
+    sender.send(Ok(AppState::LoadingImage)).await;
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::LoadingImage)
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn image_load_loop_end_to_end() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/image_load_loop_end_to_end");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let device_mock = server
+        .mock("POST", "/auth/devicecode")
+        .with_body(r#"{ "device_code": "dc", "user_code": "uc", "verification_uri": "vu", "interval": 0, "expires_in": 3600 } "#)
+        .expect(1)
+        .create();
+    let token_mock = server
+        .mock("POST", "/auth/token")
+        .with_body(r#"{ "access_token": "token", "refresh_token": "rt", "expires_in": 3600 } "#)
+        .expect(1)
+        .create();
+    let drive_mock = server
+        .mock("GET", "/drive")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$select".into(),
+            "driveType".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "driveType": "personal" }"#)
+        .expect(1)
+        .create();
+    let config_content_mock = server
+        .mock("GET", "/drive/root:/slideshow.txt:/content")
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "directories": [ "d1" ], "interval": 3600 } "#)
+        .expect(1)
+        .create();
+    let d1_folder_mock = server
+        .mock("GET", "/drive/root:/d1:/children")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$filter".into(),
+            "folder ne null".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ ] }"#)
+        .expect(1)
+        .create();
+    let d1_image_mock = server
+        .mock("GET", "/drive/root:/d1:/children")
+        .match_query(mockito::Matcher::UrlEncoded(
+            "$filter".into(),
+            "image ne null".into(),
+        ))
+        .match_header("authorization", "Bearer token")
+        .with_body(r#"{ "value": [ { "id": "the_image" } ] }"#)
+        .expect(1)
+        .create();
+    let thumbnail_mock = server
+        .mock("GET", "/drive/items/the_image/thumbnails")
+        .match_query(mockito::Matcher::Any)
+        .match_header("authorization", "Bearer token")
+        .with_body(format!(
+            r#"{{ "value": [ {{ "screen": {{ "url": "{url}/download" }} }} ] }} "#
+        ))
+        .expect(1)
+        .create();
+    let mut image_data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut image_data)
+        .encode_image(&image::RgbImage::new(1, 1))
+        .unwrap();
+    let download_mock = server
+        .mock("GET", "/download")
+        .with_body(image_data)
+        .expect(1)
+        .create();
+
+    let (sender, mut receiver) = channel(8);
+    let (command_sender, commands) = channel(8);
+    let ctx = egui::Context::default();
+    task::spawn(image_load_loop(
+        UiSender::new(
+            sender,
+            watch::channel(None).0,
+            watch::channel(Vec::new()).0,
+            watch::channel(None).0,
+            watch::channel(false).0,
+            ctx.clone(),
+        ),
+        ctx,
+        Settings {
+            cache_policy: cache::CachePolicy::Always,
+            ..Settings::default()
+        },
+        Environment {
+            auth_url: format!("{url}/auth"),
+            drive_url: format!("{url}/drive"),
+            data_directory: temp_dir.clone(),
+            token_store: TokenStore::File(temp_dir.join(REFRESH_TOKEN_FILE)),
+            refresh_token: None,
+            captive_portal_probe_url: format!("{url}/connecttest.txt"),
+            mock: None,
+        },
+        command_sender,
+        commands,
+    ));
+
+    // The full pipeline: authenticate, list the images, then download and show one.
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::WaitingForAuth(auth_url, code)) if auth_url == "vu" && code == "uc"
+    ));
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::LoadingImage)
+    ));
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::HasImage(slide)) if slide.image.size == [1, 1]
+    ));
+    device_mock.assert();
+    token_mock.assert();
+    drive_mock.assert();
     config_content_mock.assert();
     d1_folder_mock.assert();
     d1_image_mock.assert();
+    thumbnail_mock.assert();
+    download_mock.assert();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn wait_for_network_to_connect() {
+    let (sender, mut receiver) = channel(8);
+    let ui_sender = UiSender::new(
+        sender,
+        watch::channel(None).0,
+        watch::channel(Vec::new()).0,
+        watch::channel(None).0,
+        watch::channel(false).0,
+        egui::Context::default(),
+    );
+
+    // Already reachable, so there's no need to wait.
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    wait_for_network(&format!("http://{address}"), &ui_sender).await;
+    assert!(receiver.try_recv().is_err());
+
+    // Unreachable until something starts listening.
+    drop(listener);
+    let waiter = task::spawn(async move {
+        wait_for_network(&format!("http://{address}"), &ui_sender).await;
+    });
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::WaitingForNetwork)
+    ));
+    let _listener = TcpListener::bind(address).await.unwrap();
+    waiter.await.unwrap();
+    assert!(matches!(
+        receiver.recv().await.unwrap(),
+        Ok(AppState::LoadingImage)
+    ));
 }
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// How close (in kilometers) a photo has to have been taken to a place to be captioned with its
+/// name, unless the place sets its own radius.
+const DEFAULT_PLACE_RADIUS_KM: f64 = 25.0;
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Where a photo was taken, from OneDrive's location facet.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct GeoCoordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoCoordinates {
+    /// Great-circle distance between two points.
+    pub fn distance_km(&self, other: &GeoCoordinates) -> f64 {
+        let (latitude, other_latitude) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let half_latitude = (other_latitude - latitude) / 2.0;
+        let half_longitude = (other.longitude - self.longitude).to_radians() / 2.0;
+        let a = half_latitude.sin().powi(2)
+            + latitude.cos() * other_latitude.cos() * half_longitude.sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
+impl fmt::Display for GeoCoordinates {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}° {}, {:.2}° {}",
+            self.latitude.abs(),
+            if self.latitude < 0.0 { 'S' } else { 'N' },
+            self.longitude.abs(),
+            if self.longitude < 0.0 { 'W' } else { 'E' }
+        )
+    }
+}
+
+/// A named place from slideshow.txt (e.g., "Lisbon, Portugal"), so that photos taken there can be
+/// captioned with its name rather than their coordinates.
+#[derive(Debug, Deserialize)]
+pub struct Place {
+    pub name: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// How close (in kilometers) a photo has to be to count as being taken here.
+    pub radius_km: Option<f64>,
+}
+
+impl Place {
+    fn coordinates(&self) -> GeoCoordinates {
+        GeoCoordinates {
+            latitude: self.latitude,
+            longitude: self.longitude,
+        }
+    }
+}
+
+/// Names where a photo was taken: the closest of `places` that it is within, or else its
+/// coordinates.
+pub fn place_name(places: &[Place], location: GeoCoordinates) -> String {
+    places
+        .iter()
+        .map(|place| (place, place.coordinates().distance_km(&location)))
+        .filter(|(place, distance)| *distance <= place.radius_km.unwrap_or(DEFAULT_PLACE_RADIUS_KM))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or_else(|| location.to_string(), |(place, _)| place.name.clone())
+}
+
+/// What is known about a photo, to fill in a caption.
+#[derive(Debug)]
+pub struct PhotoMetadata<'a> {
+    pub name: Option<&'a str>,
+    pub folder: Option<&'a str>,
+    pub taken: Option<DateTime<Utc>>,
+    pub place: Option<String>,
+}
+
+impl PhotoMetadata<'_> {
+    fn value(&self, placeholder: &str) -> Option<String> {
+        match placeholder {
+            "name" => self.name.map(str::to_string),
+            "folder" => self.folder.map(str::to_string),
+            "date" => self
+                .taken
+                .map(|taken| taken.format("%-d %B %Y").to_string()),
+            "month" => self.taken.map(|taken| taken.format("%B %Y").to_string()),
+            "year" => self.taken.map(|taken| taken.format("%Y").to_string()),
+            "place" => self.place.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// Fills in a caption template such as `{month} · {place}`. The template is split into parts at
+/// each `·`, and any part with a placeholder that isn't known for this photo is left out, so that
+/// photos without a location still get a tidy caption.
+pub fn format_caption(template: &str, metadata: &PhotoMetadata) -> Option<String> {
+    let parts = template
+        .split('·')
+        .filter_map(|part| fill_placeholders(part.trim(), metadata))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    (!parts.is_empty()).then(|| parts.join(" · "))
+}
+
+fn fill_placeholders(part: &str, metadata: &PhotoMetadata) -> Option<String> {
+    let mut filled = String::new();
+    let mut rest = part;
+    while let Some((before, after)) = rest.split_once('{') {
+        let Some((placeholder, after)) = after.split_once('}') else {
+            break;
+        };
+        filled.push_str(before);
+        filled.push_str(&metadata.value(placeholder)?);
+        rest = after;
+    }
+    filled.push_str(rest);
+    Some(filled)
+}
+
+#[test]
+fn name_places() {
+    let lisbon = GeoCoordinates {
+        latitude: 38.7223,
+        longitude: -9.1393,
+    };
+    let places = [
+        Place {
+            name: "Lisbon, Portugal".into(),
+            latitude: 38.72,
+            longitude: -9.14,
+            radius_km: None,
+        },
+        Place {
+            name: "Belém".into(),
+            latitude: 38.6976,
+            longitude: -9.2063,
+            radius_km: Some(2.0),
+        },
+    ];
+    assert!((lisbon.distance_km(&places[1].coordinates()) - 6.4).abs() < 0.1);
+    assert_eq!(place_name(&places, lisbon), "Lisbon, Portugal");
+    // The closest place wins.
+    let tower = GeoCoordinates {
+        latitude: 38.6916,
+        longitude: -9.2160,
+    };
+    assert_eq!(place_name(&places, tower), "Belém");
+    // Anywhere else is shown by its coordinates.
+    let sydney = GeoCoordinates {
+        latitude: -33.8688,
+        longitude: 151.2093,
+    };
+    assert_eq!(place_name(&places, sydney), "33.87° S, 151.21° E");
+}
+
+#[test]
+fn fill_in_captions() {
+    let metadata = PhotoMetadata {
+        name: Some("IMG_1234.jpg"),
+        folder: Some("Vacations 2019"),
+        taken: Some("2019-06-05T14:30:00Z".parse().unwrap()),
+        place: Some("Lisbon, Portugal".into()),
+    };
+    assert_eq!(
+        format_caption("{month} · {place}", &metadata).as_deref(),
+        Some("June 2019 · Lisbon, Portugal")
+    );
+    assert_eq!(
+        format_caption("{name} ({year})·{folder}", &metadata).as_deref(),
+        Some("IMG_1234.jpg (2019) · Vacations 2019")
+    );
+
+    // Parts that aren't known are left out.
+    let metadata = PhotoMetadata {
+        taken: None,
+        place: None,
+        ..metadata
+    };
+    assert_eq!(
+        format_caption("Taken {date} · {folder} · near {place}", &metadata).as_deref(),
+        Some("Vacations 2019")
+    );
+    assert_eq!(format_caption("{unknown}", &metadata), None);
+    assert_eq!(format_caption("", &metadata), None);
+    assert_eq!(
+        format_caption("Unmatched {brace", &metadata).as_deref(),
+        Some("Unmatched {brace")
+    );
+}
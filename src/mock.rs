@@ -0,0 +1,327 @@
+use crate::{
+    auth::TokenSource,
+    image_loader::{self, Config, FailedDirectory, Image, Listing, PhotoSource},
+    source::SlideSource,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use chrono::{DateTime, TimeDelta, Utc};
+use egui::ColorImage;
+use serde_json::json;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::task;
+
+/// How many sample images to make up when there isn't a folder of images to show.
+const SAMPLE_IMAGES: usize = 12;
+const SAMPLE_LONG_SIDE: u32 = 1200;
+const SAMPLE_SHORT_SIDE: u32 = 800;
+/// Colors that the sample images fade between.
+const SAMPLE_COLORS: [[u8; 3]; 6] = [
+    [231, 76, 60],
+    [241, 196, 15],
+    [46, 204, 113],
+    [52, 152, 219],
+    [155, 89, 182],
+    [26, 188, 156],
+];
+/// How long each slide is shown for when previewing a caption template.
+const PREVIEW_INTERVAL_SECS: u64 = 3;
+/// slideshow.txt, unless the folder of images has its own.
+const MOCK_CONFIG: &str = r#"{ "directories": [ "Samples" ], "interval": 10, "photo_info": true }"#;
+
+/// An image in the mock drive.
+struct MockImage {
+    name: String,
+    data: Bytes,
+    /// Size and date taken of the made up sample images, which are unknown for real images.
+    size: Option<(u32, u32)>,
+    taken: Option<DateTime<Utc>>,
+}
+
+/// A stand-in for OneDrive, made up in memory so that the slideshow can be developed or demoed
+/// without a Microsoft account or a network connection. Every directory in its slideshow.txt has
+/// the same images.
+pub struct MockDrive {
+    images: Vec<MockImage>,
+    config: Vec<u8>,
+}
+
+impl MockDrive {
+    /// Shows the JPEG and PNG images in `folder` (along with its slideshow.txt, if it has one), or
+    /// made up sample images if there's no folder.
+    pub fn new(folder: Option<&Path>) -> Result<Self> {
+        let Some(folder) = folder else {
+            return Ok(Self {
+                images: (0..SAMPLE_IMAGES).map(sample_image).collect(),
+                config: MOCK_CONFIG.into(),
+            });
+        };
+
+        let mut paths = std::fs::read_dir(folder)
+            .with_context(|| format!("Reading {}", folder.display()))?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        ["jpg", "jpeg", "png"].contains(&extension.to_ascii_lowercase().as_str())
+                    })
+            })
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        let images = paths
+            .into_iter()
+            .map(|path| {
+                Ok(MockImage {
+                    name: path.file_name().unwrap().to_string_lossy().into_owned(),
+                    data: std::fs::read(&path)
+                        .with_context(|| format!("Reading {}", path.display()))?
+                        .into(),
+                    size: None,
+                    taken: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if images.is_empty() {
+            bail!("There are no JPEG or PNG images in {}", folder.display());
+        }
+        let config = match std::fs::read(folder.join("slideshow.txt")) {
+            Ok(config) => config,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => MOCK_CONFIG.into(),
+            Err(err) => return Err(err).with_context(|| "Reading slideshow.txt"),
+        };
+        Ok(Self { images, config })
+    }
+
+    /// Uses a slideshow.txt that quickly cycles through the images with each captioned by
+    /// `template` (see `metadata::format_caption`), to see how it looks.
+    pub fn with_preview(self, template: &str) -> Self {
+        let config = json!({
+            "directories": [ "Samples" ],
+            "interval": PREVIEW_INTERVAL_SECS,
+            "caption": template,
+        });
+        Self {
+            config: config.to_string().into_bytes(),
+            ..self
+        }
+    }
+
+    fn image(&self, image_id: &str) -> Result<&MockImage> {
+        image_id
+            .strip_prefix("mock")
+            .and_then(|index| index.parse::<usize>().ok())
+            .and_then(|index| self.images.get(index))
+            .ok_or_else(|| anyhow!("{image_id} isn't in the mock drive"))
+    }
+}
+
+impl PhotoSource for MockDrive {
+    async fn list_images(&self, _token: &str, directories: &[String]) -> Result<Listing> {
+        let images = directories
+            .iter()
+            .flat_map(|directory| {
+                let directory = Arc::<str>::from(directory.as_str());
+                self.images
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, image)| Image {
+                        id: format!("mock{index}"),
+                        name: Some(image.name.clone()),
+                        directory: directory.clone(),
+                        folder: directory.clone(),
+                        width: image.size.map(|(width, _)| width),
+                        height: image.size.map(|(_, height)| height),
+                        taken: image.taken,
+                        modified: None,
+                        location: None,
+                    })
+            })
+            .collect();
+        Ok(Listing {
+            images,
+            ..Listing::default()
+        })
+    }
+
+    /// Images are always "downloaded" at their full size.
+    async fn download_image(
+        &self,
+        _token: &str,
+        _height: u32,
+        _width: u32,
+        image_id: &str,
+    ) -> Result<Bytes> {
+        Ok(self.image(image_id)?.data.clone())
+    }
+}
+
+impl SlideSource for MockDrive {
+    async fn connect(&mut self, _token: &str) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_image_list(&self, token: &str) -> Result<(Listing, Config)> {
+        let config = Config::parse(&self.config)?;
+        let listing = self.list_images(token, &config.directories).await?;
+        Ok((listing, config))
+    }
+
+    async fn retry_failed_directory(&self, _token: &str, _failed: FailedDirectory) -> Listing {
+        // Listing never fails, so there's nothing to retry.
+        Listing::default()
+    }
+
+    async fn list_images_at(&self, token: &str, path: &str) -> Result<Vec<Image>> {
+        Ok(self.list_images(token, &[path.to_string()]).await?.images)
+    }
+
+    async fn resolve_share_url(&self, _token: &str, share_url: &str) -> Result<String> {
+        bail!("Sharing links can't be shown from the mock drive: {share_url}")
+    }
+
+    async fn create_sample_config(&self, _token: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Status reports and history exports are accepted, then forgotten.
+    async fn upload_app_file(
+        &self,
+        _token: &str,
+        _file_name: &str,
+        _contents: impl Into<Bytes> + Send,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn take_app_file(&self, _token: &str, _file_name: &str) -> Result<bool> {
+        Ok(false)
+    }
+
+    async fn load_image_data(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<Bytes> {
+        self.download_image(token, height, width, image_id).await
+    }
+
+    async fn convert(&self, data: Bytes) -> Result<ColorImage> {
+        task::spawn_blocking(move || image_loader::decode_image(&data)).await?
+    }
+
+    fn cache_only_for(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Nothing is cached, since every image is already in memory.
+    fn cached_image_ids(&self) -> HashSet<String> {
+        HashSet::new()
+    }
+
+    async fn collect_garbage(&self, _listed: Option<&HashSet<String>>) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Signs in to the mock drive straight away, so the device code is never shown.
+pub struct MockAuthenticator;
+
+impl TokenSource for MockAuthenticator {
+    async fn get_token(&mut self) -> Result<String> {
+        Ok("mock".into())
+    }
+
+    fn refresh_token_expires(&self) -> Option<DateTime<Utc>> {
+        None
+    }
+}
+
+/// Makes up a sample image that fades between two colors, alternating between landscape and
+/// portrait, and taken a month before the previous one.
+fn sample_image(index: usize) -> MockImage {
+    let (width, height) = if index % 3 == 2 {
+        (SAMPLE_SHORT_SIDE, SAMPLE_LONG_SIDE)
+    } else {
+        (SAMPLE_LONG_SIDE, SAMPLE_SHORT_SIDE)
+    };
+    let from = SAMPLE_COLORS[index % SAMPLE_COLORS.len()];
+    let to = SAMPLE_COLORS[(index + 1) % SAMPLE_COLORS.len()];
+    let image = image::RgbImage::from_fn(width, height, |x, y| {
+        let t = (x + y) as f32 / (width + height) as f32;
+        image::Rgb(std::array::from_fn(|channel| {
+            (from[channel] as f32 * (1.0 - t) + to[channel] as f32 * t) as u8
+        }))
+    });
+    let mut data = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new(&mut data)
+        .encode_image(&image)
+        .expect("Encoding a sample image");
+    MockImage {
+        name: format!("Sample {}.jpg", index + 1),
+        data: data.into(),
+        size: Some((width, height)),
+        taken: Some(Utc::now() - TimeDelta::days(30 * index as i64)),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn show_mock_images() {
+    let mut authenticator = MockAuthenticator;
+    let token = authenticator.get_token().await.unwrap();
+    assert_eq!(authenticator.refresh_token_expires(), None);
+
+    let mut drive = MockDrive::new(None).unwrap();
+    drive.connect(&token).await.unwrap();
+    let (listing, config) = drive.get_image_list(&token).await.unwrap();
+    assert_eq!(config.interval, 10);
+    assert_eq!(listing.images.len(), SAMPLE_IMAGES);
+    let portrait = &listing.images[2];
+    assert_eq!(portrait.name.as_deref(), Some("Sample 3.jpg"));
+    assert_eq!(&*portrait.directory, "Samples");
+    assert_eq!(
+        (portrait.width, portrait.height),
+        (Some(SAMPLE_SHORT_SIDE), Some(SAMPLE_LONG_SIDE))
+    );
+    assert!(portrait.taken.is_some());
+
+    let slide = drive
+        .load_image(&token, 2000, 2000, &portrait.id)
+        .await
+        .unwrap();
+    assert_eq!(
+        slide.size,
+        [SAMPLE_SHORT_SIDE as usize, SAMPLE_LONG_SIDE as usize]
+    );
+    assert!(drive.load_image(&token, 2000, 2000, "other").await.is_err());
+    assert!(drive
+        .load_image(&token, 2000, 2000, "mock99")
+        .await
+        .is_err());
+
+    // A folder of images is shown instead of the samples.
+    let folder = std::env::temp_dir().join("onedrive_slideshow_test/show_mock_images_folder");
+    std::fs::create_dir_all(&folder).unwrap();
+    std::fs::write(folder.join("beach.jpg"), &sample_image(0).data).unwrap();
+    std::fs::write(folder.join("notes.txt"), "not an image").unwrap();
+    let drive = MockDrive::new(Some(&folder)).unwrap();
+    assert_eq!(drive.images.len(), 1);
+    assert_eq!(drive.images[0].name, "beach.jpg");
+    assert!(MockDrive::new(Some(&folder.join("missing"))).is_err());
+
+    // Previews replace the folder's slideshow.txt.
+    std::fs::write(folder.join("slideshow.txt"), MOCK_CONFIG).unwrap();
+    let drive = MockDrive::new(Some(&folder))
+        .unwrap()
+        .with_preview("{name} · {month}");
+    let config = serde_json::from_slice::<serde_json::Value>(&drive.config).unwrap();
+    assert_eq!(config["caption"], "{name} · {month}");
+    assert_eq!(config["interval"], PREVIEW_INTERVAL_SECS);
+}
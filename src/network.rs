@@ -0,0 +1,224 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::{header::LOCATION, redirect::Policy, Url};
+use std::{fmt, net::SocketAddr, time::Duration};
+use tokio::net::{lookup_host, TcpStream};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const PROBE_RESPONSE: &str = "Microsoft Connect Test";
+pub const PROBE_URL: &str = "http://www.msftconnecttest.com/connecttest.txt";
+
+/// A network that requires signing in via a web page (e.g., hotel Wi-Fi) before it allows access to
+/// the internet.
+#[derive(Debug, Eq, PartialEq)]
+pub struct CaptivePortal {
+    /// The page to sign in at, if the portal redirected to it.
+    pub sign_in_url: Option<String>,
+}
+
+/// Detects captive portals by fetching a well-known page, which a captive portal will intercept.
+pub struct CaptivePortalDetector {
+    client: reqwest::Client,
+    probe_url: Url,
+}
+
+impl CaptivePortalDetector {
+    pub fn new(probe_url: &str) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .redirect(Policy::none())
+                .timeout(PROBE_TIMEOUT)
+                .build()
+                .unwrap(),
+            probe_url: Url::parse(probe_url).unwrap(),
+        }
+    }
+
+    /// Checks if the network is behind a captive portal. If the probe can't be fetched at all, then
+    /// the network is down rather than behind a portal.
+    pub async fn detect(&self) -> Option<CaptivePortal> {
+        let response = self.client.get(self.probe_url.clone()).send().await.ok()?;
+        if response.status().is_redirection() {
+            let sign_in_url = response
+                .headers()
+                .get(LOCATION)
+                .and_then(|location| location.to_str().ok())
+                .map(String::from);
+            return Some(CaptivePortal { sign_in_url });
+        }
+
+        let is_expected = response.status().is_success()
+            && response
+                .text()
+                .await
+                .map_or(false, |body| body.trim() == PROBE_RESPONSE);
+        (!is_expected).then_some(CaptivePortal { sign_in_url: None })
+    }
+}
+
+/// Whether an endpoint can be connected to over a particular IP version.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Reachability {
+    /// The endpoint has no addresses for this IP version.
+    NoAddresses,
+    Reachable,
+    Unreachable(String),
+}
+
+impl fmt::Display for Reachability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reachability::NoAddresses => write!(f, "no addresses"),
+            Reachability::Reachable => write!(f, "reachable"),
+            Reachability::Unreachable(reason) => write!(f, "unreachable ({reason})"),
+        }
+    }
+}
+
+/// Reachability of an endpoint over IPv4 and IPv6, which are checked separately since a network
+/// with broken IPv6 can make connections hang or fail intermittently.
+#[derive(Debug, Eq, PartialEq)]
+pub struct EndpointReport {
+    pub host: String,
+    pub ipv4: Reachability,
+    pub ipv6: Reachability,
+}
+
+impl EndpointReport {
+    pub fn is_reachable(&self) -> bool {
+        self.ipv4 == Reachability::Reachable || self.ipv6 == Reachability::Reachable
+    }
+}
+
+impl fmt::Display for EndpointReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: IPv4 {}, IPv6 {}", self.host, self.ipv4, self.ipv6)
+    }
+}
+
+/// Resolves the host of `url` and tries to connect to it over both IPv4 and IPv6.
+pub async fn check_endpoint(url: &str) -> Result<EndpointReport> {
+    let url = Url::parse(url).with_context(|| format!("Parsing {url}"))?;
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err(anyhow!("No host or port in {url}"));
+    };
+    let addresses = lookup_host((host, port))
+        .await
+        .with_context(|| format!("Resolving {host}"))?
+        .collect::<Vec<_>>();
+    let (ipv4, ipv6) = tokio::join!(
+        check_addresses(addresses.iter().filter(|address| address.is_ipv4())),
+        check_addresses(addresses.iter().filter(|address| address.is_ipv6()))
+    );
+    Ok(EndpointReport {
+        host: host.into(),
+        ipv4,
+        ipv6,
+    })
+}
+
+/// Tries each address in turn until one can be connected to.
+async fn check_addresses<'a>(addresses: impl Iterator<Item = &'a SocketAddr>) -> Reachability {
+    let mut reachability = Reachability::NoAddresses;
+    for address in addresses {
+        reachability =
+            match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(_)) => return Reachability::Reachable,
+                Ok(Err(err)) => Reachability::Unreachable(err.to_string()),
+                Err(_) => Reachability::Unreachable("timed out".into()),
+            };
+    }
+    reachability
+}
+
+/// Checks an error for a failure to connect, and returns the URL that couldn't be connected to.
+pub fn failed_connection_url(err: &anyhow::Error) -> Option<&Url> {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+        .find(|err| err.is_connect() || err.is_timeout())
+        .and_then(|err| err.url())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn detect_captive_portal() {
+    let mut server = mockito::Server::new_async().await;
+    let url = server.url();
+
+    let online_mock = server
+        .mock("GET", "/online")
+        .with_body(PROBE_RESPONSE)
+        .expect(1)
+        .create();
+    let redirect_mock = server
+        .mock("GET", "/redirect")
+        .with_status(302)
+        .with_header("location", "http://portal.example/login")
+        .expect(1)
+        .create();
+    let intercepted_mock = server
+        .mock("GET", "/intercepted")
+        .with_body("<html>Welcome to the hotel Wi-Fi</html>")
+        .expect(1)
+        .create();
+
+    let detect = |path: &str| CaptivePortalDetector::new(&format!("{url}/{path}"));
+    assert_eq!(detect("online").detect().await, None);
+    assert_eq!(
+        detect("redirect").detect().await,
+        Some(CaptivePortal {
+            sign_in_url: Some("http://portal.example/login".into())
+        })
+    );
+    assert_eq!(
+        detect("intercepted").detect().await,
+        Some(CaptivePortal { sign_in_url: None })
+    );
+    online_mock.assert();
+    redirect_mock.assert();
+    intercepted_mock.assert();
+
+    // Being offline isn't a captive portal.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+    assert_eq!(
+        CaptivePortalDetector::new(&format!("http://{address}/"))
+            .detect()
+            .await,
+        None
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_endpoint_reachability() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let report = check_endpoint(&format!("http://{address}/")).await.unwrap();
+    assert_eq!(
+        report,
+        EndpointReport {
+            host: "127.0.0.1".into(),
+            ipv4: Reachability::Reachable,
+            ipv6: Reachability::NoAddresses,
+        }
+    );
+    assert!(report.is_reachable());
+
+    drop(listener);
+    let report = check_endpoint(&format!("http://{address}/")).await.unwrap();
+    assert!(matches!(report.ipv4, Reachability::Unreachable(_)));
+    assert!(!report.is_reachable());
+
+    assert!(check_endpoint("not a url").await.is_err());
+
+    // Connection failures are picked out of an error's chain.
+    let err = anyhow!(reqwest::get(format!("http://{address}/"))
+        .await
+        .unwrap_err())
+    .context("Loading image");
+    assert_eq!(
+        failed_connection_url(&err).map(Url::as_str),
+        Some(format!("http://{address}/").as_str())
+    );
+    assert_eq!(failed_connection_url(&anyhow!("Bad image")), None);
+}
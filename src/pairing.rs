@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::Rng;
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{task, time::Instant};
+
+const PIN_LIFETIME: Duration = Duration::from_secs(2 * 60);
+
+/// Pairs remote control clients with the slideshow: a PIN is shown on screen, which the client
+/// exchanges for a token that it then sends with every request.
+pub struct Pairing {
+    show_pin: Box<dyn Fn(Option<String>) + Send + Sync>,
+    pending_pin: Mutex<Option<(String, Instant)>>,
+    tokens: Mutex<HashSet<String>>,
+    tokens_path: PathBuf,
+}
+
+impl Pairing {
+    /// Loads the tokens of previously paired clients from `tokens_path`. `show_pin` is called with
+    /// the PIN to show on screen, or `None` once it should be hidden.
+    pub async fn load(
+        tokens_path: PathBuf,
+        show_pin: impl Fn(Option<String>) + Send + Sync + 'static,
+    ) -> Self {
+        // If the tokens can't be read, then clients will need to pair again.
+        let tokens = tokio::fs::read_to_string(&tokens_path)
+            .await
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self {
+            show_pin: Box::new(show_pin),
+            pending_pin: Mutex::new(None),
+            tokens: Mutex::new(tokens),
+            tokens_path,
+        }
+    }
+
+    /// Starts pairing a new client by showing a PIN on screen.
+    pub fn start(self: &Arc<Self>) {
+        let pin = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        *self.pending_pin.lock().unwrap() = Some((pin.clone(), Instant::now() + PIN_LIFETIME));
+        (self.show_pin)(Some(pin.clone()));
+
+        let pairing = Arc::clone(self);
+        task::spawn(async move {
+            tokio::time::sleep(PIN_LIFETIME).await;
+            let mut pending_pin = pairing.pending_pin.lock().unwrap();
+            if pending_pin
+                .as_ref()
+                .map_or(false, |(pending, _)| *pending == pin)
+            {
+                *pending_pin = None;
+                (pairing.show_pin)(None);
+            }
+        });
+    }
+
+    /// Exchanges the PIN shown on screen for a token, or returns `None` if the PIN is wrong. Each
+    /// PIN can only be tried once so that it can't be guessed.
+    pub async fn complete(&self, pin: &str) -> Result<Option<String>> {
+        let pending_pin = self.pending_pin.lock().unwrap().take();
+        (self.show_pin)(None);
+        match pending_pin {
+            Some((expected, expires_at)) if expected == pin && Instant::now() < expires_at => {}
+            _ => return Ok(None),
+        }
+
+        let token = URL_SAFE_NO_PAD.encode(rand::thread_rng().gen::<[u8; 32]>());
+        let contents = {
+            let mut tokens = self.tokens.lock().unwrap();
+            tokens.insert(token.clone());
+            tokens.iter().fold(String::new(), |mut contents, token| {
+                contents.push_str(token);
+                contents.push('\n');
+                contents
+            })
+        };
+        if let Some(parent) = self.tokens_path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| "Create data directory")?;
+        }
+        tokio::fs::write(&self.tokens_path, contents)
+            .await
+            .with_context(|| "Save remote control tokens")?;
+        Ok(Some(token))
+    }
+
+    pub fn is_authorized(&self, token: Option<&str>) -> bool {
+        token.map_or(false, |token| self.tokens.lock().unwrap().contains(token))
+    }
+
+    #[cfg(test)]
+    pub fn with_token(token: &str) -> Self {
+        Self {
+            show_pin: Box::new(|_| {}),
+            pending_pin: Mutex::new(None),
+            tokens: Mutex::new(HashSet::from([token.to_string()])),
+            tokens_path: PathBuf::new(),
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn pair_client() {
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/pair_client");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    let tokens_path = temp_dir.join("remote_tokens");
+
+    let shown_pin = Arc::new(Mutex::new(None));
+    let captured_shown_pin = shown_pin.clone();
+    let pairing = Arc::new(
+        Pairing::load(tokens_path.clone(), move |pin| {
+            *captured_shown_pin.lock().unwrap() = pin
+        })
+        .await,
+    );
+    assert!(!pairing.is_authorized(None));
+
+    // A wrong PIN is rejected, and the PIN can't be tried again.
+    pairing.start();
+    let pin = shown_pin.lock().unwrap().clone().unwrap();
+    assert_eq!(pin.len(), 6);
+    let wrong_pin = if pin == "000000" { "111111" } else { "000000" };
+    assert_eq!(pairing.complete(wrong_pin).await.unwrap(), None);
+    assert_eq!(*shown_pin.lock().unwrap(), None);
+    assert_eq!(pairing.complete(&pin).await.unwrap(), None);
+
+    // The right PIN gets a token.
+    pairing.start();
+    let pin = shown_pin.lock().unwrap().clone().unwrap();
+    let token = pairing.complete(&pin).await.unwrap().unwrap();
+    assert_eq!(*shown_pin.lock().unwrap(), None);
+    assert!(pairing.is_authorized(Some(&token)));
+    assert!(!pairing.is_authorized(Some("not a token")));
+
+    // Tokens are remembered.
+    let pairing = Pairing::load(tokens_path, |_| {}).await;
+    assert!(pairing.is_authorized(Some(&token)));
+}
@@ -0,0 +1,257 @@
+use crate::{controller::Slide, history::ShownItem, time_from_now, GuestImages};
+use anyhow::Result;
+use egui::ColorImage;
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+/// The next slide, how long to show it for and the item it shows, or why it couldn't be loaded.
+pub type PreparedSlide = Result<(ColorImage, Duration, ShownItem)>;
+
+/// Tracks when the image load loop shows its next slide and what that slide is chosen from, as
+/// slides are shown and remote control commands change them. The loop does the loading; this only
+/// holds the state, so that how the commands change it can be tested.
+pub struct Playback {
+    show_at: Instant,
+    /// The next slide, along with the thumbnails for its photo strip, once it has been prepared. This
+    /// is kept while handling commands, so that a command arriving while waiting to show it doesn't
+    /// skip over it.
+    prepared: Option<(PreparedSlide, Vec<Arc<ColorImage>>)>,
+    /// Images to show instead of the image list, until they expire.
+    guest: Option<GuestImages>,
+    /// While paused, how much of the current slide's interval was left. The slide is held on screen
+    /// until resumed.
+    paused: Option<Duration>,
+    /// Shows the next slide as soon as it's ready, even while paused, because it was asked for.
+    advance: bool,
+    /// The slide on screen, and the one before it that `previous` swaps back to.
+    current: Option<Slide>,
+    previous: Option<Slide>,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            show_at: Instant::now(),
+            prepared: None,
+            guest: None,
+            paused: None,
+            advance: false,
+            current: None,
+            previous: None,
+        }
+    }
+}
+
+impl Playback {
+    /// When to show the next slide, or `None` while paused.
+    pub fn wait_until(&self) -> Option<Instant> {
+        (self.paused.is_none() || self.advance).then_some(self.show_at)
+    }
+
+    /// The guest images to show instead of the image list, unless they have expired.
+    pub fn guest(&mut self) -> Option<&GuestImages> {
+        // Revert to the normal image list once the guest folder has expired.
+        if self
+            .guest
+            .as_ref()
+            .is_some_and(|guest| Instant::now() >= guest.until)
+        {
+            self.guest = None;
+        }
+        self.guest.as_ref()
+    }
+
+    /// Whether the next slide still needs to be prepared.
+    pub fn needs_preparing(&self) -> bool {
+        self.prepared.is_none()
+    }
+
+    /// Keeps the next slide until it's time to show it.
+    pub fn prepare(&mut self, slide: PreparedSlide, photo_strip: Vec<Arc<ColorImage>>) {
+        self.prepared = Some((slide, photo_strip));
+    }
+
+    /// Takes the prepared slide, and its photo strip, to show it.
+    pub fn take_prepared(&mut self) -> Option<(PreparedSlide, Vec<Arc<ColorImage>>)> {
+        self.prepared.take()
+    }
+
+    /// Records that a slide has just been shown (or failed to load), so that the next one is shown
+    /// after `interval`.
+    pub fn shown(&mut self, interval: Duration) {
+        self.show_at = time_from_now(interval);
+        self.advance = false;
+        // A slide shown while paused (e.g., by asking for the next one) gets its whole interval once
+        // resumed.
+        if let Some(remaining) = &mut self.paused {
+            *remaining = interval;
+        }
+    }
+
+    /// Records the slide that has just been put on screen.
+    pub fn shown_image(&mut self, slide: Slide) {
+        self.previous = self.current.replace(slide);
+    }
+
+    /// The slide on screen, if any.
+    pub fn current(&self) -> Option<&Slide> {
+        self.current.as_ref()
+    }
+
+    /// Swaps back to the previous slide, returning it to put on screen. Doing this again swaps
+    /// forward to the slide that was replaced.
+    pub fn previous(&mut self) -> Option<Slide> {
+        let slide = self.previous.take()?;
+        self.previous = self.current.replace(slide.clone());
+        Some(slide)
+    }
+
+    /// Shows the next slide as soon as it's ready, including while paused.
+    pub fn next(&mut self) {
+        self.show_at = Instant::now();
+        self.advance = true;
+    }
+
+    /// Whether the current slide is being held on screen.
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Pauses or resumes the slideshow. Resuming picks the current slide's interval up where it was
+    /// paused.
+    pub fn set_paused(&mut self, paused: bool) {
+        if paused {
+            self.paused
+                .get_or_insert_with(|| self.show_at.saturating_duration_since(Instant::now()));
+        } else if let Some(remaining) = self.paused.take() {
+            self.show_at = time_from_now(remaining);
+        }
+    }
+
+    /// Switches to showing `guest` instead of the image list, starting straight away.
+    pub fn show_guest(&mut self, guest: GuestImages) {
+        self.guest = Some(guest);
+        self.restart();
+    }
+
+    /// Drops the prepared slide, since it may no longer be what should be shown, and shows a new
+    /// one straight away.
+    pub fn restart(&mut self) {
+        self.prepared = None;
+        self.show_at = Instant::now();
+    }
+}
+
+#[cfg(test)]
+fn slide(interval: Duration) -> PreparedSlide {
+    let item = ShownItem {
+        item_id: "item".into(),
+        name: None,
+        source: "test".into(),
+        folder: None,
+        taken: None,
+        location: None,
+    };
+    Ok((ColorImage::new([1, 1], egui::Color32::RED), interval, item))
+}
+
+#[test]
+fn prepared_slide_is_kept_until_shown() {
+    let mut playback = Playback::default();
+    assert!(playback.needs_preparing());
+    assert!(playback.wait_until().unwrap() <= Instant::now());
+
+    playback.prepare(slide(Duration::from_secs(10)), Vec::new());
+    assert!(!playback.needs_preparing());
+    let (slide, _) = playback.take_prepared().unwrap();
+    let (_, interval, _) = slide.unwrap();
+    assert!(playback.needs_preparing());
+
+    // The slide after it is shown once its interval is up.
+    playback.shown(interval);
+    assert!(playback.wait_until().unwrap() > Instant::now() + Duration::from_secs(5));
+}
+
+#[test]
+fn guest_images_are_shown_straight_away() {
+    let mut playback = Playback::default();
+    playback.shown(Duration::from_secs(60));
+    playback.prepare(slide(Duration::from_secs(60)), Vec::new());
+    assert!(playback.guest().is_none());
+
+    // The slide that was prepared from the image list is dropped.
+    playback.show_guest(GuestImages {
+        images: Vec::new(),
+        until: Instant::now() + Duration::from_secs(60),
+    });
+    assert!(playback.needs_preparing());
+    assert!(playback.wait_until().unwrap() <= Instant::now());
+    assert!(playback.guest().is_some());
+
+    // Until they expire.
+    playback.show_guest(GuestImages {
+        images: Vec::new(),
+        until: Instant::now(),
+    });
+    assert!(playback.guest().is_none());
+}
+
+#[test]
+fn next_shows_one_slide_while_paused() {
+    let mut playback = Playback::default();
+    playback.shown(Duration::from_secs(60));
+    playback.set_paused(true);
+    assert!(playback.wait_until().is_none());
+
+    // Asking for the next slide shows it straight away, and then stays paused on it.
+    playback.next();
+    assert!(playback.wait_until().unwrap() <= Instant::now());
+    playback.shown(Duration::from_secs(60));
+    assert!(playback.wait_until().is_none());
+
+    playback.set_paused(false);
+    assert!(playback.wait_until().unwrap() > Instant::now() + Duration::from_secs(30));
+}
+
+#[test]
+fn previous_swaps_back_and_forth() {
+    let mut playback = Playback::default();
+    assert!(playback.previous().is_none());
+
+    let first = Arc::new(ColorImage::new([1, 1], egui::Color32::RED));
+    let second = Arc::new(ColorImage::new([1, 1], egui::Color32::BLUE));
+    playback.shown_image(Arc::clone(&first).into());
+    assert!(playback.previous().is_none());
+    playback.shown_image(Arc::clone(&second).into());
+
+    assert!(Arc::ptr_eq(&playback.previous().unwrap().image, &first));
+    assert!(Arc::ptr_eq(&playback.current().unwrap().image, &first));
+    assert!(Arc::ptr_eq(&playback.previous().unwrap().image, &second));
+}
+
+#[test]
+fn pause_keeps_rest_of_interval() {
+    let mut playback = Playback::default();
+    playback.shown(Duration::from_secs(60));
+    playback.set_paused(true);
+    assert!(playback.is_paused());
+    assert!(playback.wait_until().is_none());
+
+    // Pausing again doesn't lose track of how long was left.
+    std::thread::sleep(Duration::from_millis(50));
+    playback.set_paused(true);
+    playback.set_paused(false);
+    assert!(!playback.is_paused());
+    let resumed_at = playback.wait_until().unwrap();
+    assert!(resumed_at > Instant::now() + Duration::from_secs(55));
+    assert!(resumed_at <= Instant::now() + Duration::from_secs(60));
+
+    // A slide that was skipped to while paused gets its whole interval once resumed.
+    playback.set_paused(true);
+    playback.next();
+    assert!(playback.wait_until().unwrap() <= Instant::now());
+    playback.shown(Duration::from_secs(120));
+    assert!(playback.wait_until().is_none());
+    playback.set_paused(false);
+    assert!(playback.wait_until().unwrap() > Instant::now() + Duration::from_secs(110));
+}
@@ -0,0 +1,176 @@
+use std::time::Duration;
+
+/// Battery level at or below which the slideshow stops downloading new slides.
+pub const LOW_BATTERY_PERCENT: u8 = 20;
+/// How much longer each slide is shown while running on battery.
+const BATTERY_INTERVAL_MULTIPLIER: u32 = 2;
+
+/// Whether this device is running on battery, and how much charge is left.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PowerStatus {
+    pub on_battery: bool,
+    pub battery_percent: Option<u8>,
+}
+
+impl PowerStatus {
+    /// Gets the current power status. Devices without a battery (or where the status can't be
+    /// read) are treated as being on external power.
+    pub fn current() -> Self {
+        power_status().unwrap_or_default()
+    }
+
+    pub fn is_battery_low(&self) -> bool {
+        self.on_battery
+            && self
+                .battery_percent
+                .map_or(false, |percent| percent <= LOW_BATTERY_PERCENT)
+    }
+
+    /// Lengthens slide intervals while on battery to save power.
+    pub fn slide_interval(&self, interval: Duration) -> Duration {
+        if self.on_battery {
+            interval.saturating_mul(BATTERY_INTERVAL_MULTIPLIER)
+        } else {
+            interval
+        }
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use super::PowerStatus;
+    use windows_sys::Win32::System::Power::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+    const NO_SYSTEM_BATTERY: u8 = 128;
+    const UNKNOWN_PERCENT: u8 = 255;
+
+    pub fn power_status() -> Option<PowerStatus> {
+        let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+        if unsafe { GetSystemPowerStatus(&mut status) } == 0
+            || status.BatteryFlag & NO_SYSTEM_BATTERY != 0
+        {
+            return None;
+        }
+        Some(PowerStatus {
+            on_battery: status.ACLineStatus == 0,
+            battery_percent: (status.BatteryLifePercent != UNKNOWN_PERCENT)
+                .then_some(status.BatteryLifePercent),
+        })
+    }
+}
+#[cfg(windows)]
+use windows::*;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::PowerStatus;
+    use std::path::Path;
+
+    pub fn power_status() -> Option<PowerStatus> {
+        read_power_supplies(Path::new("/sys/class/power_supply"))
+    }
+
+    /// Reads the status of the power supplies in sysfs.
+    pub fn read_power_supplies(root: &Path) -> Option<PowerStatus> {
+        let mut battery = None;
+        let mut external_power = false;
+        for entry in std::fs::read_dir(root).ok()?.flatten() {
+            let read = |attribute: &str| {
+                std::fs::read_to_string(entry.path().join(attribute))
+                    .map(|value| value.trim().to_string())
+                    .unwrap_or_default()
+            };
+            match read("type").as_str() {
+                "Battery" => battery = Some((read("status"), read("capacity").parse().ok())),
+                "Mains" | "USB" => external_power |= read("online") == "1",
+                _ => {}
+            }
+        }
+        let (status, battery_percent) = battery?;
+        Some(PowerStatus {
+            on_battery: !external_power && status == "Discharging",
+            battery_percent,
+        })
+    }
+}
+#[cfg(target_os = "linux")]
+use linux::*;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+fn power_status() -> Option<PowerStatus> {
+    None
+}
+
+#[test]
+fn battery_saving() {
+    let interval = Duration::from_secs(60);
+    let external_power = PowerStatus::default();
+    assert!(!external_power.is_battery_low());
+    assert_eq!(external_power.slide_interval(interval), interval);
+
+    let on_battery = PowerStatus {
+        on_battery: true,
+        battery_percent: Some(50),
+    };
+    assert!(!on_battery.is_battery_low());
+    assert_eq!(on_battery.slide_interval(interval), 2 * interval);
+
+    let low_battery = PowerStatus {
+        on_battery: true,
+        battery_percent: Some(LOW_BATTERY_PERCENT),
+    };
+    assert!(low_battery.is_battery_low());
+    // A low battery that's charging doesn't need to save power.
+    assert!(!PowerStatus {
+        on_battery: false,
+        ..low_battery
+    }
+    .is_battery_low());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn read_linux_power_supplies() {
+    let root = std::env::temp_dir().join("onedrive_slideshow_test/read_linux_power_supplies");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    let write_supply = |name: &str, attributes: &[(&str, &str)]| {
+        let path = root.join(name);
+        std::fs::create_dir_all(&path).unwrap();
+        for (attribute, value) in attributes {
+            std::fs::write(path.join(attribute), format!("{value}\n")).unwrap();
+        }
+    };
+
+    // No battery.
+    std::fs::create_dir_all(&root).unwrap();
+    assert_eq!(read_power_supplies(&root), None);
+    write_supply("AC", &[("type", "Mains"), ("online", "0")]);
+    assert_eq!(read_power_supplies(&root), None);
+
+    write_supply(
+        "BAT0",
+        &[
+            ("type", "Battery"),
+            ("status", "Discharging"),
+            ("capacity", "15"),
+        ],
+    );
+    assert_eq!(
+        read_power_supplies(&root),
+        Some(PowerStatus {
+            on_battery: true,
+            battery_percent: Some(15)
+        })
+    );
+
+    write_supply("AC", &[("type", "Mains"), ("online", "1")]);
+    assert_eq!(
+        read_power_supplies(&root),
+        Some(PowerStatus {
+            on_battery: false,
+            battery_percent: Some(15)
+        })
+    );
+}
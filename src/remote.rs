@@ -0,0 +1,673 @@
+use crate::{pairing::Pairing, status::encode_png};
+use anyhow::{bail, Context, Result};
+use egui::ColorImage;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc::Sender, watch},
+    task,
+};
+
+const DEFAULT_GUEST_DURATION: Duration = Duration::from_secs(30 * 60);
+const MAX_BODY_SIZE: usize = 64 * 1024;
+/// How long a client has to send its request, so that idle or deliberately slow connections can't
+/// keep piling up.
+const REQUEST_TIMEOUT: Duration = if cfg!(test) {
+    Duration::from_millis(500)
+} else {
+    Duration::from_secs(10)
+};
+const CONTROL_PAGE: &str = include_str!("remote.html");
+const SERVICE_TYPE: &str = "_onedrive-slideshow._tcp.local.";
+
+/// The image currently being shown, if any.
+pub type CurrentImage = watch::Receiver<Option<Arc<ColorImage>>>;
+
+/// The slides that will be shown next, in order.
+pub type Upcoming = watch::Receiver<Vec<UpcomingItem>>;
+
+/// A slide that has been chosen to be shown soon.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct UpcomingItem {
+    pub item_id: String,
+    /// Configured directory that the image was found within.
+    pub directory: String,
+    /// Whether the image has already been downloaded.
+    pub ready: bool,
+}
+
+/// State shared by all connections to the remote control server.
+pub struct RemoteControl {
+    pub sender: Sender<RemoteCommand>,
+    pub current_image: CurrentImage,
+    pub upcoming: Upcoming,
+    pub pairing: Arc<Pairing>,
+    /// PIN for turning the folder lock off and on, if it can be.
+    pub lock_pin: Option<String>,
+}
+
+/// Commands that can be sent to the slideshow via the remote control server.
+#[derive(Debug, Eq, PartialEq)]
+pub enum RemoteCommand {
+    /// Temporarily show only the images within a folder.
+    ShowFolder {
+        folder: String,
+        duration: Duration,
+    },
+    /// Immediately show a specific item, then resume the slideshow.
+    ShowItem(ItemReference),
+    /// Immediately show the next slide.
+    Next,
+    /// Don't show an upcoming slide, and choose another in its place.
+    Skip(String),
+    /// Go back to the previous slide.
+    Previous,
+    /// Stop changing slides until resumed.
+    Pause,
+    Resume,
+    /// Pause if the slideshow is running, otherwise resume it (e.g., from the keyboard).
+    TogglePause,
+    /// Create a sample slideshow.txt, since there isn't one yet.
+    CreateSampleConfig,
+    /// Save a screenshot of everything on screen to the data directory.
+    Screenshot,
+    /// Turn the folder lock on or off, once the PIN has been checked.
+    SetLocked(bool),
+}
+
+#[derive(Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemReference {
+    ItemId(String),
+    ShareUrl(String),
+}
+
+#[derive(Deserialize)]
+struct GuestRequest {
+    folder: String,
+    minutes: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct SkipRequest {
+    item_id: String,
+}
+
+#[derive(Deserialize)]
+struct PairRequest {
+    pin: String,
+}
+
+#[derive(Deserialize)]
+struct LockRequest {
+    pin: String,
+}
+
+#[derive(Serialize)]
+struct PairResponse {
+    token: String,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    /// Token from either the `Authorization` header or the `token` query parameter (since images
+    /// can't be loaded with custom headers).
+    token: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn ok() -> Self {
+        Self::text("200 OK", "OK".into())
+    }
+
+    fn html(body: &str) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    fn json(body: Vec<u8>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn png(body: Vec<u8>) -> Self {
+        Self {
+            status: "200 OK",
+            content_type: "image/png",
+            body,
+        }
+    }
+
+    fn text(status: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into_bytes(),
+        }
+    }
+}
+
+pub async fn run_server(listener: TcpListener, remote: RemoteControl) -> Result<()> {
+    let remote = Arc::new(remote);
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .with_context(|| "Accepting remote control connection")?;
+        let remote = remote.clone();
+        task::spawn(async move {
+            // A failure only affects this connection, and there's no one else to report it to.
+            handle_connection(stream, &remote).await.ok();
+        });
+    }
+}
+
+/// Advertises the remote control server via mDNS so that it can be discovered on the local network.
+/// The advertisement lasts for as long as the returned daemon is kept alive.
+pub fn advertise(address: SocketAddr, device_name: &str) -> Result<ServiceDaemon> {
+    let daemon = ServiceDaemon::new()?;
+    let host_name = format!(
+        "{}.local.",
+        sys_info::hostname().unwrap_or_else(|_| "onedrive-slideshow".into())
+    );
+    let properties = None::<HashMap<String, String>>;
+    let service = if address.ip().is_unspecified() {
+        // Listening on all interfaces, so advertise all of them.
+        ServiceInfo::new(
+            SERVICE_TYPE,
+            device_name,
+            &host_name,
+            "",
+            address.port(),
+            properties,
+        )?
+        .enable_addr_auto()
+    } else {
+        ServiceInfo::new(
+            SERVICE_TYPE,
+            device_name,
+            &host_name,
+            address.ip(),
+            address.port(),
+            properties,
+        )?
+    };
+    daemon.register(service)?;
+    Ok(daemon)
+}
+
+async fn handle_connection(mut stream: TcpStream, remote: &RemoteControl) -> Result<()> {
+    let response = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => handle_request(request, remote).await,
+        Ok(Err(err)) => Response::text("400 Bad Request", format!("{err:?}")),
+        Err(_) => Response::text("408 Request Timeout", "Request timed out".into()),
+    };
+
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    Ok(())
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("Malformed request line");
+    };
+    let method = method.to_string();
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let mut token = query
+        .split('&')
+        .find_map(|parameter| parameter.strip_prefix("token="))
+        .map(String::from);
+    let path = path.to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).await?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .with_context(|| "Invalid Content-Length")?;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                if let Some(bearer) = value.trim().strip_prefix("Bearer ") {
+                    token = Some(bearer.to_string());
+                }
+            }
+        }
+    }
+
+    if content_length > MAX_BODY_SIZE {
+        bail!("Request body is too large");
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    Ok(Request {
+        method,
+        path,
+        token,
+        body,
+    })
+}
+
+async fn handle_request(request: Request, remote: &RemoteControl) -> Response {
+    let sender = &remote.sender;
+    match (request.method.as_str(), request.path.as_str()) {
+        // The control page and pairing are the only things that don't require a token.
+        ("GET", "/") => Response::html(CONTROL_PAGE),
+        ("POST", "/pair/start") => {
+            remote.pairing.start();
+            Response::ok()
+        }
+        ("POST", "/pair") => match serde_json::from_slice::<PairRequest>(&request.body) {
+            Ok(PairRequest { pin }) => match remote.pairing.complete(&pin).await {
+                Ok(Some(token)) => {
+                    Response::json(serde_json::to_vec(&PairResponse { token }).unwrap())
+                }
+                Ok(None) => Response::text("403 Forbidden", "Incorrect or expired PIN".into()),
+                Err(err) => Response::text("500 Internal Server Error", format!("{err:?}")),
+            },
+            Err(err) => Response::text("400 Bad Request", format!("{err}")),
+        },
+        _ if !remote.pairing.is_authorized(request.token.as_deref()) => {
+            Response::text("401 Unauthorized", "Pair with the slideshow first".into())
+        }
+        ("GET", "/current.png") => {
+            // Clone the image out so that the loader isn't blocked while it's encoded.
+            let image = remote.current_image.borrow().clone();
+            match image.map(|image| encode_png(&image)) {
+                Some(Ok(png)) => Response::png(png),
+                Some(Err(err)) => Response::text("500 Internal Server Error", format!("{err:?}")),
+                None => Response::text("404 Not Found", "No image is being shown".into()),
+            }
+        }
+        ("GET", "/upcoming") => match serde_json::to_vec(&*remote.upcoming.borrow()) {
+            Ok(json) => Response::json(json),
+            Err(err) => Response::text("500 Internal Server Error", format!("{err:?}")),
+        },
+        ("POST", "/next") => send_command(sender, RemoteCommand::Next).await,
+        ("POST", "/skip") => match serde_json::from_slice::<SkipRequest>(&request.body) {
+            Ok(SkipRequest { item_id }) => send_command(sender, RemoteCommand::Skip(item_id)).await,
+            Err(err) => Response::text("400 Bad Request", format!("{err}")),
+        },
+        ("POST", "/previous") => send_command(sender, RemoteCommand::Previous).await,
+        ("POST", "/pause") => send_command(sender, RemoteCommand::Pause).await,
+        ("POST", "/resume") => send_command(sender, RemoteCommand::Resume).await,
+        ("POST", "/screenshot") => send_command(sender, RemoteCommand::Screenshot).await,
+        ("POST", path @ ("/lock" | "/unlock")) => {
+            match serde_json::from_slice::<LockRequest>(&request.body) {
+                Ok(LockRequest { pin }) => match &remote.lock_pin {
+                    Some(lock_pin) if *lock_pin == pin => {
+                        send_command(sender, RemoteCommand::SetLocked(path == "/lock")).await
+                    }
+                    Some(_) => Response::text("403 Forbidden", "Incorrect PIN".into()),
+                    None => Response::text("404 Not Found", "The folder lock has no PIN".into()),
+                },
+                Err(err) => Response::text("400 Bad Request", format!("{err}")),
+            }
+        }
+        ("POST", "/guest") => match serde_json::from_slice::<GuestRequest>(&request.body) {
+            Ok(GuestRequest { folder, minutes }) => {
+                let duration = minutes.map_or(DEFAULT_GUEST_DURATION, |minutes| {
+                    Duration::from_secs(minutes.saturating_mul(60))
+                });
+                send_command(sender, RemoteCommand::ShowFolder { folder, duration }).await
+            }
+            Err(err) => Response::text("400 Bad Request", format!("{err}")),
+        },
+        ("POST", "/show") => match serde_json::from_slice::<ItemReference>(&request.body) {
+            Ok(item) => send_command(sender, RemoteCommand::ShowItem(item)).await,
+            Err(err) => Response::text("400 Bad Request", format!("{err}")),
+        },
+        _ => Response::text("404 Not Found", "Not found".into()),
+    }
+}
+
+async fn send_command(sender: &Sender<RemoteCommand>, command: RemoteCommand) -> Response {
+    if sender.send(command).await.is_ok() {
+        Response::ok()
+    } else {
+        Response::text("503 Service Unavailable", "Slideshow is exiting".into())
+    }
+}
+
+#[cfg(test)]
+const TEST_TOKEN: &str = "test_token";
+
+#[cfg(test)]
+async fn start_test_server() -> (
+    String,
+    tokio::sync::mpsc::Receiver<RemoteCommand>,
+    watch::Sender<Option<Arc<ColorImage>>>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let (sender, receiver) = tokio::sync::mpsc::channel(8);
+    let (current_sender, current_image) = watch::channel(None);
+    let (_, upcoming) = watch::channel(vec![UpcomingItem {
+        item_id: "next_item".into(),
+        directory: "Pictures".into(),
+        ready: true,
+    }]);
+    task::spawn(run_server(
+        listener,
+        RemoteControl {
+            sender,
+            current_image,
+            upcoming,
+            pairing: Arc::new(Pairing::with_token(TEST_TOKEN)),
+            lock_pin: Some("1234".into()),
+        },
+    ));
+    (url, receiver, current_sender)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_requests_time_out() {
+    let (url, mut receiver, _current_image) = start_test_server().await;
+
+    // A client that starts a request but never finishes it is cut off.
+    let mut stream = TcpStream::connect(url.trim_start_matches("http://"))
+        .await
+        .unwrap();
+    stream
+        .write_all(b"POST /guest HTTP/1.1\r\nContent-Length: 100\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT * 4, stream.read_to_string(&mut response))
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(response.starts_with("HTTP/1.1 408"), "{response}");
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn show_folder() {
+    let (url, mut receiver, _current_image) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{url}/guest"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "folder": "Trips/Paris", "minutes": 5 }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        receiver.recv().await.unwrap(),
+        RemoteCommand::ShowFolder {
+            folder: "Trips/Paris".into(),
+            duration: Duration::from_secs(5 * 60)
+        }
+    );
+
+    // Duration is optional.
+    let response = client
+        .post(format!("{url}/guest"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "folder": "Trips/Rome" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        receiver.recv().await.unwrap(),
+        RemoteCommand::ShowFolder {
+            folder: "Trips/Rome".into(),
+            duration: DEFAULT_GUEST_DURATION
+        }
+    );
+
+    // Bad requests are rejected.
+    let response = client
+        .post(format!("{url}/guest"))
+        .bearer_auth(TEST_TOKEN)
+        .body("not json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    let response = client
+        .get(format!("{url}/unknown"))
+        .bearer_auth(TEST_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn show_item() {
+    let (url, mut receiver, _current_image) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .post(format!("{url}/show"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "item_id": "the_item" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        receiver.recv().await.unwrap(),
+        RemoteCommand::ShowItem(ItemReference::ItemId("the_item".into()))
+    );
+
+    let response = client
+        .post(format!("{url}/show"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "share_url": "https://1drv.ms/i/s!abc" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        receiver.recv().await.unwrap(),
+        RemoteCommand::ShowItem(ItemReference::ShareUrl("https://1drv.ms/i/s!abc".into()))
+    );
+
+    let response = client
+        .post(format!("{url}/show"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "path": "Pictures/photo.jpg" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn upcoming_slides() {
+    let (url, mut receiver, _current_image) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{url}/upcoming"))
+        .bearer_auth(TEST_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        response.json::<serde_json::Value>().await.unwrap(),
+        serde_json::json!([{ "item_id": "next_item", "directory": "Pictures", "ready": true }])
+    );
+
+    let response = client
+        .post(format!("{url}/skip"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "item_id": "next_item" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(
+        receiver.recv().await.unwrap(),
+        RemoteCommand::Skip("next_item".into())
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn control_page() {
+    let (url, mut receiver, current_sender) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.text().await.unwrap().contains("<button"));
+
+    for (path, command) in [
+        ("next", RemoteCommand::Next),
+        ("previous", RemoteCommand::Previous),
+        ("pause", RemoteCommand::Pause),
+        ("resume", RemoteCommand::Resume),
+        ("screenshot", RemoteCommand::Screenshot),
+    ] {
+        let response = client
+            .post(format!("{url}/{path}"))
+            .bearer_auth(TEST_TOKEN)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(receiver.recv().await.unwrap(), command);
+    }
+
+    // Nothing to preview until an image is shown.
+    let response = client
+        .get(format!("{url}/current.png"))
+        .bearer_auth(TEST_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    current_sender.send_replace(Some(Arc::new(ColorImage::new([2, 2], egui::Color32::BLUE))));
+    let response = client
+        .get(format!("{url}/current.png?123"))
+        .bearer_auth(TEST_TOKEN)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/png");
+    let preview = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!((preview.width(), preview.height()), (2, 2));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lock_with_pin() {
+    let (url, mut receiver, _current_image) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    for (path, locked) in [("unlock", false), ("lock", true)] {
+        let response = client
+            .post(format!("{url}/{path}"))
+            .bearer_auth(TEST_TOKEN)
+            .body(r#"{ "pin": "1234" }"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            receiver.recv().await.unwrap(),
+            RemoteCommand::SetLocked(locked)
+        );
+    }
+
+    // The wrong PIN is rejected.
+    let response = client
+        .post(format!("{url}/unlock"))
+        .bearer_auth(TEST_TOKEN)
+        .body(r#"{ "pin": "0000" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+    assert!(receiver.try_recv().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn requires_pairing() {
+    let (url, mut receiver, _current_image) = start_test_server().await;
+    let client = reqwest::Client::new();
+
+    let response = client.post(format!("{url}/next")).send().await.unwrap();
+    assert_eq!(response.status(), 401);
+    let response = client
+        .post(format!("{url}/next"))
+        .bearer_auth("not_the_token")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    let response = client
+        .get(format!("{url}/current.png?token=not_the_token"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert!(receiver.try_recv().is_err());
+
+    // The token can also be passed as a query parameter.
+    let response = client
+        .post(format!("{url}/next?token={TEST_TOKEN}"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(receiver.recv().await.unwrap(), RemoteCommand::Next);
+
+    // Pairing with the wrong PIN fails.
+    let response = client
+        .post(format!("{url}/pair/start"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .post(format!("{url}/pair"))
+        .body(r#"{ "pin": "not a pin" }"#)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+}
@@ -0,0 +1,305 @@
+#[cfg(any(feature = "framebuffer", test))]
+use egui::ColorImage;
+use egui::{
+    epaint::{Mesh, RectShape, Rounding, TextureId},
+    layers::ShapeIdx,
+    pos2, vec2, LayerId, Pos2, RawInput, Rect, Shape, Vec2,
+};
+use std::{
+    collections::HashSet,
+    f32::consts::{FRAC_PI_2, PI},
+};
+
+/// How far (clockwise) to turn everything that is drawn, for screens that are mounted on their
+/// side or upside down where the OS can't rotate its output (e.g., some kiosk setups).
+///
+/// The slideshow is laid out on a "logical" screen that is the shape of the mounted panel, then
+/// turned onto the physical screen as it is drawn.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Rotation {
+    Degrees90,
+    Degrees180,
+    Degrees270,
+}
+
+impl TryFrom<&str> for Rotation {
+    type Error = ();
+
+    fn try_from(degrees: &str) -> Result<Self, Self::Error> {
+        match degrees {
+            "90" => Ok(Self::Degrees90),
+            "180" => Ok(Self::Degrees180),
+            "270" => Ok(Self::Degrees270),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Rotation {
+    fn is_quarter_turn(self) -> bool {
+        self != Self::Degrees180
+    }
+
+    fn angle(self) -> f32 {
+        match self {
+            Self::Degrees90 => FRAC_PI_2,
+            Self::Degrees180 => PI,
+            Self::Degrees270 => PI + FRAC_PI_2,
+        }
+    }
+
+    /// Size of the logical screen that fills a physical screen of `physical` size once turned.
+    pub fn logical_size(self, physical: Vec2) -> Vec2 {
+        if self.is_quarter_turn() {
+            vec2(physical.y, physical.x)
+        } else {
+            physical
+        }
+    }
+
+    /// Where a point on the logical screen ends up on the physical screen.
+    pub fn to_physical(self, pos: Pos2, physical: Vec2) -> Pos2 {
+        match self {
+            Self::Degrees90 => pos2(physical.x - pos.y, pos.x),
+            Self::Degrees180 => pos2(physical.x - pos.x, physical.y - pos.y),
+            Self::Degrees270 => pos2(pos.y, physical.y - pos.x),
+        }
+    }
+
+    /// Where a point on the physical screen (e.g., a click) is on the logical screen.
+    pub fn to_logical(self, pos: Pos2, physical: Vec2) -> Pos2 {
+        match self {
+            Self::Degrees90 => pos2(pos.y, physical.x - pos.x),
+            Self::Degrees180 => pos2(physical.x - pos.x, physical.y - pos.y),
+            Self::Degrees270 => pos2(physical.y - pos.y, pos.x),
+        }
+    }
+
+    /// Turns a direction on the physical screen (e.g., scrolling) into one on the logical screen.
+    fn vec_to_logical(self, delta: Vec2) -> Vec2 {
+        match self {
+            Self::Degrees90 => vec2(delta.y, -delta.x),
+            Self::Degrees180 => -delta,
+            Self::Degrees270 => vec2(-delta.y, delta.x),
+        }
+    }
+
+    fn rect_to_physical(self, rect: Rect, physical: Vec2) -> Rect {
+        Rect::from_two_pos(
+            self.to_physical(rect.min, physical),
+            self.to_physical(rect.max, physical),
+        )
+    }
+
+    /// Gives egui the logical screen to lay out on, and moves pointer input onto it so that clicks
+    /// and touches land on what is drawn underneath them. Returns the size of the physical screen.
+    pub fn rotate_input(self, raw_input: &mut RawInput) -> Option<Vec2> {
+        let screen_rect = raw_input.screen_rect?;
+        let physical = screen_rect.size();
+        raw_input.screen_rect = Some(Rect::from_min_size(
+            screen_rect.min,
+            self.logical_size(physical),
+        ));
+        for event in &mut raw_input.events {
+            match event {
+                egui::Event::PointerMoved(pos)
+                | egui::Event::PointerButton { pos, .. }
+                | egui::Event::Touch { pos, .. } => *pos = self.to_logical(*pos, physical),
+                egui::Event::MouseMoved(delta) | egui::Event::MouseWheel { delta, .. } => {
+                    *delta = self.vec_to_logical(*delta)
+                }
+                _ => {}
+            }
+        }
+        Some(physical)
+    }
+
+    /// Turns everything that has been painted this frame from the logical screen onto the physical
+    /// screen. This has to be done after everything else has been drawn.
+    pub fn rotate_painted(self, ctx: &egui::Context, physical: Vec2) {
+        let layers = ctx.memory(|memory| {
+            memory
+                .layer_ids()
+                .chain([LayerId::background()])
+                .collect::<HashSet<_>>()
+        });
+        ctx.graphics_mut(|graphics| {
+            for layer in layers {
+                let paint_list = graphics.entry(layer);
+                for index in 0..paint_list.next_idx().0 {
+                    paint_list.mutate_shape(ShapeIdx(index), |clipped| {
+                        clipped.clip_rect = self.rect_to_physical(clipped.clip_rect, physical);
+                        self.rotate_shape(&mut clipped.shape, physical);
+                    });
+                }
+            }
+        });
+    }
+
+    fn rotate_shape(self, shape: &mut Shape, physical: Vec2) {
+        let to_physical = |pos: &mut Pos2| *pos = self.to_physical(*pos, physical);
+        match shape {
+            Shape::Vec(shapes) => {
+                for shape in shapes {
+                    self.rotate_shape(shape, physical);
+                }
+            }
+            Shape::Circle(circle) => to_physical(&mut circle.center),
+            Shape::Ellipse(ellipse) => {
+                to_physical(&mut ellipse.center);
+                if self.is_quarter_turn() {
+                    ellipse.radius = vec2(ellipse.radius.y, ellipse.radius.x);
+                }
+            }
+            Shape::LineSegment { points, .. } => points.iter_mut().for_each(to_physical),
+            Shape::Path(path) => path.points.iter_mut().for_each(to_physical),
+            Shape::QuadraticBezier(bezier) => bezier.points.iter_mut().for_each(to_physical),
+            Shape::CubicBezier(bezier) => bezier.points.iter_mut().for_each(to_physical),
+            // Images are drawn as textured rectangles, which can only be turned as a mesh.
+            Shape::Rect(rect) if rect.fill_texture_id != TextureId::default() => {
+                let mut mesh = Mesh::with_texture(rect.fill_texture_id);
+                mesh.add_rect_with_uv(rect.rect, rect.uv, rect.fill);
+                *shape = Shape::Mesh(mesh);
+                self.rotate_shape(shape, physical);
+            }
+            Shape::Rect(rect) => self.rotate_rect(rect, physical),
+            Shape::Text(text) => {
+                // Text is turned around its top-left corner.
+                to_physical(&mut text.pos);
+                text.angle += self.angle();
+            }
+            Shape::Mesh(mesh) => mesh
+                .vertices
+                .iter_mut()
+                .for_each(|vertex| to_physical(&mut vertex.pos)),
+            Shape::Noop | Shape::Callback(_) => {}
+        }
+    }
+
+    fn rotate_rect(self, rect: &mut RectShape, physical: Vec2) {
+        rect.rect = self.rect_to_physical(rect.rect, physical);
+        // Each corner's rounding moves to the corner it is turned onto.
+        let Rounding { nw, ne, sw, se } = rect.rounding;
+        rect.rounding = match self {
+            Self::Degrees90 => Rounding {
+                nw: sw,
+                ne: nw,
+                se: ne,
+                sw: se,
+            },
+            Self::Degrees180 => Rounding {
+                nw: se,
+                ne: sw,
+                se: nw,
+                sw: ne,
+            },
+            Self::Degrees270 => Rounding {
+                nw: ne,
+                ne: se,
+                se: sw,
+                sw: nw,
+            },
+        };
+    }
+
+    /// Turns an image, e.g. to draw it straight to a framebuffer.
+    #[cfg(any(feature = "framebuffer", test))]
+    pub fn rotate_image(self, image: &ColorImage) -> ColorImage {
+        let [width, height] = image.size;
+        let size = if self.is_quarter_turn() {
+            [height, width]
+        } else {
+            [width, height]
+        };
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..size[1] {
+            for x in 0..size[0] {
+                let (source_x, source_y) = match self {
+                    Self::Degrees90 => (y, height - 1 - x),
+                    Self::Degrees180 => (width - 1 - x, height - 1 - y),
+                    Self::Degrees270 => (width - 1 - y, x),
+                };
+                pixels.push(image.pixels[source_y * width + source_x]);
+            }
+        }
+        ColorImage { size, pixels }
+    }
+}
+
+#[test]
+fn rotate_screen() {
+    let physical = vec2(800.0, 600.0);
+    for rotation in [
+        Rotation::Degrees90,
+        Rotation::Degrees180,
+        Rotation::Degrees270,
+    ] {
+        // Points round trip, and the logical screen exactly covers the physical one.
+        let logical = rotation.logical_size(physical);
+        let pos = pos2(10.0, 20.0);
+        assert_eq!(
+            rotation.to_logical(rotation.to_physical(pos, physical), physical),
+            pos
+        );
+        assert_eq!(
+            rotation.rect_to_physical(Rect::from_min_size(Pos2::ZERO, logical), physical),
+            Rect::from_min_size(Pos2::ZERO, physical)
+        );
+    }
+
+    // The top left of the logical screen is turned to the top right when turned clockwise.
+    let rotation = Rotation::Degrees90;
+    assert_eq!(rotation.logical_size(physical), vec2(600.0, 800.0));
+    assert_eq!(rotation.to_physical(Pos2::ZERO, physical), pos2(800.0, 0.0));
+    assert_eq!(
+        rotation.to_physical(pos2(600.0, 0.0), physical),
+        pos2(800.0, 600.0)
+    );
+
+    // Clicks land on the logical screen.
+    let mut raw_input = RawInput {
+        screen_rect: Some(Rect::from_min_size(Pos2::ZERO, physical)),
+        events: vec![egui::Event::PointerMoved(pos2(800.0, 0.0))],
+        ..Default::default()
+    };
+    assert_eq!(rotation.rotate_input(&mut raw_input), Some(physical));
+    assert_eq!(raw_input.screen_rect.unwrap().size(), vec2(600.0, 800.0));
+    assert_eq!(raw_input.events, [egui::Event::PointerMoved(Pos2::ZERO)]);
+
+    assert_eq!(Rotation::try_from("270"), Ok(Rotation::Degrees270));
+    assert!(Rotation::try_from("45").is_err());
+}
+
+#[test]
+fn rotate_image() {
+    use egui::Color32;
+
+    // 1 2 3
+    // 4 5 6
+    let image = ColorImage {
+        size: [3, 2],
+        pixels: (1..=6).map(Color32::from_gray).collect(),
+    };
+    let values = |image: ColorImage| {
+        (
+            image.size,
+            image
+                .pixels
+                .iter()
+                .map(|pixel| pixel.r())
+                .collect::<Vec<_>>(),
+        )
+    };
+    assert_eq!(
+        values(Rotation::Degrees90.rotate_image(&image)),
+        ([2, 3], vec![4, 1, 5, 2, 6, 3])
+    );
+    assert_eq!(
+        values(Rotation::Degrees180.rotate_image(&image)),
+        ([3, 2], vec![6, 5, 4, 3, 2, 1])
+    );
+    assert_eq!(
+        values(Rotation::Degrees270.rotate_image(&image)),
+        ([2, 3], vec![3, 6, 2, 5, 1, 4])
+    );
+}
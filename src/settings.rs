@@ -0,0 +1,712 @@
+use crate::{
+    cache::{CachePolicy, DEFAULT_MIN_FREE_PERCENT},
+    rotation::Rotation,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use eframe::HardwareAcceleration;
+use egui::{pos2, vec2, Pos2, Vec2};
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+/// Gap between a widget and the edges of the screen.
+const WIDGET_MARGIN: f32 = 16.0;
+const DEFAULT_WIDGET_SIZE: Vec2 = vec2(320.0, 240.0);
+/// Devices with less memory than this (in KB) are treated as low-end hardware.
+const LOW_RESOURCE_MEMORY_KB: u64 = 1024 * 1024;
+/// Prefix of environment variables that provide settings, e.g. `ONEDRIVE_SLIDESHOW_DEVICE_NAME` is
+/// the same as `--device-name`.
+const ENV_PREFIX: &str = "ONEDRIVE_SLIDESHOW_";
+/// Arguments that don't take a value, so are turned on or off by their environment variable.
+const SWITCHES: &[&str] = &[
+    "--report-status",
+    "--export-history",
+    "--offer-sample-config",
+    "--check",
+    "--background",
+    "--click-through",
+    "--windowed",
+    "--mock",
+];
+
+/// Settings for this device, as opposed to the slideshow configuration that is stored in OneDrive.
+#[derive(Debug, Default, PartialEq)]
+pub struct Settings {
+    /// Address to listen on for remote control commands.
+    pub remote_control: Option<SocketAddr>,
+    /// What to do with downloaded images when the disk is running low on space.
+    pub cache_policy: CachePolicy,
+    /// JPEG quality to re-encode images at before caching them, or `None` to cache them as they
+    /// were downloaded.
+    pub cache_quality: Option<u8>,
+    /// Whether to periodically write the slideshow's status to its app folder in OneDrive.
+    pub report_status: bool,
+    /// Whether to upload a monthly summary of the slides that have been shown to the slideshow's
+    /// app folder in OneDrive.
+    pub export_history: bool,
+    /// Whether to offer to create a sample slideshow.txt if there isn't one.
+    pub offer_sample_config: bool,
+    /// Keeps this slideshow's cache and credentials apart from other slideshows running on the same
+    /// device, e.g. to show different accounts on different monitors.
+    pub profile: Option<String>,
+    /// Directory to keep all of this slideshow's data in, instead of the temp directory and the
+    /// system's credential store.
+    pub data_dir: Option<PathBuf>,
+    /// Name of this device, used to tell multiple devices apart.
+    pub device_name: Option<String>,
+    /// How long after the user last clicked on the slideshow to return to the latest slide.
+    pub return_to_live: Option<Duration>,
+    /// How long to wait before starting the slideshow, e.g. to let the network connect on boot.
+    pub start_delay: Duration,
+    /// Local image to show until the first slide is ready.
+    pub splash: Option<PathBuf>,
+    /// Run behind all other windows, like a desktop background, instead of fullscreen.
+    pub background: bool,
+    /// Run as a small window on top of all other windows, instead of fullscreen.
+    pub widget: Option<Widget>,
+    /// Let mouse clicks pass through the slideshow's window to whatever is behind it.
+    pub click_through: bool,
+    /// Run in an ordinary window instead of fullscreen, e.g. while developing.
+    pub windowed: bool,
+    /// Size of the window, when running in a window.
+    pub window_size: Option<Vec2>,
+    /// Where on the desktop to put the window. When running fullscreen, this picks the monitor that
+    /// the slideshow fills.
+    pub window_position: Option<Pos2>,
+    /// Turn everything that is drawn, for a screen that is mounted on its side or upside down.
+    pub rotation: Option<Rotation>,
+    /// PIN that must be entered before the slideshow can be closed, e.g. on a shared display.
+    pub exit_pin: Option<String>,
+    /// Whether to require or avoid drawing with the GPU, or `None` to use it if it's available. Some
+    /// GPUs and drivers draw incorrectly or crash, in which case it should be turned off.
+    pub hardware_acceleration: Option<HardwareAcceleration>,
+    /// Most requests to make to OneDrive each day, after which only cached images are shown.
+    pub daily_requests: Option<u64>,
+    /// The only folders (paths from the root of the drive) whose images are shown while locked,
+    /// however slideshow.txt changes.
+    pub locked_folders: Vec<String>,
+    /// PIN for turning the folder lock off and on from the remote control. Without one, the lock is
+    /// always on.
+    pub lock_pin: Option<String>,
+    /// Most megabytes to download from OneDrive each day, after which only cached images are shown.
+    pub daily_download_mb: Option<u64>,
+    /// Whether to save CPU and memory on low-end hardware, or `None` to decide based on how much
+    /// memory the device has.
+    pub low_resource: Option<bool>,
+    /// Check whether OneDrive can be reached from this device, then exit.
+    pub check: bool,
+    /// Print this many of the most recently shown slides, then exit.
+    pub history: Option<usize>,
+    /// Time how long it takes to get this many slides ready, then exit.
+    pub bench: Option<usize>,
+    /// Render the most recently shown slides into a video at this path, then exit.
+    pub export_video: Option<PathBuf>,
+    /// How many slides to include in an exported video.
+    pub video_slides: Option<usize>,
+    /// Show this many slides by logging them to the console instead of opening a window, then exit.
+    pub headless_cycle: Option<usize>,
+    /// Show made up images instead of signing in to OneDrive, e.g. to work on how slides are shown
+    /// or for a demo.
+    pub mock: bool,
+    /// Folder of images for `--mock` to show, instead of made up sample images.
+    pub mock_images: Option<PathBuf>,
+    /// Caption template to try out by quickly cycling through the images that `--mock` shows.
+    pub preview: Option<String>,
+    /// File (or `-` for stdin) to read a refresh token from, so that the slideshow is signed in
+    /// without the device code. The token is checked and stored, then the slideshow exits.
+    pub provision_token: Option<PathBuf>,
+    /// Framebuffer device to draw slides to, instead of opening a window.
+    #[cfg(feature = "framebuffer")]
+    pub framebuffer: Option<PathBuf>,
+}
+
+/// A corner of the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Placement of the slideshow when it is running as an always-on-top widget.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Widget {
+    pub corner: Corner,
+    pub size: Vec2,
+}
+
+impl Widget {
+    /// Where the widget's window should be placed on a monitor of the given size.
+    pub fn position(&self, monitor_size: Vec2) -> Pos2 {
+        let left = WIDGET_MARGIN;
+        let top = WIDGET_MARGIN;
+        let right = monitor_size.x - self.size.x - WIDGET_MARGIN;
+        let bottom = monitor_size.y - self.size.y - WIDGET_MARGIN;
+        match self.corner {
+            Corner::TopLeft => pos2(left, top),
+            Corner::TopRight => pos2(right, top),
+            Corner::BottomLeft => pos2(left, bottom),
+            Corner::BottomRight => pos2(right, bottom),
+        }
+    }
+}
+
+impl Settings {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut settings = Self::default();
+        let mut widget_size = None;
+        let mut cache_policy = "skip".to_string();
+        let mut min_free_percent = DEFAULT_MIN_FREE_PERCENT;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--remote-control" => {
+                    let address = next_value(&mut args, &arg)?;
+                    settings.remote_control = Some(
+                        address
+                            .parse()
+                            .with_context(|| format!("Invalid address for {arg}: {address}"))?,
+                    );
+                }
+                "--report-status" => settings.report_status = true,
+                "--export-history" => settings.export_history = true,
+                "--offer-sample-config" => settings.offer_sample_config = true,
+                "--check" => settings.check = true,
+                "--history" => {
+                    let count = next_value(&mut args, &arg)?;
+                    settings.history =
+                        Some(count.parse().with_context(|| {
+                            format!("Invalid number of slides for {arg}: {count}")
+                        })?);
+                }
+                "--bench" => {
+                    let slides = next_value(&mut args, &arg)?;
+                    settings.bench = Some(slides.parse().with_context(|| {
+                        format!("Invalid number of slides for {arg}: {slides}")
+                    })?);
+                }
+                "--provision-token" => {
+                    settings.provision_token = Some(next_value(&mut args, &arg)?.into())
+                }
+                "--export-video" => {
+                    settings.export_video = Some(next_value(&mut args, &arg)?.into())
+                }
+                "--video-slides" => {
+                    let slides = next_value(&mut args, &arg)?;
+                    settings.video_slides = Some(slides.parse().with_context(|| {
+                        format!("Invalid number of slides for {arg}: {slides}")
+                    })?);
+                }
+                "--headless-cycle" => {
+                    let slides = next_value(&mut args, &arg)?;
+                    settings.headless_cycle = Some(slides.parse().with_context(|| {
+                        format!("Invalid number of slides for {arg}: {slides}")
+                    })?);
+                }
+                "--background" => settings.background = true,
+                "--widget" => {
+                    let corner = next_value(&mut args, &arg)?;
+                    settings.widget = Some(Widget {
+                        corner: match corner.as_str() {
+                            "top-left" => Corner::TopLeft,
+                            "top-right" => Corner::TopRight,
+                            "bottom-left" => Corner::BottomLeft,
+                            "bottom-right" => Corner::BottomRight,
+                            _ => bail!("Invalid corner for {arg}: {corner}"),
+                        },
+                        size: DEFAULT_WIDGET_SIZE,
+                    });
+                }
+                "--widget-size" => {
+                    widget_size = Some(parse_size(&arg, next_value(&mut args, &arg)?)?)
+                }
+                "--click-through" => settings.click_through = true,
+                "--windowed" => settings.windowed = true,
+                "--mock" => settings.mock = true,
+                "--mock-images" => settings.mock_images = Some(next_value(&mut args, &arg)?.into()),
+                "--preview" => settings.preview = Some(next_value(&mut args, &arg)?),
+                "--size" => {
+                    settings.window_size = Some(parse_size(&arg, next_value(&mut args, &arg)?)?)
+                }
+                "--position" => {
+                    let position = next_value(&mut args, &arg)?;
+                    settings.window_position = Some(
+                        position
+                            .split_once(',')
+                            .and_then(|(x, y)| {
+                                Some(pos2(x.trim().parse().ok()?, y.trim().parse().ok()?))
+                            })
+                            .ok_or_else(|| anyhow!("Invalid position for {arg}: {position}"))?,
+                    );
+                }
+                // The windowing library only lists monitors once the window exists, which is too late
+                // to open it on the right one, so monitors are picked by position instead.
+                "--monitor" => bail!(
+                    "--monitor isn't supported, use --position <x>,<y> with a point on the monitor instead"
+                ),
+                "--rotate" => {
+                    let degrees = next_value(&mut args, &arg)?;
+                    settings.rotation = Some(
+                        Rotation::try_from(degrees.as_str())
+                            .map_err(|()| anyhow!("Invalid rotation for {arg}: {degrees}"))?,
+                    );
+                }
+                "--hardware-acceleration" => {
+                    let value = next_value(&mut args, &arg)?;
+                    settings.hardware_acceleration = match value.as_str() {
+                        "on" => Some(HardwareAcceleration::Required),
+                        "off" => Some(HardwareAcceleration::Off),
+                        "auto" => None,
+                        _ => bail!("Invalid value for {arg}: {value}"),
+                    };
+                }
+                "--low-resource" => {
+                    let value = next_value(&mut args, &arg)?;
+                    settings.low_resource = match value.as_str() {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        "auto" => None,
+                        _ => bail!("Invalid value for {arg}: {value}"),
+                    };
+                }
+                "--start-delay" => {
+                    let seconds = next_value(&mut args, &arg)?;
+                    settings.start_delay = Duration::from_secs(
+                        seconds
+                            .parse()
+                            .with_context(|| format!("Invalid seconds for {arg}: {seconds}"))?,
+                    );
+                }
+                "--return-to-live" => {
+                    let seconds = next_value(&mut args, &arg)?;
+                    settings.return_to_live =
+                        Some(Duration::from_secs(seconds.parse().with_context(|| {
+                            format!("Invalid seconds for {arg}: {seconds}")
+                        })?));
+                }
+                "--splash" => settings.splash = Some(next_value(&mut args, &arg)?.into()),
+                #[cfg(feature = "framebuffer")]
+                "--framebuffer" => settings.framebuffer = Some(next_value(&mut args, &arg)?.into()),
+                "--profile" => {
+                    let profile = next_value(&mut args, &arg)?;
+                    if profile.is_empty()
+                        || !profile
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                    {
+                        bail!("Invalid name for {arg}: {profile}");
+                    }
+                    settings.profile = Some(profile);
+                }
+                "--data-dir" => settings.data_dir = Some(next_value(&mut args, &arg)?.into()),
+                "--device-name" => settings.device_name = Some(next_value(&mut args, &arg)?),
+                "--cache-policy" => cache_policy = next_value(&mut args, &arg)?,
+                "--cache-min-free" => {
+                    let percent = next_value(&mut args, &arg)?;
+                    min_free_percent = percent
+                        .parse()
+                        .ok()
+                        .filter(|value| *value <= 100)
+                        .ok_or_else(|| anyhow!("Invalid percentage for {arg}: {percent}"))?;
+                }
+                "--daily-requests" => {
+                    let count = next_value(&mut args, &arg)?;
+                    settings.daily_requests = Some(
+                        count
+                            .parse()
+                            .ok()
+                            .filter(|value| *value > 0)
+                            .ok_or_else(|| anyhow!("Invalid count for {arg}: {count}"))?,
+                    );
+                }
+                "--daily-download-mb" => {
+                    let megabytes = next_value(&mut args, &arg)?;
+                    settings.daily_download_mb = Some(
+                        megabytes
+                            .parse()
+                            .ok()
+                            .filter(|value| *value > 0)
+                            .ok_or_else(|| anyhow!("Invalid size for {arg}: {megabytes}"))?,
+                    );
+                }
+                "--locked-folders" => {
+                    settings.locked_folders = next_value(&mut args, &arg)?
+                        .split(',')
+                        .map(|folder| folder.trim().trim_matches('/').to_string())
+                        .filter(|folder| !folder.is_empty())
+                        .collect();
+                }
+                "--exit-pin" => {
+                    let pin = next_value(&mut args, &arg)?;
+                    if pin.is_empty() {
+                        bail!("Invalid PIN for {arg}");
+                    }
+                    settings.exit_pin = Some(pin);
+                }
+                "--lock-pin" => {
+                    let pin = next_value(&mut args, &arg)?;
+                    if pin.is_empty() {
+                        bail!("Invalid PIN for {arg}");
+                    }
+                    settings.lock_pin = Some(pin);
+                }
+                "--cache-quality" => {
+                    let quality = next_value(&mut args, &arg)?;
+                    settings.cache_quality = Some(
+                        quality
+                            .parse()
+                            .ok()
+                            .filter(|value| (1..=100).contains(value))
+                            .ok_or_else(|| anyhow!("Invalid quality for {arg}: {quality}"))?,
+                    );
+                }
+                _ => bail!("Unknown argument: {arg}"),
+            }
+        }
+        if settings.video_slides.is_some() && settings.export_video.is_none() {
+            bail!("--video-slides requires --export-video");
+        }
+        if settings.lock_pin.is_some() && settings.locked_folders.is_empty() {
+            bail!("--lock-pin requires --locked-folders");
+        }
+        if settings.windowed && (settings.background || settings.widget.is_some()) {
+            bail!("--windowed can't be used with --background or --widget");
+        }
+        if settings.window_size.is_some() && !settings.windowed {
+            bail!("--size requires --windowed");
+        }
+        if settings.mock_images.is_some() && !settings.mock && settings.preview.is_none() {
+            bail!("--mock-images requires --mock or --preview");
+        }
+        if (settings.mock || settings.preview.is_some())
+            && (settings.check || settings.bench.is_some() || settings.provision_token.is_some())
+        {
+            bail!("--mock and --preview can't be used with --check, --bench or --provision-token, which need OneDrive");
+        }
+        if settings.window_position.is_some() && settings.widget.is_some() {
+            bail!("--position can't be used with --widget, which is placed in a corner");
+        }
+        if let Some(size) = widget_size {
+            let Some(widget) = &mut settings.widget else {
+                bail!("--widget-size requires --widget");
+            };
+            widget.size = size;
+        }
+        settings.cache_policy = match cache_policy.as_str() {
+            "always" => CachePolicy::Always,
+            "skip" => CachePolicy::SkipWhenLow { min_free_percent },
+            "evict" => CachePolicy::EvictWhenLow { min_free_percent },
+            _ => bail!("Invalid cache policy: {cache_policy}"),
+        };
+        Ok(settings)
+    }
+
+    /// Parses settings from `ONEDRIVE_SLIDESHOW_*` environment variables and then the command line,
+    /// so that arguments take precedence over environment variables.
+    pub fn from_env_and_args(
+        vars: impl Iterator<Item = (String, String)>,
+        args: impl Iterator<Item = String>,
+    ) -> Result<Self> {
+        Self::parse(args_from_env(vars)?.into_iter().chain(args))
+    }
+
+    pub fn low_resource(&self) -> bool {
+        self.low_resource.unwrap_or_else(|| {
+            sys_info::mem_info().map_or(false, |memory| memory.total < LOW_RESOURCE_MEMORY_KB)
+        })
+    }
+
+    pub fn device_name(&self) -> String {
+        self.device_name
+            .clone()
+            .unwrap_or_else(|| sys_info::hostname().unwrap_or_else(|_| "onedrive-slideshow".into()))
+    }
+}
+
+/// Converts environment variables that provide settings into the equivalent arguments.
+fn args_from_env(vars: impl Iterator<Item = (String, String)>) -> Result<Vec<String>> {
+    let mut vars = vars
+        .filter_map(|(name, value)| Some((name.strip_prefix(ENV_PREFIX)?.to_string(), value)))
+        .collect::<Vec<_>>();
+    // Keep the order stable, since later arguments override earlier ones.
+    vars.sort();
+    let mut args = Vec::new();
+    for (name, value) in vars {
+        let arg = format!("--{}", name.to_ascii_lowercase().replace('_', "-"));
+        if SWITCHES.contains(&arg.as_str()) {
+            match value.to_ascii_lowercase().as_str() {
+                "1" | "true" | "on" => args.push(arg),
+                "0" | "false" | "off" | "" => {}
+                _ => bail!("Invalid value for {ENV_PREFIX}{name}: {value}"),
+            }
+        } else {
+            args.push(arg);
+            args.push(value);
+        }
+    }
+    Ok(args)
+}
+
+/// Parses a size such as `400x300`.
+fn parse_size(arg: &str, size: String) -> Result<Vec2> {
+    size.split_once('x')
+        .and_then(|(width, height)| Some(vec2(width.parse().ok()?, height.parse().ok()?)))
+        .filter(|size| size.x > 0.0 && size.y > 0.0)
+        .ok_or_else(|| anyhow!("Invalid size for {arg}: {size}"))
+}
+
+fn next_value(args: &mut impl Iterator<Item = String>, arg: &str) -> Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("Missing value for {arg}"))
+}
+
+#[test]
+fn parse_settings() {
+    let parse = |args: &[&str]| Settings::parse(args.iter().map(|arg| arg.to_string()));
+
+    assert_eq!(parse(&[]).unwrap(), Settings::default());
+    assert_eq!(
+        parse(&["--remote-control", "127.0.0.1:8080"])
+            .unwrap()
+            .remote_control,
+        Some("127.0.0.1:8080".parse().unwrap())
+    );
+    assert!(parse(&["--remote-control"]).is_err());
+    assert!(parse(&["--remote-control", "not an address"]).is_err());
+    assert!(parse(&["--unknown"]).is_err());
+    assert!(parse(&["--report-status"]).unwrap().report_status);
+    assert!(parse(&["--export-history"]).unwrap().export_history);
+    assert!(
+        parse(&["--offer-sample-config"])
+            .unwrap()
+            .offer_sample_config
+    );
+    assert!(parse(&["--check"]).unwrap().check);
+    assert_eq!(parse(&["--bench", "5"]).unwrap().bench, Some(5));
+    assert!(parse(&["--bench", "lots"]).is_err());
+    assert_eq!(
+        parse(&["--headless-cycle", "3"]).unwrap().headless_cycle,
+        Some(3)
+    );
+    assert!(parse(&["--headless-cycle", "many"]).is_err());
+    let settings = parse(&["--export-video", "recap.mp4", "--video-slides", "10"]).unwrap();
+    assert_eq!(settings.export_video, Some("recap.mp4".into()));
+    assert_eq!(settings.video_slides, Some(10));
+    assert!(parse(&["--video-slides", "10"]).is_err());
+    assert_eq!(parse(&["--history", "20"]).unwrap().history, Some(20));
+    assert_eq!(
+        parse(&["--provision-token", "-"]).unwrap().provision_token,
+        Some("-".into())
+    );
+    assert!(parse(&["--provision-token"]).is_err());
+    assert!(parse(&["--history", "all"]).is_err());
+    assert!(parse(&["--background"]).unwrap().background);
+    assert!(parse(&["--click-through"]).unwrap().click_through);
+    assert_eq!(
+        parse(&["--rotate", "90"]).unwrap().rotation,
+        Some(Rotation::Degrees90)
+    );
+    assert!(parse(&["--rotate", "45"]).is_err());
+    assert_eq!(
+        parse(&["--hardware-acceleration", "off"])
+            .unwrap()
+            .hardware_acceleration,
+        Some(HardwareAcceleration::Off)
+    );
+    assert_eq!(
+        parse(&["--hardware-acceleration", "on"])
+            .unwrap()
+            .hardware_acceleration,
+        Some(HardwareAcceleration::Required)
+    );
+    assert!(parse(&["--hardware-acceleration", "fast"]).is_err());
+    assert!(parse(&["--low-resource", "on"]).unwrap().low_resource());
+    assert!(!parse(&["--low-resource", "off"]).unwrap().low_resource());
+    assert_eq!(
+        parse(&["--low-resource", "auto"]).unwrap().low_resource,
+        None
+    );
+    assert!(parse(&["--low-resource", "maybe"]).is_err());
+    let widget = parse(&["--widget", "bottom-right", "--widget-size", "400x300"])
+        .unwrap()
+        .widget
+        .unwrap();
+    assert_eq!(
+        widget,
+        Widget {
+            corner: Corner::BottomRight,
+            size: vec2(400.0, 300.0)
+        }
+    );
+    assert_eq!(widget.position(vec2(1920.0, 1080.0)), pos2(1504.0, 764.0));
+    assert_eq!(
+        parse(&["--widget", "top-left"])
+            .unwrap()
+            .widget
+            .unwrap()
+            .size,
+        DEFAULT_WIDGET_SIZE
+    );
+    assert!(parse(&["--widget", "middle"]).is_err());
+    assert!(parse(&["--widget", "top-left", "--widget-size", "big"]).is_err());
+    assert!(parse(&["--widget-size", "400x300"]).is_err());
+    let settings = parse(&["--windowed", "--size", "1280x720", "--position", "1920, 0"]).unwrap();
+    assert!(settings.windowed);
+    assert_eq!(settings.window_size, Some(vec2(1280.0, 720.0)));
+    assert_eq!(settings.window_position, Some(pos2(1920.0, 0.0)));
+    assert_eq!(
+        parse(&["--position", "-1080,0"]).unwrap().window_position,
+        Some(pos2(-1080.0, 0.0))
+    );
+    assert!(parse(&["--position", "second"]).is_err());
+    assert!(parse(&["--monitor", "2"]).is_err());
+    assert!(parse(&["--size", "1280x720"]).is_err());
+    assert!(parse(&["--windowed", "--size", "0x720"]).is_err());
+    assert!(parse(&["--windowed", "--background"]).is_err());
+    assert!(parse(&["--widget", "top-left", "--position", "0,0"]).is_err());
+    assert_eq!(
+        parse(&["--start-delay", "30"]).unwrap().start_delay,
+        Duration::from_secs(30)
+    );
+    assert!(parse(&["--start-delay", "soon"]).is_err());
+    assert_eq!(
+        parse(&["--return-to-live", "120"]).unwrap().return_to_live,
+        Some(Duration::from_secs(120))
+    );
+    assert!(parse(&["--return-to-live", "later"]).is_err());
+    assert_eq!(
+        parse(&["--splash", "splash.png"]).unwrap().splash,
+        Some("splash.png".into())
+    );
+    #[cfg(feature = "framebuffer")]
+    assert_eq!(
+        parse(&["--framebuffer", "/dev/fb0"]).unwrap().framebuffer,
+        Some("/dev/fb0".into())
+    );
+    assert_eq!(
+        parse(&["--profile", "work_2"]).unwrap().profile.as_deref(),
+        Some("work_2")
+    );
+    assert!(parse(&["--profile", "../home"]).is_err());
+    assert!(parse(&["--profile", ""]).is_err());
+    assert_eq!(
+        parse(&["--data-dir", "/media/usb/slideshow"])
+            .unwrap()
+            .data_dir,
+        Some("/media/usb/slideshow".into())
+    );
+    assert_eq!(
+        parse(&["--device-name", "Kitchen"]).unwrap().device_name(),
+        "Kitchen"
+    );
+
+    assert_eq!(
+        parse(&["--cache-policy", "evict", "--cache-min-free", "25"])
+            .unwrap()
+            .cache_policy,
+        CachePolicy::EvictWhenLow {
+            min_free_percent: 25
+        }
+    );
+    assert_eq!(
+        parse(&["--cache-min-free", "5"]).unwrap().cache_policy,
+        CachePolicy::SkipWhenLow {
+            min_free_percent: 5
+        }
+    );
+    assert_eq!(
+        parse(&["--cache-policy", "always"]).unwrap().cache_policy,
+        CachePolicy::Always
+    );
+    assert!(parse(&["--cache-policy", "sometimes"]).is_err());
+    assert!(parse(&["--cache-min-free", "101"]).is_err());
+    assert_eq!(
+        parse(&["--cache-quality", "75"]).unwrap().cache_quality,
+        Some(75)
+    );
+    assert!(parse(&["--cache-quality", "0"]).is_err());
+    assert!(parse(&["--cache-quality", "high"]).is_err());
+    let settings = parse(&["--daily-requests", "2000", "--daily-download-mb", "50"]).unwrap();
+    assert_eq!(settings.daily_requests, Some(2000));
+    assert_eq!(settings.daily_download_mb, Some(50));
+    assert!(parse(&["--daily-download-mb", "0"]).is_err());
+    let settings = parse(&[
+        "--locked-folders",
+        "Kids, /Family/Pets/,",
+        "--lock-pin",
+        "4321",
+    ])
+    .unwrap();
+    assert_eq!(settings.locked_folders, ["Kids", "Family/Pets"]);
+    assert_eq!(settings.lock_pin.as_deref(), Some("4321"));
+    assert!(parse(&["--lock-pin", "4321"]).is_err());
+    assert_eq!(
+        parse(&["--exit-pin", "2468"]).unwrap().exit_pin.as_deref(),
+        Some("2468")
+    );
+    assert!(parse(&["--exit-pin", ""]).is_err());
+    assert!(parse(&["--mock"]).unwrap().mock);
+    assert_eq!(
+        parse(&["--mock", "--mock-images", "samples"])
+            .unwrap()
+            .mock_images,
+        Some("samples".into())
+    );
+    assert!(parse(&["--mock-images", "samples"]).is_err());
+    let settings = parse(&["--preview", "{month} · {place}", "--mock-images", "samples"]).unwrap();
+    assert_eq!(settings.preview.as_deref(), Some("{month} · {place}"));
+    assert!(!settings.mock);
+    assert!(parse(&["--preview"]).is_err());
+    assert!(parse(&["--mock", "--check"]).is_err());
+    assert!(parse(&["--mock", "--bench", "5"]).is_err());
+    assert!(parse(&["--mock", "--provision-token", "-"]).is_err());
+    assert!(parse(&["--preview", "{name}", "--check"]).is_err());
+}
+
+#[test]
+fn settings_from_env() {
+    let vars = |vars: &[(&str, &str)]| {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    };
+    let args = |args: &[&str]| {
+        args.iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>()
+            .into_iter()
+    };
+
+    let settings = Settings::from_env_and_args(
+        vars(&[
+            ("ONEDRIVE_SLIDESHOW_DEVICE_NAME", "kitchen"),
+            ("ONEDRIVE_SLIDESHOW_REPORT_STATUS", "true"),
+            ("ONEDRIVE_SLIDESHOW_BACKGROUND", "0"),
+            ("ONEDRIVE_SLIDESHOW_CACHE_QUALITY", "80"),
+            ("PATH", "/usr/bin"),
+        ]),
+        args(&[]),
+    )
+    .unwrap();
+    assert_eq!(settings.device_name.as_deref(), Some("kitchen"));
+    assert!(settings.report_status);
+    assert!(!settings.background);
+    assert_eq!(settings.cache_quality, Some(80));
+
+    // Arguments take precedence over environment variables.
+    let settings = Settings::from_env_and_args(
+        vars(&[("ONEDRIVE_SLIDESHOW_DEVICE_NAME", "kitchen")]),
+        args(&["--device-name", "hallway"]),
+    )
+    .unwrap();
+    assert_eq!(settings.device_name.as_deref(), Some("hallway"));
+
+    assert!(
+        Settings::from_env_and_args(vars(&[("ONEDRIVE_SLIDESHOW_CHECK", "maybe")]), args(&[]))
+            .is_err()
+    );
+    assert!(Settings::from_env_and_args(
+        vars(&[("ONEDRIVE_SLIDESHOW_CACHE_QUALITY", "best")]),
+        args(&[])
+    )
+    .is_err());
+}
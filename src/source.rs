@@ -0,0 +1,162 @@
+use crate::{
+    cache::CacheStore,
+    image_loader::{Config, FailedDirectory, Image, ImageLoader, Listing, PhotoSource},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use egui::ColorImage;
+use std::{collections::HashSet, future::Future, time::Duration};
+
+/// Everything the slideshow needs from where its images come from: OneDrive (through
+/// [`ImageLoader`]), or a stand-in for it such as [`MockDrive`](crate::mock::MockDrive).
+pub trait SlideSource: PhotoSource + 'static {
+    /// Gets ready to list and load images, once signed in.
+    fn connect(&mut self, token: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Reads slideshow.txt, then lists the images in its directories. Fails with a `ConfigError`
+    /// if slideshow.txt is missing or invalid.
+    fn get_image_list(&self, token: &str)
+        -> impl Future<Output = Result<(Listing, Config)>> + Send;
+
+    /// Lists a directory that previously failed, and everything under it.
+    fn retry_failed_directory(
+        &self,
+        token: &str,
+        failed: FailedDirectory,
+    ) -> impl Future<Output = Listing> + Send;
+
+    /// Lists the images at `path`, which may either be a single image or a folder of images.
+    fn list_images_at(
+        &self,
+        token: &str,
+        path: &str,
+    ) -> impl Future<Output = Result<Vec<Image>>> + Send;
+
+    /// Resolves a sharing link to the id of the item that it refers to.
+    fn resolve_share_url(
+        &self,
+        token: &str,
+        share_url: &str,
+    ) -> impl Future<Output = Result<String>> + Send;
+
+    /// Creates slideshow.txt with a sample configuration.
+    fn create_sample_config(&self, token: &str) -> impl Future<Output = Result<()>> + Send;
+
+    /// Writes a file into the app's folder.
+    fn upload_app_file(
+        &self,
+        token: &str,
+        file_name: &str,
+        contents: impl Into<Bytes> + Send,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    /// Checks if a file exists in the app's folder and, if it does, deletes it.
+    fn take_app_file(
+        &self,
+        token: &str,
+        file_name: &str,
+    ) -> impl Future<Output = Result<bool>> + Send;
+
+    /// Gets the encoded image, sized to fit within `width` by `height`.
+    fn load_image_data(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> impl Future<Output = Result<Bytes>> + Send;
+
+    /// Decodes an image that has already been loaded.
+    fn convert(&self, data: Bytes) -> impl Future<Output = Result<ColorImage>> + Send;
+
+    /// Loads an image, sized to fit within `width` by `height`, and decodes it.
+    fn load_image(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> impl Future<Output = Result<ColorImage>> + Send {
+        async move {
+            let data = self.load_image_data(token, height, width, image_id).await?;
+            self.convert(data).await
+        }
+    }
+
+    /// If only cached images should be shown for now, how long until that changes.
+    fn cache_only_for(&self) -> Option<Duration>;
+
+    /// IDs of the images that can be shown without downloading them.
+    fn cached_image_ids(&self) -> HashSet<String>;
+
+    /// Removes cached images that won't be shown again, returning how many were removed.
+    fn collect_garbage(
+        &self,
+        listed: Option<&HashSet<String>>,
+    ) -> impl Future<Output = Result<usize>> + Send;
+}
+
+impl<C: CacheStore + 'static> SlideSource for ImageLoader<C> {
+    async fn connect(&mut self, token: &str) -> Result<()> {
+        self.detect_drive_type(token).await
+    }
+
+    async fn get_image_list(&self, token: &str) -> Result<(Listing, Config)> {
+        ImageLoader::get_image_list(self, token).await
+    }
+
+    async fn retry_failed_directory(&self, token: &str, failed: FailedDirectory) -> Listing {
+        ImageLoader::retry_failed_directory(self, token, failed).await
+    }
+
+    async fn list_images_at(&self, token: &str, path: &str) -> Result<Vec<Image>> {
+        ImageLoader::list_images_at(self, token, path).await
+    }
+
+    async fn resolve_share_url(&self, token: &str, share_url: &str) -> Result<String> {
+        ImageLoader::resolve_share_url(self, token, share_url).await
+    }
+
+    async fn create_sample_config(&self, token: &str) -> Result<()> {
+        ImageLoader::create_sample_config(self, token).await
+    }
+
+    async fn upload_app_file(
+        &self,
+        token: &str,
+        file_name: &str,
+        contents: impl Into<Bytes> + Send,
+    ) -> Result<()> {
+        ImageLoader::upload_app_file(self, token, file_name, contents).await
+    }
+
+    async fn take_app_file(&self, token: &str, file_name: &str) -> Result<bool> {
+        ImageLoader::take_app_file(self, token, file_name).await
+    }
+
+    async fn load_image_data(
+        &self,
+        token: &str,
+        height: u32,
+        width: u32,
+        image_id: &str,
+    ) -> Result<Bytes> {
+        ImageLoader::load_image_data(self, token, height, width, image_id).await
+    }
+
+    async fn convert(&self, data: Bytes) -> Result<ColorImage> {
+        ImageLoader::convert(self, data).await
+    }
+
+    fn cache_only_for(&self) -> Option<Duration> {
+        ImageLoader::cache_only_for(self)
+    }
+
+    fn cached_image_ids(&self) -> HashSet<String> {
+        ImageLoader::cached_image_ids(self)
+    }
+
+    async fn collect_garbage(&self, listed: Option<&HashSet<String>>) -> Result<usize> {
+        ImageLoader::collect_garbage(self, listed).await
+    }
+}
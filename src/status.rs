@@ -0,0 +1,120 @@
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use egui::ColorImage;
+use serde::Serialize;
+use std::collections::VecDeque;
+
+const MAX_LOG_LINES: usize = 200;
+
+/// Health of the slideshow, which is periodically written to the app's folder in OneDrive so that
+/// the slideshow can be checked on remotely.
+#[derive(Clone, Debug, Serialize)]
+pub struct Status {
+    pub version: &'static str,
+    pub device: String,
+    pub last_slide_time: Option<DateTime<Local>>,
+    pub image_count: usize,
+    pub error_count: u64,
+    pub last_error: Option<String>,
+    /// Recent activity, which is only uploaded when diagnostics are requested.
+    #[serde(skip)]
+    log: VecDeque<String>,
+}
+
+impl Status {
+    pub fn new(device: String) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            device,
+            last_slide_time: None,
+            image_count: 0,
+            error_count: 0,
+            last_error: None,
+            log: VecDeque::new(),
+        }
+    }
+
+    pub fn record_slide(&mut self, image_count: usize) {
+        let now = Local::now();
+        self.last_slide_time = Some(now);
+        self.image_count = image_count;
+        self.log(format!("{now} Showed slide ({image_count} images)"));
+    }
+
+    pub fn record_error(&mut self, err: &anyhow::Error) {
+        let message = format!("{err:#}");
+        self.error_count += 1;
+        self.log(format!("{} Error: {message}", Local::now()));
+        self.last_error = Some(message);
+    }
+
+    fn log(&mut self, line: String) {
+        if self.log.len() == MAX_LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    pub fn log_text(&self) -> String {
+        self.log.iter().fold(String::new(), |mut text, line| {
+            text.push_str(line);
+            text.push('\n');
+            text
+        })
+    }
+
+    /// Name of a file in the app's folder that belongs to this device, so that multiple devices
+    /// using the same account don't overwrite each other's files.
+    pub fn device_file_name(&self, name: &str, extension: &str) -> String {
+        format!("{name}-{}.{extension}", self.device)
+    }
+}
+
+pub fn encode_png(image: &ColorImage) -> Result<Vec<u8>> {
+    let pixels = image
+        .pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_srgba_unmultiplied())
+        .collect();
+    let buffer = image::RgbaImage::from_raw(image.width() as u32, image.height() as u32, pixels)
+        .ok_or_else(|| anyhow::anyhow!("Image size doesn't match its pixels"))?;
+    let mut png = Vec::new();
+    buffer.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[test]
+fn record_status() {
+    let mut status = Status::new("frame".into());
+    status.record_error(&anyhow::anyhow!("Failed"));
+    status.record_slide(42);
+    status.record_error(&anyhow::anyhow!("Failed again"));
+
+    assert!(status.last_slide_time.is_some());
+    assert_eq!(status.device_file_name("log", "txt"), "log-frame.txt");
+    assert_eq!(status.image_count, 42);
+    assert_eq!(status.error_count, 2);
+    assert_eq!(status.last_error.as_deref(), Some("Failed again"));
+
+    let log = status.log_text();
+    let lines = log.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].ends_with("Error: Failed"));
+    assert!(lines[1].ends_with("Showed slide (42 images)"));
+    assert!(lines[2].ends_with("Error: Failed again"));
+
+    // The log doesn't grow without bound.
+    for _ in 0..MAX_LOG_LINES {
+        status.record_slide(42);
+    }
+    assert_eq!(status.log_text().lines().count(), MAX_LOG_LINES);
+}
+
+#[test]
+fn encode_screenshot() {
+    let image = ColorImage::new([2, 3], egui::Color32::RED);
+    let png = encode_png(&image).unwrap();
+    let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+    assert_eq!(decoded.dimensions(), (2, 3));
+    assert_eq!(decoded.get_pixel(1, 2).0, [255, 0, 0, 255]);
+}
@@ -0,0 +1,170 @@
+use crate::history::History;
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// How long each slide is on screen in an exported video, including fading in and out.
+const SLIDE_SECONDS: u32 = 4;
+const FADE_SECONDS: u32 = 1;
+const VIDEO_WIDTH: u32 = 1920;
+const VIDEO_HEIGHT: u32 = 1080;
+const VIDEO_FPS: u32 = 30;
+
+/// Finds the most recently shown images that are still in the cache, oldest first. Images that
+/// were shown more than once are only included once.
+pub fn recent_cached_images(
+    history: &History,
+    cache_directory: &Path,
+    count: usize,
+) -> Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut images = Vec::new();
+    for entry in history.recent(usize::MAX)?.into_iter().rev() {
+        if images.len() == count {
+            break;
+        }
+        let path = cache_directory.join(&entry.item.item_id);
+        if path.exists() && seen.insert(entry.item.item_id) {
+            images.push(path);
+        }
+    }
+    images.reverse();
+    Ok(images)
+}
+
+/// Renders `images` as an MP4 slideshow with `ffmpeg`, cross-fading between them.
+pub fn export(images: &[PathBuf], output: &Path) -> Result<()> {
+    if images.is_empty() {
+        bail!("No cached images to export: the images need to have been shown recently");
+    }
+    let status = Command::new("ffmpeg")
+        .args(ffmpeg_args(images, output))
+        .status()
+        .context("Running ffmpeg (is it installed?)")?;
+    if !status.success() {
+        bail!("ffmpeg failed: {status}");
+    }
+    Ok(())
+}
+
+fn ffmpeg_args(images: &[PathBuf], output: &Path) -> Vec<OsString> {
+    let mut args = Vec::<OsString>::new();
+    let mut filters = Vec::new();
+    for (index, image) in images.iter().enumerate() {
+        args.extend([
+            "-loop".into(),
+            "1".into(),
+            "-t".into(),
+            SLIDE_SECONDS.to_string().into(),
+        ]);
+        args.extend(["-i".into(), image.clone().into_os_string()]);
+        // Letterbox every image to the same size, since cross-fading needs matching inputs.
+        filters.push(format!(
+            "[{index}:v]scale={VIDEO_WIDTH}:{VIDEO_HEIGHT}:force_original_aspect_ratio=decrease,\
+             pad={VIDEO_WIDTH}:{VIDEO_HEIGHT}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={VIDEO_FPS},\
+             format=yuv420p[s{index}]"
+        ));
+    }
+    let mut previous = "s0".to_string();
+    for index in 1..images.len() {
+        // Each fade starts before the end of the video so far, which overlaps the slides.
+        let offset = index as u32 * (SLIDE_SECONDS - FADE_SECONDS);
+        let faded = format!("f{index}");
+        filters.push(format!(
+            "[{previous}][s{index}]xfade=transition=fade:duration={FADE_SECONDS}:offset={offset}[{faded}]"
+        ));
+        previous = faded;
+    }
+    args.extend(["-filter_complex".into(), filters.join(";").into()]);
+    args.extend(["-map".into(), format!("[{previous}]").into()]);
+    for arg in [
+        "-c:v",
+        "libx264",
+        "-pix_fmt",
+        "yuv420p",
+        "-movflags",
+        "+faststart",
+        "-y",
+    ] {
+        args.push(arg.into());
+    }
+    args.push(output.as_os_str().to_owned());
+    args
+}
+
+#[test]
+fn build_ffmpeg_args() {
+    let args = |images: &[&str]| {
+        ffmpeg_args(
+            &images.iter().map(PathBuf::from).collect::<Vec<_>>(),
+            Path::new("recap.mp4"),
+        )
+        .into_iter()
+        .map(|arg| arg.into_string().unwrap())
+        .collect::<Vec<_>>()
+    };
+    let arg_after = |args: &[String], name: &str| {
+        let index = args.iter().position(|arg| arg == name).unwrap();
+        args[index + 1].clone()
+    };
+
+    // A single image doesn't need any fades.
+    let single = args(&["a"]);
+    assert_eq!(single[..6], ["-loop", "1", "-t", "4", "-i", "a"]);
+    assert!(!arg_after(&single, "-filter_complex").contains("xfade"));
+    assert_eq!(arg_after(&single, "-map"), "[s0]");
+    assert_eq!(single.last().unwrap(), "recap.mp4");
+
+    let multiple = args(&["a", "b", "c"]);
+    let filter = arg_after(&multiple, "-filter_complex");
+    assert!(filter.contains("[s0][s1]xfade=transition=fade:duration=1:offset=3[f1]"));
+    assert!(filter.contains("[f1][s2]xfade=transition=fade:duration=1:offset=6[f2]"));
+    assert_eq!(arg_after(&multiple, "-map"), "[f2]");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn find_recent_cached_images() {
+    use crate::history::{HistoryEntry, ShownItem};
+
+    let temp_dir = std::env::temp_dir().join("onedrive_slideshow_test/recent_cached_images");
+    if temp_dir.exists() {
+        tokio::fs::remove_dir_all(&temp_dir).await.unwrap();
+    }
+    let cache_directory = temp_dir.join("cache");
+    std::fs::create_dir_all(&cache_directory).unwrap();
+    let history = History::new(temp_dir.join("history.jsonl"));
+    for item_id in ["a", "b", "evicted", "c", "b"] {
+        if item_id != "evicted" {
+            std::fs::write(cache_directory.join(item_id), b"image").unwrap();
+        }
+        history
+            .record(&HistoryEntry {
+                time: chrono::Local::now(),
+                item: ShownItem {
+                    item_id: item_id.into(),
+                    name: None,
+                    source: "Pictures".into(),
+                    folder: None,
+                    taken: None,
+                    location: None,
+                },
+            })
+            .await
+            .unwrap();
+    }
+
+    let names = |count| {
+        recent_cached_images(&history, &cache_directory, count)
+            .unwrap()
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names(2), ["c", "b"]);
+    assert_eq!(names(10), ["a", "c", "b"]);
+    assert!(export(&[], Path::new("recap.mp4")).is_err());
+}
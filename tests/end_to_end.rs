@@ -0,0 +1,33 @@
+//! Runs the slideshow from start to finish: signing in, listing the images, then choosing,
+//! loading and decoding slides. This uses the slideshow's own stand-in for OneDrive (`--mock`)
+//! and runs without a display (`--headless-cycle`), so it works anywhere.
+
+use std::process::Command;
+
+#[test]
+fn headless_cycle_against_mock() {
+    let output = Command::new(env!("CARGO_BIN_EXE_onedrive_slideshow"))
+        .args(["--mock", "--headless-cycle", "3"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{stdout}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Each slide is printed along with the image that it showed.
+    let slides = stdout
+        .lines()
+        .filter(|line| line.starts_with("Slide "))
+        .collect::<Vec<_>>();
+    assert_eq!(slides.len(), 3, "{stdout}");
+    for (index, slide) in slides.iter().enumerate() {
+        assert!(
+            slide.starts_with(&format!("Slide {}: ", index + 1)),
+            "{slide}"
+        );
+        assert!(slide.contains("Sample "), "{slide}");
+    }
+}